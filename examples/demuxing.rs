@@ -1,3 +1,5 @@
+#![cfg(target_os = "android")]
+
 use log::debug;
 use mediacodec::{Frame, MediaExtractor, SampleFormat, VideoFrame};
 
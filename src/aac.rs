@@ -0,0 +1,570 @@
+use crate::bitstream::{BitReader, BitWriter};
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+
+/// AAC Main
+pub const AAC_OBJECT_MAIN: u8 = 1;
+/// AAC Low Complexity, the most common object type
+pub const AAC_OBJECT_LC: u8 = 2;
+/// AAC Scalable Sample Rate
+pub const AAC_OBJECT_SSR: u8 = 3;
+/// AAC Long Term Prediction
+pub const AAC_OBJECT_LTP: u8 = 4;
+/// Spectral Band Replication, used by HE-AAC
+pub const AAC_OBJECT_SBR: u8 = 5;
+/// Parametric Stereo, used by HE-AAC v2
+pub const AAC_OBJECT_PS: u8 = 29;
+
+/// The sample rates for `sampling_frequency_index` 0 to 12
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Returns the `sampling_frequency_index` of a sample rate, if it has one
+pub fn aac_sample_rate_index(sample_rate: u32) -> Option<u8> {
+    SAMPLE_RATES
+        .iter()
+        .position(|&rate| rate == sample_rate)
+        .map(|index| index as u8)
+}
+
+/// Returns the number of channels for a `channel_configuration`. 0 means the channels are described in the stream itself
+pub fn aac_channel_count(channel_config: u8) -> Option<u32> {
+    match channel_config {
+        1..=6 => Some(channel_config as u32),
+        7 => Some(8),
+        _ => None,
+    }
+}
+
+fn read_sample_rate(reader: &mut BitReader) -> Option<u32> {
+    let index = reader.read_bits(4)?;
+    if index == 15 {
+        return reader.read_bits(24);
+    }
+
+    SAMPLE_RATES.get(index as usize).copied()
+}
+
+fn write_sample_rate(writer: &mut BitWriter, sample_rate: u32) {
+    match aac_sample_rate_index(sample_rate) {
+        Some(index) => writer.write_bits(index as u64, 4),
+        None => {
+            writer.write_bits(15, 4);
+            writer.write_bits(sample_rate as u64, 24);
+        }
+    }
+}
+
+fn read_object_type(reader: &mut BitReader) -> Option<u8> {
+    let object_type = reader.read_bits(5)? as u8;
+    if object_type == 31 {
+        return Some(32 + reader.read_bits(6)? as u8);
+    }
+
+    Some(object_type)
+}
+
+fn write_object_type(writer: &mut BitWriter, object_type: u8) {
+    // 31 is the escape value itself, only the object types after it need the extra 6 bits
+    if object_type > 31 {
+        writer.write_bits(31, 5);
+        writer.write_bits((object_type - 32) as u64, 6);
+    } else {
+        writer.write_bits(object_type as u64, 5);
+    }
+}
+
+/// The AudioSpecificConfig of an AAC stream, which is the `csd-0` buffer of `audio/mp4a-latm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    /// The object type of the core AAC stream, usually [AAC_OBJECT_LC]
+    pub object_type: u8,
+    /// The sample rate of the core AAC stream
+    pub sample_rate: u32,
+    pub channel_config: u8,
+    /// Frames of 960 samples instead of 1024
+    pub frame_length_960: bool,
+    /// Whether Spectral Band Replication is signalled (HE-AAC)
+    pub sbr: bool,
+    /// Whether Parametric Stereo is signalled (HE-AAC v2)
+    pub ps: bool,
+    /// The output sample rate with SBR, usually twice the core sample rate
+    pub extension_sample_rate: Option<u32>,
+}
+
+impl AudioSpecificConfig {
+    /// Creates a config for a plain AAC stream
+    pub fn new(object_type: u8, sample_rate: u32, channel_config: u8) -> Self {
+        Self {
+            object_type,
+            sample_rate,
+            channel_config,
+            frame_length_960: false,
+            sbr: false,
+            ps: false,
+            extension_sample_rate: None,
+        }
+    }
+
+    /// Parses an AudioSpecificConfig, with explicit or backward compatible SBR / PS signalling
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data);
+
+        let mut object_type = read_object_type(&mut reader)?;
+        let sample_rate = read_sample_rate(&mut reader)?;
+        let channel_config = reader.read_bits(4)? as u8;
+
+        let mut config = Self::new(object_type, sample_rate, channel_config);
+
+        if object_type == AAC_OBJECT_SBR || object_type == AAC_OBJECT_PS {
+            config.sbr = true;
+            config.ps = object_type == AAC_OBJECT_PS;
+            config.extension_sample_rate = Some(read_sample_rate(&mut reader)?);
+            object_type = read_object_type(&mut reader)?;
+            config.object_type = object_type;
+        }
+
+        if !matches!(object_type, 1..=4 | 6 | 7 | 17 | 19..=23) {
+            // Not a GASpecificConfig, there's nothing more we understand
+            return Some(config);
+        }
+
+        config.frame_length_960 = reader.read_bit()?;
+        // dependsOnCoreCoder
+        if reader.read_bit()? {
+            reader.skip_bits(14)?;
+        }
+        let extension = reader.read_bit()?;
+
+        if channel_config == 0 {
+            // A program_config_element follows, we can't look past it without parsing it
+            return Some(config);
+        }
+
+        if matches!(object_type, 6 | 20) {
+            // layerNr
+            reader.skip_bits(3)?;
+        }
+
+        if extension {
+            if object_type == 22 {
+                // numOfSubFrame and layer_length
+                reader.skip_bits(16)?;
+            }
+
+            if matches!(object_type, 17 | 19 | 20 | 23) {
+                // The resilience flags
+                reader.skip_bits(3)?;
+            }
+
+            // extensionFlag3
+            reader.skip_bits(1)?;
+        }
+
+        // Backward compatible signalling, after the core config
+        if !config.sbr
+            && reader.bits_left() >= 16
+            && reader.read_bits(11)? == 0x2b7
+            && read_object_type(&mut reader)? == AAC_OBJECT_SBR
+        {
+            config.sbr = reader.read_bit()?;
+            if config.sbr {
+                config.extension_sample_rate = Some(read_sample_rate(&mut reader)?);
+
+                if reader.bits_left() >= 12 && reader.read_bits(11)? == 0x548 {
+                    config.ps = reader.read_bit()?;
+                }
+            }
+        }
+
+        Some(config)
+    }
+
+    /// Serializes the config, with explicit SBR / PS signalling
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+
+        let object_type = match (self.sbr, self.ps) {
+            (true, true) => AAC_OBJECT_PS,
+            (true, false) => AAC_OBJECT_SBR,
+            _ => self.object_type,
+        };
+
+        write_object_type(&mut writer, object_type);
+        write_sample_rate(&mut writer, self.sample_rate);
+        writer.write_bits(self.channel_config as u64, 4);
+
+        if self.sbr {
+            let extension_sample_rate = self.extension_sample_rate.unwrap_or(self.sample_rate * 2);
+            write_sample_rate(&mut writer, extension_sample_rate);
+            write_object_type(&mut writer, self.object_type);
+        }
+
+        // GASpecificConfig: frameLengthFlag, dependsOnCoreCoder and extensionFlag
+        writer.write_bit(self.frame_length_960);
+        writer.write_bits(0, 2);
+
+        writer.into_bytes()
+    }
+
+    /// The number of channels, 0 if it's not known from the config alone
+    pub fn channel_count(&self) -> u32 {
+        // Parametric stereo streams are coded as mono
+        if self.ps && self.channel_config == 1 {
+            return 2;
+        }
+
+        aac_channel_count(self.channel_config).unwrap_or(0)
+    }
+
+    /// The sample rate of the decoded audio
+    pub fn output_sample_rate(&self) -> u32 {
+        self.extension_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    /// The number of samples (per channel) in a frame of the core stream
+    pub fn samples_per_frame(&self) -> u32 {
+        if self.frame_length_960 {
+            960
+        } else {
+            1024
+        }
+    }
+
+    /// The duration of a frame, in microseconds
+    pub fn frame_duration_us(&self) -> i64 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+
+        self.samples_per_frame() as i64 * 1_000_000 / self.sample_rate as i64
+    }
+
+    /// The value of the `aac-profile` key in a `MediaFormat`
+    pub fn media_codec_profile(&self) -> i32 {
+        if self.ps {
+            AAC_OBJECT_PS as i32
+        } else if self.sbr {
+            AAC_OBJECT_SBR as i32
+        } else {
+            self.object_type as i32
+        }
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize an `audio/mp4a-latm` decoder, for raw (non ADTS) frames
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "audio/mp4a-latm");
+        format.set_i32("sample-rate", self.sample_rate as i32);
+        format.set_i32("channel-count", self.channel_count() as i32);
+        format.set_i32("aac-profile", self.media_codec_profile());
+        format.set_i32("is-adts", 0);
+        format.set_buffer("csd-0", &self.to_bytes());
+
+        Some(format)
+    }
+
+    /// Returns the ADTS header for a raw frame of `payload_size` bytes.
+    ///
+    /// Returns `None` if the config can't be described by an ADTS header, which only knows about the first 4 object types and the standard sample rates
+    pub fn adts_header(&self, payload_size: usize) -> Option<AdtsHeader> {
+        if !(1..=4).contains(&self.object_type) || self.channel_config > 7 {
+            return None;
+        }
+
+        let header = AdtsHeader {
+            mpeg2: false,
+            protection_absent: true,
+            object_type: self.object_type,
+            sample_rate_index: aac_sample_rate_index(self.sample_rate)?,
+            channel_config: self.channel_config,
+            frame_length: (payload_size + 7).try_into().ok()?,
+            buffer_fullness: 0x7ff,
+            raw_data_blocks: 1,
+        };
+
+        if header.frame_length > 0x1fff {
+            return None;
+        }
+
+        Some(header)
+    }
+
+    /// Prepends an ADTS header to a raw AAC frame, like the output of an encoder
+    pub fn to_adts(&self, raw: &[u8]) -> Option<Vec<u8>> {
+        let header = self.adts_header(raw.len())?;
+
+        let mut output = Vec::with_capacity(raw.len() + 7);
+        output.extend_from_slice(&header.to_bytes());
+        output.extend_from_slice(raw);
+
+        Some(output)
+    }
+}
+
+/// The header of an ADTS frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// MPEG-2 instead of MPEG-4
+    pub mpeg2: bool,
+    /// The header has no CRC
+    pub protection_absent: bool,
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    pub channel_config: u8,
+    /// The size of the frame, including the header
+    pub frame_length: u16,
+    /// 0x7ff for variable bitrate streams
+    pub buffer_fullness: u16,
+    /// The number of raw data blocks in the frame, usually 1
+    pub raw_data_blocks: u8,
+}
+
+impl AdtsHeader {
+    /// Parses an ADTS header at the start of `data`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data.get(..7)?);
+
+        if reader.read_bits(12)? != 0xfff {
+            return None;
+        }
+
+        let mpeg2 = reader.read_bit()?;
+        // layer, always 0
+        if reader.read_bits(2)? != 0 {
+            return None;
+        }
+
+        let protection_absent = reader.read_bit()?;
+        let object_type = reader.read_bits(2)? as u8 + 1;
+        let sample_rate_index = reader.read_bits(4)? as u8;
+        if sample_rate_index as usize >= SAMPLE_RATES.len() {
+            return None;
+        }
+
+        // private_bit
+        reader.skip_bits(1)?;
+        let channel_config = reader.read_bits(3)? as u8;
+        // original_copy, home, copyright_identification_bit and copyright_identification_start
+        reader.skip_bits(4)?;
+
+        let header = Self {
+            mpeg2,
+            protection_absent,
+            object_type,
+            sample_rate_index,
+            channel_config,
+            frame_length: reader.read_bits(13)? as u16,
+            buffer_fullness: reader.read_bits(11)? as u16,
+            raw_data_blocks: reader.read_bits(2)? as u8 + 1,
+        };
+
+        if (header.frame_length as usize) < header.header_size() {
+            return None;
+        }
+
+        Some(header)
+    }
+
+    /// The size of the header, 9 bytes with a CRC and 7 without
+    pub fn header_size(&self) -> usize {
+        if self.protection_absent {
+            7
+        } else {
+            9
+        }
+    }
+
+    /// The size of the raw AAC data after the header
+    pub fn payload_size(&self) -> usize {
+        self.frame_length as usize - self.header_size()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+
+    /// Serializes the header, without a CRC
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let mut writer = BitWriter::new();
+
+        writer.write_bits(0xfff, 12);
+        writer.write_bit(self.mpeg2);
+        writer.write_bits(0, 2);
+        writer.write_bit(true);
+        writer.write_bits((self.object_type - 1) as u64, 2);
+        writer.write_bits(self.sample_rate_index as u64, 4);
+        writer.write_bit(false);
+        writer.write_bits(self.channel_config as u64, 3);
+        writer.write_bits(0, 4);
+        // Without a CRC, the header is 2 bytes shorter
+        let frame_length = if self.protection_absent {
+            self.frame_length
+        } else {
+            self.frame_length - 2
+        };
+        writer.write_bits(frame_length as u64, 13);
+        writer.write_bits(self.buffer_fullness as u64, 11);
+        writer.write_bits((self.raw_data_blocks.max(1) - 1) as u64, 2);
+
+        let mut output = [0; 7];
+        output.copy_from_slice(&writer.into_bytes());
+        output
+    }
+
+    /// The AudioSpecificConfig matching this header, to use as `csd-0`
+    pub fn audio_specific_config(&self) -> AudioSpecificConfig {
+        AudioSpecificConfig::new(self.object_type, self.sample_rate(), self.channel_config)
+    }
+}
+
+/// An ADTS frame
+#[derive(Debug, Clone, Copy)]
+pub struct AdtsFrame<'a> {
+    pub header: AdtsHeader,
+    /// The raw AAC data, without the header
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the frames of an ADTS stream, like a `.aac` file.
+///
+/// Garbage between frames is skipped. Iteration stops at the first truncated frame
+#[derive(Debug, Clone)]
+pub struct AdtsFrames<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Returns an iterator over the frames of an ADTS stream
+pub fn adts_frames(data: &[u8]) -> AdtsFrames<'_> {
+    AdtsFrames { data, position: 0 }
+}
+
+impl<'a> AdtsFrames<'a> {
+    /// The number of bytes consumed so far. Anything after that is an incomplete frame
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> Iterator for AdtsFrames<'a> {
+    type Item = AdtsFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position + 7 <= self.data.len() {
+            let data = &self.data[self.position..];
+
+            let header = match AdtsHeader::parse(data) {
+                Some(header) => header,
+                None => {
+                    self.position += 1;
+                    continue;
+                }
+            };
+
+            let frame = data.get(..header.frame_length as usize)?;
+            self.position += frame.len();
+
+            return Some(AdtsFrame {
+                header,
+                payload: &frame[header.header_size()..],
+            });
+        }
+
+        None
+    }
+}
+
+/// Creates a `MediaFormat` for the ADTS stream in `data`, from the header of its first frame
+#[cfg(target_os = "android")]
+pub fn adts_media_format(data: &[u8]) -> Option<MediaFormat> {
+    adts_frames(data)
+        .next()?
+        .header
+        .audio_specific_config()
+        .media_format()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+
+        assert_eq!(config, AudioSpecificConfig::new(AAC_OBJECT_LC, 44100, 2));
+        assert_eq!(config.channel_count(), 2);
+        assert_eq!(config.samples_per_frame(), 1024);
+        assert_eq!(config.to_bytes(), [0x12, 0x10]);
+    }
+
+    #[test]
+    fn explicit_sbr_round_trip() {
+        let config = AudioSpecificConfig {
+            sbr: true,
+            extension_sample_rate: Some(48000),
+            ..AudioSpecificConfig::new(AAC_OBJECT_LC, 24000, 2)
+        };
+
+        let parsed = AudioSpecificConfig::parse(&config.to_bytes()).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.output_sample_rate(), 48000);
+    }
+
+    #[test]
+    fn backward_compatible_sbr() {
+        let config = AudioSpecificConfig::parse(&[0x13, 0x10, 0x56, 0xe5, 0x98]).unwrap();
+
+        assert_eq!(
+            (config.object_type, config.sample_rate),
+            (AAC_OBJECT_LC, 24000)
+        );
+        assert!(config.sbr);
+        assert_eq!(config.extension_sample_rate, Some(48000));
+    }
+
+    #[test]
+    fn escaped_object_types() {
+        for object_type in [30, 32, 42] {
+            let config = AudioSpecificConfig::new(object_type, 48000, 1);
+            let parsed = AudioSpecificConfig::parse(&config.to_bytes()).unwrap();
+
+            assert_eq!(parsed.object_type, object_type);
+        }
+    }
+
+    #[test]
+    fn adts_round_trip() {
+        let config = AudioSpecificConfig::new(AAC_OBJECT_LC, 44100, 2);
+        let frame = config.to_adts(&[1, 2, 3]).unwrap();
+
+        let header = AdtsHeader::parse(&frame).unwrap();
+        assert!(header.protection_absent);
+        assert_eq!(header.header_size(), 7);
+        assert_eq!(header.frame_length, 10);
+        assert_eq!(header.payload_size(), 3);
+        assert_eq!(header.sample_rate(), 44100);
+        assert_eq!(header.audio_specific_config(), config);
+        assert_eq!(header.to_bytes(), frame[..7]);
+    }
+
+    #[test]
+    fn adts_stream() {
+        let config = AudioSpecificConfig::new(AAC_OBJECT_LC, 48000, 1);
+        let mut stream = vec![9, 9];
+        stream.extend(config.to_adts(&[1, 2, 3]).unwrap());
+        stream.extend(config.to_adts(&[4, 5]).unwrap());
+        // A truncated header at the end
+        stream.push(0xff);
+
+        let frames: Vec<_> = adts_frames(&stream).collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload, [1, 2, 3]);
+        assert_eq!(frames[1].payload, [4, 5]);
+        assert_eq!(frames[1].header.audio_specific_config(), config);
+    }
+}
@@ -0,0 +1,855 @@
+use crate::{
+    annexb_nal_units, av1_obus, hevc_temporal_id, Av1CodecConfigurationRecord, Av1FrameHeader,
+    Av1FrameType, Av1ObuType, Av1SequenceHeader, AvcDecoderConfigurationRecord, AvcNalType, AvcPps,
+    AvcSliceHeader, AvcSliceType, AvcSps, HevcDecoderConfigurationRecord, HevcNalType, HevcPps,
+    HevcSliceHeader, HevcSliceType, HevcSps, NalFormat, Vp9FrameHeader,
+};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecOutputBuffer, MediaFormat};
+
+/// The codecs understood by [FrameAnalyzer](FrameAnalyzer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyzerCodec {
+    Avc,
+    Hevc,
+    Vp9,
+    Av1,
+}
+
+impl AnalyzerCodec {
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "video/avc" => Some(Self::Avc),
+            "video/hevc" => Some(Self::Hevc),
+            "video/x-vnd.on2.vp9" => Some(Self::Vp9),
+            "video/av01" => Some(Self::Av1),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Avc => "video/avc",
+            Self::Hevc => "video/hevc",
+            Self::Vp9 => "video/x-vnd.on2.vp9",
+            Self::Av1 => "video/av01",
+        }
+    }
+}
+
+/// The coding type of a frame.
+///
+/// A frame made of several slice types takes the least constrained one, so a picture with I and P slices is a P frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FrameType {
+    /// Intra coded, without references to other frames
+    I,
+    /// Predicted from previous frames
+    P,
+    /// Bidirectionally predicted
+    B,
+}
+
+impl FrameType {
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::I => 'I',
+            Self::P => 'P',
+            Self::B => 'B',
+        }
+    }
+}
+
+/// The type of a unit of the bitstream of a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitstreamUnitType {
+    Avc(AvcNalType),
+    Hevc(HevcNalType),
+    /// A frame of a VP9 superframe, or the whole packet
+    Vp9Frame,
+    Av1(Av1ObuType),
+}
+
+/// A NAL unit, OBU or VP9 frame in a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitstreamUnit {
+    pub unit_type: BitstreamUnitType,
+    /// The size of the unit, including its header but without any start code or length prefix
+    pub size: usize,
+    pub temporal_id: u8,
+}
+
+/// What the analyzer found in a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub pts_us: i64,
+    pub size: usize,
+    /// The coding type of the frame, `None` when the packet has no picture or its headers couldn't be parsed
+    pub frame_type: Option<FrameType>,
+    /// Whether decoding can start at this frame (IDR for H.264, IRAP for HEVC)
+    pub keyframe: bool,
+    /// Whether other frames can use this one as reference. `None` when the bitstream doesn't tell (AV1 inter frames)
+    pub reference: Option<bool>,
+    /// Whether the packet displays a frame. Alt-ref frames of VP9 and AV1 are hidden
+    pub shown: bool,
+    /// The highest temporal layer of the units in the packet
+    pub temporal_id: u8,
+    /// The QP of the first slice for H.264 and HEVC, and `base_q_idx` (0 to 255) for VP9
+    pub qp: Option<i32>,
+    pub units: Vec<BitstreamUnit>,
+}
+
+impl FrameInfo {
+    fn new(pts_us: i64, size: usize) -> Self {
+        Self {
+            pts_us,
+            size,
+            frame_type: None,
+            keyframe: false,
+            reference: None,
+            shown: false,
+            temporal_id: 0,
+            qp: None,
+            units: vec![],
+        }
+    }
+
+    fn add_frame_type(&mut self, frame_type: FrameType) {
+        self.frame_type = self.frame_type.max(Some(frame_type));
+    }
+
+    fn add_reference(&mut self, reference: bool) {
+        self.reference = Some(self.reference.unwrap_or(false) || reference);
+    }
+}
+
+/// A summary of a group of pictures, which starts at a key frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct GopSummary {
+    pub start_pts_us: i64,
+    /// The time until the start of the next GOP. For the last GOP, this is up to its latest frame
+    pub duration_us: i64,
+    pub frame_count: usize,
+    pub i_frames: usize,
+    pub p_frames: usize,
+    pub b_frames: usize,
+    pub non_reference_frames: usize,
+    pub hidden_frames: usize,
+    /// The size of the packets in bytes
+    pub size: usize,
+    /// The frame types in decode order, like `IPBBPBB`. Frames of unknown type are `?`
+    pub pattern: String,
+    qp_sum: i64,
+    qp_count: usize,
+}
+
+impl GopSummary {
+    fn new(start_pts_us: i64) -> Self {
+        Self {
+            start_pts_us,
+            duration_us: 0,
+            frame_count: 0,
+            i_frames: 0,
+            p_frames: 0,
+            b_frames: 0,
+            non_reference_frames: 0,
+            hidden_frames: 0,
+            size: 0,
+            pattern: String::new(),
+            qp_sum: 0,
+            qp_count: 0,
+        }
+    }
+
+    fn add(&mut self, info: &FrameInfo) {
+        self.frame_count += 1;
+        self.size += info.size;
+        self.duration_us = self.duration_us.max(info.pts_us - self.start_pts_us);
+
+        match info.frame_type {
+            Some(FrameType::I) => self.i_frames += 1,
+            Some(FrameType::P) => self.p_frames += 1,
+            Some(FrameType::B) => self.b_frames += 1,
+            None => {}
+        }
+
+        if info.reference == Some(false) {
+            self.non_reference_frames += 1;
+        }
+
+        if !info.shown {
+            self.hidden_frames += 1;
+        }
+
+        if let Some(qp) = info.qp {
+            self.qp_sum += qp as i64;
+            self.qp_count += 1;
+        }
+
+        self.pattern.push(
+            info.frame_type
+                .map_or('?', |frame_type| frame_type.as_char()),
+        );
+    }
+
+    /// The length of the GOP in seconds, which is what `i-frame-interval` asks for
+    pub fn interval_secs(&self) -> f64 {
+        self.duration_us as f64 / 1_000_000.0
+    }
+
+    pub fn has_b_frames(&self) -> bool {
+        self.b_frames > 0
+    }
+
+    /// The average QP of the frames that have one
+    pub fn average_qp(&self) -> Option<f64> {
+        if self.qp_count == 0 {
+            return None;
+        }
+
+        Some(self.qp_sum as f64 / self.qp_count as f64)
+    }
+
+    /// The bitrate of the GOP in bits per second
+    pub fn bitrate(&self) -> Option<f64> {
+        if self.duration_us <= 0 {
+            return None;
+        }
+
+        Some(self.size as f64 * 8.0 * 1_000_000.0 / self.duration_us as f64)
+    }
+}
+
+/// Analyzes the packets of an encoder, or of a demuxer, to find the type of their frames and the GOP structure.
+///
+/// Parameter sets are learned from codec config buffers and from the packets themselves.
+/// H.264 and HEVC packets are expected in Annex-B, like MediaCodec and MediaExtractor output.
+/// Learning an avcC or hvcC record switches to the length-prefixed samples that go with it, or use [with_nal_format](FrameAnalyzer::with_nal_format)
+#[derive(Debug)]
+pub struct FrameAnalyzer {
+    codec: AnalyzerCodec,
+    nal_format: NalFormat,
+    avc_sps: Vec<AvcSps>,
+    avc_pps: Vec<AvcPps>,
+    hevc_sps: Vec<HevcSps>,
+    hevc_pps: Vec<HevcPps>,
+    av1_sequence_header: Option<Av1SequenceHeader>,
+    gops: Vec<GopSummary>,
+}
+
+impl FrameAnalyzer {
+    pub fn new(codec: AnalyzerCodec) -> Self {
+        Self {
+            codec,
+            nal_format: NalFormat::AnnexB,
+            avc_sps: vec![],
+            avc_pps: vec![],
+            hevc_sps: vec![],
+            hevc_pps: vec![],
+            av1_sequence_header: None,
+            gops: vec![],
+        }
+    }
+
+    /// Creates an analyzer for the codec of a format, learning the parameter sets from its `csd-*` buffers.
+    ///
+    /// This is usually the output format of an encoder
+    #[cfg(target_os = "android")]
+    pub fn from_format(format: &MediaFormat) -> Option<Self> {
+        let codec = AnalyzerCodec::from_mime(&format.get_string("mime")?)?;
+        let mut analyzer = Self::new(codec);
+
+        for name in ["csd-0", "csd-1", "csd-2"] {
+            if let Some(csd) = format.get_buffer(name) {
+                analyzer.add_config(csd);
+            }
+        }
+
+        Some(analyzer)
+    }
+
+    /// Sets how the NAL units of H.264 and HEVC packets are delimited. The default is Annex-B
+    pub fn with_nal_format(mut self, nal_format: NalFormat) -> Self {
+        self.nal_format = nal_format;
+        self
+    }
+
+    pub fn codec(&self) -> AnalyzerCodec {
+        self.codec
+    }
+
+    pub fn nal_format(&self) -> NalFormat {
+        self.nal_format
+    }
+
+    /// Learns the parameter sets of a codec config buffer.
+    ///
+    /// H.264 and HEVC config can be an avcC or hvcC record, which also sets the format of the packets, or Annex-B parameter sets.
+    /// AV1 config can be an av1C record or plain OBUs
+    pub fn add_config(&mut self, data: &[u8]) {
+        match self.codec {
+            AnalyzerCodec::Avc | AnalyzerCodec::Hevc => {
+                let record = match self.codec {
+                    AnalyzerCodec::Avc => {
+                        AvcDecoderConfigurationRecord::parse(data).map(|record| {
+                            (
+                                record.length_size,
+                                record.sps.into_iter().chain(record.pps).collect(),
+                            )
+                        })
+                    }
+                    _ => HevcDecoderConfigurationRecord::parse(data).map(|record| {
+                        let units = record.arrays.into_iter().flat_map(|array| array.units);
+                        (record.length_size, units.collect())
+                    }),
+                };
+
+                let units: Vec<Vec<u8>> = match record {
+                    Some((length_size, units)) => {
+                        self.nal_format = NalFormat::LengthPrefixed(length_size as usize);
+                        units
+                    }
+                    None => annexb_nal_units(data).map(|nal| nal.to_vec()).collect(),
+                };
+
+                for nal in units.iter().filter(|nal| !nal.is_empty()) {
+                    self.add_parameter_set(nal);
+                }
+            }
+            AnalyzerCodec::Vp9 => {}
+            AnalyzerCodec::Av1 => {
+                let header = Av1CodecConfigurationRecord::parse(data)
+                    .and_then(|record| record.parse_sequence_header())
+                    .or_else(|| Av1SequenceHeader::find(data));
+
+                if header.is_some() {
+                    self.av1_sequence_header = header;
+                }
+            }
+        }
+    }
+
+    /// Analyzes an encoder output buffer.
+    ///
+    /// Codec config buffers are only learned from, and return `None` like empty buffers
+    #[cfg(target_os = "android")]
+    pub fn analyze_buffer(&mut self, buffer: &CodecOutputBuffer) -> Option<FrameInfo> {
+        let data = buffer.data().filter(|data| !data.is_empty())?;
+        let info = buffer.info();
+
+        if BufferFlag::CodecConfig.is_contained_in(info.flags() as i32) {
+            self.add_config(data);
+            return None;
+        }
+
+        Some(self.analyze(data, info.presentation_time_us()))
+    }
+
+    /// Analyzes a packet, and adds it to the GOP summaries when it holds a frame
+    pub fn analyze(&mut self, data: &[u8], pts_us: i64) -> FrameInfo {
+        let mut info = FrameInfo::new(pts_us, data.len());
+
+        let has_picture = match self.codec {
+            AnalyzerCodec::Avc => self.analyze_avc(data, &mut info),
+            AnalyzerCodec::Hevc => self.analyze_hevc(data, &mut info),
+            AnalyzerCodec::Vp9 => analyze_vp9(data, &mut info),
+            AnalyzerCodec::Av1 => self.analyze_av1(data, &mut info),
+        };
+
+        if has_picture {
+            self.add_to_gop(&info);
+        }
+
+        info
+    }
+
+    /// The GOPs seen so far, in decode order. The last one is still open
+    pub fn gops(&self) -> &[GopSummary] {
+        &self.gops
+    }
+
+    fn add_to_gop(&mut self, info: &FrameInfo) {
+        if info.keyframe || self.gops.is_empty() {
+            if let Some(previous) = self.gops.last_mut() {
+                previous.duration_us = info.pts_us - previous.start_pts_us;
+            }
+
+            self.gops.push(GopSummary::new(info.pts_us));
+        }
+
+        if let Some(gop) = self.gops.last_mut() {
+            gop.add(info);
+        }
+    }
+
+    fn add_parameter_set(&mut self, nal: &[u8]) {
+        match self.codec {
+            AnalyzerCodec::Avc => match AvcNalType::from_header(nal[0]) {
+                AvcNalType::Sps => {
+                    if let Some(sps) = AvcSps::parse(nal) {
+                        self.avc_sps.retain(|other| other.sps_id != sps.sps_id);
+                        self.avc_sps.push(sps);
+                    }
+                }
+                AvcNalType::Pps => {
+                    if let Some(pps) = AvcPps::parse(nal) {
+                        self.avc_pps.retain(|other| other.pps_id != pps.pps_id);
+                        self.avc_pps.push(pps);
+                    }
+                }
+                _ => {}
+            },
+            AnalyzerCodec::Hevc => match HevcNalType::from_header(nal[0]) {
+                HevcNalType::Sps => {
+                    if let Some(sps) = HevcSps::parse(nal) {
+                        self.hevc_sps.retain(|other| other.sps_id != sps.sps_id);
+                        self.hevc_sps.push(sps);
+                    }
+                }
+                HevcNalType::Pps => {
+                    if let Some(pps) = HevcPps::parse(nal) {
+                        self.hevc_pps.retain(|other| other.pps_id != pps.pps_id);
+                        self.hevc_pps.push(pps);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn analyze_avc(&mut self, data: &[u8], info: &mut FrameInfo) -> bool {
+        let mut has_picture = false;
+
+        for nal in self.nal_format.nal_units(data) {
+            let nal_type = AvcNalType::from_header(nal[0]);
+            let mut temporal_id = 0;
+
+            match nal_type {
+                AvcNalType::Sps | AvcNalType::Pps => self.add_parameter_set(nal),
+                // The SVC extension of the prefix NAL unit carries the temporal layer of the next slice
+                AvcNalType::Prefix if nal.len() >= 4 && nal[1] & 0x80 != 0 => {
+                    temporal_id = nal[3] >> 5;
+                }
+                AvcNalType::Slice | AvcNalType::IdrSlice => {
+                    has_picture = true;
+                    info.shown = true;
+                    info.keyframe |= nal_type == AvcNalType::IdrSlice;
+                    info.add_reference(nal[0] & 0x60 != 0);
+
+                    if let Some(header) = AvcSliceHeader::parse(nal, &self.avc_sps, &self.avc_pps) {
+                        info.add_frame_type(match header.slice_type {
+                            AvcSliceType::I | AvcSliceType::Si => FrameType::I,
+                            AvcSliceType::P | AvcSliceType::Sp => FrameType::P,
+                            AvcSliceType::B => FrameType::B,
+                        });
+                        info.qp = info.qp.or(Some(header.qp));
+                    } else if nal_type == AvcNalType::IdrSlice {
+                        info.add_frame_type(FrameType::I);
+                    }
+                }
+                _ => {}
+            }
+
+            info.temporal_id = info.temporal_id.max(temporal_id);
+            info.units.push(BitstreamUnit {
+                unit_type: BitstreamUnitType::Avc(nal_type),
+                size: nal.len(),
+                temporal_id,
+            });
+        }
+
+        has_picture
+    }
+
+    fn analyze_hevc(&mut self, data: &[u8], info: &mut FrameInfo) -> bool {
+        let mut has_picture = false;
+
+        for nal in self.nal_format.nal_units(data) {
+            if nal.len() < 2 {
+                continue;
+            }
+
+            let nal_type = HevcNalType::from_header(nal[0]);
+            let temporal_id = hevc_temporal_id(nal).unwrap_or(0);
+
+            if matches!(nal_type, HevcNalType::Sps | HevcNalType::Pps) {
+                self.add_parameter_set(nal);
+            } else if nal_type.is_vcl() {
+                has_picture = true;
+                info.keyframe |= nal_type.is_irap();
+                // The even types below 16 are the sub-layer non-reference pictures
+                let value = nal_type.value();
+                info.add_reference(value >= 16 || value & 1 != 0);
+
+                if let Some(header) = HevcSliceHeader::parse(nal, &self.hevc_sps, &self.hevc_pps) {
+                    info.shown |= header.pic_output;
+                    info.add_frame_type(match header.slice_type {
+                        HevcSliceType::I => FrameType::I,
+                        HevcSliceType::P => FrameType::P,
+                        HevcSliceType::B => FrameType::B,
+                    });
+
+                    if header.first_slice_segment_in_pic {
+                        info.qp = header.qp;
+                    }
+                } else {
+                    info.shown = true;
+                    if nal_type.is_irap() {
+                        info.add_frame_type(FrameType::I);
+                    }
+                }
+            }
+
+            info.temporal_id = info.temporal_id.max(temporal_id);
+            info.units.push(BitstreamUnit {
+                unit_type: BitstreamUnitType::Hevc(nal_type),
+                size: nal.len(),
+                temporal_id,
+            });
+        }
+
+        has_picture
+    }
+
+    fn analyze_av1(&mut self, data: &[u8], info: &mut FrameInfo) -> bool {
+        let mut has_picture = false;
+
+        for obu in av1_obus(data) {
+            match obu.obu_type {
+                Av1ObuType::SequenceHeader => {
+                    if let Some(header) = Av1SequenceHeader::parse(obu.payload) {
+                        self.av1_sequence_header = Some(header);
+                    }
+                }
+                Av1ObuType::FrameHeader | Av1ObuType::Frame => {
+                    has_picture = true;
+
+                    let header = self
+                        .av1_sequence_header
+                        .as_ref()
+                        .and_then(|sequence_header| {
+                            Av1FrameHeader::parse(obu.payload, sequence_header)
+                        });
+
+                    if let Some(header) = header {
+                        info.shown |= header.show_frame;
+
+                        match header.frame_type {
+                            Some(Av1FrameType::Key) => {
+                                info.keyframe = true;
+                                info.add_reference(true);
+                                info.add_frame_type(FrameType::I);
+                            }
+                            Some(Av1FrameType::IntraOnly) => info.add_frame_type(FrameType::I),
+                            Some(Av1FrameType::Inter | Av1FrameType::Switch) => {
+                                info.add_frame_type(FrameType::P)
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            info.temporal_id = info.temporal_id.max(obu.temporal_id);
+            info.units.push(BitstreamUnit {
+                unit_type: BitstreamUnitType::Av1(obu.obu_type),
+                size: obu.data.len(),
+                temporal_id: obu.temporal_id,
+            });
+        }
+
+        has_picture
+    }
+}
+
+fn analyze_vp9(data: &[u8], info: &mut FrameInfo) -> bool {
+    let mut has_picture = false;
+
+    for frame in vp9_superframe(data) {
+        info.units.push(BitstreamUnit {
+            unit_type: BitstreamUnitType::Vp9Frame,
+            size: frame.len(),
+            temporal_id: 0,
+        });
+
+        let header = match Vp9FrameHeader::parse(frame) {
+            Some(header) => header,
+            None => continue,
+        };
+
+        has_picture = true;
+        info.shown |= header.show_frame || header.show_existing_frame;
+
+        if header.show_existing_frame {
+            continue;
+        }
+
+        info.keyframe |= header.keyframe;
+        info.add_reference(header.refresh_frame_flags != 0);
+        info.add_frame_type(if header.keyframe || header.intra_only {
+            FrameType::I
+        } else {
+            FrameType::P
+        });
+
+        if let Some(base_q_idx) = header.base_q_idx {
+            info.qp = Some(base_q_idx as i32);
+        }
+    }
+
+    has_picture
+}
+
+/// Splits a VP9 superframe into its frames, using the index at its end
+fn vp9_superframe(data: &[u8]) -> Vec<&[u8]> {
+    let marker = match data.last() {
+        Some(&marker) if marker & 0xe0 == 0xc0 => marker,
+        _ => return vec![data],
+    };
+
+    let frames = (marker & 0x07) as usize + 1;
+    let size_bytes = ((marker >> 3) & 0x03) as usize + 1;
+    let index_size = 2 + size_bytes * frames;
+
+    if data.len() < index_size || data[data.len() - index_size] != marker {
+        return vec![data];
+    }
+
+    let index = &data[data.len() - index_size + 1..];
+    let mut output = vec![];
+    let mut position = 0;
+
+    for size in index.chunks_exact(size_bytes).take(frames) {
+        let size = size
+            .iter()
+            .rev()
+            .fold(0usize, |value, &byte| (value << 8) | byte as usize);
+
+        match data.get(position..position + size) {
+            Some(frame) if !frame.is_empty() => output.push(frame),
+            Some(_) => {}
+            None => break,
+        }
+
+        position += size;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{annexb_to_length_prefixed, bitstream::BitWriter, escape_rbsp};
+
+    /// CAVLC with a QP of 26, for the SPS below
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    /// Finishes a NAL unit: the stop bit, and the emulation prevention bytes
+    fn nal(header: u8, mut writer: BitWriter) -> Vec<u8> {
+        writer.write_bit(true);
+
+        let mut nal = vec![header];
+        nal.extend(escape_rbsp(&writer.into_bytes()));
+        nal
+    }
+
+    /// A 320x240 baseline SPS with 4 bit frame numbers and no picture order count
+    fn sps() -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(66, 8);
+        writer.write_bits(0xc0, 8);
+        writer.write_bits(30, 8);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(2);
+        writer.write_ue(1);
+        writer.write_bit(false);
+        writer.write_ue(19);
+        writer.write_ue(14);
+        // frame_mbs_only_flag, direct_8x8_inference_flag, no cropping and no VUI
+        writer.write_bits(0b1100, 4);
+
+        nal(0x67, writer)
+    }
+
+    fn slice(header: u8, slice_type: u32, frame_num: u64, qp_delta: i32) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_ue(0);
+        writer.write_ue(slice_type);
+        writer.write_ue(0);
+        writer.write_bits(frame_num, 4);
+
+        match slice_type {
+            // idr_pic_id, then no_output_of_prior_pics_flag and long_term_reference_flag
+            7 => {
+                writer.write_ue(0);
+                writer.write_bits(0, 2);
+            }
+            // num_ref_idx_active_override_flag and ref_pic_list_modification_flag_l0
+            5 => writer.write_bits(0, 2),
+            // direct_spatial_mv_pred_flag, num_ref_idx_active_override_flag and both ref_pic_list_modification flags
+            _ => writer.write_bits(0b1000, 4),
+        }
+
+        if header & 0x60 != 0 && slice_type != 7 {
+            // adaptive_ref_pic_marking_mode_flag
+            writer.write_bit(false);
+        }
+
+        writer.write_se(qp_delta);
+        nal(header, writer)
+    }
+
+    fn annexb(units: &[&[u8]]) -> Vec<u8> {
+        let mut output = vec![];
+        for nal in units {
+            output.extend_from_slice(&[0, 0, 0, 1]);
+            output.extend_from_slice(nal);
+        }
+
+        output
+    }
+
+    #[test]
+    fn avc_frames() {
+        let mut analyzer = FrameAnalyzer::new(AnalyzerCodec::Avc);
+        analyzer.add_config(&annexb(&[&sps(), &PPS]));
+
+        let idr = slice(0x65, 7, 0, -2);
+        let info = analyzer.analyze(&annexb(&[&idr]), 0);
+        assert_eq!(info.frame_type, Some(FrameType::I));
+        assert!(info.keyframe);
+        assert_eq!(info.reference, Some(true));
+        assert_eq!(info.qp, Some(24));
+
+        let info = analyzer.analyze(&annexb(&[&slice(0x01, 5, 1, 3)]), 33_333);
+        assert_eq!(info.frame_type, Some(FrameType::P));
+        assert_eq!(info.reference, Some(false));
+        assert_eq!(info.qp, Some(29));
+
+        let info = analyzer.analyze(&annexb(&[&slice(0x21, 6, 2, 0)]), 66_666);
+        assert_eq!(info.frame_type, Some(FrameType::B));
+        assert_eq!(info.reference, Some(true));
+        assert_eq!(
+            info.units[0].unit_type,
+            BitstreamUnitType::Avc(AvcNalType::Slice)
+        );
+
+        analyzer.analyze(&annexb(&[&idr]), 100_000);
+
+        let gops = analyzer.gops();
+        assert_eq!(gops.len(), 2);
+        assert_eq!(gops[0].pattern, "IPB");
+        assert_eq!(gops[0].duration_us, 100_000);
+        assert_eq!(gops[0].non_reference_frames, 1);
+        assert_eq!(gops[0].average_qp(), Some(79.0 / 3.0));
+    }
+
+    #[test]
+    fn length_prefixed_packets() {
+        let sps = sps();
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(&[&sps], &[PPS]).unwrap();
+
+        let mut analyzer = FrameAnalyzer::new(AnalyzerCodec::Avc);
+        analyzer.add_config(&record.to_bytes());
+        assert_eq!(analyzer.nal_format(), NalFormat::LengthPrefixed(4));
+
+        // A slice of 256 bytes has a length of 00 00 01 00, which looks like a start code
+        let mut idr = slice(0x65, 7, 0, 0);
+        idr.resize(256, 0x80);
+
+        let info = analyzer.analyze(&annexb_to_length_prefixed(&annexb(&[&idr]), 4), 0);
+        assert_eq!(info.units.len(), 1);
+        assert_eq!(info.units[0].size, 256);
+        assert_eq!(info.frame_type, Some(FrameType::I));
+
+        let mut analyzer =
+            FrameAnalyzer::new(AnalyzerCodec::Avc).with_nal_format(NalFormat::LengthPrefixed(2));
+        analyzer.add_config(&annexb(&[&sps, &PPS]));
+
+        let info = analyzer.analyze(&annexb_to_length_prefixed(&annexb(&[&idr]), 2), 0);
+        assert_eq!(info.frame_type, Some(FrameType::I));
+    }
+
+    /// Writes the fields of a VP9 frame header, given as `(value, bits)`
+    fn vp9_frame(fields: &[(u64, u32)]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        for &(value, bits) in fields {
+            writer.write_bits(value, bits);
+        }
+
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn vp9_superframe() {
+        // A 320x240 key frame with a base_q_idx of 100: the frame marker, profile 0, then show_frame,
+        // the sync code, BT.601, the size, no render size, the frame context, no loop filter and the delta flags
+        let key = vp9_frame(&[
+            (2, 2),
+            (0, 2),
+            (0, 2),
+            (1, 1),
+            (0, 1),
+            (0x498342, 24),
+            (2, 3),
+            (0, 1),
+            (319, 16),
+            (239, 16),
+            (0, 1),
+            (3, 2),
+            (0, 2),
+            (0, 9),
+            (2, 2),
+            (100, 8),
+        ]);
+        // A hidden inter frame with a base_q_idx of 50: refreshing slot 2 from the first reference slots,
+        // with the size of the first one, high precision motion vectors, a switchable filter and no delta flags
+        let inter = vp9_frame(&[
+            (2, 2),
+            (0, 2),
+            (0, 1),
+            (1, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 2),
+            (4, 8),
+            (0, 12),
+            (1, 1),
+            (0, 1),
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            (0, 1),
+            (0, 2),
+            (0, 9),
+            (0, 1),
+            (50, 8),
+        ]);
+        let existing = vp9_frame(&[(2, 2), (0, 2), (1, 1), (1, 3)]);
+
+        let mut analyzer = FrameAnalyzer::new(AnalyzerCodec::Vp9);
+        let info = analyzer.analyze(&key, 0);
+        assert_eq!(info.frame_type, Some(FrameType::I));
+        assert!(info.keyframe && info.shown);
+        assert_eq!(info.qp, Some(100));
+
+        let mut superframe = inter.clone();
+        superframe.extend_from_slice(&existing);
+        superframe.extend_from_slice(&[0xc1, inter.len() as u8, existing.len() as u8, 0xc1]);
+
+        let info = analyzer.analyze(&superframe, 33_333);
+        assert_eq!(info.units.len(), 2);
+        assert_eq!(info.frame_type, Some(FrameType::P));
+        assert!(info.shown);
+        assert_eq!(info.qp, Some(50));
+
+        let info = analyzer.analyze(&inter, 66_666);
+        assert!(!info.shown);
+        assert_eq!(analyzer.gops()[0].hidden_frames, 1);
+    }
+}
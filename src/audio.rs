@@ -0,0 +1,766 @@
+use log::debug;
+#[cfg(target_os = "android")]
+use log::warn;
+
+use crate::{AudioFrame, SampleFormat, ENCODING_PCM_16BIT, ENCODING_PCM_8BIT, ENCODING_PCM_FLOAT};
+#[cfg(target_os = "android")]
+use crate::{MediaFormat, ENCODING_PCM_24BIT_PACKED, ENCODING_PCM_32BIT};
+
+/// A speaker position. The values are the bits of Android's `channel-mask` (`AudioFormat.CHANNEL_OUT_*`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ChannelPosition {
+    FrontLeft = 0x4,
+    FrontRight = 0x8,
+    FrontCenter = 0x10,
+    LowFrequency = 0x20,
+    BackLeft = 0x40,
+    BackRight = 0x80,
+    FrontLeftOfCenter = 0x100,
+    FrontRightOfCenter = 0x200,
+    BackCenter = 0x400,
+    SideLeft = 0x800,
+    SideRight = 0x1000,
+    TopCenter = 0x2000,
+    TopFrontLeft = 0x4000,
+    TopFrontCenter = 0x8000,
+    TopFrontRight = 0x10000,
+    TopBackLeft = 0x20000,
+    TopBackCenter = 0x40000,
+    TopBackRight = 0x80000,
+}
+
+impl ChannelPosition {
+    /// All the positions, in the order of the channels of an interleaved frame
+    pub const ALL: [ChannelPosition; 18] = [
+        Self::FrontLeft,
+        Self::FrontRight,
+        Self::FrontCenter,
+        Self::LowFrequency,
+        Self::BackLeft,
+        Self::BackRight,
+        Self::FrontLeftOfCenter,
+        Self::FrontRightOfCenter,
+        Self::BackCenter,
+        Self::SideLeft,
+        Self::SideRight,
+        Self::TopCenter,
+        Self::TopFrontLeft,
+        Self::TopFrontCenter,
+        Self::TopFrontRight,
+        Self::TopBackLeft,
+        Self::TopBackCenter,
+        Self::TopBackRight,
+    ];
+
+    pub fn mask(&self) -> u32 {
+        *self as u32
+    }
+}
+
+/// The speaker positions of the channels of a buffer, as a `channel-mask`.
+///
+/// Channels are interleaved in the order of [ChannelPosition::ALL]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelLayout {
+    mask: u32,
+}
+
+impl ChannelLayout {
+    pub const MONO: Self = Self::from_mask(0x4);
+    pub const STEREO: Self = Self::from_mask(0xc);
+    pub const QUAD: Self = Self::from_mask(0xcc);
+    pub const SURROUND_5_1: Self = Self::from_mask(0xfc);
+    pub const SURROUND_7_1: Self = Self::from_mask(0x18fc);
+
+    /// The layout of a `channel-mask`. Bits that aren't speaker positions are ignored
+    pub const fn from_mask(mask: u32) -> Self {
+        Self {
+            mask: mask & 0xffffc,
+        }
+    }
+
+    /// The layout Android assumes for a number of channels when there's no `channel-mask`
+    pub fn default_for(channels: u32) -> Option<Self> {
+        let mask = match channels {
+            1 => 0x4,
+            2 => 0xc,
+            3 => 0x1c,
+            4 => 0xcc,
+            5 => 0xdc,
+            6 => 0xfc,
+            7 => 0x4fc,
+            8 => 0x18fc,
+            _ => return None,
+        };
+
+        Some(Self::from_mask(mask))
+    }
+
+    /// The layout of a decoder output, from its `channel-mask` if it matches the `channel-count`,
+    /// otherwise the default for the number of channels
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(format: &MediaFormat) -> Option<Self> {
+        let channels = format.get_i32("channel-count").filter(|count| *count > 0)? as u32;
+
+        format
+            .get_i32("channel-mask")
+            .map(|mask| Self::from_mask(mask as u32))
+            .filter(|layout| layout.channels() == channels)
+            .or_else(|| Self::default_for(channels))
+    }
+
+    /// The layout of a `dwChannelMask` from a WAV header, where the front left speaker is the first bit
+    pub fn from_wav_mask(mask: u32) -> Self {
+        Self::from_mask(mask << 2)
+    }
+
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// The layout as a WAV `dwChannelMask`
+    pub fn wav_mask(&self) -> u32 {
+        self.mask >> 2
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.mask.count_ones()
+    }
+
+    pub fn contains(&self, position: ChannelPosition) -> bool {
+        self.mask & position.mask() != 0
+    }
+
+    /// The positions of the channels, in order
+    pub fn positions(&self) -> Vec<ChannelPosition> {
+        ChannelPosition::ALL
+            .into_iter()
+            .filter(|position| self.contains(*position))
+            .collect()
+    }
+
+    /// The index of the channel of a position in an interleaved frame
+    pub fn index_of(&self, position: ChannelPosition) -> Option<usize> {
+        self.contains(position)
+            .then(|| (self.mask & (position.mask() - 1)).count_ones() as usize)
+    }
+}
+
+/// The type of the samples of an [AudioBuffer]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleType {
+    /// Unsigned 8 bit, silence is 128
+    U8,
+    S16,
+    F32,
+}
+
+impl SampleType {
+    /// The value of the `pcm-encoding` key for this sample type
+    pub fn pcm_encoding(&self) -> usize {
+        match self {
+            Self::U8 => ENCODING_PCM_8BIT,
+            Self::S16 => ENCODING_PCM_16BIT,
+            Self::F32 => ENCODING_PCM_FLOAT,
+        }
+    }
+
+    pub fn sample_size(&self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::S16 => 2,
+            Self::F32 => 4,
+        }
+    }
+}
+
+/// Owned audio samples, interleaved or of a single channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioSamples {
+    U8(Vec<u8>),
+    S16(Vec<i16>),
+    F32(Vec<f32>),
+}
+
+impl AudioSamples {
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            Self::U8(_) => SampleType::U8,
+            Self::S16(_) => SampleType::S16,
+            Self::F32(_) => SampleType::F32,
+        }
+    }
+
+    /// The number of samples, of all channels
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8(samples) => samples.len(),
+            Self::S16(samples) => samples.len(),
+            Self::F32(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts the samples to another type. Float samples are clamped to [-1, 1] and rounded
+    pub fn convert(&self, sample_type: SampleType) -> Self {
+        match (self, sample_type) {
+            (Self::U8(samples), SampleType::U8) => Self::U8(samples.clone()),
+            (Self::S16(samples), SampleType::S16) => Self::S16(samples.clone()),
+            (Self::F32(samples), SampleType::F32) => Self::F32(samples.clone()),
+            (Self::U8(samples), SampleType::S16) => {
+                Self::S16(samples.iter().map(|sample| u8_to_s16(*sample)).collect())
+            }
+            (Self::S16(samples), SampleType::U8) => {
+                Self::U8(samples.iter().map(|sample| s16_to_u8(*sample)).collect())
+            }
+            (_, SampleType::F32) => Self::F32(self.to_f32()),
+            (Self::F32(samples), SampleType::S16) => {
+                Self::S16(samples.iter().map(|sample| f32_to_s16(*sample)).collect())
+            }
+            (Self::F32(samples), SampleType::U8) => Self::U8(
+                samples
+                    .iter()
+                    .map(|sample| s16_to_u8(f32_to_s16(*sample)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The samples as floats in [-1, 1]
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            Self::U8(samples) => samples
+                .iter()
+                .map(|sample| u8_to_s16(*sample) as f32 / 32768.0)
+                .collect(),
+            Self::S16(samples) => samples
+                .iter()
+                .map(|sample| *sample as f32 / 32768.0)
+                .collect(),
+            Self::F32(samples) => samples.clone(),
+        }
+    }
+
+    /// Appends the samples as little endian bytes, like the input buffers of an encoder expect them
+    pub fn write_bytes(&self, output: &mut Vec<u8>) {
+        match self {
+            Self::U8(samples) => output.extend_from_slice(samples),
+            Self::S16(samples) => samples
+                .iter()
+                .for_each(|sample| output.extend_from_slice(&sample.to_le_bytes())),
+            Self::F32(samples) => samples
+                .iter()
+                .for_each(|sample| output.extend_from_slice(&sample.to_le_bytes())),
+        }
+    }
+
+    /// Samples `start, start + step, ...`
+    fn step_by(&self, start: usize, step: usize) -> Self {
+        match self {
+            Self::U8(samples) => {
+                Self::U8(samples.iter().skip(start).step_by(step).copied().collect())
+            }
+            Self::S16(samples) => {
+                Self::S16(samples.iter().skip(start).step_by(step).copied().collect())
+            }
+            Self::F32(samples) => {
+                Self::F32(samples.iter().skip(start).step_by(step).copied().collect())
+            }
+        }
+    }
+}
+
+fn u8_to_s16(sample: u8) -> i16 {
+    ((sample as i16) - 128) << 8
+}
+
+fn s16_to_u8(sample: i16) -> u8 {
+    ((sample >> 8) + 128) as u8
+}
+
+fn f32_to_s16(sample: f32) -> i16 {
+    (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+}
+
+/// Owned audio samples with their sample rate, timestamp and channel layout.
+///
+/// Unlike [AudioFrame], it doesn't borrow the codec output buffer, so it can be kept or sent to another thread
+/// once the buffer is released
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    /// Interleaved samples
+    samples: AudioSamples,
+    channels: u32,
+    sample_rate: u32,
+    layout: Option<ChannelLayout>,
+    pts_us: i64,
+}
+
+impl AudioBuffer {
+    /// Creates a buffer from interleaved samples, with the default layout for the number of channels.
+    ///
+    /// Returns `None` without channels or sample rate, or if the samples aren't a whole number of frames
+    pub fn new(samples: AudioSamples, channels: u32, sample_rate: u32) -> Option<Self> {
+        let whole_frames = |channels: usize| samples.len() / channels * channels == samples.len();
+        if channels == 0 || sample_rate == 0 || !whole_frames(channels as usize) {
+            debug!(
+                "Invalid audio buffer: {} samples, {channels} channels at {sample_rate} Hz",
+                samples.len()
+            );
+            return None;
+        }
+
+        Some(Self {
+            samples,
+            channels,
+            sample_rate,
+            layout: ChannelLayout::default_for(channels),
+            pts_us: 0,
+        })
+    }
+
+    /// Creates a buffer by interleaving one buffer per channel. The channels must all have the same type and length
+    pub fn from_planes(planes: &[AudioSamples], sample_rate: u32) -> Option<Self> {
+        let first = planes.first()?;
+        if planes
+            .iter()
+            .any(|plane| plane.len() != first.len() || plane.sample_type() != first.sample_type())
+        {
+            debug!("The channels have different types or lengths");
+            return None;
+        }
+
+        fn interleave<T: Copy>(planes: &[&Vec<T>]) -> Vec<T> {
+            let length = planes.first().map_or(0, |plane| plane.len());
+            (0..length)
+                .flat_map(|index| planes.iter().map(move |plane| plane[index]))
+                .collect()
+        }
+
+        macro_rules! interleave {
+            ($variant:ident) => {
+                AudioSamples::$variant(interleave(
+                    &planes
+                        .iter()
+                        .filter_map(|plane| match plane {
+                            AudioSamples::$variant(samples) => Some(samples),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>(),
+                ))
+            };
+        }
+
+        let samples = match first {
+            AudioSamples::U8(_) => interleave!(U8),
+            AudioSamples::S16(_) => interleave!(S16),
+            AudioSamples::F32(_) => interleave!(F32),
+        };
+
+        Self::new(samples, planes.len() as u32, sample_rate)
+    }
+
+    /// Copies the PCM output of a decoder, described by the `sample-rate`, `channel-count`, `pcm-encoding` and
+    /// `channel-mask` keys of its format.
+    ///
+    /// Samples are 16 bit when there's no `pcm-encoding`, like Android does. 24 and 32 bit integer samples
+    /// are converted to floats
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(data: &[u8], format: &MediaFormat) -> Option<Self> {
+        let sample_rate = format.get_i32("sample-rate").filter(|rate| *rate > 0)? as u32;
+        let channels = format.get_i32("channel-count").filter(|count| *count > 0)? as u32;
+        let encoding = format
+            .get_i32("pcm-encoding")
+            .map_or(ENCODING_PCM_16BIT, |encoding| encoding as usize);
+
+        let sample_size = match encoding {
+            ENCODING_PCM_8BIT => 1,
+            ENCODING_PCM_16BIT => 2,
+            ENCODING_PCM_24BIT_PACKED => 3,
+            ENCODING_PCM_32BIT | ENCODING_PCM_FLOAT => 4,
+            _ => {
+                debug!("Unsupported PCM encoding {encoding}");
+                return None;
+            }
+        };
+
+        // Drop a partial frame at the end
+        let frame_size = sample_size * channels as usize;
+        let size = data.len() / frame_size * frame_size;
+        if size != data.len() {
+            warn!(
+                "{} bytes of PCM aren't a whole number of {channels} channel frames",
+                data.len()
+            );
+        }
+        let data = &data[..size];
+
+        let samples = match encoding {
+            ENCODING_PCM_8BIT => AudioSamples::U8(data.to_vec()),
+            ENCODING_PCM_16BIT => AudioSamples::S16(
+                data.chunks_exact(2)
+                    .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                    .collect(),
+            ),
+            ENCODING_PCM_24BIT_PACKED => AudioSamples::F32(
+                data.chunks_exact(3)
+                    .map(|sample| {
+                        i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) as f32
+                            / 2147483648.0
+                    })
+                    .collect(),
+            ),
+            ENCODING_PCM_32BIT => AudioSamples::F32(
+                data.chunks_exact(4)
+                    .map(|sample| {
+                        i32::from_le_bytes(sample.try_into().unwrap()) as f32 / 2147483648.0
+                    })
+                    .collect(),
+            ),
+            _ => AudioSamples::F32(
+                data.chunks_exact(4)
+                    .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+                    .collect(),
+            ),
+        };
+
+        let mut buffer = Self::new(samples, channels, sample_rate)?;
+        buffer.layout = ChannelLayout::from_media_format(format);
+
+        Some(buffer)
+    }
+
+    /// Sets the presentation time of the first sample, in microseconds
+    pub fn with_pts_us(mut self, pts_us: i64) -> Self {
+        self.pts_us = pts_us;
+        self
+    }
+
+    /// Sets the speaker positions of the channels. Ignored if the layout doesn't have as many channels as the buffer
+    pub fn with_layout(mut self, layout: ChannelLayout) -> Self {
+        if layout.channels() == self.channels {
+            self.layout = Some(layout);
+        }
+        self
+    }
+
+    /// The interleaved samples
+    pub fn samples(&self) -> &AudioSamples {
+        &self.samples
+    }
+
+    /// Takes the interleaved samples
+    pub fn into_samples(self) -> AudioSamples {
+        self.samples
+    }
+
+    pub fn sample_type(&self) -> SampleType {
+        self.samples.sample_type()
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The speaker positions of the channels, `None` if they're unknown
+    pub fn layout(&self) -> Option<ChannelLayout> {
+        self.layout
+    }
+
+    /// The presentation time of the first sample, in microseconds
+    pub fn pts_us(&self) -> i64 {
+        self.pts_us
+    }
+
+    /// The number of samples per channel
+    pub fn nb_samples(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration_us(&self) -> i64 {
+        (self.nb_samples() as u64 * 1_000_000 / self.sample_rate as u64) as i64
+    }
+
+    /// The presentation time of the sample following the buffer
+    pub fn end_pts_us(&self) -> i64 {
+        self.pts_us + self.duration_us()
+    }
+
+    /// A borrowed view of the samples, `None` for 8 bit samples which [AudioFrame] doesn't support
+    pub fn as_frame(&self) -> Option<AudioFrame<'_>> {
+        let format = match &self.samples {
+            AudioSamples::U8(_) => return None,
+            AudioSamples::S16(samples) => SampleFormat::S16(samples),
+            AudioSamples::F32(samples) => SampleFormat::F32(samples),
+        };
+
+        Some(AudioFrame::new(format, self.channels))
+    }
+
+    /// The samples of one channel
+    pub fn channel(&self, index: usize) -> Option<AudioSamples> {
+        (index < self.channels as usize)
+            .then(|| self.samples.step_by(index, self.channels as usize))
+    }
+
+    /// The samples of each channel, in order
+    pub fn planes(&self) -> Vec<AudioSamples> {
+        (0..self.channels as usize)
+            .map(|index| self.samples.step_by(index, self.channels as usize))
+            .collect()
+    }
+
+    /// A copy of the buffer with another sample type
+    pub fn convert(&self, sample_type: SampleType) -> Self {
+        Self {
+            samples: self.samples.convert(sample_type),
+            ..*self
+        }
+    }
+
+    /// The samples as little endian bytes, like the input buffers of an encoder expect them
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.samples.len() * self.sample_type().sample_size());
+        self.samples.write_bytes(&mut output);
+        output
+    }
+}
+
+impl AudioFrame<'_> {
+    /// Copies the samples into an [AudioBuffer], `None` if the frame isn't a whole number of samples per channel
+    pub fn to_owned(&self, sample_rate: u32) -> Option<AudioBuffer> {
+        let samples = match self.format() {
+            SampleFormat::S16(samples) => AudioSamples::S16(samples.to_vec()),
+            SampleFormat::F32(samples) => AudioSamples::F32(samples.to_vec()),
+        };
+
+        AudioBuffer::new(samples, self.channels(), sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_layouts() {
+        use ChannelPosition::*;
+
+        let defaults = [
+            (1, vec![FrontLeft]),
+            (2, vec![FrontLeft, FrontRight]),
+            (3, vec![FrontLeft, FrontRight, FrontCenter]),
+            (4, vec![FrontLeft, FrontRight, BackLeft, BackRight]),
+            (
+                5,
+                vec![FrontLeft, FrontRight, FrontCenter, BackLeft, BackRight],
+            ),
+            (
+                6,
+                vec![
+                    FrontLeft,
+                    FrontRight,
+                    FrontCenter,
+                    LowFrequency,
+                    BackLeft,
+                    BackRight,
+                ],
+            ),
+            (
+                7,
+                vec![
+                    FrontLeft,
+                    FrontRight,
+                    FrontCenter,
+                    LowFrequency,
+                    BackLeft,
+                    BackRight,
+                    BackCenter,
+                ],
+            ),
+            (
+                8,
+                vec![
+                    FrontLeft,
+                    FrontRight,
+                    FrontCenter,
+                    LowFrequency,
+                    BackLeft,
+                    BackRight,
+                    SideLeft,
+                    SideRight,
+                ],
+            ),
+        ];
+        for (channels, positions) in defaults {
+            let layout = ChannelLayout::default_for(channels).unwrap();
+            assert_eq!(layout.channels(), channels);
+            assert_eq!(layout.positions(), positions);
+        }
+        assert_eq!(ChannelLayout::default_for(0), None);
+        assert_eq!(ChannelLayout::default_for(9), None);
+        assert_eq!(ChannelLayout::default_for(2), Some(ChannelLayout::STEREO));
+        assert_eq!(
+            ChannelLayout::default_for(6),
+            Some(ChannelLayout::SURROUND_5_1)
+        );
+
+        // WAV masks start at the front left speaker, Android masks two bits later
+        assert_eq!(ChannelLayout::from_wav_mask(0x3), ChannelLayout::STEREO);
+        assert_eq!(
+            ChannelLayout::from_wav_mask(0x3f),
+            ChannelLayout::SURROUND_5_1
+        );
+        assert_eq!(
+            ChannelLayout::from_wav_mask(0x63f),
+            ChannelLayout::SURROUND_7_1
+        );
+        assert_eq!(ChannelLayout::SURROUND_7_1.wav_mask(), 0x63f);
+        // 5.1 with side speakers
+        let side = ChannelLayout::from_wav_mask(0x60f);
+        assert_eq!(
+            side.positions(),
+            vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight
+            ]
+        );
+        assert_eq!(side.wav_mask(), 0x60f);
+        // Bits above the top back right speaker aren't positions
+        assert_eq!(ChannelLayout::from_wav_mask(0xfff_ffff).channels(), 18);
+        assert_eq!(ChannelLayout::from_mask(0x3).channels(), 0);
+
+        let layout = ChannelLayout::SURROUND_7_1;
+        assert_eq!(layout.index_of(FrontLeft), Some(0));
+        assert_eq!(layout.index_of(LowFrequency), Some(3));
+        assert_eq!(layout.index_of(SideLeft), Some(6));
+        assert_eq!(layout.index_of(SideRight), Some(7));
+        assert_eq!(layout.index_of(BackCenter), None);
+        assert!(layout
+            .positions()
+            .iter()
+            .all(|position| layout.contains(*position)));
+    }
+
+    #[test]
+    fn sample_conversion() {
+        let s16 = AudioSamples::S16(vec![0, 1000, -32768, 32767, 256, -256]);
+
+        let u8 = s16.convert(SampleType::U8);
+        assert_eq!(u8, AudioSamples::U8(vec![128, 131, 0, 255, 129, 127]));
+        // The low byte is lost
+        assert_eq!(
+            u8.convert(SampleType::S16),
+            AudioSamples::S16(vec![0, 768, -32768, 32512, 256, -256])
+        );
+
+        let f32 = s16.convert(SampleType::F32);
+        assert_eq!(
+            f32,
+            AudioSamples::F32(vec![
+                0.0,
+                1000.0 / 32768.0,
+                -1.0,
+                32767.0 / 32768.0,
+                256.0 / 32768.0,
+                -256.0 / 32768.0
+            ])
+        );
+        assert_eq!(f32.convert(SampleType::S16), s16);
+        assert_eq!(
+            u8.convert(SampleType::F32),
+            AudioSamples::F32(vec![0.0, 0.0234375, -1.0, 0.9921875, 0.0078125, -0.0078125])
+        );
+
+        // Floats are clamped and rounded
+        let floats = AudioSamples::F32(vec![2.0, -2.0, 1.0, -1.0, 0.4 / 32768.0, 0.6 / 32768.0]);
+        assert_eq!(
+            floats.convert(SampleType::S16),
+            AudioSamples::S16(vec![32767, -32768, 32767, -32768, 0, 1])
+        );
+        assert_eq!(
+            floats.convert(SampleType::U8),
+            AudioSamples::U8(vec![255, 0, 255, 0, 128, 128])
+        );
+        assert_eq!(
+            AudioSamples::F32(vec![f32::INFINITY, f32::NEG_INFINITY]).convert(SampleType::S16),
+            AudioSamples::S16(vec![32767, -32768])
+        );
+
+        let mut bytes = Vec::new();
+        s16.write_bytes(&mut bytes);
+        assert_eq!(bytes[..6], [0, 0, 0xe8, 0x03, 0x00, 0x80]);
+        bytes.clear();
+        AudioSamples::F32(vec![1.0]).write_bytes(&mut bytes);
+        assert_eq!(bytes, 1.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn audio_buffer() {
+        let buffer = AudioBuffer::new(
+            AudioSamples::S16(vec![0, 1000, -32768, 32767, 256, -256]),
+            2,
+            48000,
+        )
+        .unwrap()
+        .with_pts_us(10);
+        assert_eq!(buffer.nb_samples(), 3);
+        assert_eq!(buffer.layout(), Some(ChannelLayout::STEREO));
+        assert_eq!(buffer.duration_us(), 62);
+        assert_eq!(buffer.end_pts_us(), 72);
+
+        assert!(AudioBuffer::new(AudioSamples::S16(vec![1, 2, 3]), 2, 48000).is_none());
+        assert!(AudioBuffer::new(AudioSamples::S16(vec![]), 0, 48000).is_none());
+        assert!(AudioBuffer::new(AudioSamples::S16(vec![1, 2]), 2, 0).is_none());
+        // No default layout for 9 channels
+        let nine = AudioBuffer::new(AudioSamples::U8(vec![128; 9]), 9, 8000).unwrap();
+        assert_eq!(nine.layout(), None);
+
+        // A layout with another number of channels is ignored
+        let layout = buffer.clone().with_layout(ChannelLayout::MONO).layout();
+        assert_eq!(layout, Some(ChannelLayout::STEREO));
+
+        assert_eq!(
+            buffer.channel(1),
+            Some(AudioSamples::S16(vec![1000, 32767, -256]))
+        );
+        assert_eq!(buffer.channel(2), None);
+        let planes = buffer.planes();
+        assert_eq!(
+            AudioBuffer::from_planes(&planes, 48000).unwrap().samples(),
+            buffer.samples()
+        );
+        assert!(AudioBuffer::from_planes(
+            &[AudioSamples::S16(vec![1]), AudioSamples::F32(vec![1.0])],
+            48000
+        )
+        .is_none());
+        assert!(AudioBuffer::from_planes(
+            &[AudioSamples::S16(vec![1]), AudioSamples::S16(vec![1, 2])],
+            48000
+        )
+        .is_none());
+
+        let converted = buffer.convert(SampleType::F32);
+        assert_eq!(converted.pts_us(), 10);
+        assert_eq!(converted.layout(), buffer.layout());
+        assert_eq!(converted.to_bytes().len(), 24);
+
+        assert!(buffer.convert(SampleType::U8).as_frame().is_none());
+        let frame = buffer.as_frame().unwrap();
+        assert_eq!(frame.nb_samples(), 3);
+        assert_eq!(frame.to_owned(48000).unwrap().samples(), buffer.samples());
+    }
+}
@@ -0,0 +1,569 @@
+#[cfg(target_os = "android")]
+use log::debug;
+use log::warn;
+
+use crate::{AudioBuffer, BufferFlag, MediaStatus, SampleType};
+#[cfg(target_os = "android")]
+use crate::{
+    AudioConverter, ChannelLayout, MediaCodec, MediaFormat, OpusHead, ResampleQuality,
+    ENCODING_PCM_16BIT, ENCODING_PCM_FLOAT, OPUS_SAMPLE_RATE,
+};
+
+/// The samples per channel in an AAC-LC frame
+#[cfg(target_os = "android")]
+const AAC_FRAME_SAMPLES: usize = 1024;
+/// HE-AAC frames are twice as long, the SBR layer doubles the sample rate of the core
+#[cfg(target_os = "android")]
+const HE_AAC_FRAME_SAMPLES: usize = 2048;
+/// The delay of the common AAC encoders, used when the encoder doesn't report it
+#[cfg(target_os = "android")]
+const AAC_DEFAULT_PRIMING: u32 = 2048;
+/// The lookahead of libopus at 48kHz, used until the encoder gives its `OpusHead`
+#[cfg(target_os = "android")]
+const OPUS_DEFAULT_PRIMING: u32 = 312;
+
+/// A packet out of an [AudioEncoder]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedAudioPacket {
+    pub data: Vec<u8>,
+    /// The presentation time of the packet, in microseconds. Packets holding the priming samples start before
+    /// the first input sample, so their time can be negative
+    pub pts_us: i64,
+    /// The buffer flags of the packet, see [BufferFlag]
+    pub flags: u32,
+}
+
+impl EncodedAudioPacket {
+    /// Whether the packet is codec specific data (like the `AudioSpecificConfig` or the `OpusHead`) rather than audio
+    pub fn is_config(&self) -> bool {
+        BufferFlag::CodecConfig.is_contained_in(self.flags as i32)
+    }
+}
+
+/// Encodes PCM to `audio/mp4a-latm` or `audio/opus` with a MediaCodec encoder.
+///
+/// Audio of any length, sample rate or channel layout can be queued: it's converted to the format of the encoder
+/// and sent in whole codec frames. Packet timestamps are computed from the number of samples, starting at the
+/// time of the first queued buffer minus the encoder delay (priming).
+///
+/// Call [receive](Self::receive) after queuing audio until it returns `None`, then [finish](Self::finish) at the
+/// end of the stream and [receive](Self::receive) until [is_finished](Self::is_finished)
+#[cfg(target_os = "android")]
+#[derive(Debug)]
+pub struct AudioEncoder {
+    codec: MediaCodec<'static>,
+    mime: String,
+    sample_rate: u32,
+    layout: ChannelLayout,
+    priming_samples: u32,
+    /// Whether the priming was set by the user or the encoder, and shouldn't be guessed anymore
+    priming_known: bool,
+    converter: AudioConverter,
+    frames: FrameSplitter,
+    packet_count: u64,
+    finished: bool,
+}
+
+#[cfg(target_os = "android")]
+impl AudioEncoder {
+    /// Creates and starts an encoder for a format with a `mime` (`audio/mp4a-latm` or `audio/opus`),
+    /// `sample-rate` and `channel-count`, and optionally `channel-mask`, `pcm-encoding` (16 bit or float),
+    /// `aac-profile` and `bitrate`
+    pub fn new(format: &MediaFormat) -> Result<Self, MediaStatus> {
+        let Some(mime) = format.get_string("mime") else {
+            warn!("The encoder format has no mime type");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        let converter = AudioConverter::from_media_format(format, ResampleQuality::default())?;
+        let sample_rate = converter.sample_rate();
+
+        let (frame_samples, priming_samples) = match mime.as_str() {
+            "audio/mp4a-latm" => match format.get_i32("aac-profile") {
+                Some(5 | 29) => (HE_AAC_FRAME_SAMPLES, AAC_DEFAULT_PRIMING),
+                _ => (AAC_FRAME_SAMPLES, AAC_DEFAULT_PRIMING),
+            },
+            // 20ms frames
+            "audio/opus" => (
+                sample_rate as usize / 50,
+                OPUS_DEFAULT_PRIMING * sample_rate / OPUS_SAMPLE_RATE,
+            ),
+            _ => {
+                warn!("Unsupported audio encoder {mime}");
+                return Err(MediaStatus::ErrorUnsupported);
+            }
+        };
+
+        let sample_type = match format
+            .get_i32("pcm-encoding")
+            .map_or(ENCODING_PCM_16BIT, |encoding| encoding as usize)
+        {
+            ENCODING_PCM_16BIT => SampleType::S16,
+            ENCODING_PCM_FLOAT => SampleType::F32,
+            encoding => {
+                warn!("Unsupported PCM encoding {encoding} for the encoder");
+                return Err(MediaStatus::ErrorUnsupported);
+            }
+        };
+
+        let Some(mut codec) = MediaCodec::create_encoder(&mime) else {
+            warn!("Could not create an encoder for {mime}");
+            return Err(MediaStatus::ErrorUnsupported);
+        };
+        codec.init(format, None, BufferFlag::Encode as u32)?;
+        codec.start()?;
+
+        let layout = converter.layout();
+
+        Ok(Self {
+            codec,
+            sample_rate,
+            layout,
+            priming_samples,
+            priming_known: false,
+            converter,
+            frames: FrameSplitter::new(sample_rate, layout.channels(), sample_type, frame_samples),
+            packet_count: 0,
+            finished: false,
+            mime,
+        })
+    }
+
+    /// Overrides the encoder delay, in samples per channel, when the encoder doesn't report it
+    pub fn with_priming_samples(mut self, samples: u32) -> Self {
+        self.priming_samples = samples;
+        self.priming_known = true;
+        self
+    }
+
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    /// The samples per channel of a codec frame. Input buffers are filled with whole frames, except the last one
+    pub fn frame_samples(&self) -> usize {
+        self.frames.frame_samples
+    }
+
+    /// The encoder delay, in samples per channel: the decoded audio starts with that much silence
+    pub fn priming_samples(&self) -> u32 {
+        self.priming_samples
+    }
+
+    /// The silence added after the last input sample to fill the last frame, known once the encoder is finished
+    pub fn padding_samples(&self) -> Option<u32> {
+        self.finished.then(|| {
+            self.frames
+                .padding_samples(self.packet_count, self.priming_samples)
+        })
+    }
+
+    /// The output format of the encoder once it has produced its codec specific data, with the `encoder-delay`
+    /// key and `encoder-padding` once it's known. This is the format to give to a muxer
+    pub fn output_format(&self) -> Option<MediaFormat> {
+        let mut format = self.codec.output_format()?;
+
+        format.set_i32("encoder-delay", self.priming_samples as i32);
+        if let Some(padding) = self.padding_samples() {
+            format.set_i32("encoder-padding", padding as i32);
+        }
+
+        Some(format)
+    }
+
+    /// Queues audio to encode. It's converted to the sample rate, channel layout and sample type of the encoder.
+    ///
+    /// The samples are sent to the encoder as input buffers are available, call [receive](Self::receive) to
+    /// get the packets and make room
+    pub fn queue(&mut self, buffer: &AudioBuffer) -> Result<(), MediaStatus> {
+        if self.frames.is_ended() {
+            warn!("Can't queue audio after the end of the stream");
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        let converted = self.converter.process(buffer)?;
+        self.frames.push(&converted);
+
+        self.feed()
+    }
+
+    /// Ends the stream: the converter is flushed, and the last partial frame is sent with the end of stream flag
+    pub fn finish(&mut self) -> Result<(), MediaStatus> {
+        if self.frames.is_ended() {
+            return Ok(());
+        }
+
+        if let Some(tail) = self.converter.flush()? {
+            self.frames.push(&tail);
+        }
+        self.frames.end();
+
+        self.feed()
+    }
+
+    /// Whether the encoder returned its last packet
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Sends what can be sent to the encoder, then returns the next packet, if there's one yet
+    pub fn receive(&mut self) -> Result<Option<EncodedAudioPacket>, MediaStatus> {
+        self.feed()?;
+
+        if self.finished {
+            return Ok(None);
+        }
+
+        // Nothing to read doesn't have its own status
+        let Ok(buffer) = self.codec.dequeue_output() else {
+            return Ok(None);
+        };
+
+        let flags = buffer.info().flags();
+        let data = buffer.data().unwrap_or_default().to_vec();
+        drop(buffer);
+
+        if BufferFlag::EndOfStream.is_contained_in(flags as i32) {
+            self.finished = true;
+            debug!(
+                "Encoded {} samples in {} packets",
+                self.frames.input_samples, self.packet_count
+            );
+        }
+
+        if BufferFlag::CodecConfig.is_contained_in(flags as i32) {
+            self.update_priming(&data);
+            return Ok(Some(EncodedAudioPacket {
+                data,
+                pts_us: 0,
+                flags,
+            }));
+        }
+
+        // The end of stream can come with an empty buffer
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let pts_us = self
+            .frames
+            .packet_pts_us(self.packet_count, self.priming_samples);
+        self.packet_count += 1;
+
+        Ok(Some(EncodedAudioPacket {
+            data,
+            pts_us,
+            flags,
+        }))
+    }
+
+    /// Takes the delay from the encoder, when it tells
+    fn update_priming(&mut self, config: &[u8]) {
+        if self.priming_known {
+            return;
+        }
+
+        let reported = self
+            .codec
+            .output_format()
+            .and_then(|format| format.get_i32("encoder-delay"))
+            .filter(|delay| *delay >= 0)
+            .map(|delay| delay as u32);
+
+        let pre_skip = match self.mime.as_str() {
+            "audio/opus" => OpusHead::parse(config)
+                .map(|head| head.pre_skip as u32 * self.sample_rate / OPUS_SAMPLE_RATE),
+            _ => None,
+        };
+
+        if let Some(priming) = reported.or(pre_skip) {
+            self.priming_samples = priming;
+            self.priming_known = true;
+        }
+    }
+
+    /// Fills the available input buffers with whole frames, and the end of the stream once everything else is sent
+    fn feed(&mut self) -> Result<(), MediaStatus> {
+        while self.frames.wants_input() {
+            // No input buffer for now
+            let Ok(mut input) = self.codec.dequeue_input() else {
+                return Ok(());
+            };
+
+            let chunk = self.frames.next_input(input.size())?;
+            input.write_data(&chunk.data);
+            input.set_time(chunk.pts_us.max(0) as u64);
+
+            if chunk.end {
+                let mut flags = 0;
+                BufferFlag::EndOfStream.add_to_flag(&mut flags);
+                input.set_flags(flags as u32);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The content of an encoder input buffer
+#[derive(Debug, Clone, PartialEq)]
+struct InputChunk {
+    data: Vec<u8>,
+    /// The presentation time of the first sample, in microseconds
+    pts_us: i64,
+    /// Whether the buffer ends the stream
+    end: bool,
+}
+
+/// Cuts the converted samples into encoder input buffers of whole codec frames, and computes the timestamps
+/// from the number of samples, so they don't drift whatever the size of the queued buffers
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+#[derive(Debug)]
+struct FrameSplitter {
+    sample_rate: u32,
+    sample_type: SampleType,
+    /// The bytes of one sample of every channel
+    sample_size: usize,
+    frame_samples: usize,
+    /// Samples in the encoder format, waiting for an input buffer
+    pending: Vec<u8>,
+    start_pts_us: Option<i64>,
+    /// The samples (per channel) given to the encoder, and the ones sent to the codec so far
+    input_samples: u64,
+    queued_samples: u64,
+    end_pending: bool,
+    end_queued: bool,
+}
+
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+impl FrameSplitter {
+    fn new(sample_rate: u32, channels: u32, sample_type: SampleType, frame_samples: usize) -> Self {
+        Self {
+            sample_rate,
+            sample_type,
+            sample_size: sample_type.sample_size() * channels as usize,
+            frame_samples,
+            pending: Vec::new(),
+            start_pts_us: None,
+            input_samples: 0,
+            queued_samples: 0,
+            end_pending: false,
+            end_queued: false,
+        }
+    }
+
+    /// Adds samples in the encoder sample rate and layout. The first buffer gives the time of the stream
+    fn push(&mut self, buffer: &AudioBuffer) {
+        self.start_pts_us.get_or_insert(buffer.pts_us());
+        buffer
+            .samples()
+            .convert(self.sample_type)
+            .write_bytes(&mut self.pending);
+        self.input_samples += buffer.nb_samples() as u64;
+    }
+
+    /// Marks the end of the stream, the last partial frame can be sent
+    fn end(&mut self) {
+        self.end_pending = true;
+    }
+
+    fn is_ended(&self) -> bool {
+        self.end_pending || self.end_queued
+    }
+
+    /// Whether there's a whole frame to send, or the end of the stream
+    fn wants_input(&self) -> bool {
+        let frame_size = self.frame_samples * self.sample_size;
+        !self.end_queued && (self.pending.len() >= frame_size || self.end_pending)
+    }
+
+    /// Takes the samples for an input buffer of `capacity` bytes
+    fn next_input(&mut self, capacity: usize) -> Result<InputChunk, MediaStatus> {
+        let frame_size = self.frame_samples * self.sample_size;
+        let (size, end) = input_size(self.pending.len(), capacity, frame_size, self.end_pending);
+        if size == 0 && !end {
+            warn!(
+                "The encoder input buffers of {capacity} bytes can't hold a frame of {frame_size} bytes"
+            );
+            return Err(MediaStatus::ErrorInsufficientResource);
+        }
+
+        let pts_us = self.start_pts_us.unwrap_or(0)
+            + (self.queued_samples * 1_000_000 / self.sample_rate as u64) as i64;
+        self.queued_samples += (size / self.sample_size) as u64;
+        self.end_queued = end;
+
+        Ok(InputChunk {
+            data: self.pending.drain(..size).collect(),
+            pts_us,
+            end,
+        })
+    }
+
+    /// The presentation time of a packet, from the number of samples before it
+    fn packet_pts_us(&self, index: u64, priming_samples: u32) -> i64 {
+        let samples = (index * self.frame_samples as u64) as i64 - priming_samples as i64;
+        self.start_pts_us.unwrap_or(0) + samples * 1_000_000 / self.sample_rate as i64
+    }
+
+    /// The silence the encoder added after the last sample to fill the last of `packet_count` frames
+    fn padding_samples(&self, packet_count: u64, priming_samples: u32) -> u32 {
+        let encoded = packet_count * self.frame_samples as u64;
+        encoded.saturating_sub(self.input_samples + priming_samples as u64) as u32
+    }
+}
+
+/// How many of the `pending` bytes go into an input buffer of `capacity` bytes, and whether the end of stream
+/// goes with them. Only whole frames are sent, except for the last samples of the stream
+fn input_size(pending: usize, capacity: usize, frame_size: usize, end: bool) -> (usize, bool) {
+    if end && pending <= capacity {
+        return (pending, true);
+    }
+
+    let frames = (pending / frame_size).min(capacity / frame_size);
+    (frames * frame_size, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::AudioSamples;
+
+    /// Stereo 16 bit at 48kHz, with frames of 1024 samples
+    fn splitter() -> FrameSplitter {
+        FrameSplitter::new(48000, 2, SampleType::S16, 1024)
+    }
+
+    fn buffer(nb_samples: usize, pts_us: i64) -> AudioBuffer {
+        AudioBuffer::new(AudioSamples::F32(vec![0.5; 2 * nb_samples]), 2, 48000)
+            .unwrap()
+            .with_pts_us(pts_us)
+    }
+
+    #[test]
+    fn input_sizes() {
+        assert_eq!(input_size(10000, 8192, 4096, false), (8192, false));
+        assert_eq!(input_size(5000, 8192, 4096, false), (4096, false));
+        assert_eq!(input_size(3000, 8192, 4096, false), (0, false));
+        assert_eq!(input_size(5000, 8192, 4096, true), (5000, true));
+        assert_eq!(input_size(9000, 8192, 4096, true), (8192, false));
+        assert_eq!(input_size(0, 8192, 4096, true), (0, true));
+    }
+
+    #[test]
+    fn whole_frames() {
+        let mut frames = splitter();
+        frames.push(&buffer(1000, 0));
+        assert!(!frames.wants_input());
+
+        // The samples are converted to the encoder sample type
+        frames.push(&buffer(1100, 21000));
+        assert!(frames.wants_input());
+        let chunk = frames.next_input(4096).unwrap();
+        assert_eq!(chunk.data.len(), 4096);
+        assert_eq!(chunk.data[..4], [0, 0x40, 0, 0x40]);
+        assert!(!chunk.end);
+        assert!(frames.wants_input());
+        frames.next_input(4096).unwrap();
+        assert!(!frames.wants_input());
+
+        // Input buffers take as many whole frames as they hold
+        frames.push(&buffer(3000, 0));
+        assert_eq!(
+            frames.next_input(3 * 4096 - 1).unwrap().data.len(),
+            2 * 4096
+        );
+        assert!(!frames.wants_input());
+
+        // A buffer smaller than a frame is an error
+        frames.push(&buffer(1024, 0));
+        assert_eq!(
+            frames.next_input(4095),
+            Err(MediaStatus::ErrorInsufficientResource)
+        );
+        assert!(frames.wants_input());
+    }
+
+    #[test]
+    fn continuous_pts() {
+        let mut frames = splitter();
+        let mut pts_us = 1000;
+        let mut nb_samples = 0;
+        let mut queued = Vec::new();
+
+        // Buffers of odd sizes, with timestamps that don't matter after the first one
+        for size in [1, 441, 1023, 1025, 4000, 17] {
+            frames.push(&buffer(size, pts_us));
+            pts_us += 12345;
+            nb_samples += size;
+            while frames.wants_input() {
+                queued.push(frames.next_input(4096).unwrap());
+            }
+        }
+        assert_eq!(nb_samples / 1024, queued.len());
+
+        for (index, chunk) in queued.iter().enumerate() {
+            assert_eq!(chunk.pts_us, 1000 + index as i64 * 1024 * 1_000_000 / 48000);
+        }
+
+        // Packets start the priming samples earlier
+        assert_eq!(frames.packet_pts_us(0, 0), 1000);
+        assert_eq!(frames.packet_pts_us(0, 2048), 1000 - 42666);
+        assert_eq!(frames.packet_pts_us(3, 2048), 1000 + 21333);
+        assert_eq!(frames.packet_pts_us(375, 0), 1000 + 8_000_000);
+    }
+
+    #[test]
+    fn end_of_stream() {
+        let mut frames = splitter();
+        frames.push(&buffer(2500, -500));
+        frames.next_input(8192).unwrap();
+        assert!(!frames.wants_input());
+        assert!(!frames.is_ended());
+
+        // The partial frame goes with the end of stream
+        frames.end();
+        assert!(frames.is_ended());
+        assert!(frames.wants_input());
+        let chunk = frames.next_input(8192).unwrap();
+        assert_eq!(chunk.data.len(), 452 * 4);
+        assert_eq!(chunk.pts_us, -500 + 2048 * 1_000_000 / 48000);
+        assert!(chunk.end);
+        assert!(!frames.wants_input());
+        assert!(frames.is_ended());
+
+        // 3 frames hold the 2048 priming samples, 2500 samples and 596 samples of padding
+        assert_eq!(frames.padding_samples(3, 0), 3 * 1024 - 2500);
+        assert_eq!(frames.padding_samples(5, 2048), 5 * 1024 - 2500 - 2048);
+        assert_eq!(frames.padding_samples(2, 2048), 0);
+
+        // The end of stream fits a buffer once whole frames are sent
+        let mut frames = splitter();
+        frames.push(&buffer(2100, 0));
+        frames.end();
+        let chunk = frames.next_input(4096).unwrap();
+        assert_eq!((chunk.data.len(), chunk.end), (4096, false));
+        let chunk = frames.next_input(4096).unwrap();
+        assert_eq!((chunk.data.len(), chunk.end), (4096, false));
+        let chunk = frames.next_input(4096).unwrap();
+        assert_eq!((chunk.data.len(), chunk.end), (52 * 4, true));
+        assert_eq!(chunk.pts_us, 2048 * 1_000_000 / 48000);
+
+        // An empty stream still sends the end of stream
+        let mut frames = splitter();
+        frames.end();
+        let chunk = frames.next_input(4096).unwrap();
+        assert!(chunk.data.is_empty() && chunk.end);
+        assert_eq!(chunk.pts_us, 0);
+        assert!(!frames.wants_input());
+    }
+}
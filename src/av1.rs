@@ -0,0 +1,782 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{bitstream::BitReader, VideoColorInfo};
+
+/// AV1 OBU types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Av1ObuType {
+    SequenceHeader,
+    TemporalDelimiter,
+    FrameHeader,
+    TileGroup,
+    Metadata,
+    Frame,
+    RedundantFrameHeader,
+    TileList,
+    Padding,
+    Other(u8),
+}
+
+impl Av1ObuType {
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            1 => Self::SequenceHeader,
+            2 => Self::TemporalDelimiter,
+            3 => Self::FrameHeader,
+            4 => Self::TileGroup,
+            5 => Self::Metadata,
+            6 => Self::Frame,
+            7 => Self::RedundantFrameHeader,
+            8 => Self::TileList,
+            15 => Self::Padding,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// An AV1 Open Bitstream Unit
+#[derive(Debug, Clone, Copy)]
+pub struct Av1Obu<'a> {
+    pub obu_type: Av1ObuType,
+    /// The temporal and spatial layers, when the OBU has an extension header
+    pub temporal_id: u8,
+    pub spatial_id: u8,
+    /// The whole OBU, including its header
+    pub data: &'a [u8],
+    /// The payload of the OBU, after its header and size
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the OBUs of a temporal unit, in the low overhead bitstream format used by MP4, Matroska, IVF and MediaCodec.
+///
+/// Stops at the first malformed OBU. An OBU without a size field takes the rest of the data
+#[derive(Debug, Clone)]
+pub struct Av1Obus<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Returns an iterator over the OBUs of a temporal unit
+pub fn av1_obus(data: &[u8]) -> Av1Obus<'_> {
+    Av1Obus { data, position: 0 }
+}
+
+impl<'a> Iterator for Av1Obus<'a> {
+    type Item = Av1Obu<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data.get(self.position..)?;
+        let header = *data.first()?;
+
+        // obu_forbidden_bit
+        if header & 0x80 != 0 {
+            self.position = self.data.len();
+            return None;
+        }
+
+        let has_extension = header & 0x04 != 0;
+        let has_size = header & 0x02 != 0;
+
+        let mut header_size = 1;
+        let (temporal_id, spatial_id) = if has_extension {
+            let extension = *data.get(1)?;
+            header_size += 1;
+            (extension >> 5, (extension >> 3) & 0x03)
+        } else {
+            (0, 0)
+        };
+
+        let payload_size = if has_size {
+            let (size, length) = read_leb128(&data[header_size..])?;
+            header_size += length;
+            usize::try_from(size).ok()?
+        } else {
+            data.len() - header_size
+        };
+
+        let end = header_size.checked_add(payload_size)?;
+        let obu = data.get(..end)?;
+        self.position += end;
+
+        Some(Av1Obu {
+            obu_type: Av1ObuType::from_value((header >> 3) & 0x0f),
+            temporal_id,
+            spatial_id,
+            data: obu,
+            payload: &obu[header_size..],
+        })
+    }
+}
+
+/// Reads an unsigned LEB128 value, returning it with the number of bytes it took
+fn read_leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+
+    for (index, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+
+    None
+}
+
+/// Reads a `uvlc()` value
+fn read_uvlc(reader: &mut BitReader) -> Option<u32> {
+    let mut leading_zeros = 0;
+    while !reader.read_bit()? {
+        leading_zeros += 1;
+    }
+
+    if leading_zeros >= 32 {
+        return Some(u32::MAX);
+    }
+
+    let value = reader.read_bits(leading_zeros)? as u64;
+    Some((value + (1u64 << leading_zeros) - 1) as u32)
+}
+
+/// An AV1 sequence header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1SequenceHeader {
+    pub seq_profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    /// The level of the first operating point. The level is `2 + (seq_level_idx >> 2)`.`seq_level_idx & 3`
+    pub seq_level_idx: u8,
+    /// The high tier when set, the main tier otherwise
+    pub seq_tier: bool,
+    pub initial_display_delay: Option<u8>,
+    pub num_units_in_display_tick: u32,
+    pub time_scale: u32,
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+    pub frame_id_numbers_present: bool,
+    pub enable_order_hint: bool,
+    pub order_hint_bits: u32,
+    pub bit_depth: u8,
+    pub mono_chrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub color: VideoColorInfo,
+    /// Whether the color primaries, transfer and matrix were signalled
+    pub color_description_present: bool,
+}
+
+impl Av1SequenceHeader {
+    /// Parses the payload of a sequence header OBU
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(payload);
+
+        let seq_profile = reader.read_bits(3)? as u8;
+        let still_picture = reader.read_bit()?;
+        let reduced_still_picture_header = reader.read_bit()?;
+
+        let mut header = Self {
+            seq_profile,
+            still_picture,
+            reduced_still_picture_header,
+            seq_level_idx: 0,
+            seq_tier: false,
+            initial_display_delay: None,
+            num_units_in_display_tick: 0,
+            time_scale: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
+            frame_id_numbers_present: false,
+            enable_order_hint: false,
+            order_hint_bits: 0,
+            bit_depth: 8,
+            mono_chrome: false,
+            chroma_subsampling_x: true,
+            chroma_subsampling_y: true,
+            chroma_sample_position: 0,
+            color: VideoColorInfo::default(),
+            color_description_present: false,
+        };
+
+        if reduced_still_picture_header {
+            header.seq_level_idx = reader.read_bits(5)? as u8;
+        } else {
+            let mut decoder_model_info_present = false;
+            let mut buffer_delay_length = 0;
+
+            // timing_info_present_flag
+            if reader.read_bit()? {
+                header.num_units_in_display_tick = reader.read_bits(32)?;
+                header.time_scale = reader.read_bits(32)?;
+
+                // equal_picture_interval
+                if reader.read_bit()? {
+                    read_uvlc(&mut reader)?;
+                }
+
+                decoder_model_info_present = reader.read_bit()?;
+                if decoder_model_info_present {
+                    buffer_delay_length = reader.read_bits(5)? as usize + 1;
+                    // num_units_in_decoding_tick, buffer_removal_time_length_minus_1 and frame_presentation_time_length_minus_1
+                    reader.skip_bits(32 + 5 + 5)?;
+                }
+            }
+
+            let initial_display_delay_present = reader.read_bit()?;
+            let operating_points = reader.read_bits(5)? + 1;
+
+            for index in 0..operating_points {
+                // operating_point_idc
+                reader.skip_bits(12)?;
+                let level = reader.read_bits(5)? as u8;
+                let tier = level > 7 && reader.read_bit()?;
+
+                // decoder_model_present_for_this_op
+                if decoder_model_info_present && reader.read_bit()? {
+                    // decoder_buffer_delay, encoder_buffer_delay and low_delay_mode_flag
+                    reader.skip_bits(buffer_delay_length * 2 + 1)?;
+                }
+
+                let mut display_delay = None;
+                if initial_display_delay_present && reader.read_bit()? {
+                    display_delay = Some(reader.read_bits(4)? as u8 + 1);
+                }
+
+                if index == 0 {
+                    header.seq_level_idx = level;
+                    header.seq_tier = tier;
+                    header.initial_display_delay = display_delay;
+                }
+            }
+        }
+
+        let width_bits = reader.read_bits(4)? + 1;
+        let height_bits = reader.read_bits(4)? + 1;
+        header.max_frame_width = reader.read_bits(width_bits)? + 1;
+        header.max_frame_height = reader.read_bits(height_bits)? + 1;
+
+        if !reduced_still_picture_header {
+            header.frame_id_numbers_present = reader.read_bit()?;
+            if header.frame_id_numbers_present {
+                // delta_frame_id_length_minus_2 and additional_frame_id_length_minus_1
+                reader.skip_bits(7)?;
+            }
+        }
+
+        // use_128x128_superblock, enable_filter_intra and enable_intra_edge
+        reader.skip_bits(3)?;
+
+        if !reduced_still_picture_header {
+            // enable_interintra_compound, enable_masked_compound, enable_warped_motion and enable_dual_filter
+            reader.skip_bits(4)?;
+            header.enable_order_hint = reader.read_bit()?;
+            if header.enable_order_hint {
+                // enable_jnt_comp and enable_ref_frame_mvs
+                reader.skip_bits(2)?;
+            }
+
+            // seq_choose_screen_content_tools
+            let force_screen_content_tools = if reader.read_bit()? {
+                2
+            } else {
+                reader.read_bits(1)?
+            };
+
+            // seq_choose_integer_mv, then seq_force_integer_mv
+            if force_screen_content_tools > 0 && !reader.read_bit()? {
+                reader.skip_bits(1)?;
+            }
+
+            if header.enable_order_hint {
+                header.order_hint_bits = reader.read_bits(3)? + 1;
+            }
+        }
+
+        // enable_superres, enable_cdef and enable_restoration
+        reader.skip_bits(3)?;
+
+        header.read_color_config(&mut reader)?;
+
+        Some(header)
+    }
+
+    fn read_color_config(&mut self, reader: &mut BitReader) -> Option<()> {
+        let high_bitdepth = reader.read_bit()?;
+        self.bit_depth = match (self.seq_profile, high_bitdepth) {
+            (2, true) if reader.read_bit()? => 12,
+            (_, true) => 10,
+            _ => 8,
+        };
+
+        self.mono_chrome = self.seq_profile != 1 && reader.read_bit()?;
+
+        // Unspecified, unless signalled
+        self.color = VideoColorInfo::default();
+        self.color_description_present = reader.read_bit()?;
+        if self.color_description_present {
+            self.color.primaries = reader.read_u8()?;
+            self.color.transfer = reader.read_u8()?;
+            self.color.matrix = reader.read_u8()?;
+        }
+
+        let srgb = self.color.primaries == 1 && self.color.transfer == 13 && self.color.matrix == 0;
+
+        if self.mono_chrome {
+            self.color.full_range = reader.read_bit()?;
+            self.chroma_subsampling_x = true;
+            self.chroma_subsampling_y = true;
+            return Some(());
+        }
+
+        if srgb {
+            self.color.full_range = true;
+            self.chroma_subsampling_x = false;
+            self.chroma_subsampling_y = false;
+            return Some(());
+        }
+
+        self.color.full_range = reader.read_bit()?;
+        match self.seq_profile {
+            0 => {
+                self.chroma_subsampling_x = true;
+                self.chroma_subsampling_y = true;
+            }
+            1 => {
+                self.chroma_subsampling_x = false;
+                self.chroma_subsampling_y = false;
+            }
+            _ if self.bit_depth == 12 => {
+                self.chroma_subsampling_x = reader.read_bit()?;
+                self.chroma_subsampling_y = self.chroma_subsampling_x && reader.read_bit()?;
+            }
+            _ => {
+                self.chroma_subsampling_x = true;
+                self.chroma_subsampling_y = false;
+            }
+        }
+
+        if self.chroma_subsampling_x && self.chroma_subsampling_y {
+            self.chroma_sample_position = reader.read_bits(2)? as u8;
+        }
+
+        Some(())
+    }
+
+    /// Finds and parses the sequence header in a temporal unit
+    pub fn find(data: &[u8]) -> Option<Self> {
+        av1_obus(data)
+            .find(|obu| obu.obu_type == Av1ObuType::SequenceHeader)
+            .and_then(|obu| Self::parse(obu.payload))
+    }
+
+    /// The frame rate signalled in the timing info, if any
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_display_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+
+        Some(self.time_scale as f64 / self.num_units_in_display_tick as f64)
+    }
+
+    /// The value of the `profile` key in a `MediaFormat` (the `AV1Profile*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_profile(&self) -> Option<i32> {
+        if self.seq_profile != 0 {
+            // Android only knows about the Main profile
+            return None;
+        }
+
+        match self.bit_depth {
+            8 => Some(0x1),
+            // PQ streams need the HDR10 profile, otherwise decoders treat them as SDR
+            10 if self.color.transfer == 16 => Some(0x1000),
+            10 => Some(0x2),
+            _ => None,
+        }
+    }
+
+    /// The value of the `level` key in a `MediaFormat` (the `AV1Level*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_level(&self) -> Option<i32> {
+        if self.seq_level_idx > 23 {
+            return None;
+        }
+
+        // AV1Level2 is 1, and every level after it is the next bit
+        Some(1 << self.seq_level_idx)
+    }
+}
+
+/// AV1 frame types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Av1FrameType {
+    Key,
+    Inter,
+    IntraOnly,
+    Switch,
+}
+
+/// The start of the uncompressed header of an AV1 frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Av1FrameHeader {
+    pub show_existing_frame: bool,
+    /// The reference slot shown again when `show_existing_frame` is set
+    pub frame_to_show_map_idx: u8,
+    /// The type of the frame. Unknown for frames that show an existing frame
+    pub frame_type: Option<Av1FrameType>,
+    pub show_frame: bool,
+}
+
+impl Av1FrameHeader {
+    /// Parses the start of the payload of a frame header or frame OBU
+    pub fn parse(payload: &[u8], sequence_header: &Av1SequenceHeader) -> Option<Self> {
+        if sequence_header.reduced_still_picture_header {
+            return Some(Self {
+                show_existing_frame: false,
+                frame_to_show_map_idx: 0,
+                frame_type: Some(Av1FrameType::Key),
+                show_frame: true,
+            });
+        }
+
+        let mut reader = BitReader::new(payload);
+
+        if reader.read_bit()? {
+            return Some(Self {
+                show_existing_frame: true,
+                frame_to_show_map_idx: reader.read_bits(3)? as u8,
+                frame_type: None,
+                show_frame: true,
+            });
+        }
+
+        let frame_type = match reader.read_bits(2)? {
+            0 => Av1FrameType::Key,
+            1 => Av1FrameType::Inter,
+            2 => Av1FrameType::IntraOnly,
+            _ => Av1FrameType::Switch,
+        };
+
+        Some(Self {
+            show_existing_frame: false,
+            frame_to_show_map_idx: 0,
+            frame_type: Some(frame_type),
+            show_frame: reader.read_bit()?,
+        })
+    }
+}
+
+/// The av1C record, used as the codec private data of AV1 in MP4 and Matroska, and as the `csd-0` buffer of `video/av01`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Av1CodecConfigurationRecord {
+    pub seq_profile: u8,
+    pub seq_level_idx: u8,
+    pub seq_tier: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub mono_chrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay: Option<u8>,
+    /// The sequence header OBU, and possibly metadata OBUs
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1CodecConfigurationRecord {
+    /// Builds a record from a temporal unit containing a sequence header OBU, like the first packet of an encoder.
+    ///
+    /// Returns `None` if there's no valid sequence header
+    pub fn from_temporal_unit(data: &[u8]) -> Option<Self> {
+        let obu = av1_obus(data).find(|obu| obu.obu_type == Av1ObuType::SequenceHeader)?;
+        let header = Av1SequenceHeader::parse(obu.payload)?;
+
+        Some(Self {
+            seq_profile: header.seq_profile,
+            seq_level_idx: header.seq_level_idx,
+            seq_tier: header.seq_tier,
+            high_bitdepth: header.bit_depth > 8,
+            twelve_bit: header.bit_depth == 12,
+            mono_chrome: header.mono_chrome,
+            chroma_subsampling_x: header.chroma_subsampling_x,
+            chroma_subsampling_y: header.chroma_subsampling_y,
+            chroma_sample_position: header.chroma_sample_position,
+            initial_presentation_delay: None,
+            config_obus: obu.data.to_vec(),
+        })
+    }
+
+    /// Parses an av1C record
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data.get(..4)?);
+
+        // marker and version
+        if reader.read_u8()? != 0x81 {
+            return None;
+        }
+
+        let seq_profile = reader.read_bits(3)? as u8;
+        let seq_level_idx = reader.read_bits(5)? as u8;
+        let seq_tier = reader.read_bit()?;
+        let high_bitdepth = reader.read_bit()?;
+        let twelve_bit = reader.read_bit()?;
+        let mono_chrome = reader.read_bit()?;
+        let chroma_subsampling_x = reader.read_bit()?;
+        let chroma_subsampling_y = reader.read_bit()?;
+        let chroma_sample_position = reader.read_bits(2)? as u8;
+        reader.skip_bits(3)?;
+        let delay_present = reader.read_bit()?;
+        let delay = reader.read_bits(4)? as u8;
+
+        Some(Self {
+            seq_profile,
+            seq_level_idx,
+            seq_tier,
+            high_bitdepth,
+            twelve_bit,
+            mono_chrome,
+            chroma_subsampling_x,
+            chroma_subsampling_y,
+            chroma_sample_position,
+            initial_presentation_delay: delay_present.then_some(delay + 1),
+            config_obus: data[4..].to_vec(),
+        })
+    }
+
+    /// Serializes the record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(4 + self.config_obus.len());
+
+        output.push(0x81);
+        output.push((self.seq_profile << 5) | (self.seq_level_idx & 0x1f));
+        output.push(
+            ((self.seq_tier as u8) << 7)
+                | ((self.high_bitdepth as u8) << 6)
+                | ((self.twelve_bit as u8) << 5)
+                | ((self.mono_chrome as u8) << 4)
+                | ((self.chroma_subsampling_x as u8) << 3)
+                | ((self.chroma_subsampling_y as u8) << 2)
+                | (self.chroma_sample_position & 0x03),
+        );
+        output.push(match self.initial_presentation_delay {
+            Some(delay) => 0x10 | (delay.clamp(1, 16) - 1),
+            None => 0,
+        });
+        output.extend_from_slice(&self.config_obus);
+
+        output
+    }
+
+    /// Parses the sequence header in the config OBUs
+    pub fn parse_sequence_header(&self) -> Option<Av1SequenceHeader> {
+        Av1SequenceHeader::find(&self.config_obus)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a `video/av01` decoder.
+    ///
+    /// The dimensions are the largest ones from the sequence header, the actual frames can be smaller
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let header = self.parse_sequence_header()?;
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "video/av01");
+        format.set_i32("width", header.max_frame_width as i32);
+        format.set_i32("height", header.max_frame_height as i32);
+        format.set_buffer("csd-0", &self.to_bytes());
+
+        if let Some(frame_rate) = header.frame_rate() {
+            format.set_f32("frame-rate", frame_rate as f32);
+        }
+
+        if let Some(profile) = header.media_codec_profile() {
+            format.set_i32("profile", profile);
+        }
+
+        if let Some(level) = header.media_codec_level() {
+            format.set_i32("level", level);
+        }
+
+        // The range is always signalled, the rest is left out when unspecified
+        header.color.apply_to(&mut format);
+
+        Some(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The payload of a sequence header OBU for 1080p 8 bit 4:2:0 in BT.709, level 4.0
+    const SEQUENCE_HEADER: [u8; 14] = [
+        0x00, 0x00, 0x00, 0x42, 0xab, 0xbf, 0xc3, 0x73, 0xff, 0xe6, 0x40, 0x40, 0x40, 0x41,
+    ];
+
+    /// The payload of a sequence header OBU for 2160p 10 bit PQ in full range, level 5.0 high tier, with timing info
+    /// at 59.94 fps and an initial display delay
+    const HDR_SEQUENCE_HEADER: [u8; 24] = [
+        0x04, 0x00, 0x00, 0x0f, 0xa4, 0x00, 0x03, 0xa9, 0x80, 0x80, 0x00, 0x19, 0xcd, 0xdf, 0x7f,
+        0xc3, 0x79, 0xff, 0xf3, 0xa1, 0x22, 0x01, 0x34, 0x80,
+    ];
+
+    /// A temporal unit: a temporal delimiter, the sequence header, then a key frame with an extension header
+    fn temporal_unit(sequence_header: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x12, 0x00, 0x0a, sequence_header.len() as u8];
+        data.extend_from_slice(sequence_header);
+        data.extend_from_slice(&[0x36, 0x20, 0x03, 0x10, 0xaa, 0xbb]);
+        data
+    }
+
+    #[test]
+    fn obus() {
+        let data = temporal_unit(&SEQUENCE_HEADER);
+        let obus: Vec<_> = av1_obus(&data).collect();
+        assert_eq!(obus.len(), 3);
+
+        assert_eq!(obus[0].obu_type, Av1ObuType::TemporalDelimiter);
+        assert!(obus[0].payload.is_empty());
+        assert_eq!(obus[1].obu_type, Av1ObuType::SequenceHeader);
+        assert_eq!(obus[1].payload, SEQUENCE_HEADER);
+        assert_eq!(obus[1].data.len(), SEQUENCE_HEADER.len() + 2);
+        assert_eq!(obus[2].obu_type, Av1ObuType::Frame);
+        assert_eq!((obus[2].temporal_id, obus[2].spatial_id), (1, 0));
+        assert_eq!(obus[2].payload, [0x10, 0xaa, 0xbb]);
+
+        // A size past the end, and the forbidden bit
+        assert_eq!(av1_obus(&data[..data.len() - 1]).count(), 2);
+        assert_eq!(av1_obus(&[0x12, 0x00, 0x92, 0x00]).count(), 1);
+
+        // Without a size field, the OBU takes the rest
+        let obus: Vec<_> = av1_obus(&[0x30, 0x10, 0xaa]).collect();
+        assert_eq!(obus.len(), 1);
+        assert_eq!(obus[0].payload, [0x10, 0xaa]);
+
+        assert_eq!(read_leb128(&[0xe5, 0x8e, 0x26]), Some((624_485, 3)));
+        assert_eq!(read_leb128(&[0x80; 8]), None);
+    }
+
+    #[test]
+    fn sequence_header() {
+        let header = Av1SequenceHeader::parse(&SEQUENCE_HEADER).unwrap();
+        assert_eq!(header.seq_profile, 0);
+        assert!(!header.still_picture && !header.reduced_still_picture_header);
+        assert_eq!((header.seq_level_idx, header.seq_tier), (8, false));
+        assert_eq!(header.initial_display_delay, None);
+        assert_eq!(
+            (header.max_frame_width, header.max_frame_height),
+            (1920, 1080)
+        );
+        assert_eq!(header.frame_rate(), None);
+        assert!(header.enable_order_hint);
+        assert_eq!(header.order_hint_bits, 7);
+        assert_eq!(header.bit_depth, 8);
+        assert!(!header.mono_chrome);
+        assert!(header.chroma_subsampling_x && header.chroma_subsampling_y);
+        assert!(header.color_description_present);
+        let color = VideoColorInfo {
+            full_range: false,
+            primaries: 1,
+            transfer: 1,
+            matrix: 1,
+        };
+        assert_eq!(header.color, color);
+        assert_eq!(header.media_codec_profile(), Some(0x1));
+        assert_eq!(header.media_codec_level(), Some(0x100));
+
+        let header = Av1SequenceHeader::parse(&HDR_SEQUENCE_HEADER).unwrap();
+        assert_eq!((header.seq_level_idx, header.seq_tier), (12, true));
+        assert_eq!(header.initial_display_delay, Some(10));
+        assert_eq!(
+            (header.max_frame_width, header.max_frame_height),
+            (3840, 2160)
+        );
+        assert_eq!(header.frame_rate(), Some(60000.0 / 1001.0));
+        assert_eq!(header.bit_depth, 10);
+        assert_eq!(header.chroma_sample_position, 1);
+        let color = VideoColorInfo {
+            full_range: true,
+            primaries: 9,
+            transfer: 16,
+            matrix: 9,
+        };
+        assert_eq!(header.color, color);
+        assert_eq!(header.media_codec_profile(), Some(0x1000));
+
+        assert_eq!(
+            Av1SequenceHeader::find(&temporal_unit(&HDR_SEQUENCE_HEADER)),
+            Some(header)
+        );
+    }
+
+    #[test]
+    fn truncated_sequence_header() {
+        // The color config ends in the second to last byte
+        for end in 0..SEQUENCE_HEADER.len() - 1 {
+            assert_eq!(Av1SequenceHeader::parse(&SEQUENCE_HEADER[..end]), None);
+        }
+        for end in 0..HDR_SEQUENCE_HEADER.len() - 1 {
+            assert_eq!(Av1SequenceHeader::parse(&HDR_SEQUENCE_HEADER[..end]), None);
+        }
+
+        assert!(Av1CodecConfigurationRecord::from_temporal_unit(&[0x12, 0x00]).is_none());
+    }
+
+    #[test]
+    fn frame_header() {
+        let sequence_header = Av1SequenceHeader::parse(&SEQUENCE_HEADER).unwrap();
+        let parse = |payload: &[u8]| Av1FrameHeader::parse(payload, &sequence_header);
+
+        let key = parse(&[0x10]).unwrap();
+        assert_eq!(key.frame_type, Some(Av1FrameType::Key));
+        assert!(key.show_frame && !key.show_existing_frame);
+
+        let hidden = parse(&[0x20]).unwrap();
+        assert_eq!(hidden.frame_type, Some(Av1FrameType::Inter));
+        assert!(!hidden.show_frame);
+
+        let existing = parse(&[0xb0]).unwrap();
+        assert!(existing.show_existing_frame);
+        assert_eq!(
+            (existing.frame_to_show_map_idx, existing.frame_type),
+            (3, None)
+        );
+
+        assert_eq!(parse(&[]), None);
+    }
+
+    #[test]
+    fn record() {
+        let record =
+            Av1CodecConfigurationRecord::from_temporal_unit(&temporal_unit(&SEQUENCE_HEADER))
+                .unwrap();
+        let bytes = record.to_bytes();
+        assert_eq!(bytes[..4], [0x81, 0x08, 0x0c, 0x00]);
+        assert_eq!(bytes[4..6], [0x0a, SEQUENCE_HEADER.len() as u8]);
+        assert_eq!(bytes[6..], SEQUENCE_HEADER);
+
+        assert_eq!(
+            Av1CodecConfigurationRecord::parse(&bytes),
+            Some(record.clone())
+        );
+        assert_eq!(
+            record.parse_sequence_header(),
+            Av1SequenceHeader::parse(&SEQUENCE_HEADER)
+        );
+
+        let mut record =
+            Av1CodecConfigurationRecord::from_temporal_unit(&temporal_unit(&HDR_SEQUENCE_HEADER))
+                .unwrap();
+        assert!(record.seq_tier && record.high_bitdepth && !record.twelve_bit);
+        assert_eq!(record.chroma_sample_position, 1);
+
+        record.initial_presentation_delay = Some(10);
+        let bytes = record.to_bytes();
+        assert_eq!(bytes[..4], [0x81, 0x0c, 0xcd, 0x19]);
+        assert_eq!(Av1CodecConfigurationRecord::parse(&bytes), Some(record));
+
+        // Truncated, or not a version 1 record
+        assert_eq!(Av1CodecConfigurationRecord::parse(&bytes[..3]), None);
+        assert_eq!(
+            Av1CodecConfigurationRecord::parse(&[0x80, 0x08, 0x0c, 0x00]),
+            None
+        );
+    }
+}
@@ -0,0 +1,238 @@
+/// Reads bits out of a byte slice, most significant bit first.
+///
+/// All reads return `None` when they run past the end of the data
+#[derive(Debug, Clone)]
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// The number of bits left to read
+    pub fn bits_left(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        let byte = *self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+
+        Some(bit == 1)
+    }
+
+    /// Reads `count` bits (up to 32) as an unsigned integer
+    pub fn read_bits(&mut self, count: u32) -> Option<u32> {
+        debug_assert!(count <= 32);
+        self.read_bits_u64(count).map(|value| value as u32)
+    }
+
+    /// Reads `count` bits (up to 64) as an unsigned integer
+    pub fn read_bits_u64(&mut self, count: u32) -> Option<u64> {
+        debug_assert!(count <= 64);
+
+        if self.bits_left() < count as usize {
+            return None;
+        }
+
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+
+        Some(value)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_bits(8).map(|value| value as u8)
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        self.read_bits(16).map(|value| value as u16)
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Option<()> {
+        if self.bits_left() < count {
+            return None;
+        }
+
+        self.position += count;
+        Some(())
+    }
+
+    /// Reads an unsigned Exp-Golomb code, `ue(v)`
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+
+        if leading_zeros == 0 {
+            return Some(0);
+        }
+
+        let suffix = self.read_bits(leading_zeros)? as u64;
+        u32::try_from((1u64 << leading_zeros) - 1 + suffix).ok()
+    }
+
+    /// Reads a signed Exp-Golomb code, `se(v)`
+    pub fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()? as i64;
+        let value = if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        };
+
+        Some(value as i32)
+    }
+
+    /// Returns whether there's more data before the `rbsp_trailing_bits`
+    pub fn more_rbsp_data(&self) -> bool {
+        // The last bit set in the data is the stop bit
+        let last = match self.data.iter().rposition(|&byte| byte != 0) {
+            Some(last) => last,
+            None => return false,
+        };
+        let stop_bit = last * 8 + 7 - self.data[last].trailing_zeros() as usize;
+
+        self.position < stop_bit
+    }
+}
+
+/// Writes bits into a byte vector, most significant bit first.
+///
+/// The last byte is padded with zeros
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BitWriter {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        if self.position & 7 == 0 {
+            self.data.push(0);
+        }
+
+        if bit {
+            let last = self.data.len() - 1;
+            self.data[last] |= 1 << (7 - self.position % 8);
+        }
+
+        self.position += 1;
+    }
+
+    /// Writes the `count` lowest bits of `value`
+    pub fn write_bits(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            self.write_bit((value >> bit) & 1 == 1);
+        }
+    }
+
+    /// Writes an Exp-Golomb coded value, to build parameter sets in tests
+    #[cfg(test)]
+    pub fn write_ue(&mut self, value: u32) {
+        let value = value as u64 + 1;
+        let bits = 64 - value.leading_zeros();
+        self.write_bits(0, bits - 1);
+        self.write_bits(value, bits);
+    }
+
+    /// Writes a signed Exp-Golomb coded value, to build slice headers in tests
+    #[cfg(test)]
+    pub fn write_se(&mut self, value: i32) {
+        let value = if value > 0 { value * 2 - 1 } else { -value * 2 };
+        self.write_ue(value as u32);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads big-endian integers out of a byte slice, like the fields of MP4 boxes.
+///
+/// All reads return `None` when they run past the end of the data
+#[derive(Debug, Clone)]
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// The number of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn skip(&mut self, count: usize) -> Option<()> {
+        self.read_bytes(count).map(|_| ())
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.remaining() < count {
+            return None;
+        }
+
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+
+        Some(bytes)
+    }
+
+    /// Returns everything that hasn't been read yet
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position..];
+        self.position = self.data.len();
+
+        rest
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.read_bytes(N).map(|bytes| bytes.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_array::<1>().map(|bytes| bytes[0])
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        self.read_array().map(u16::from_be_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        self.read_array().map(u32::from_be_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Option<u64> {
+        self.read_array().map(u64::from_be_bytes)
+    }
+
+    pub fn read_i16(&mut self) -> Option<i16> {
+        self.read_array().map(i16::from_be_bytes)
+    }
+
+    pub fn read_i32(&mut self) -> Option<i32> {
+        self.read_array().map(i32::from_be_bytes)
+    }
+
+    pub fn read_i64(&mut self) -> Option<i64> {
+        self.read_array().map(i64::from_be_bytes)
+    }
+}
@@ -0,0 +1,929 @@
+use crate::{
+    sei_messages, unescape_rbsp, AvcNalType, HevcNalType, NalFormat,
+    SEI_USER_DATA_REGISTERED_ITU_T_T35,
+};
+#[cfg(target_os = "android")]
+use crate::{CodecInputBuffer, MediaFormat};
+
+/// The ATSC A/53 `user_identifier` of caption data
+const A53_USER_IDENTIFIER: &[u8] = b"GA94";
+
+/// The A/53 `user_data_type_code` of `cc_data()`
+const A53_CC_DATA: u8 = 0x03;
+
+/// How many packets are kept to put the caption data back in presentation order
+const REORDER_DEPTH: usize = 8;
+
+const CEA608_ROWS: usize = 15;
+const CEA608_COLUMNS: usize = 32;
+
+/// A `cc_data_pkt` of A/53 (or CEA-708) caption data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CcData {
+    pub valid: bool,
+    /// 0 and 1 are CEA-608 byte pairs of the first and second field, 2 and 3 are DTVCC (CEA-708) packet data and packet start
+    pub cc_type: u8,
+    pub data: [u8; 2],
+}
+
+/// Parses the `cc_data()` of a `user_data_registered_itu_t_t35` SEI payload with ATSC A/53 captions.
+///
+/// Returns `None` if the payload is something else
+pub fn a53_cc_data(payload: &[u8]) -> Option<Vec<CcData>> {
+    // itu_t_t35_country_code (United States) and itu_t_t35_provider_code (ATSC)
+    if payload.get(..3)? != [0xb5, 0x00, 0x31] {
+        return None;
+    }
+
+    if payload.get(3..7)? != A53_USER_IDENTIFIER || *payload.get(7)? != A53_CC_DATA {
+        return None;
+    }
+
+    let flags = *payload.get(8)?;
+    // process_cc_data_flag
+    if flags & 0x40 == 0 {
+        return Some(vec![]);
+    }
+
+    // cc_count, then a reserved byte (em_data)
+    let count = (flags & 0x1f) as usize;
+    let packets = payload.get(10..10 + count * 3)?;
+
+    Some(
+        packets
+            .chunks_exact(3)
+            .map(|packet| CcData {
+                valid: packet[0] & 0x04 != 0,
+                cc_type: packet[0] & 0x03,
+                data: [packet[1], packet[2]],
+            })
+            .collect(),
+    )
+}
+
+/// Returns the caption data of the SEI NAL units of an H.264 or HEVC packet
+fn packet_cc_data(data: &[u8], hevc: bool, nal_format: NalFormat) -> Vec<CcData> {
+    let mut output = vec![];
+
+    for nal in nal_format.nal_units(data) {
+        let rbsp = match hevc {
+            false if AvcNalType::from_header(nal[0]) == AvcNalType::Sei => unescape_rbsp(&nal[1..]),
+            true if nal.len() > 2 && HevcNalType::from_header(nal[0]) == HevcNalType::PrefixSei => {
+                unescape_rbsp(&nal[2..])
+            }
+            _ => continue,
+        };
+
+        for message in sei_messages(&rbsp) {
+            if message.payload_type != SEI_USER_DATA_REGISTERED_ITU_T_T35 {
+                continue;
+            }
+
+            if let Some(cc_data) = a53_cc_data(message.payload) {
+                output.extend(cc_data);
+            }
+        }
+    }
+
+    output
+}
+
+/// A caption displayed from `start_us` to `end_us`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptionCue {
+    /// The CEA-608 channel, from 1 (CC1) to 4 (CC4)
+    pub channel: u8,
+    pub start_us: i64,
+    pub end_us: i64,
+    /// The rows of the caption, separated by new lines
+    pub text: String,
+}
+
+/// How the captions of a CEA-608 channel are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaptionMode {
+    /// Captions are loaded off screen and displayed at once
+    PopOn,
+    /// Captions are written directly on screen
+    PaintOn,
+    /// Captions scroll up a window of the given number of rows
+    RollUp(usize),
+}
+
+type Screen = [[char; CEA608_COLUMNS]; CEA608_ROWS];
+
+/// The state of one of the two data channels of a CEA-608 field
+#[derive(Debug)]
+struct Cea608Channel {
+    number: u8,
+    mode: CaptionMode,
+    displayed: Screen,
+    non_displayed: Screen,
+    row: usize,
+    column: usize,
+    /// Text mode (TR and RTD) is not captioning, and is dropped
+    text_mode: bool,
+    open_cue: Option<(i64, String)>,
+    cues: Vec<CaptionCue>,
+}
+
+impl Cea608Channel {
+    fn new(number: u8) -> Self {
+        Self {
+            number,
+            mode: CaptionMode::PopOn,
+            displayed: [['\0'; CEA608_COLUMNS]; CEA608_ROWS],
+            non_displayed: [['\0'; CEA608_COLUMNS]; CEA608_ROWS],
+            row: CEA608_ROWS - 1,
+            column: 0,
+            text_mode: false,
+            open_cue: None,
+            cues: vec![],
+        }
+    }
+
+    /// Whether the characters are written to the displayed memory
+    fn writes_displayed(&self) -> bool {
+        self.mode != CaptionMode::PopOn
+    }
+
+    fn screen(&mut self) -> &mut Screen {
+        if self.writes_displayed() {
+            &mut self.displayed
+        } else {
+            &mut self.non_displayed
+        }
+    }
+
+    /// Writes a character at the cursor. Returns whether the displayed caption changed
+    fn write_char(&mut self, character: char) -> bool {
+        if self.text_mode {
+            return false;
+        }
+
+        let (row, column) = (self.row, self.column);
+        self.screen()[row][column] = character;
+        self.column = (column + 1).min(CEA608_COLUMNS - 1);
+
+        self.mode == CaptionMode::PaintOn
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            let (row, column) = (self.row, self.column);
+            self.screen()[row][column] = '\0';
+        }
+    }
+
+    fn preamble(&mut self, row: usize, indent: usize) {
+        if let CaptionMode::RollUp(rows) = self.mode {
+            // The roll-up window moves with its base row
+            let content = self.displayed;
+            self.displayed = [['\0'; CEA608_COLUMNS]; CEA608_ROWS];
+            for offset in 0..rows.min(row + 1) {
+                self.displayed[row - offset] = content[self.row.saturating_sub(offset)];
+            }
+        }
+
+        self.row = row;
+        self.column = indent;
+    }
+
+    fn roll_up(&mut self, rows: usize) {
+        let top = (self.row + 1).saturating_sub(rows);
+
+        for row in 0..CEA608_ROWS {
+            if row < top || row > self.row {
+                self.displayed[row] = ['\0'; CEA608_COLUMNS];
+            } else if row < self.row {
+                self.displayed[row] = self.displayed[row + 1];
+            }
+        }
+
+        self.displayed[self.row] = ['\0'; CEA608_COLUMNS];
+        self.column = 0;
+    }
+
+    /// Handles a miscellaneous control code. Returns whether the displayed caption changed
+    fn control(&mut self, code: u8) -> bool {
+        let (mode, text_mode) = match code {
+            // RCL, resume caption loading
+            0x20 => (CaptionMode::PopOn, false),
+            // BS, backspace
+            0x21 => {
+                self.backspace();
+                return self.mode == CaptionMode::PaintOn;
+            }
+            // DER, delete to end of row
+            0x24 => {
+                let (row, column) = (self.row, self.column);
+                self.screen()[row][column..].fill('\0');
+                return self.mode == CaptionMode::PaintOn;
+            }
+            // RU2, RU3 and RU4, roll-up captions
+            0x25..=0x27 => {
+                let rows = (code - 0x23) as usize;
+                if !matches!(self.mode, CaptionMode::RollUp(_)) {
+                    self.displayed = [['\0'; CEA608_COLUMNS]; CEA608_ROWS];
+                    self.non_displayed = [['\0'; CEA608_COLUMNS]; CEA608_ROWS];
+                    self.row = CEA608_ROWS - 1;
+                    self.column = 0;
+                }
+
+                self.mode = CaptionMode::RollUp(rows);
+                self.text_mode = false;
+                return true;
+            }
+            // RDC, resume direct captioning
+            0x29 => (CaptionMode::PaintOn, false),
+            // TR and RTD, text restart and resume text display
+            0x2a | 0x2b => (self.mode, true),
+            // EDM, erase displayed memory
+            0x2c => {
+                self.displayed = [['\0'; CEA608_COLUMNS]; CEA608_ROWS];
+                return true;
+            }
+            // CR, carriage return
+            0x2d => {
+                if let CaptionMode::RollUp(rows) = self.mode {
+                    if !self.text_mode {
+                        self.roll_up(rows);
+                        return true;
+                    }
+                }
+
+                return false;
+            }
+            // ENM, erase non-displayed memory
+            0x2e => {
+                self.non_displayed = [['\0'; CEA608_COLUMNS]; CEA608_ROWS];
+                return false;
+            }
+            // EOC, end of caption
+            0x2f => {
+                std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.mode = CaptionMode::PopOn;
+                self.text_mode = false;
+                return true;
+            }
+            // AOF, AON and FON aren't used
+            _ => return false,
+        };
+
+        self.mode = mode;
+        self.text_mode = text_mode;
+        false
+    }
+
+    /// The text of the displayed memory
+    fn text(&self) -> String {
+        let rows: Vec<String> = self
+            .displayed
+            .iter()
+            .map(|row| {
+                let row: String = row
+                    .iter()
+                    .map(|&character| if character == '\0' { ' ' } else { character })
+                    .collect();
+                row.trim().to_string()
+            })
+            .filter(|row| !row.is_empty())
+            .collect();
+
+        rows.join("\n")
+    }
+
+    /// Closes the current cue and opens a new one if the displayed caption is different
+    fn update(&mut self, pts_us: i64) {
+        let text = self.text();
+        if self
+            .open_cue
+            .as_ref()
+            .is_some_and(|(_, open)| *open == text)
+        {
+            return;
+        }
+
+        self.close(pts_us);
+        if !text.is_empty() {
+            self.open_cue = Some((pts_us, text));
+        }
+    }
+
+    fn close(&mut self, pts_us: i64) {
+        if let Some((start_us, text)) = self.open_cue.take() {
+            // Captions replaced in the same packet were never on screen
+            if pts_us > start_us {
+                self.cues.push(CaptionCue {
+                    channel: self.number,
+                    start_us,
+                    end_us: pts_us,
+                    text,
+                });
+            }
+        }
+    }
+}
+
+/// A CEA-608 decoder for one field, which carries two caption channels (CC1 and CC2, or CC3 and CC4).
+///
+/// Roll-up captions are updated at each carriage return, so a row shows up once it's complete
+#[derive(Debug)]
+pub struct Cea608Decoder {
+    channels: [Cea608Channel; 2],
+    current: usize,
+    last_control: Option<[u8; 2]>,
+    /// Extended data services are only in the second field, and aren't captions
+    xds: bool,
+}
+
+impl Cea608Decoder {
+    /// Creates a decoder for the first (1) or second (2) field
+    pub fn new(field: u8) -> Self {
+        let first = if field == 2 { 3 } else { 1 };
+
+        Self {
+            channels: [Cea608Channel::new(first), Cea608Channel::new(first + 1)],
+            current: 0,
+            last_control: None,
+            xds: false,
+        }
+    }
+
+    /// Decodes a byte pair, with its parity bits, that is presented at `pts_us`
+    pub fn decode(&mut self, data: [u8; 2], pts_us: i64) {
+        let (first, second) = match (strip_parity(data[0]), strip_parity(data[1])) {
+            (Some(first), Some(second)) => (first, second),
+            _ => return,
+        };
+
+        // Padding
+        if first == 0 && second == 0 {
+            return;
+        }
+
+        if (0x01..=0x0f).contains(&first) {
+            // The start (or the end, with 0x0f) of an XDS packet
+            self.xds = first != 0x0f;
+            self.last_control = None;
+            return;
+        }
+
+        if (0x10..=0x1f).contains(&first) {
+            self.xds = false;
+
+            // Control codes are sent twice, in case one gets lost
+            if self.last_control == Some([first, second]) {
+                self.last_control = None;
+                return;
+            }
+
+            self.last_control = Some([first, second]);
+            self.current = ((first & 0x08) >> 3) as usize;
+
+            let changed = self.control(first & 0xf7, second);
+            if changed {
+                self.channels[self.current].update(pts_us);
+            }
+
+            return;
+        }
+
+        self.last_control = None;
+        if self.xds || first < 0x20 {
+            return;
+        }
+
+        let channel = &mut self.channels[self.current];
+        let mut changed = channel.write_char(basic_char(first));
+        if second >= 0x20 {
+            changed |= channel.write_char(basic_char(second));
+        }
+
+        if changed {
+            channel.update(pts_us);
+        }
+    }
+
+    /// Handles a control code of the current channel, with the channel bit cleared
+    fn control(&mut self, first: u8, second: u8) -> bool {
+        let channel = &mut self.channels[self.current];
+
+        match (first, second) {
+            // Miscellaneous control codes. Some encoders use 0x15 in the second field
+            (0x14 | 0x15, 0x20..=0x2f) => channel.control(second),
+            // Tab offsets
+            (0x17, 0x21..=0x23) => {
+                channel.column =
+                    (channel.column + (second - 0x20) as usize).min(CEA608_COLUMNS - 1);
+                false
+            }
+            // Mid-row style codes show up as a space
+            (0x11, 0x20..=0x2f) => channel.write_char(' '),
+            (0x11, 0x30..=0x3f) => channel.write_char(SPECIAL_CHARS[(second - 0x30) as usize]),
+            // Extended characters replace the basic character sent before them
+            (0x12 | 0x13, 0x20..=0x3f) => {
+                let table = if first == 0x12 {
+                    &EXTENDED_CHARS_SPANISH_FRENCH
+                } else {
+                    &EXTENDED_CHARS_PORTUGUESE_GERMAN
+                };
+
+                if !channel.text_mode {
+                    channel.backspace();
+                }
+                channel.write_char(table[(second - 0x20) as usize])
+            }
+            (_, 0x40..=0x7f) => {
+                if let Some(row) = preamble_row(first, second) {
+                    // Indent codes are in steps of 4 columns
+                    let indent = if second & 0x10 != 0 {
+                        ((second & 0x0e) >> 1) as usize * 4
+                    } else {
+                        0
+                    };
+                    channel.preamble(row, indent);
+                }
+
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Closes the open cues at `pts_us`
+    pub fn flush(&mut self, pts_us: i64) {
+        for channel in &mut self.channels {
+            channel.close(pts_us);
+        }
+    }
+
+    /// Returns the cues that are finished, leaving the displayed ones
+    pub fn take_cues(&mut self) -> Vec<CaptionCue> {
+        let mut cues: Vec<CaptionCue> = self
+            .channels
+            .iter_mut()
+            .flat_map(|channel| channel.cues.drain(..))
+            .collect();
+
+        cues.sort_by_key(|cue| cue.start_us);
+        cues
+    }
+
+    /// The caption of a channel that is on screen, with its start time
+    fn open_cue(&self, channel: u8) -> Option<(i64, &str)> {
+        self.channels
+            .iter()
+            .find(|state| state.number == channel)?
+            .open_cue
+            .as_ref()
+            .map(|(start_us, text)| (*start_us, text.as_str()))
+    }
+}
+
+/// Checks the odd parity of a CEA-608 byte, and removes the parity bit
+fn strip_parity(byte: u8) -> Option<u8> {
+    if byte.count_ones() & 1 == 1 {
+        Some(byte & 0x7f)
+    } else {
+        None
+    }
+}
+
+/// The row (0 to 14) of a preamble address code
+fn preamble_row(first: u8, second: u8) -> Option<usize> {
+    let low = (second & 0x20 != 0) as usize;
+
+    match first {
+        0x10 if low == 0 => Some(10),
+        0x11 => Some(low),
+        0x12 => Some(2 + low),
+        0x13 => Some(11 + low),
+        0x14 => Some(13 + low),
+        0x15 => Some(4 + low),
+        0x16 => Some(6 + low),
+        0x17 => Some(8 + low),
+        _ => None,
+    }
+}
+
+/// A character of the basic CEA-608 set, which is ASCII with a few exceptions
+fn basic_char(byte: u8) -> char {
+    match byte {
+        0x2a => 'á',
+        0x5c => 'é',
+        0x5e => 'í',
+        0x5f => 'ó',
+        0x60 => 'ú',
+        0x7b => 'ç',
+        0x7c => '÷',
+        0x7d => 'Ñ',
+        0x7e => 'ñ',
+        0x7f => '█',
+        byte => byte as char,
+    }
+}
+
+const SPECIAL_CHARS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', ' ', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_CHARS_SPANISH_FRENCH: [char; 32] = [
+    'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '\'', '—', '©', '℠', '•', '“', '”', 'À', 'Â', 'Ç',
+    'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+];
+
+const EXTENDED_CHARS_PORTUGUESE_GERMAN: [char; 32] = [
+    'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~', 'Ä', 'ä', 'Ö',
+    'ö', 'ß', '¥', '¤', '¦', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+];
+
+/// A service block of a DTVCC (CEA-708) packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtvccServiceBlock {
+    /// The caption service, 1 being the primary one
+    pub service_number: u8,
+    pub data: Vec<u8>,
+}
+
+/// A DTVCC (CEA-708) caption packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtvccPacket {
+    pub pts_us: i64,
+    pub sequence_number: u8,
+    pub service_blocks: Vec<DtvccServiceBlock>,
+}
+
+impl DtvccPacket {
+    /// Parses a packet, including its header byte
+    pub fn parse(data: &[u8], pts_us: i64) -> Option<Self> {
+        let header = *data.first()?;
+        let mut service_blocks = vec![];
+        let mut position = 1;
+
+        while let Some(&block_header) = data.get(position) {
+            let mut service_number = block_header >> 5;
+            let size = (block_header & 0x1f) as usize;
+            position += 1;
+
+            // A null service block ends the packet
+            if service_number == 0 {
+                break;
+            }
+
+            if service_number == 7 {
+                service_number = *data.get(position)? & 0x3f;
+                position += 1;
+            }
+
+            service_blocks.push(DtvccServiceBlock {
+                service_number,
+                data: data.get(position..position + size)?.to_vec(),
+            });
+            position += size;
+        }
+
+        Some(Self {
+            pts_us,
+            sequence_number: header >> 6,
+            service_blocks,
+        })
+    }
+
+    /// The size of a packet, including its header, from its header byte
+    fn size(header: u8) -> usize {
+        match header & 0x3f {
+            0 => 128,
+            code => code as usize * 2,
+        }
+    }
+}
+
+/// Extracts the closed captions of H.264 and HEVC packets, from the A/53 caption data of their SEI NAL units.
+///
+/// Packets are expected in decode order and in Annex-B, as they come from `MediaExtractor`. A few of them are kept to put the captions back in presentation order.
+/// CEA-608 captions are decoded into cues, while CEA-708 captions are returned as DTVCC packets
+#[derive(Debug)]
+pub struct CaptionExtractor {
+    hevc: bool,
+    nal_format: NalFormat,
+    pending: Vec<(i64, Vec<CcData>)>,
+    fields: [Cea608Decoder; 2],
+    dtvcc: Vec<u8>,
+    dtvcc_pts_us: i64,
+    dtvcc_packets: Vec<DtvccPacket>,
+    cues: Vec<CaptionCue>,
+    last_pts_us: i64,
+}
+
+impl CaptionExtractor {
+    /// Creates an extractor for `video/avc` or `video/hevc` packets
+    pub fn new(mime: &str) -> Option<Self> {
+        let hevc = match mime {
+            "video/avc" => false,
+            "video/hevc" => true,
+            _ => return None,
+        };
+
+        Some(Self {
+            hevc,
+            nal_format: NalFormat::AnnexB,
+            pending: vec![],
+            fields: [Cea608Decoder::new(1), Cea608Decoder::new(2)],
+            dtvcc: vec![],
+            dtvcc_pts_us: 0,
+            dtvcc_packets: vec![],
+            cues: vec![],
+            last_pts_us: 0,
+        })
+    }
+
+    /// Sets how the NAL units of the packets are delimited. The default is Annex-B
+    pub fn with_nal_format(mut self, nal_format: NalFormat) -> Self {
+        self.nal_format = nal_format;
+        self
+    }
+
+    /// Creates an extractor for the track of a format
+    #[cfg(target_os = "android")]
+    pub fn from_format(format: &MediaFormat) -> Option<Self> {
+        Self::new(&format.get_string("mime")?)
+    }
+
+    /// Extracts the captions of a packet presented at `pts_us`
+    pub fn push_packet(&mut self, data: &[u8], pts_us: i64) {
+        let cc_data = packet_cc_data(data, self.hevc, self.nal_format);
+        self.pending.push((pts_us, cc_data));
+
+        if self.pending.len() > REORDER_DEPTH {
+            self.process_next();
+        }
+    }
+
+    /// Extracts the captions of a packet that was read into a decoder input buffer, like with `MediaExtractor::read_next`
+    #[cfg(target_os = "android")]
+    pub fn push_input(&mut self, buffer: &CodecInputBuffer) {
+        self.push_packet(buffer.data(), buffer.time() as i64);
+    }
+
+    /// Processes the packets that are still pending, and closes the displayed captions.
+    ///
+    /// This is meant for the end of the stream
+    pub fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            self.process_next();
+        }
+
+        for field in &mut self.fields {
+            field.flush(self.last_pts_us);
+        }
+
+        self.collect_cues();
+    }
+
+    /// Processes the pending packet with the lowest presentation time
+    fn process_next(&mut self) {
+        let index = match self
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (pts_us, _))| *pts_us)
+        {
+            Some((index, _)) => index,
+            None => return,
+        };
+
+        let (pts_us, cc_data) = self.pending.remove(index);
+        self.last_pts_us = pts_us;
+
+        for cc in cc_data {
+            if !cc.valid {
+                continue;
+            }
+
+            match cc.cc_type {
+                0 | 1 => self.fields[cc.cc_type as usize].decode(cc.data, pts_us),
+                // Packet data without the start of its packet is dropped
+                2 if !self.dtvcc.is_empty() => self.dtvcc.extend_from_slice(&cc.data),
+                2 => {}
+                _ => {
+                    self.finish_dtvcc();
+                    self.dtvcc.extend_from_slice(&cc.data);
+                    self.dtvcc_pts_us = pts_us;
+                }
+            }
+
+            if self
+                .dtvcc
+                .first()
+                .is_some_and(|&header| self.dtvcc.len() >= DtvccPacket::size(header))
+            {
+                self.finish_dtvcc();
+            }
+        }
+
+        self.collect_cues();
+    }
+
+    /// Parses the DTVCC packet being assembled, if it's complete
+    fn finish_dtvcc(&mut self) {
+        let data = std::mem::take(&mut self.dtvcc);
+        let size = match data.first() {
+            Some(&header) => DtvccPacket::size(header),
+            None => return,
+        };
+
+        if data.len() < size {
+            return;
+        }
+
+        if let Some(packet) = DtvccPacket::parse(&data[..size], self.dtvcc_pts_us) {
+            self.dtvcc_packets.push(packet);
+        }
+    }
+
+    fn collect_cues(&mut self) {
+        for field in &mut self.fields {
+            self.cues.extend(field.take_cues());
+        }
+    }
+
+    /// The finished cues, in the order they ended
+    pub fn cues(&self) -> &[CaptionCue] {
+        &self.cues
+    }
+
+    /// Returns and removes the finished cues
+    pub fn take_cues(&mut self) -> Vec<CaptionCue> {
+        std::mem::take(&mut self.cues)
+    }
+
+    /// Returns and removes the DTVCC packets
+    pub fn take_dtvcc_packets(&mut self) -> Vec<DtvccPacket> {
+        std::mem::take(&mut self.dtvcc_packets)
+    }
+
+    /// The caption of a channel (1 to 4) to show with the frame presented at `pts_us`, like the output of a decoder
+    pub fn text_at(&self, channel: u8, pts_us: i64) -> Option<&str> {
+        let finished = self
+            .cues
+            .iter()
+            .find(|cue| cue.channel == channel && cue.start_us <= pts_us && pts_us < cue.end_us);
+
+        if let Some(cue) = finished {
+            return Some(&cue.text);
+        }
+
+        let field = &self.fields[(channel > 2) as usize];
+        match field.open_cue(channel) {
+            Some((start_us, text)) if start_us <= pts_us => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{annexb_to_length_prefixed, escape_rbsp};
+
+    /// Sets the odd parity bit of a CEA-608 byte
+    fn parity(byte: u8) -> u8 {
+        if byte.count_ones() & 1 == 0 {
+            byte | 0x80
+        } else {
+            byte
+        }
+    }
+
+    /// A CEA-608 byte pair for field 1
+    fn cea608(first: u8, second: u8) -> (u8, [u8; 2]) {
+        (0, [parity(first), parity(second)])
+    }
+
+    /// An access unit with an A/53 SEI message carrying `cc_data`, followed by a slice
+    fn packet(cc_data: &[(u8, [u8; 2])], hevc: bool) -> Vec<u8> {
+        let mut payload = vec![0xb5, 0x00, 0x31];
+        payload.extend_from_slice(A53_USER_IDENTIFIER);
+        payload.extend_from_slice(&[A53_CC_DATA, 0x40 | cc_data.len() as u8, 0xff]);
+        for (cc_type, data) in cc_data {
+            payload.extend_from_slice(&[0xfc | cc_type, data[0], data[1]]);
+        }
+        payload.push(0xff);
+
+        let mut rbsp = vec![
+            SEI_USER_DATA_REGISTERED_ITU_T_T35 as u8,
+            payload.len() as u8,
+        ];
+        rbsp.extend(payload);
+        rbsp.push(0x80);
+
+        let mut output = if hevc {
+            vec![0, 0, 0, 1, 0x4e, 0x01]
+        } else {
+            vec![0, 0, 0, 1, 0x06]
+        };
+        output.extend(escape_rbsp(&rbsp));
+        output.extend_from_slice(&[0, 0, 0, 1, 0x41, 0x9a]);
+        output
+    }
+
+    #[test]
+    fn parse_cc_data() {
+        let data = packet(&[cea608(b'H', b'I'), (3, [0x02, 0x21])], false);
+        let cc_data = packet_cc_data(&data, false, NalFormat::AnnexB);
+
+        assert_eq!(cc_data.len(), 2);
+        assert!(cc_data[0].valid);
+        assert_eq!(cc_data[0].data, [parity(b'H'), parity(b'I')]);
+        assert_eq!(cc_data[1].cc_type, 3);
+    }
+
+    #[test]
+    fn pop_on_captions() {
+        let mut extractor = CaptionExtractor::new("video/avc").unwrap();
+
+        // In decode order, with the B frame at 1000 coming after the frame at 2000.
+        // The caption is loaded, shown at 1000 with the DTVCC packet, and erased at 2000
+        let load = [
+            cea608(0x14, 0x20),
+            cea608(0x14, 0x20),
+            cea608(0x14, 0x70),
+            cea608(b'H', b'I'),
+            cea608(0x11, 0x37),
+        ];
+        extractor.push_packet(&packet(&load, false), 0);
+        extractor.push_packet(
+            &packet(&[cea608(0x14, 0x2c), cea608(0x14, 0x2c)], false),
+            2000,
+        );
+
+        let show = [
+            cea608(0x14, 0x2f),
+            cea608(0x14, 0x2f),
+            (3, [0x02, 0x21]),
+            (2, [0x41, 0x00]),
+        ];
+        extractor.push_packet(&packet(&show, false), 1000);
+
+        for index in 3..12 {
+            extractor.push_packet(&packet(&[cea608(0, 0)], false), index * 1000);
+        }
+
+        let cue = CaptionCue {
+            channel: 1,
+            start_us: 1000,
+            end_us: 2000,
+            text: "HI♪".into(),
+        };
+        assert_eq!(extractor.cues(), [cue]);
+
+        let packets = extractor.take_dtvcc_packets();
+        let block = DtvccServiceBlock {
+            service_number: 1,
+            data: vec![0x41],
+        };
+        assert_eq!(packets[0].service_blocks, [block]);
+    }
+
+    #[test]
+    fn roll_up_captions() {
+        let mut extractor = CaptionExtractor::new("video/hevc").unwrap();
+
+        // Two rows of roll-up on CC2, with a carriage return after each row
+        let start = [
+            cea608(0x1c, 0x26),
+            cea608(0x1c, 0x2d),
+            cea608(b'A', b'B'),
+            cea608(0x1c, 0x2d),
+        ];
+        extractor.push_packet(&packet(&start, true), 0);
+        extractor.push_packet(
+            &packet(&[cea608(b'C', b'D'), cea608(0x1c, 0x2d)], true),
+            1000,
+        );
+        extractor.push_packet(&packet(&[cea608(b'E', 0), cea608(0x1c, 0x2d)], true), 2000);
+        extractor.flush();
+
+        let cues: Vec<_> = extractor
+            .take_cues()
+            .into_iter()
+            .map(|cue| (cue.channel, cue.start_us, cue.text))
+            .collect();
+        assert_eq!(cues, [(2, 0, "AB".into()), (2, 1000, "AB\nCD".into())]);
+        assert_eq!(extractor.text_at(2, 2500), None);
+    }
+
+    #[test]
+    fn length_prefixed_packets() {
+        let data = packet(&[cea608(b'H', b'I')], false);
+        let prefixed = annexb_to_length_prefixed(&data, 4);
+
+        let cc_data = packet_cc_data(&prefixed, false, NalFormat::LengthPrefixed(4));
+        assert_eq!(cc_data.len(), 1);
+        assert_eq!(cc_data[0].data, [parity(b'H'), parity(b'I')]);
+
+        assert!(packet_cc_data(&prefixed, false, NalFormat::AnnexB).is_empty());
+    }
+}
@@ -1,9 +1,13 @@
+#[cfg(target_os = "android")]
 use log::{debug, warn};
 
+#[cfg(target_os = "android")]
 use crate::{
-    AMediaCrypto, AMediaFormat, ANativeWindow, AudioFrame, Frame, MediaFormat, MediaStatus,
-    NativeWindow, SampleFormat, VideoFrame, ENCODING_PCM_16BIT, ENCODING_PCM_FLOAT,
+    AMediaCrypto, AMediaFormat, ANativeWindow, AudioBuffer, AudioFrame, Frame, FramePool,
+    MediaFormat, MediaStatus, NativeWindow, RawVideoFrame, SampleFormat, VideoFrame, VideoFrameBuf,
+    ENCODING_PCM_16BIT, ENCODING_PCM_FLOAT,
 };
+#[cfg(target_os = "android")]
 use std::{
     ffi::{c_void, CString},
     marker::PhantomData,
@@ -20,6 +24,38 @@ pub struct BufferInfo {
     flags: u32,
 }
 
+impl BufferInfo {
+    /// Creates buffer information for a sample
+    pub fn new(offset: i32, size: i32, presentation_time_us: i64, flags: u32) -> Self {
+        Self {
+            offset,
+            size,
+            presentation_time_us,
+            flags,
+        }
+    }
+
+    /// The offset of the sample data in the buffer
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// The size (in bytes) of the sample data
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// The presentation time of the sample in microseconds
+    pub fn presentation_time_us(&self) -> i64 {
+        self.presentation_time_us
+    }
+
+    /// The buffer flags of the sample. See [BufferFlag](BufferFlag)
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct AMediaCodecCryptoInfo {
@@ -27,6 +63,7 @@ pub struct AMediaCodecCryptoInfo {
     _marker: core::marker::PhantomData<(*mut u8, core::marker::PhantomPinned)>,
 }
 
+#[cfg(target_os = "android")]
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct AMediaCodec {
@@ -66,7 +103,7 @@ impl TryFrom<BufferFlag> for i32 {
 
 impl BufferFlag {
     pub fn is_contained_in(&self, flag: i32) -> bool {
-        return flag & (*self as i32) > 0;
+        flag & (*self as i32) > 0
     }
 
     pub fn add_to_flag(&self, flag: &mut i32) {
@@ -104,7 +141,7 @@ impl TryFrom<InfoFlag> for i32 {
 
 impl InfoFlag {
     pub fn is_contained_in(&self, flag: i32) -> bool {
-        return flag & (*self as i32) > 0;
+        flag & (*self as i32) > 0
     }
 
     pub fn add_to_flag(&self, flag: &mut i32) {
@@ -128,6 +165,7 @@ pub struct CryptoInfoPattern {
     pub skip_blocks: i32,
 }
 
+#[cfg(target_os = "android")]
 type _AMediaCodecOnAsyncInputAvailable = extern "C" fn(
     // Codec
     *const AMediaCodec,
@@ -137,6 +175,7 @@ type _AMediaCodecOnAsyncInputAvailable = extern "C" fn(
     index: i32,
 );
 
+#[cfg(target_os = "android")]
 type _AMediaCodecOnAsyncOutputAvailable = extern "C" fn(
     // Codec
     *const AMediaCodec,
@@ -148,6 +187,7 @@ type _AMediaCodecOnAsyncOutputAvailable = extern "C" fn(
     *const BufferInfo,
 );
 
+#[cfg(target_os = "android")]
 type _AMediaCodecOnAsyncFormatChanged = extern "C" fn(
     // Codec
     *const AMediaCodec,
@@ -157,6 +197,7 @@ type _AMediaCodecOnAsyncFormatChanged = extern "C" fn(
     *const AMediaFormat,
 );
 
+#[cfg(target_os = "android")]
 type _AMediaCodecOnAsyncError = extern "C" fn(
     // Codec
     *const AMediaCodec,
@@ -170,6 +211,7 @@ type _AMediaCodecOnAsyncError = extern "C" fn(
     *const c_char,
 );
 
+#[cfg(target_os = "android")]
 #[repr(C)]
 struct _AMediaCodecOnAsyncNotifyCallback {
     on_async_input_available: _AMediaCodecOnAsyncInputAvailable,
@@ -180,6 +222,7 @@ struct _AMediaCodecOnAsyncNotifyCallback {
 
 // FFI FUNCTIONS BEGIN
 
+#[cfg(target_os = "android")]
 #[link(name = "mediandk")]
 extern "C" {
     /// Create codec by name. Use this if you know the exact codec you want to use.
@@ -497,6 +540,7 @@ extern "C" {
 
 /// This represents a buffer returned by mediacodec's input
 /// This buffer should be filled with input data depending on whether the codec is an encoder or decoder
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct CodecInputBuffer<'a> {
     pub(crate) _marker: PhantomData<&'a (*mut u8, core::marker::PhantomPinned)>,
@@ -509,6 +553,7 @@ pub struct CodecInputBuffer<'a> {
     pub(crate) flags: u32,
 }
 
+#[cfg(target_os = "android")]
 impl CodecInputBuffer<'_> {
     /// Creates a new Codec Input Buffer from the parameters
     fn new(codec: *mut AMediaCodec, index: usize, buffer: *mut u8, size: usize) -> Self {
@@ -566,8 +611,30 @@ impl CodecInputBuffer<'_> {
     pub fn set_write_size(&mut self, write_size: usize) {
         self.write_size = write_size;
     }
+
+    /// The data written into this buffer, up to the write size
+    pub fn data(&self) -> &[u8] {
+        unsafe { &*slice_from_raw_parts(self.buffer, self.write_size.min(self.size)) }
+    }
+
+    /// Copies `data` into this buffer and sets the write size.
+    ///
+    /// Returns false (and writes nothing) if `data` doesn't fit
+    pub fn write_data(&mut self, data: &[u8]) -> bool {
+        if data.len() > self.size {
+            return false;
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.buffer, data.len());
+        }
+        self.write_size = data.len();
+
+        true
+    }
 }
 
+#[cfg(target_os = "android")]
 impl Drop for CodecInputBuffer<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -583,7 +650,9 @@ impl Drop for CodecInputBuffer<'_> {
     }
 }
 
+#[cfg(target_os = "android")]
 unsafe impl Send for CodecInputBuffer<'_> {}
+#[cfg(target_os = "android")]
 unsafe impl Sync for CodecInputBuffer<'_> {}
 
 /// Represents a mediacodec output buffer
@@ -591,6 +660,7 @@ unsafe impl Sync for CodecInputBuffer<'_> {}
 /// For decoders, this is a raw frame.
 ///
 /// For encoders, this is an encoded packet
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct CodecOutputBuffer<'a> {
     _marker: PhantomData<&'a (*mut u8, core::marker::PhantomPinned)>,
@@ -604,6 +674,7 @@ pub struct CodecOutputBuffer<'a> {
     render: bool,
 }
 
+#[cfg(target_os = "android")]
 impl CodecOutputBuffer<'_> {
     /// Create a new codec output buffer from the parameters
     fn new(
@@ -660,12 +731,33 @@ impl CodecOutputBuffer<'_> {
             // Return the size of the readable buffer, instead of the buffer size itself.
             // Returning the entire buffer size is useless for the output buffer, as we only need to read data from it
             Some(&*slice_from_raw_parts(
-                (self.buffer as i32 + self.info.offset) as *mut u8,
+                self.buffer.add(self.info.offset as usize),
                 self.info.size as usize,
             ))
         }
     }
 
+    /// Returns the readable data contained in this buffer.
+    ///
+    /// Returns `None` if the codec is rendering to a surface
+    pub fn data(&self) -> Option<&[u8]> {
+        self.buffer_slice()
+    }
+
+    /// Returns a fresh copy of the codec's current output format.
+    ///
+    /// Unlike [format](Self::format), the returned format is owned, so it can be handed over to things like `MediaMuxer::add_track`
+    pub fn codec_output_format(&self) -> Option<MediaFormat> {
+        unsafe {
+            let format = AMediaCodec_getOutputFormat(self.codec);
+            if format.is_null() {
+                return None;
+            }
+
+            Some(MediaFormat::from_raw(format))
+        }
+    }
+
     /// Returns the frame contained in this buffer.
     /// Can either be an audio frame or a video frame
     pub fn frame(&self) -> Option<Frame> {
@@ -685,8 +777,11 @@ impl CodecOutputBuffer<'_> {
         }
 
         if is_audio {
-            // Fetch the PCM Encoding
-            let encoding = self.format.get_i32("pcm-encoding")?;
+            // Fetch the PCM Encoding, 16 bit if the decoder doesn't tell
+            let encoding = self
+                .format
+                .get_i32("pcm-encoding")
+                .unwrap_or(ENCODING_PCM_16BIT as i32);
             let channels = self.format.get_i32("channel-count")?;
 
             // Can't have invalid channels!
@@ -751,10 +846,45 @@ impl CodecOutputBuffer<'_> {
             // We have a surface buffer, so return a video frame with surface buffer for it
             if !self.using_buffers {
                 return Some(Frame::Video(VideoFrame::Hardware));
-            } else {
-                unimplemented!();
             }
+
+            let frame = RawVideoFrame::from_media_format(self.buffer_slice()?, &self.format)?;
+            Some(Frame::Video(VideoFrame::RawFrame(frame)))
+        }
+    }
+
+    /// Copies the raw video frame of this buffer using `pool`, with its presentation time and the
+    /// `rotation-degrees` of the format, so the buffer can be released before the frame is processed.
+    ///
+    /// Returns `None` for audio, hardware frames and unknown pixel formats
+    pub fn copy_video_frame(&self, pool: &FramePool) -> Option<VideoFrameBuf> {
+        if !self.using_buffers {
+            return None;
+        }
+
+        let frame = RawVideoFrame::from_media_format(self.buffer_slice()?, &self.format)?;
+
+        Some(
+            pool.copy_frame(&frame)
+                .with_pts_us(self.info.presentation_time_us())
+                .with_rotation(self.format.get_i32("rotation-degrees").unwrap_or(0)),
+        )
+    }
+
+    /// Copies the PCM samples of this buffer, with its presentation time and the channel layout of the format,
+    /// so the buffer can be released before the samples are processed.
+    ///
+    /// Returns `None` for video and unsupported PCM encodings
+    pub fn copy_audio_buffer(&self) -> Option<AudioBuffer> {
+        let mime = self.format.get_string("mime")?;
+        if !mime.contains("audio") {
+            return None;
         }
+
+        Some(
+            AudioBuffer::from_media_format(self.buffer_slice()?, &self.format)?
+                .with_pts_us(self.info.presentation_time_us()),
+        )
     }
 
     /// Set whether this buffer should render when it gets dropped.
@@ -764,6 +894,7 @@ impl CodecOutputBuffer<'_> {
     }
 }
 
+#[cfg(target_os = "android")]
 impl Drop for CodecOutputBuffer<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -772,12 +903,15 @@ impl Drop for CodecOutputBuffer<'_> {
     }
 }
 
+#[cfg(target_os = "android")]
 unsafe impl Send for CodecOutputBuffer<'_> {}
+#[cfg(target_os = "android")]
 unsafe impl Sync for CodecOutputBuffer<'_> {}
 
 /// The MediaCodec structure itself.
 ///
 /// Represents either a decoder or an encoder
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct MediaCodec<'a> {
     inner: *mut AMediaCodec,
@@ -785,6 +919,7 @@ pub struct MediaCodec<'a> {
     using_buffers: bool,
 }
 
+#[cfg(target_os = "android")]
 impl<'a> MediaCodec<'a> {
     /// Creates a MediaCodec instance from raw pointer
     fn from_ptr(ptr: *mut AMediaCodec) -> Self {
@@ -969,6 +1104,7 @@ impl<'a> MediaCodec<'a> {
     }
 }
 
+#[cfg(target_os = "android")]
 impl<'a> Drop for MediaCodec<'a> {
     fn drop(&mut self) {
         unsafe {
@@ -977,5 +1113,7 @@ impl<'a> Drop for MediaCodec<'a> {
     }
 }
 
+#[cfg(target_os = "android")]
 unsafe impl<'a> Send for MediaCodec<'a> {}
+#[cfg(target_os = "android")]
 unsafe impl<'a> Sync for MediaCodec<'a> {}
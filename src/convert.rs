@@ -0,0 +1,785 @@
+use log::warn;
+
+use crate::{
+    y4m::{pack_planes, SampleEncoding},
+    MediaStatus, PixelFormat, RawVideoFrame, VideoPlane,
+};
+#[cfg(target_os = "android")]
+use crate::{
+    MediaFormat, COLOR_RANGE_FULL, COLOR_STANDARD_BT2020, COLOR_STANDARD_BT601_NTSC,
+    COLOR_STANDARD_BT601_PAL, COLOR_STANDARD_BT709,
+};
+
+/// The fractional bits of the YUV to RGB coefficients
+const YUV_TO_RGB_SHIFT: i32 = 13;
+/// The fractional bits of the RGB to YUV coefficients
+const RGB_TO_YUV_SHIFT: i32 = 16;
+
+/// The matrix coefficients used to go between RGB and YUV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl YuvMatrix {
+    /// The red and blue weights of the luma
+    fn kr_kb(&self) -> (f64, f64) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+            Self::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+/// How YUV samples relate to RGB: the matrix, and whether the samples use the full range or the limited
+/// (16-235 for luma, 16-240 for chroma) range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YuvColorSpace {
+    pub matrix: YuvMatrix,
+    pub full_range: bool,
+}
+
+impl YuvColorSpace {
+    pub fn new(matrix: YuvMatrix, full_range: bool) -> Self {
+        Self { matrix, full_range }
+    }
+
+    /// The color space of a video format, from its `color-standard` and `color-range` keys.
+    ///
+    /// Without `color-standard`, BT.709 is used for HD sizes and BT.601 below, like Android does.
+    /// Without `color-range`, the range is limited
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(format: &MediaFormat) -> Self {
+        let matrix = match format.get_i32("color-standard") {
+            Some(COLOR_STANDARD_BT709) => YuvMatrix::Bt709,
+            Some(COLOR_STANDARD_BT601_PAL | COLOR_STANDARD_BT601_NTSC) => YuvMatrix::Bt601,
+            Some(COLOR_STANDARD_BT2020) => YuvMatrix::Bt2020,
+            _ if format.get_i32("height").unwrap_or(0) >= 720 => YuvMatrix::Bt709,
+            _ => YuvMatrix::Bt601,
+        };
+
+        Self {
+            matrix,
+            full_range: format.get_i32("color-range") == Some(COLOR_RANGE_FULL),
+        }
+    }
+
+    /// The scales of luma and chroma, and the luma offset, in 8 bits
+    fn range(&self) -> (f64, f64, i32) {
+        if self.full_range {
+            (1.0, 1.0, 0)
+        } else {
+            (219.0 / 255.0, 224.0 / 255.0, 16)
+        }
+    }
+
+    fn yuv_to_rgb(&self) -> YuvToRgb {
+        let (kr, kb) = self.matrix.kr_kb();
+        let kg = 1.0 - kr - kb;
+        let (y_scale, c_scale, y_offset) = self.range();
+        let fixed = |value: f64| (value * (1 << YUV_TO_RGB_SHIFT) as f64).round() as i32;
+
+        YuvToRgb {
+            y_offset,
+            y: fixed(1.0 / y_scale),
+            r_v: fixed(2.0 * (1.0 - kr) / c_scale),
+            g_u: fixed(2.0 * kb * (1.0 - kb) / kg / c_scale),
+            g_v: fixed(2.0 * kr * (1.0 - kr) / kg / c_scale),
+            b_u: fixed(2.0 * (1.0 - kb) / c_scale),
+        }
+    }
+
+    /// The coefficients to get `bit_depth` YUV samples from 8 bit RGB
+    fn rgb_to_yuv(&self, bit_depth: u32) -> RgbToYuv {
+        let (kr, kb) = self.matrix.kr_kb();
+        let kg = 1.0 - kr - kb;
+        let (y_scale, c_scale, y_offset) = self.range();
+        let scale = (1 << (bit_depth - 8)) as f64;
+        let fixed = |value: f64| (value * scale * (1 << RGB_TO_YUV_SHIFT) as f64).round() as i64;
+
+        let u = [-kr / (2.0 * (1.0 - kb)), -kg / (2.0 * (1.0 - kb)), 0.5];
+        let v = [0.5, -kg / (2.0 * (1.0 - kr)), -kb / (2.0 * (1.0 - kr))];
+
+        RgbToYuv {
+            y: [kr, kg, kb].map(|value| fixed(value * y_scale)),
+            u: u.map(|value| fixed(value * c_scale)),
+            v: v.map(|value| fixed(value * c_scale)),
+            y_offset: fixed(y_offset as f64),
+            c_offset: fixed(128.0),
+            max: (1 << bit_depth) - 1,
+        }
+    }
+}
+
+/// The fixed point coefficients to go from 8 bit YUV to RGB
+#[derive(Debug, Clone, Copy)]
+struct YuvToRgb {
+    y_offset: i32,
+    y: i32,
+    r_v: i32,
+    g_u: i32,
+    g_v: i32,
+    b_u: i32,
+}
+
+/// The fixed point coefficients to go from 8 bit RGB to YUV, applied to R, G and B
+#[derive(Debug, Clone, Copy)]
+struct RgbToYuv {
+    y: [i64; 3],
+    u: [i64; 3],
+    v: [i64; 3],
+    y_offset: i64,
+    c_offset: i64,
+    max: i64,
+}
+
+impl RgbToYuv {
+    /// Applies `coefficients` to the sum of `count` pixels, rounding the average
+    fn apply(&self, coefficients: &[i64; 3], offset: i64, rgb: [i64; 3], count: i64) -> u16 {
+        let sum: i64 = coefficients
+            .iter()
+            .zip(rgb)
+            .map(|(c, value)| c * value)
+            .sum();
+        let divisor = count << RGB_TO_YUV_SHIFT;
+        let value = (sum + offset * count + divisor / 2).div_euclid(divisor);
+
+        value.clamp(0, self.max) as u16
+    }
+}
+
+/// Converts the visible part of a decoded frame to RGBA, with an opaque alpha.
+///
+/// `output` is resized to `4 * width * height` of the crop rectangle. P010 frames are converted to 8 bits first
+pub fn yuv_to_rgba(
+    frame: &RawVideoFrame,
+    color: YuvColorSpace,
+    output: &mut Vec<u8>,
+) -> Result<(), MediaStatus> {
+    let Some(planes) = frame.planes() else {
+        warn!(
+            "The buffer of {} bytes is too small for a {}x{} frame",
+            frame.buffer().len(),
+            frame.width(),
+            frame.height()
+        );
+        return Err(MediaStatus::ErrorMalformed);
+    };
+
+    let crop = frame.crop();
+    let (left, top) = (crop.left as usize, crop.top as usize);
+    let (width, height) = (crop.width() as usize, crop.height() as usize);
+    let ten_bit = frame.pixel_format() == PixelFormat::P010;
+
+    let coefficients = color.yuv_to_rgb();
+    output.clear();
+    output.resize(width * height * 4, 0);
+
+    let mut luma = vec![0; width];
+    let mut u = vec![0; width];
+    let mut v = vec![0; width];
+
+    // 8 bit samples, rounded for 10 bit frames
+    let sample = |plane: &VideoPlane, x: usize, y: usize| match ten_bit {
+        true => ((plane.sample(x, y) as u32 + 0x80) >> 8).min(255) as u8,
+        false => plane.sample(x, y) as u8,
+    };
+
+    for (row, rgba) in output.chunks_exact_mut(width * 4).enumerate() {
+        let y = top + row;
+
+        let luma_row = match planes[0].row(left, y, width) {
+            Some(samples) if !ten_bit => samples,
+            _ => {
+                for (x, value) in luma.iter_mut().enumerate() {
+                    *value = sample(&planes[0], left + x, y);
+                }
+                &luma
+            }
+        };
+
+        // Every pixel gets the chroma samples of its 2x2 block
+        for x in 0..width {
+            u[x] = sample(&planes[1], (left + x) / 2, y / 2);
+            v[x] = sample(&planes[2], (left + x) / 2, y / 2);
+        }
+
+        yuv_row_to_rgba(&coefficients, luma_row, &u, &v, rgba);
+    }
+
+    Ok(())
+}
+
+/// Converts RGBA pixels to YUV, in the layout of `format` and without padding, ignoring the alpha.
+///
+/// The chroma samples are the average of their 2x2 block. `output` is resized to the size of the frame
+pub fn rgba_to_yuv(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    color: YuvColorSpace,
+    format: PixelFormat,
+    output: &mut Vec<u8>,
+) -> Result<(), MediaStatus> {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 || rgba.len() < width * height * 4 {
+        warn!("{} bytes of RGBA for a {width}x{height} frame", rgba.len());
+        return Err(MediaStatus::ErrorInvalidParameter);
+    }
+
+    let (bit_depth, encoding) = match format {
+        PixelFormat::P010 => (10, SampleEncoding::High10),
+        _ => (8, SampleEncoding::Bits8),
+    };
+    let coefficients = color.rgb_to_yuv(bit_depth);
+    let shift = 16 - bit_depth;
+
+    // The samples are computed as I420 with 16 bit samples, then packed in the layout of the format
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let luma_size = width * height * 2;
+    let chroma_size = chroma_width * chroma_height * 2;
+    let mut planar = vec![0; luma_size + 2 * chroma_size];
+    let (luma, chroma) = planar.split_at_mut(luma_size);
+    let (u, v) = chroma.split_at_mut(chroma_size);
+
+    let pixel = |x: usize, y: usize| {
+        let index = (y * width + x) * 4;
+        [rgba[index], rgba[index + 1], rgba[index + 2]].map(|value| value as i64)
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let value = coefficients.apply(&coefficients.y, coefficients.y_offset, pixel(x, y), 1);
+            let index = (y * width + x) * 2;
+            luma[index..index + 2].copy_from_slice(&(value << shift).to_le_bytes());
+        }
+    }
+
+    for y in 0..chroma_height {
+        for x in 0..chroma_width {
+            let mut sum = [0; 3];
+            let mut count = 0;
+
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (px, py) = (2 * x + dx, 2 * y + dy);
+                if px < width && py < height {
+                    let rgb = pixel(px, py);
+                    sum.iter_mut()
+                        .zip(rgb)
+                        .for_each(|(sum, value)| *sum += value);
+                    count += 1;
+                }
+            }
+
+            let index = (y * chroma_width + x) * 2;
+            let offset = coefficients.c_offset;
+            let u_value = coefficients.apply(&coefficients.u, offset, sum, count);
+            let v_value = coefficients.apply(&coefficients.v, offset, sum, count);
+            u[index..index + 2].copy_from_slice(&(u_value << shift).to_le_bytes());
+            v[index..index + 2].copy_from_slice(&(v_value << shift).to_le_bytes());
+        }
+    }
+
+    let plane = |offset: usize, row_stride: usize| VideoPlane {
+        data: &planar[offset..],
+        row_stride,
+        pixel_stride: 2,
+        bytes_per_sample: 2,
+    };
+    let planes = [
+        plane(0, width * 2),
+        plane(luma_size, chroma_width * 2),
+        plane(luma_size + chroma_size, chroma_width * 2),
+    ];
+
+    output.clear();
+    output.reserve(format.frame_size(width as u32, height as u32));
+    pack_planes(
+        &planes,
+        SampleEncoding::High10,
+        (0, 0, width, height),
+        format,
+        encoding,
+        output,
+    );
+
+    Ok(())
+}
+
+/// Converts a row of 8 bit YUV samples (with a chroma sample per pixel) to RGBA
+fn yuv_row_to_rgba(coefficients: &YuvToRgb, y: &[u8], u: &[u8], v: &[u8], rgba: &mut [u8]) {
+    let done = simd::yuv_row_to_rgba(coefficients, y, u, v, rgba);
+
+    yuv_row_to_rgba_scalar(
+        coefficients,
+        &y[done..],
+        &u[done..],
+        &v[done..],
+        &mut rgba[done * 4..],
+    );
+}
+
+fn yuv_row_to_rgba_scalar(c: &YuvToRgb, y: &[u8], u: &[u8], v: &[u8], rgba: &mut [u8]) {
+    let round = 1 << (YUV_TO_RGB_SHIFT - 1);
+    let to_u8 = |value: i32| (value >> YUV_TO_RGB_SHIFT).clamp(0, 255) as u8;
+
+    for (((pixel, y), u), v) in rgba.chunks_exact_mut(4).zip(y).zip(u).zip(v) {
+        let luma = (*y as i32 - c.y_offset) * c.y + round;
+        let (u, v) = (*u as i32 - 128, *v as i32 - 128);
+
+        pixel[0] = to_u8(luma + c.r_v * v);
+        pixel[1] = to_u8(luma - c.g_u * u - c.g_v * v);
+        pixel[2] = to_u8(luma + c.b_u * u);
+        pixel[3] = 255;
+    }
+}
+
+/// SSE2 is always there on x86_64
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    use super::{YuvToRgb, YUV_TO_RGB_SHIFT};
+
+    /// Converts pixels 8 at a time, returns the number of pixels converted
+    pub(super) fn yuv_row_to_rgba(
+        c: &YuvToRgb,
+        y: &[u8],
+        u: &[u8],
+        v: &[u8],
+        rgba: &mut [u8],
+    ) -> usize {
+        let count = y.len().min(u.len()).min(v.len()).min(rgba.len() / 4) / 8 * 8;
+
+        // SAFETY: the loads and stores stay within the first `count` pixels of the slices
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let y_offset = _mm_set1_epi16(c.y_offset as i16);
+            let chroma_offset = _mm_set1_epi16(128);
+            let round = _mm_set1_epi32(1 << (YUV_TO_RGB_SHIFT - 1));
+            let alpha = _mm_set1_epi8(-1);
+
+            // _mm_madd_epi16 multiplies pairs of 16 bit values and adds the products
+            let pair = |first: i32, second: i32| _mm_set1_epi32((first & 0xffff) | (second << 16));
+            let r_yv = pair(c.y, c.r_v);
+            let g_yu = pair(c.y, -c.g_u);
+            let g_v = pair(-c.g_v, 0);
+            let b_yu = pair(c.y, c.b_u);
+
+            let channel = |low: __m128i, high: __m128i| {
+                let low = _mm_srai_epi32::<YUV_TO_RGB_SHIFT>(_mm_add_epi32(low, round));
+                let high = _mm_srai_epi32::<YUV_TO_RGB_SHIFT>(_mm_add_epi32(high, round));
+                let value = _mm_packs_epi32(low, high);
+                _mm_packus_epi16(value, value)
+            };
+
+            for i in (0..count).step_by(8) {
+                let load = |data: &[u8]| {
+                    let bytes = _mm_loadl_epi64(data.as_ptr().add(i) as *const __m128i);
+                    _mm_unpacklo_epi8(bytes, zero)
+                };
+
+                let luma = _mm_sub_epi16(load(y), y_offset);
+                let u = _mm_sub_epi16(load(u), chroma_offset);
+                let v = _mm_sub_epi16(load(v), chroma_offset);
+
+                let (yv_low, yv_high) = (_mm_unpacklo_epi16(luma, v), _mm_unpackhi_epi16(luma, v));
+                let (yu_low, yu_high) = (_mm_unpacklo_epi16(luma, u), _mm_unpackhi_epi16(luma, u));
+                let (v_low, v_high) = (_mm_unpacklo_epi16(v, zero), _mm_unpackhi_epi16(v, zero));
+
+                let r = channel(_mm_madd_epi16(yv_low, r_yv), _mm_madd_epi16(yv_high, r_yv));
+                let g = channel(
+                    _mm_add_epi32(_mm_madd_epi16(yu_low, g_yu), _mm_madd_epi16(v_low, g_v)),
+                    _mm_add_epi32(_mm_madd_epi16(yu_high, g_yu), _mm_madd_epi16(v_high, g_v)),
+                );
+                let b = channel(_mm_madd_epi16(yu_low, b_yu), _mm_madd_epi16(yu_high, b_yu));
+
+                let rg = _mm_unpacklo_epi8(r, g);
+                let ba = _mm_unpacklo_epi8(b, alpha);
+                let output = rgba.as_mut_ptr().add(i * 4) as *mut __m128i;
+                _mm_storeu_si128(output, _mm_unpacklo_epi16(rg, ba));
+                _mm_storeu_si128(output.add(1), _mm_unpackhi_epi16(rg, ba));
+            }
+        }
+
+        count
+    }
+}
+
+/// NEON is always there on aarch64
+#[cfg(target_arch = "aarch64")]
+mod simd {
+    use std::arch::aarch64::*;
+
+    use super::{YuvToRgb, YUV_TO_RGB_SHIFT};
+
+    /// Converts pixels 8 at a time, returns the number of pixels converted
+    pub(super) fn yuv_row_to_rgba(
+        c: &YuvToRgb,
+        y: &[u8],
+        u: &[u8],
+        v: &[u8],
+        rgba: &mut [u8],
+    ) -> usize {
+        let count = y.len().min(u.len()).min(v.len()).min(rgba.len() / 4) / 8 * 8;
+
+        // SAFETY: the loads and stores stay within the first `count` pixels of the slices
+        unsafe {
+            let y_offset = vdupq_n_s16(c.y_offset as i16);
+            let chroma_offset = vdupq_n_s16(128);
+            let round = vdupq_n_s32(1 << (YUV_TO_RGB_SHIFT - 1));
+            let alpha = vdup_n_u8(255);
+
+            let channel = |low: int32x4_t, high: int32x4_t| {
+                let low = vqmovn_s32(vshrq_n_s32::<YUV_TO_RGB_SHIFT>(vaddq_s32(low, round)));
+                let high = vqmovn_s32(vshrq_n_s32::<YUV_TO_RGB_SHIFT>(vaddq_s32(high, round)));
+                vqmovun_s16(vcombine_s16(low, high))
+            };
+
+            for i in (0..count).step_by(8) {
+                let load =
+                    |data: &[u8]| vreinterpretq_s16_u16(vmovl_u8(vld1_u8(data.as_ptr().add(i))));
+
+                let luma = vsubq_s16(load(y), y_offset);
+                let u = vsubq_s16(load(u), chroma_offset);
+                let v = vsubq_s16(load(v), chroma_offset);
+
+                let halves = |value: int16x8_t| (vget_low_s16(value), vget_high_s16(value));
+                let (luma_low, luma_high) = halves(luma);
+                let (u_low, u_high) = halves(u);
+                let (v_low, v_high) = halves(v);
+
+                let luma_low = vmull_n_s16(luma_low, c.y as i16);
+                let luma_high = vmull_n_s16(luma_high, c.y as i16);
+
+                let r = channel(
+                    vmlal_n_s16(luma_low, v_low, c.r_v as i16),
+                    vmlal_n_s16(luma_high, v_high, c.r_v as i16),
+                );
+                let g = channel(
+                    vmlsl_n_s16(
+                        vmlsl_n_s16(luma_low, u_low, c.g_u as i16),
+                        v_low,
+                        c.g_v as i16,
+                    ),
+                    vmlsl_n_s16(
+                        vmlsl_n_s16(luma_high, u_high, c.g_u as i16),
+                        v_high,
+                        c.g_v as i16,
+                    ),
+                );
+                let b = channel(
+                    vmlal_n_s16(luma_low, u_low, c.b_u as i16),
+                    vmlal_n_s16(luma_high, u_high, c.b_u as i16),
+                );
+
+                vst4_u8(rgba.as_mut_ptr().add(i * 4), uint8x8x4_t(r, g, b, alpha));
+            }
+        }
+
+        count
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod simd {
+    use super::YuvToRgb;
+
+    /// Everything goes through the scalar path
+    pub(super) fn yuv_row_to_rgba(
+        _c: &YuvToRgb,
+        _y: &[u8],
+        _u: &[u8],
+        _v: &[u8],
+        _rgba: &mut [u8],
+    ) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BT.601, BT.709 and BT.2020, in limited then full range
+    fn color_spaces() -> [YuvColorSpace; 6] {
+        [YuvMatrix::Bt601, YuvMatrix::Bt709, YuvMatrix::Bt2020]
+            .map(|matrix| [false, true].map(|full_range| YuvColorSpace::new(matrix, full_range)))
+            .concat()
+            .try_into()
+            .unwrap()
+    }
+
+    /// Red, green, blue, white, black and grey
+    const COLORS: [[u8; 3]; 6] = [
+        [255, 0, 0],
+        [0, 255, 0],
+        [0, 0, 255],
+        [255, 255, 255],
+        [0, 0, 0],
+        [128, 128, 128],
+    ];
+
+    /// The Y, U and V of the colors for each color space, the usual values of the BT.601 and BT.709 tables
+    const YUV_COLORS: [[[u8; 3]; 6]; 6] = [
+        [
+            [81, 90, 240],
+            [145, 54, 34],
+            [41, 240, 110],
+            [235, 128, 128],
+            [16, 128, 128],
+            [126, 128, 128],
+        ],
+        [
+            [76, 85, 255],
+            [150, 44, 21],
+            [29, 255, 107],
+            [255, 128, 128],
+            [0, 128, 128],
+            [128, 128, 128],
+        ],
+        [
+            [63, 102, 240],
+            [173, 42, 26],
+            [32, 240, 118],
+            [235, 128, 128],
+            [16, 128, 128],
+            [126, 128, 128],
+        ],
+        [
+            [54, 99, 255],
+            [182, 30, 12],
+            [18, 255, 116],
+            [255, 128, 128],
+            [0, 128, 128],
+            [128, 128, 128],
+        ],
+        [
+            [74, 97, 240],
+            [164, 47, 25],
+            [29, 240, 119],
+            [235, 128, 128],
+            [16, 128, 128],
+            [126, 128, 128],
+        ],
+        [
+            [67, 92, 255],
+            [173, 36, 11],
+            [15, 255, 118],
+            [255, 128, 128],
+            [0, 128, 128],
+            [128, 128, 128],
+        ],
+    ];
+
+    const PIXEL_FORMATS: [PixelFormat; 5] = [
+        PixelFormat::I420,
+        PixelFormat::Yv12,
+        PixelFormat::Nv12,
+        PixelFormat::Nv21,
+        PixelFormat::P010,
+    ];
+
+    /// Pseudo random bytes
+    fn noise(size: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..size)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// A `width` x `height` frame of one color
+    fn flat(rgb: [u8; 3], width: u32, height: u32) -> Vec<u8> {
+        [rgb[0], rgb[1], rgb[2], 255].repeat((width * height) as usize)
+    }
+
+    fn max_difference(rgba: &[u8], other: &[u8]) -> i32 {
+        rgba.iter()
+            .zip(other)
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        for (index, color) in color_spaces().iter().enumerate() {
+            let coefficients = color.yuv_to_rgb();
+
+            // Every length around the vector sizes, with random samples and the extremes
+            for width in 0..70 {
+                let seed = (index * 100 + width) as u32;
+                let mut samples = noise(3 * width, seed);
+                if width > 6 {
+                    samples[..3].fill(0);
+                    samples[3..6].fill(255);
+                }
+                let (y, chroma) = samples.split_at(width);
+                let (u, v) = chroma.split_at(width);
+
+                let mut rgba = vec![0; 4 * width];
+                let mut expected = vec![0; 4 * width];
+                yuv_row_to_rgba(&coefficients, y, u, v, &mut rgba);
+                yuv_row_to_rgba_scalar(&coefficients, y, u, v, &mut expected);
+                assert_eq!(rgba, expected, "{color:?}, {width} pixels");
+            }
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        for (color, expected) in color_spaces().iter().zip(YUV_COLORS) {
+            for (rgb, yuv) in COLORS.iter().zip(expected) {
+                let mut output = vec![];
+                rgba_to_yuv(
+                    &flat(*rgb, 2, 2),
+                    2,
+                    2,
+                    *color,
+                    PixelFormat::I420,
+                    &mut output,
+                )
+                .unwrap();
+                assert_eq!([output[0], output[4], output[5]], yuv, "{color:?} {rgb:?}");
+
+                // And back, the RGB values come from rounded YUV values
+                let frame = [yuv[0]; 4]
+                    .iter()
+                    .chain(&yuv[1..])
+                    .copied()
+                    .collect::<Vec<_>>();
+                let frame = RawVideoFrame::new(&frame, PixelFormat::I420, 2, 2);
+                yuv_to_rgba(&frame, *color, &mut output).unwrap();
+                assert_eq!(output.len(), 16);
+                assert!(
+                    max_difference(&output[..3], rgb) <= 2,
+                    "{color:?} {rgb:?}: {output:?}"
+                );
+                assert_eq!(output[3], 255);
+            }
+        }
+
+        // The extremes of the ranges are exact
+        let limited = YuvColorSpace::new(YuvMatrix::Bt601, false);
+        let mut output = vec![];
+        let frame = [235, 235, 235, 235, 128, 128];
+        yuv_to_rgba(
+            &RawVideoFrame::new(&frame, PixelFormat::I420, 2, 2),
+            limited,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, [255; 16]);
+
+        // Below black and above white is clamped
+        let frame = [0, 0, 255, 255, 128, 128];
+        yuv_to_rgba(
+            &RawVideoFrame::new(&frame, PixelFormat::I420, 2, 2),
+            limited,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output[..8], [0, 0, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(output[8..], [255; 8]);
+    }
+
+    #[test]
+    fn pixel_format_layouts() {
+        // Red in BT.601 limited range: U is 90 and V is 240
+        let color = color_spaces()[0];
+        let rgba = flat(COLORS[0], 4, 2);
+        let mut output = vec![];
+
+        let mut chroma = |format| {
+            rgba_to_yuv(&rgba, 4, 2, color, format, &mut output).unwrap();
+            assert_eq!(output.len(), format.frame_size(4, 2));
+            output[8..].to_vec()
+        };
+
+        assert_eq!(chroma(PixelFormat::I420), [90, 90, 240, 240]);
+        assert_eq!(chroma(PixelFormat::Yv12), [240, 240, 90, 90]);
+        assert_eq!(chroma(PixelFormat::Nv12), [90, 240, 90, 240]);
+        assert_eq!(chroma(PixelFormat::Nv21), [240, 90, 240, 90]);
+
+        // 10 bit values in the high bits of little endian samples
+        rgba_to_yuv(&rgba, 4, 2, color, PixelFormat::P010, &mut output).unwrap();
+        assert_eq!(output.len(), 24);
+        let samples: Vec<u16> = output
+            .chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) >> 6)
+            .collect();
+        assert_eq!(samples[..8], [326; 8]);
+        assert_eq!(samples[8..], [361, 960, 361, 960]);
+        assert!(output.chunks_exact(2).all(|sample| sample[0] & 0x3f == 0));
+    }
+
+    #[test]
+    fn round_trip() {
+        // Odd sizes, and smooth gradients so that the chroma subsampling barely matters
+        let (width, height) = (17, 9);
+        let mut rgba = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                let value = (x * 8 + y * 5) as u8 + 20;
+                rgba.extend_from_slice(&[value + 10, value, value + 20, 255]);
+            }
+        }
+
+        for format in PIXEL_FORMATS {
+            for color in color_spaces() {
+                let mut yuv = vec![];
+                rgba_to_yuv(&rgba, width, height, color, format, &mut yuv).unwrap();
+                assert_eq!(yuv.len(), format.frame_size(width, height));
+
+                let frame = RawVideoFrame::new(&yuv, format, width, height);
+                let mut output = vec![];
+                yuv_to_rgba(&frame, color, &mut output).unwrap();
+                assert_eq!(output.len(), rgba.len());
+                assert!(max_difference(&output, &rgba) <= 2, "{format:?} {color:?}");
+            }
+        }
+
+        // Saturated colors survive too
+        for format in PIXEL_FORMATS {
+            for color in color_spaces() {
+                for rgb in COLORS {
+                    let mut yuv = vec![];
+                    rgba_to_yuv(&flat(rgb, 6, 2), 6, 2, color, format, &mut yuv).unwrap();
+                    let mut output = vec![];
+                    let frame = RawVideoFrame::new(&yuv, format, 6, 2);
+                    yuv_to_rgba(&frame, color, &mut output).unwrap();
+                    assert!(
+                        max_difference(&output, &flat(rgb, 6, 2)) <= 2,
+                        "{format:?} {color:?} {rgb:?}"
+                    );
+                }
+            }
+        }
+
+        let mut yuv = vec![];
+        assert!(rgba_to_yuv(
+            &rgba,
+            width + 1,
+            height,
+            color_spaces()[0],
+            PixelFormat::I420,
+            &mut yuv
+        )
+        .is_err());
+        assert!(rgba_to_yuv(
+            &rgba,
+            0,
+            height,
+            color_spaces()[0],
+            PixelFormat::I420,
+            &mut yuv
+        )
+        .is_err());
+    }
+}
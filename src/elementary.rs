@@ -0,0 +1,631 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use log::{debug, warn};
+
+use crate::{
+    nal::find_start_code, AvcDecoderConfigurationRecord, AvcNalType,
+    HevcDecoderConfigurationRecord, HevcNalType, MediaStatus, PacketSource, TrackFormat,
+    ANNEXB_START_CODE, SAMPLE_FLAG_SYNC,
+};
+#[cfg(target_os = "android")]
+use crate::{CodecInputBuffer, MediaFormat};
+
+/// How much data is read from the underlying reader at a time
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The frame rate used when neither the caller nor the stream tell us
+const DEFAULT_FRAME_RATE: f64 = 30.0;
+
+/// The largest access unit we're willing to buffer
+const MAX_ACCESS_UNIT_SIZE: usize = 64 * 1024 * 1024;
+
+/// The codec of an elementary stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementaryStreamCodec {
+    Avc,
+    Hevc,
+}
+
+impl ElementaryStreamCodec {
+    /// Guesses the codec from a file extension, like `.h264` or `.265`
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "h264" | "264" | "avc" | "jsv" => Some(Self::Avc),
+            "h265" | "265" | "hevc" => Some(Self::Hevc),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Avc => "video/avc",
+            Self::Hevc => "video/hevc",
+        }
+    }
+
+    fn is_vcl(&self, nal: &[u8]) -> bool {
+        match self {
+            Self::Avc => AvcNalType::from_header(nal[0]).is_vcl(),
+            Self::Hevc => HevcNalType::from_header(nal[0]).is_vcl(),
+        }
+    }
+
+    fn is_sync(&self, nal: &[u8]) -> bool {
+        match self {
+            Self::Avc => AvcNalType::from_header(nal[0]) == AvcNalType::IdrSlice,
+            Self::Hevc => HevcNalType::from_header(nal[0]).is_irap(),
+        }
+    }
+
+    /// Whether `nal` starts a new access unit, if the current one already has a picture
+    fn starts_access_unit(&self, nal: &[u8]) -> bool {
+        match self {
+            Self::Avc => match nal[0] & 0x1f {
+                // The first slice of a picture has first_mb_in_slice = 0, which is a single 1 bit in ue(v)
+                1 | 5 => nal.get(1).is_some_and(|byte| byte & 0x80 != 0),
+                // SEI, SPS, PPS, access unit delimiter and the reserved types 14 to 18
+                6..=9 | 14..=18 => true,
+                _ => false,
+            },
+            Self::Hevc => match (nal[0] >> 1) & 0x3f {
+                // first_slice_segment_in_pic_flag
+                0..=31 => nal.get(2).is_some_and(|byte| byte & 0x80 != 0),
+                // VPS, SPS, PPS, access unit delimiter, prefix SEI and the reserved types
+                32..=35 | 39 | 41..=44 | 48..=55 => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// The parameter sets found at the start of a stream
+#[derive(Debug, Clone)]
+enum StreamConfig {
+    Avc(AvcDecoderConfigurationRecord),
+    Hevc(HevcDecoderConfigurationRecord),
+}
+
+impl StreamConfig {
+    fn from_annexb(codec: ElementaryStreamCodec, data: &[u8]) -> Option<Self> {
+        match codec {
+            ElementaryStreamCodec::Avc => {
+                AvcDecoderConfigurationRecord::from_annexb(data).map(Self::Avc)
+            }
+            ElementaryStreamCodec::Hevc => {
+                HevcDecoderConfigurationRecord::from_annexb(data).map(Self::Hevc)
+            }
+        }
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        match self {
+            Self::Avc(record) => record.parse_sps()?.frame_rate(),
+            Self::Hevc(record) => record.parse_sps()?.frame_rate(),
+        }
+    }
+
+    /// The size of the pictures, after cropping
+    fn size(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::Avc(record) => record.parse_sps().map(|sps| (sps.width(), sps.height())),
+            Self::Hevc(record) => record.parse_sps().map(|sps| (sps.width(), sps.height())),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Avc(record) => record.to_bytes(),
+            Self::Hevc(record) => record.to_bytes(),
+        }
+    }
+}
+
+/// An access unit (all the NAL units of a picture) of an elementary stream
+#[derive(Debug, Clone, Default)]
+struct AccessUnit {
+    /// The NAL units in Annex-B format
+    data: Vec<u8>,
+    has_vcl: bool,
+    sync: bool,
+}
+
+/// Reads a raw H.264 or HEVC elementary stream (Annex-B, like `.h264` or `.h265` files), one access unit at a time.
+///
+/// `AMediaExtractor` doesn't demux these, so this reader takes its place: it implements [PacketSource](PacketSource)
+/// with a single video track, and has the same `read_next` interface as [MediaExtractor](crate::MediaExtractor).
+///
+/// The stream is split into access units at access unit delimiters, parameter sets, SEI and the first slice of every picture.
+/// Access units with an IDR (H.264) or IRAP (HEVC) picture are flagged as sync samples.
+///
+/// Elementary streams have no timestamps, so they're generated from the frame rate, in decoding order
+#[derive(Debug)]
+pub struct ElementaryStreamReader<R: Read> {
+    reader: R,
+    codec: ElementaryStreamCodec,
+    /// Data read from `reader` that hasn't been split into NAL units yet. Starts right after a start code
+    buffer: Vec<u8>,
+    /// Where to resume looking for a start code in `buffer`
+    scan_from: usize,
+    eof: bool,
+    /// A NAL unit that starts the next access unit
+    pending_nal: Option<Vec<u8>>,
+    current: Option<AccessUnit>,
+    /// The number of access units before the current one
+    index: u64,
+    frame_rate: f64,
+    config: Option<StreamConfig>,
+}
+
+impl ElementaryStreamReader<BufReader<File>> {
+    /// Opens an elementary stream file. The codec is guessed from the extension of `path`.
+    ///
+    /// Without a `frame_rate`, the one signalled in the stream is used, or 30 frames per second if there's none
+    pub fn open(path: &str, frame_rate: Option<f64>) -> Result<Self, MediaStatus> {
+        let codec = ElementaryStreamCodec::from_path(path).ok_or_else(|| {
+            warn!("Can't tell the codec of {path} from its extension");
+            MediaStatus::ErrorUnsupported
+        })?;
+
+        Self::open_with_codec(path, codec, frame_rate)
+    }
+
+    /// Opens an elementary stream file of the given codec
+    pub fn open_with_codec(
+        path: &str,
+        codec: ElementaryStreamCodec,
+        frame_rate: Option<f64>,
+    ) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open elementary stream {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file), codec, frame_rate)
+    }
+}
+
+impl<R: Read> ElementaryStreamReader<R> {
+    /// Creates a reader, reading the stream up to the end of its first access unit.
+    ///
+    /// Without a `frame_rate`, the one signalled in the stream is used, or 30 frames per second if there's none
+    pub fn new(
+        reader: R,
+        codec: ElementaryStreamCodec,
+        frame_rate: Option<f64>,
+    ) -> Result<Self, MediaStatus> {
+        let mut me = Self {
+            reader,
+            codec,
+            buffer: vec![],
+            scan_from: 0,
+            eof: false,
+            pending_nal: None,
+            current: None,
+            index: 0,
+            frame_rate: frame_rate.unwrap_or(0.0),
+            config: None,
+        };
+
+        me.skip_to_first_start_code()?;
+        me.current = me.read_access_unit()?;
+
+        let first = me.current.as_ref().ok_or_else(|| {
+            warn!("No access unit found in the elementary stream");
+            MediaStatus::ErrorMalformed
+        })?;
+
+        me.config = StreamConfig::from_annexb(codec, &first.data);
+        if me.config.is_none() {
+            warn!("The elementary stream doesn't start with parameter sets");
+        }
+
+        if me.frame_rate <= 0.0 {
+            me.frame_rate = me
+                .config
+                .as_ref()
+                .and_then(|config| config.frame_rate())
+                .unwrap_or(0.0);
+        }
+
+        if !(me.frame_rate > 0.0 && me.frame_rate.is_finite()) {
+            me.frame_rate = DEFAULT_FRAME_RATE;
+        }
+
+        Ok(me)
+    }
+
+    pub fn codec(&self) -> ElementaryStreamCodec {
+        self.codec
+    }
+
+    /// The frame rate used to generate the timestamps
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    /// Returns the description of the stream, from the parameter sets at its start.
+    ///
+    /// Returns `None` if the stream doesn't start with parameter sets
+    pub fn track_format(&self) -> Option<TrackFormat> {
+        let config = self.config.as_ref()?;
+        let (width, height) = config.size().unwrap_or_default();
+
+        Some(TrackFormat {
+            mime: self.codec.mime(),
+            timescale: 90_000,
+            width,
+            height,
+            frame_rate: Some(self.frame_rate as f32),
+            codec_private: config.to_bytes(),
+            ..Default::default()
+        })
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a decoder for this stream, from the parameter sets at its start.
+    ///
+    /// Returns `None` if the stream doesn't start with parameter sets
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        self.track_format()?.into_media_format()
+    }
+
+    /// Returns whether there are still access units to read
+    pub fn has_next(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the time of the next access unit in microseconds, or -1 at the end of the stream
+    pub fn sample_time(&self) -> i64 {
+        if self.current.is_none() {
+            return -1;
+        }
+
+        (self.index as f64 * 1_000_000.0 / self.frame_rate).round() as i64
+    }
+
+    /// Returns the sample flags of the next access unit, like [MediaExtractor::sample_flags](crate::MediaExtractor::sample_flags)
+    pub fn sample_flags(&self) -> u32 {
+        match &self.current {
+            Some(unit) if unit.sync => SAMPLE_FLAG_SYNC,
+            _ => 0,
+        }
+    }
+
+    /// Returns the next access unit, in Annex-B format, without advancing the reader
+    pub fn sample_data(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|unit| unit.data.as_slice())
+    }
+
+    /// Reads the next access unit into `buffer`, without advancing the reader.
+    ///
+    /// Returns its size, or `None` if there's no access unit or `buffer` is too small
+    pub fn read_sample(&self, buffer: &mut [u8]) -> Option<usize> {
+        let data = self.sample_data()?;
+        buffer.get_mut(..data.len())?.copy_from_slice(data);
+
+        Some(data.len())
+    }
+
+    /// Read an access unit into `buffer` and advance the reader.
+    /// Returns true if there's still more data to read
+    ///
+    /// Access units that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        let time_us = self.sample_time();
+        let flags = self.sample_flags();
+
+        let data = match self.sample_data() {
+            Some(data) => data,
+            None => return false,
+        };
+
+        if buffer.write_data(data) {
+            buffer.set_time(time_us as u64);
+            buffer.set_flags(flags);
+        } else {
+            warn!(
+                "Access unit at {time_us}us doesn't fit in the input buffer ({} > {})",
+                data.len(),
+                buffer.size()
+            );
+        }
+
+        self.advance()
+    }
+
+    /// Advances to the next access unit.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        if self.current.is_none() {
+            return false;
+        }
+
+        self.index += 1;
+        // Read errors end the stream, like a truncated file
+        self.current = self.read_access_unit().unwrap_or(None);
+
+        self.has_next()
+    }
+
+    /// Reads more data from the underlying reader. Returns false at the end of the stream
+    fn fill_buffer(&mut self) -> Result<bool, MediaStatus> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let length = self.buffer.len();
+        self.buffer.resize(length + READ_CHUNK_SIZE, 0);
+
+        let count = loop {
+            match self.reader.read(&mut self.buffer[length..]) {
+                Ok(count) => break count,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    warn!("Could not read the elementary stream: {error}");
+                    self.buffer.truncate(length);
+                    return Err(MediaStatus::ErrorIO);
+                }
+            }
+        };
+
+        self.buffer.truncate(length + count);
+        self.eof = count == 0;
+
+        Ok(!self.eof)
+    }
+
+    fn skip_to_first_start_code(&mut self) -> Result<(), MediaStatus> {
+        loop {
+            if let Some((_, next)) = find_start_code(&self.buffer, 0) {
+                self.buffer.drain(..next);
+                return Ok(());
+            }
+
+            // Keep the end, it could be the start of a start code
+            let keep = self.buffer.len().min(2);
+            self.buffer.drain(..self.buffer.len() - keep);
+
+            if !self.fill_buffer()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the next NAL unit, without its start code
+    fn read_nal(&mut self) -> Result<Option<Vec<u8>>, MediaStatus> {
+        loop {
+            if let Some((end, next)) = find_start_code(&self.buffer, self.scan_from) {
+                let nal = trim_trailing_zeros(&self.buffer[..end]).to_vec();
+                self.buffer.drain(..next);
+                self.scan_from = 0;
+
+                if nal.is_empty() {
+                    continue;
+                }
+
+                return Ok(Some(nal));
+            }
+
+            if self.buffer.len() > MAX_ACCESS_UNIT_SIZE {
+                warn!("NAL unit larger than {MAX_ACCESS_UNIT_SIZE} bytes");
+                return Err(MediaStatus::ErrorMalformed);
+            }
+
+            // The start code might straddle the end of the buffer
+            self.scan_from = self.buffer.len().saturating_sub(2);
+
+            if !self.fill_buffer()? {
+                let nal = trim_trailing_zeros(&self.buffer).to_vec();
+                self.buffer.clear();
+                self.scan_from = 0;
+
+                return Ok((!nal.is_empty()).then_some(nal));
+            }
+        }
+    }
+
+    fn read_access_unit(&mut self) -> Result<Option<AccessUnit>, MediaStatus> {
+        let mut unit = AccessUnit::default();
+
+        if let Some(nal) = self.pending_nal.take() {
+            self.push_nal(&mut unit, &nal);
+        }
+
+        while let Some(nal) = self.read_nal()? {
+            if unit.has_vcl && self.codec.starts_access_unit(&nal) {
+                self.pending_nal = Some(nal);
+                break;
+            }
+
+            self.push_nal(&mut unit, &nal);
+
+            if unit.data.len() > MAX_ACCESS_UNIT_SIZE {
+                warn!("Access unit larger than {MAX_ACCESS_UNIT_SIZE} bytes");
+                return Err(MediaStatus::ErrorMalformed);
+            }
+        }
+
+        if unit.data.is_empty() {
+            return Ok(None);
+        }
+
+        if !unit.has_vcl {
+            debug!("Access unit without any picture at the end of the stream");
+        }
+
+        Ok(Some(unit))
+    }
+
+    fn push_nal(&self, unit: &mut AccessUnit, nal: &[u8]) {
+        if self.codec.is_vcl(nal) {
+            unit.has_vcl = true;
+            unit.sync |= self.codec.is_sync(nal);
+        }
+
+        unit.data.extend_from_slice(&ANNEXB_START_CODE);
+        unit.data.extend_from_slice(nal);
+    }
+}
+
+fn trim_trailing_zeros(mut data: &[u8]) -> &[u8] {
+    while let Some((0, rest)) = data.split_last() {
+        data = rest;
+    }
+
+    data
+}
+
+impl<R: Read> PacketSource for ElementaryStreamReader<R> {
+    fn track_count(&self) -> usize {
+        1
+    }
+
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        if index != 0 {
+            return None;
+        }
+
+        ElementaryStreamReader::track_format(self)
+    }
+
+    fn select_track(&mut self, _index: usize) {}
+
+    fn unselect_track(&mut self, _index: usize) {}
+
+    fn track_index(&self) -> i32 {
+        if self.has_next() {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn sample_time(&self) -> i64 {
+        ElementaryStreamReader::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        ElementaryStreamReader::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        ElementaryStreamReader::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        ElementaryStreamReader::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        ElementaryStreamReader::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        ElementaryStreamReader::read_next(self, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1080p baseline SPS and its PPS
+    const SPS: [u8; 10] = [0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    /// A reader that hands out one byte at a time, so start codes get split across reads
+    struct ByteByByte(Vec<u8>, usize);
+
+    impl Read for ByteByByte {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let Some(&byte) = self.0.get(self.1) else {
+                return Ok(0);
+            };
+
+            buffer[0] = byte;
+            self.1 += 1;
+            Ok(1)
+        }
+    }
+
+    /// A stream with garbage before the first start code, an IDR picture, a picture of two slices and a picture after an AUD
+    fn stream() -> Vec<u8> {
+        let units: [&[u8]; 7] = [
+            &SPS,
+            &PPS,
+            &[0x65, 0x88, 1, 2],
+            &[0x41, 0x9a, 3],
+            &[0x41, 0x40, 4],
+            &[0x09, 0xf0],
+            &[0x41, 0x9a, 5, 0],
+        ];
+
+        let mut data = vec![0xaa];
+        for (index, nal) in units.iter().enumerate() {
+            let start_code: &[u8] = if index % 2 == 0 {
+                &[0, 0, 0, 1]
+            } else {
+                &[0, 0, 1]
+            };
+            data.extend_from_slice(start_code);
+            data.extend_from_slice(nal);
+        }
+
+        data
+    }
+
+    #[test]
+    fn access_units() {
+        let source = ByteByByte(stream(), 0);
+        let mut reader =
+            ElementaryStreamReader::new(source, ElementaryStreamCodec::Avc, Some(25.0)).unwrap();
+
+        assert_eq!(reader.sample_time(), 0);
+        assert_eq!(reader.sample_flags(), SAMPLE_FLAG_SYNC);
+        assert_eq!(
+            reader.sample_data().unwrap().len(),
+            3 * 4 + SPS.len() + PPS.len() + 4
+        );
+
+        assert!(reader.advance());
+        assert_eq!(reader.sample_time(), 40_000);
+        assert_eq!(reader.sample_flags(), 0);
+        assert_eq!(
+            reader.sample_data().unwrap(),
+            [0, 0, 0, 1, 0x41, 0x9a, 3, 0, 0, 0, 1, 0x41, 0x40, 4]
+        );
+
+        // The trailing zero belongs to the end of the stream, not to the slice
+        assert!(reader.advance());
+        assert_eq!(
+            reader.sample_data().unwrap(),
+            [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x41, 0x9a, 5]
+        );
+
+        assert!(!reader.advance());
+        assert_eq!(reader.sample_time(), -1);
+    }
+
+    #[test]
+    fn track_format() {
+        let reader =
+            ElementaryStreamReader::new(ByteByByte(stream(), 0), ElementaryStreamCodec::Avc, None)
+                .unwrap();
+
+        let format = PacketSource::track_format(&reader, 0).unwrap();
+        assert_eq!(format.mime, "video/avc");
+        assert_eq!((format.width, format.height), (1920, 1080));
+        assert_eq!(format.frame_rate, Some(DEFAULT_FRAME_RATE as f32));
+        assert_eq!(format.codec_string().as_deref(), Some("avc1.42c028"));
+
+        assert_eq!(PacketSource::track_format(&reader, 1), None);
+    }
+}
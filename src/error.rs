@@ -88,17 +88,19 @@ impl MediaStatus {
         Err(*self)
     }
 
-    fn is_ok(&self) -> bool {
+    /// Returns true if the status isn't one of the error codes
+    pub fn is_ok(&self) -> bool {
         let mut valuez = Self::values();
         // Remove the Ok. Now, the rest are errors
         valuez.remove(0);
 
         // If we get none, there were no errors
-        return valuez.iter().find(|&&x| *self == x).is_none();
+        !valuez.contains(self)
     }
 
-    fn is_err(&self) -> bool {
-        return !self.is_ok();
+    /// Returns true if the status is one of the error codes
+    pub fn is_err(&self) -> bool {
+        !self.is_ok()
     }
 }
 
@@ -112,6 +114,6 @@ impl TryFrom<isize> for MediaStatus {
             }
         }
 
-        return Err("Not Found");
+        Err("Not Found")
     }
 }
@@ -1,13 +1,17 @@
+#[cfg(target_os = "android")]
 use std::{
     ffi::{CStr, CString},
     marker::PhantomData,
     os::raw::c_char,
 };
 
+#[cfg(target_os = "android")]
 use log::{debug, info};
 
+#[cfg(target_os = "android")]
 use crate::{AMediaFormat, CodecInputBuffer, MediaFormat, MediaStatus};
 
+#[cfg(target_os = "android")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 struct AMediaExtractor {
@@ -15,6 +19,24 @@ struct AMediaExtractor {
     _marker: core::marker::PhantomData<(*mut u8, core::marker::PhantomPinned)>,
 }
 
+/// Where `MediaExtractor::seek_to` lands, relative to the requested position
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekMode {
+    /// The sync sample at or before the position
+    PreviousSync = 0,
+    /// The sync sample at or after the position
+    NextSync = 1,
+    /// The sync sample closest to the position
+    ClosestSync = 2,
+}
+
+/// The sample is a sync sample (a keyframe)
+pub const SAMPLE_FLAG_SYNC: u32 = 1;
+/// The sample is encrypted
+pub const SAMPLE_FLAG_ENCRYPTED: u32 = 2;
+
+#[cfg(target_os = "android")]
 #[link(name = "mediandk")]
 extern "C" {
     /// Since: API 21
@@ -70,15 +92,28 @@ extern "C" {
 
     /// Since: API 21
     fn AMediaExtractor_advance(extractor: *mut AMediaExtractor) -> bool;
+
+    /// Since: API 21
+    fn AMediaExtractor_seekTo(
+        extractor: *mut AMediaExtractor,
+        seek_pos_us: i64,
+        mode: SeekMode,
+    ) -> MediaStatus;
+
+    /// Since: API 28
+    #[cfg(feature = "api28")]
+    fn AMediaExtractor_getFileFormat(extractor: *mut AMediaExtractor) -> *mut AMediaFormat;
 }
 
 /// MediaExtractor is a demuxer that opens a file or resource and demuxes the data to hand over to MediaCodec
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct MediaExtractor {
     inner: *mut AMediaExtractor,
     has_next: bool,
 }
 
+#[cfg(target_os = "android")]
 impl MediaExtractor {
     /// Creates a new MediaExtractor
     fn new() -> Self {
@@ -178,8 +213,61 @@ impl MediaExtractor {
     pub fn has_next(&self) -> bool {
         self.has_next
     }
+
+    /// Reads the current packet into `buffer`, without advancing the extractor.
+    ///
+    /// Returns the number of bytes read, or `None` if there's no packet or `buffer` is too small
+    pub fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if !self.has_next {
+            return None;
+        }
+
+        let count = unsafe {
+            AMediaExtractor_readSampleData(self.inner, buffer.as_mut_ptr(), buffer.len())
+        };
+
+        if count < 0 {
+            return None;
+        }
+
+        Some(count as usize)
+    }
+
+    /// Advances to the next packet.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        if self.has_next {
+            self.has_next = unsafe { AMediaExtractor_advance(self.inner) };
+        }
+
+        self.has_next
+    }
+
+    /// Seeks all selected tracks to `time_us`. Where the tracks land depends on `mode`
+    pub fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        unsafe { AMediaExtractor_seekTo(self.inner, time_us, mode) }.result()?;
+
+        // Seeking can bring us back from the end of the stream
+        self.has_next = self.sample_time() >= 0;
+
+        Ok(())
+    }
+
+    /// Returns the format of the container itself, with things like the duration or the location it was recorded at
+    #[cfg(feature = "api28")]
+    pub fn file_format(&self) -> Option<MediaFormat> {
+        unsafe {
+            let format = AMediaExtractor_getFileFormat(self.inner);
+            if format.is_null() {
+                return None;
+            }
+
+            Some(MediaFormat::from_raw(format))
+        }
+    }
 }
 
+#[cfg(target_os = "android")]
 impl Drop for MediaExtractor {
     fn drop(&mut self) {
         unsafe {
@@ -189,5 +277,7 @@ impl Drop for MediaExtractor {
     }
 }
 
+#[cfg(target_os = "android")]
 unsafe impl Send for MediaExtractor {}
+#[cfg(target_os = "android")]
 unsafe impl Sync for MediaExtractor {}
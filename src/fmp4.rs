@@ -0,0 +1,1042 @@
+use std::collections::VecDeque;
+
+use log::warn;
+
+use crate::{
+    annexb_to_length_prefixed, MediaStatus, OpusHead, TrackFormat, Vp9FrameHeader,
+    VpCodecConfigurationRecord,
+};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecOutputBuffer, MediaFormat};
+
+/// The sample flags of sync samples in fragments: they don't depend on other samples
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// The sample flags of other samples in fragments: they depend on others, and are marked as non sync samples
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// tfhd flags: the data offsets are relative to the start of the `moof`
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x20000;
+/// trun flags: data offset, and sample duration, size, flags and composition time offset
+const TRUN_FLAGS: u32 = 0x1 | 0x100 | 0x200 | 0x400 | 0x800;
+
+/// The codecs the muxer can write a sample entry for
+const FMP4_MIMES: &[&str] = &[
+    "video/avc",
+    "video/hevc",
+    "video/av01",
+    "video/x-vnd.on2.vp8",
+    "video/x-vnd.on2.vp9",
+    "audio/mp4a-latm",
+    "audio/opus",
+    "audio/flac",
+];
+
+/// A media segment written by [Fmp4Muxer](Fmp4Muxer)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fmp4Segment {
+    /// The sequence number of the fragment, starting at 1
+    pub sequence_number: u32,
+    pub start_us: i64,
+    pub duration_us: i64,
+    /// The segment starts with a sync sample on every video track, so playback can start there
+    pub independent: bool,
+    /// The `styp`, `moof` and `mdat` boxes
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct PendingSample {
+    data: Vec<u8>,
+    /// The presentation time, in timescale units
+    pts: i64,
+    sync: bool,
+}
+
+/// The samples of a track in a segment, ready to be written
+#[derive(Debug)]
+struct TrackRun {
+    track_id: u32,
+    base_dts: i64,
+    /// Duration, size, flags and composition time offset of each sample
+    entries: Vec<(u32, u32, u32, i32)>,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Fmp4Track {
+    format: TrackFormat,
+    /// The samples of the segment being built
+    samples: Vec<PendingSample>,
+    /// The duration of the last sample written, used when the duration of a sample can't be known
+    last_duration: u32,
+    /// Where the last run ended, the decode times of the next one can't go back before it
+    end_dts: Option<i64>,
+}
+
+impl Fmp4Track {
+    fn us_to_ticks(&self, time_us: i64) -> i64 {
+        let timescale = self.format.timescale as i128;
+        ((time_us as i128 * timescale + 500_000).div_euclid(1_000_000)) as i64
+    }
+
+    fn ticks_to_us(&self, ticks: i64) -> i64 {
+        (ticks as i128 * 1_000_000 / self.format.timescale.max(1) as i128) as i64
+    }
+
+    /// Whether the sample entry can be written
+    fn is_ready(&self) -> bool {
+        match self.format.mime {
+            // VP8 and VP9 get along with a default record
+            "video/x-vnd.on2.vp8" | "video/x-vnd.on2.vp9" => true,
+            _ => !self.format.codec_private.is_empty(),
+        }
+    }
+
+    /// Takes the first `count` pending samples, giving them decode times and durations.
+    ///
+    /// Encoders only give presentation times. The decode times are the same times in increasing order, so samples
+    /// reordered by B-frames get negative composition offsets. `end` is where the last sample ends, when known.
+    ///
+    /// With open GOPs, the B-frames after the sync sample starting a run are presented before it, and before the end of
+    /// the previous run. Their decode times are pushed after the previous run, so they keep increasing across runs
+    fn take_run(&mut self, count: usize, end: Option<i64>) -> Option<TrackRun> {
+        if count == 0 {
+            return None;
+        }
+
+        let samples: Vec<_> = self.samples.drain(..count).collect();
+
+        let mut dts: Vec<i64> = samples.iter().map(|sample| sample.pts).collect();
+        dts.sort_unstable();
+
+        let mut minimum = self.end_dts;
+        for time in dts.iter_mut() {
+            if let Some(minimum) = minimum {
+                *time = (*time).max(minimum);
+            }
+            minimum = Some(*time + 1);
+        }
+
+        let mut entries = Vec::with_capacity(samples.len());
+        let mut data = vec![];
+
+        for (index, sample) in samples.iter().enumerate() {
+            let next = dts.get(index + 1).copied().or(end);
+            let duration = match next.map(|next| next - dts[index]) {
+                Some(duration) if duration > 0 => duration.min(u32::MAX as i64) as u32,
+                _ => self.last_duration,
+            };
+            self.last_duration = duration;
+
+            let flags = if sample.sync {
+                SYNC_SAMPLE_FLAGS
+            } else {
+                NON_SYNC_SAMPLE_FLAGS
+            };
+            let cts_offset = (sample.pts - dts[index]) as i32;
+
+            entries.push((duration, sample.data.len() as u32, flags, cts_offset));
+            data.extend_from_slice(&sample.data);
+        }
+
+        let duration: i64 = entries.iter().map(|entry| entry.0 as i64).sum();
+        self.end_dts = Some(dts[0] + duration);
+
+        Some(TrackRun {
+            track_id: self.format.track_id,
+            base_dts: dts[0],
+            entries,
+            data,
+        })
+    }
+}
+
+/// Writes fragmented MP4 (CMAF style) for HLS and DASH, as the encoders produce the samples.
+///
+/// There's one init segment, with the codec configuration of the tracks, then media segments that are cut at the first
+/// sync sample of the first video track (or the first track) once they are at least the target duration long.
+/// Every media segment has one fragment with all the tracks. For DASH, which wants one track per representation,
+/// use one muxer per track.
+///
+/// Segments are queued as they are completed, and handed out by [take_segment](Self::take_segment)
+#[derive(Debug)]
+pub struct Fmp4Muxer {
+    tracks: Vec<Fmp4Track>,
+    target_duration_us: i64,
+    sequence_number: u32,
+    /// The start of the segment being built
+    segment_start_us: Option<i64>,
+    segments: VecDeque<Fmp4Segment>,
+}
+
+impl Fmp4Muxer {
+    /// Creates a muxer cutting segments of at least `target_duration_us`
+    pub fn new(target_duration_us: i64) -> Self {
+        Self {
+            tracks: vec![],
+            target_duration_us,
+            sequence_number: 0,
+            segment_start_us: None,
+            segments: VecDeque::new(),
+        }
+    }
+
+    /// Adds a track, returning its index.
+    ///
+    /// The codec private data can come later from codec config buffers, the init segment is only available once it's there
+    pub fn add_track(&mut self, mut format: TrackFormat) -> Result<usize, MediaStatus> {
+        if !FMP4_MIMES.contains(&format.mime) {
+            warn!("Can't write {} in fragmented MP4", format.mime);
+            return Err(MediaStatus::ErrorUnsupported);
+        }
+
+        if format.timescale == 0 {
+            format.timescale = match format.sample_rate {
+                0 => 90_000,
+                sample_rate => sample_rate,
+            };
+        }
+
+        format.track_id = self.tracks.len() as u32 + 1;
+        if matches!(format.mime, "video/avc" | "video/hevc") {
+            format.nal_length_size = Some(4);
+        }
+
+        self.tracks.push(Fmp4Track {
+            format,
+            samples: vec![],
+            last_duration: 0,
+            end_dts: None,
+        });
+
+        Ok(self.tracks.len() - 1)
+    }
+
+    /// Adds a track for the output format of an encoder, returning its index
+    #[cfg(target_os = "android")]
+    pub fn add_media_format(&mut self, format: &MediaFormat) -> Result<usize, MediaStatus> {
+        let track = TrackFormat::from_media_format(format).ok_or(MediaStatus::ErrorUnsupported)?;
+        self.add_track(track)
+    }
+
+    /// Returns the number of tracks
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns the description of a track, as it will be written in the init segment
+    pub fn track(&self, index: usize) -> Option<&TrackFormat> {
+        self.tracks.get(index).map(|track| &track.format)
+    }
+
+    /// Returns the init segment (`ftyp` and `moov`), or `None` while the codec configuration of a track is missing
+    pub fn init_segment(&self) -> Option<Vec<u8>> {
+        if self.tracks.is_empty() || !self.tracks.iter().all(|track| track.is_ready()) {
+            return None;
+        }
+
+        let mut output = vec![];
+
+        write_box(&mut output, b"ftyp", |output| {
+            output.extend_from_slice(b"iso6");
+            output.extend_from_slice(&0u32.to_be_bytes());
+            for brand in [b"iso6", b"cmfc", b"dash"] {
+                output.extend_from_slice(brand);
+            }
+        });
+
+        write_box(&mut output, b"moov", |output| {
+            write_mvhd(output, self.tracks.len() as u32 + 1);
+
+            for track in &self.tracks {
+                write_trak(output, &track.format);
+            }
+
+            write_box(output, b"mvex", |output| {
+                for track in &self.tracks {
+                    write_full_box(output, b"trex", 0, 0, |output| {
+                        output.extend_from_slice(&track.format.track_id.to_be_bytes());
+                        // Sample description index, then default duration, size and flags
+                        output.extend_from_slice(&1u32.to_be_bytes());
+                        output.extend_from_slice(&[0; 12]);
+                    });
+                }
+            });
+        });
+
+        Some(output)
+    }
+
+    /// Writes an encoder output buffer for `track`.
+    ///
+    /// Codec config buffers set the codec private data of the track, and end of stream buffers are ignored
+    #[cfg(target_os = "android")]
+    pub fn write(&mut self, track: usize, buffer: &CodecOutputBuffer) -> Result<(), MediaStatus> {
+        let entry = self
+            .tracks
+            .get_mut(track)
+            .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        let info = *buffer.info();
+        let flags = info.flags() as i32;
+
+        let data = match buffer.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return Ok(()),
+        };
+
+        if BufferFlag::CodecConfig.is_contained_in(flags) {
+            if !entry.format.set_codec_config(data) {
+                warn!("Invalid codec config for track {track}");
+            }
+
+            return Ok(());
+        }
+
+        let sync = BufferFlag::Encode.is_contained_in(flags);
+        self.write_sample(track, data, info.presentation_time_us(), sync)
+    }
+
+    /// Writes a sample for `track`. AVC and HEVC samples are expected in Annex-B format, like encoders output them.
+    ///
+    /// Writing a sync sample on the first video track completes the current segment if it's long enough
+    pub fn write_sample(
+        &mut self,
+        track: usize,
+        data: &[u8],
+        pts_us: i64,
+        sync: bool,
+    ) -> Result<(), MediaStatus> {
+        if track >= self.tracks.len() {
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        if sync && track == self.primary_track() {
+            if let Some(start_us) = self.segment_start_us {
+                if pts_us - start_us >= self.target_duration_us {
+                    self.flush(Some(pts_us));
+                }
+            }
+        }
+
+        let entry = &mut self.tracks[track];
+
+        // VP9 encoders don't give any codec config, the record comes from the first key frame
+        if entry.format.mime == "video/x-vnd.on2.vp9" && entry.format.codec_private.is_empty() {
+            if let Some(record) = Vp9FrameHeader::parse(data)
+                .and_then(|header| VpCodecConfigurationRecord::from_vp9_header(&header))
+            {
+                entry.format.codec_private = record.to_bytes();
+            }
+        }
+
+        let data = match entry.format.nal_length_size {
+            Some(length_size) => annexb_to_length_prefixed(data, length_size as usize),
+            None => data.to_vec(),
+        };
+
+        let pts = entry.us_to_ticks(pts_us);
+        entry.samples.push(PendingSample { data, pts, sync });
+
+        self.segment_start_us.get_or_insert(pts_us);
+
+        Ok(())
+    }
+
+    /// Completes the last segment with all the samples that are left
+    pub fn finish(&mut self) {
+        self.flush(None);
+    }
+
+    /// Returns the oldest completed segment that wasn't taken yet
+    pub fn take_segment(&mut self) -> Option<Fmp4Segment> {
+        self.segments.pop_front()
+    }
+
+    /// The track that decides where segments are cut: the first video track, or the first track
+    fn primary_track(&self) -> usize {
+        self.tracks
+            .iter()
+            .position(|track| track.format.is_video())
+            .unwrap_or(0)
+    }
+
+    /// Writes a segment with the pending samples, cut at `cut_us` when it's in the middle of the stream.
+    ///
+    /// When cutting in the middle of the stream, the last sample of the other tracks stays for the next segment,
+    /// so that its duration can be known
+    fn flush(&mut self, cut_us: Option<i64>) {
+        let primary = self.primary_track();
+        let Some(start_us) = self.segment_start_us else {
+            return;
+        };
+
+        let mut runs = vec![];
+        let mut independent = true;
+        let mut end_of_samples_us = start_us;
+
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            let end = match cut_us {
+                Some(cut_us) if index == primary => Some(track.us_to_ticks(cut_us)),
+                Some(_) => track.samples.last().map(|sample| sample.pts),
+                None => None,
+            };
+            let count = match cut_us {
+                Some(_) if index != primary => track.samples.len().saturating_sub(1),
+                _ => track.samples.len(),
+            };
+
+            if track.format.is_video() && track.samples.first().is_some_and(|sample| !sample.sync) {
+                independent = false;
+            }
+
+            if let Some(run) = track.take_run(count, end) {
+                let duration: i64 = run.entries.iter().map(|entry| entry.0 as i64).sum();
+                end_of_samples_us =
+                    end_of_samples_us.max(track.ticks_to_us(run.base_dts + duration));
+                runs.push(run);
+            }
+        }
+
+        if runs.is_empty() {
+            return;
+        }
+
+        self.sequence_number += 1;
+        let end_us = cut_us.unwrap_or(end_of_samples_us);
+
+        let mut data = vec![];
+        write_box(&mut data, b"styp", |output| {
+            output.extend_from_slice(b"msdh");
+            output.extend_from_slice(&0u32.to_be_bytes());
+            output.extend_from_slice(b"msdh");
+            output.extend_from_slice(b"msix");
+        });
+
+        // The data offsets depend on the size of the moof, which doesn't depend on the offsets
+        let moof_size = moof_bytes(self.sequence_number, &runs, 0).len();
+        data.extend(moof_bytes(self.sequence_number, &runs, moof_size + 8));
+
+        write_box(&mut data, b"mdat", |output| {
+            for run in &runs {
+                output.extend_from_slice(&run.data);
+            }
+        });
+
+        self.segments.push_back(Fmp4Segment {
+            sequence_number: self.sequence_number,
+            start_us,
+            duration_us: end_us - start_us,
+            independent,
+            data,
+        });
+
+        // Segments follow each other, the next one starts where this one was cut
+        self.segment_start_us = cut_us;
+    }
+}
+
+/// Writes a box, with the content written by `content`
+fn write_box(output: &mut Vec<u8>, box_type: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = output.len();
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(box_type);
+
+    content(output);
+
+    let size = (output.len() - start) as u32;
+    output[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Writes a full box, with a version and flags before the content
+fn write_full_box(
+    output: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(output, box_type, |output| {
+        output.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+        content(output);
+    });
+}
+
+/// The `moof` of a segment, `data_offset` being where the first sample is relative to the start of the `moof`
+fn moof_bytes(sequence_number: u32, runs: &[TrackRun], mut data_offset: usize) -> Vec<u8> {
+    let mut output = vec![];
+
+    write_box(&mut output, b"moof", |output| {
+        write_full_box(output, b"mfhd", 0, 0, |output| {
+            output.extend_from_slice(&sequence_number.to_be_bytes());
+        });
+
+        for run in runs {
+            write_box(output, b"traf", |output| {
+                write_full_box(output, b"tfhd", 0, TFHD_DEFAULT_BASE_IS_MOOF, |output| {
+                    output.extend_from_slice(&run.track_id.to_be_bytes());
+                });
+
+                write_full_box(output, b"tfdt", 1, 0, |output| {
+                    output.extend_from_slice(&(run.base_dts.max(0) as u64).to_be_bytes());
+                });
+
+                // Version 1 for signed composition time offsets
+                write_full_box(output, b"trun", 1, TRUN_FLAGS, |output| {
+                    output.extend_from_slice(&(run.entries.len() as u32).to_be_bytes());
+                    output.extend_from_slice(&(data_offset as u32).to_be_bytes());
+
+                    for &(duration, size, flags, cts_offset) in &run.entries {
+                        output.extend_from_slice(&duration.to_be_bytes());
+                        output.extend_from_slice(&size.to_be_bytes());
+                        output.extend_from_slice(&flags.to_be_bytes());
+                        output.extend_from_slice(&cts_offset.to_be_bytes());
+                    }
+                });
+            });
+
+            data_offset += run.data.len();
+        }
+    });
+
+    output
+}
+
+/// The unity matrix, rotated clockwise by `rotation` degrees. Values are 16.16 fixed point, except the last column
+fn rotation_matrix(rotation: i32) -> [i32; 9] {
+    const ONE: i32 = 0x10000;
+    const W: i32 = 0x4000_0000;
+
+    match rotation.rem_euclid(360) {
+        90 => [0, ONE, 0, -ONE, 0, 0, 0, 0, W],
+        180 => [-ONE, 0, 0, 0, -ONE, 0, 0, 0, W],
+        270 => [0, -ONE, 0, ONE, 0, 0, 0, 0, W],
+        _ => [ONE, 0, 0, 0, ONE, 0, 0, 0, W],
+    }
+}
+
+fn write_mvhd(output: &mut Vec<u8>, next_track_id: u32) {
+    write_full_box(output, b"mvhd", 0, 0, |output| {
+        // Creation and modification times, timescale and duration
+        output.extend_from_slice(&[0; 8]);
+        output.extend_from_slice(&1000u32.to_be_bytes());
+        output.extend_from_slice(&0u32.to_be_bytes());
+        // Rate, volume and reserved
+        output.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+        output.extend_from_slice(&0x0100u16.to_be_bytes());
+        output.extend_from_slice(&[0; 10]);
+        for value in rotation_matrix(0) {
+            output.extend_from_slice(&value.to_be_bytes());
+        }
+        // Pre-defined
+        output.extend_from_slice(&[0; 24]);
+        output.extend_from_slice(&next_track_id.to_be_bytes());
+    });
+}
+
+fn write_trak(output: &mut Vec<u8>, format: &TrackFormat) {
+    write_box(output, b"trak", |output| {
+        // Enabled and in movie
+        write_full_box(output, b"tkhd", 0, 3, |output| {
+            output.extend_from_slice(&[0; 8]);
+            output.extend_from_slice(&format.track_id.to_be_bytes());
+            output.extend_from_slice(&[0; 4]);
+            output.extend_from_slice(&0u32.to_be_bytes());
+            // Reserved, layer and alternate group
+            output.extend_from_slice(&[0; 12]);
+            let volume: u16 = if format.is_audio() { 0x0100 } else { 0 };
+            output.extend_from_slice(&volume.to_be_bytes());
+            output.extend_from_slice(&[0; 2]);
+            for value in rotation_matrix(format.rotation) {
+                output.extend_from_slice(&value.to_be_bytes());
+            }
+            output.extend_from_slice(&(format.width << 16).to_be_bytes());
+            output.extend_from_slice(&(format.height << 16).to_be_bytes());
+        });
+
+        write_box(output, b"mdia", |output| {
+            write_full_box(output, b"mdhd", 0, 0, |output| {
+                output.extend_from_slice(&[0; 8]);
+                output.extend_from_slice(&format.timescale.to_be_bytes());
+                output.extend_from_slice(&0u32.to_be_bytes());
+                output.extend_from_slice(&language_code(format.language.as_deref()).to_be_bytes());
+                output.extend_from_slice(&[0; 2]);
+            });
+
+            let (handler, name): (&[u8; 4], &[u8]) = if format.is_video() {
+                (b"vide", b"VideoHandler\0")
+            } else {
+                (b"soun", b"SoundHandler\0")
+            };
+            write_full_box(output, b"hdlr", 0, 0, |output| {
+                output.extend_from_slice(&[0; 4]);
+                output.extend_from_slice(handler);
+                output.extend_from_slice(&[0; 12]);
+                output.extend_from_slice(name);
+            });
+
+            write_box(output, b"minf", |output| {
+                if format.is_video() {
+                    write_full_box(output, b"vmhd", 0, 1, |output| {
+                        output.extend_from_slice(&[0; 8]);
+                    });
+                } else {
+                    write_full_box(output, b"smhd", 0, 0, |output| {
+                        output.extend_from_slice(&[0; 4]);
+                    });
+                }
+
+                write_box(output, b"dinf", |output| {
+                    write_full_box(output, b"dref", 0, 0, |output| {
+                        output.extend_from_slice(&1u32.to_be_bytes());
+                        // The data is in the same file
+                        write_full_box(output, b"url ", 0, 1, |_| {});
+                    });
+                });
+
+                write_box(output, b"stbl", |output| {
+                    write_full_box(output, b"stsd", 0, 0, |output| {
+                        output.extend_from_slice(&1u32.to_be_bytes());
+                        if format.is_video() {
+                            write_visual_sample_entry(output, format);
+                        } else {
+                            write_audio_sample_entry(output, format);
+                        }
+                    });
+
+                    // The samples are all in the fragments
+                    for box_type in [b"stts", b"stsc", b"stco"] {
+                        write_full_box(output, box_type, 0, 0, |output| {
+                            output.extend_from_slice(&0u32.to_be_bytes());
+                        });
+                    }
+                    write_full_box(output, b"stsz", 0, 0, |output| {
+                        output.extend_from_slice(&[0; 8]);
+                    });
+                });
+            });
+        });
+    });
+}
+
+/// Packs an ISO 639-2 language code in the 15 bits of `mdhd`
+fn language_code(language: Option<&str>) -> u16 {
+    let language = match language {
+        Some(language)
+            if language.len() == 3 && language.bytes().all(|c| c.is_ascii_lowercase()) =>
+        {
+            language
+        }
+        _ => "und",
+    };
+
+    language
+        .bytes()
+        .fold(0, |code, c| (code << 5) | (c - 0x60) as u16)
+}
+
+fn write_visual_sample_entry(output: &mut Vec<u8>, format: &TrackFormat) {
+    let entry_type = match format.mime {
+        "video/avc" => b"avc1",
+        "video/hevc" => b"hvc1",
+        "video/av01" => b"av01",
+        "video/x-vnd.on2.vp8" => b"vp08",
+        _ => b"vp09",
+    };
+
+    write_box(output, entry_type, |output| {
+        // Reserved and data reference index
+        output.extend_from_slice(&[0; 6]);
+        output.extend_from_slice(&1u16.to_be_bytes());
+        // Pre-defined and reserved
+        output.extend_from_slice(&[0; 16]);
+        output.extend_from_slice(&(format.width as u16).to_be_bytes());
+        output.extend_from_slice(&(format.height as u16).to_be_bytes());
+        // 72 dpi
+        output.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+        output.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+        output.extend_from_slice(&[0; 4]);
+        // Frame count, compressor name, depth and pre-defined
+        output.extend_from_slice(&1u16.to_be_bytes());
+        output.extend_from_slice(&[0; 32]);
+        output.extend_from_slice(&0x0018u16.to_be_bytes());
+        output.extend_from_slice(&(-1i16).to_be_bytes());
+
+        match format.mime {
+            "video/avc" => write_box(output, b"avcC", |output| {
+                output.extend_from_slice(&format.codec_private)
+            }),
+            "video/hevc" => write_box(output, b"hvcC", |output| {
+                output.extend_from_slice(&format.codec_private)
+            }),
+            "video/av01" => write_box(output, b"av1C", |output| {
+                output.extend_from_slice(&format.codec_private)
+            }),
+            _ => {
+                let record = match format.codec_private.is_empty() {
+                    false => format.codec_private.clone(),
+                    true => default_vp_record(format).to_bytes(),
+                };
+                write_full_box(output, b"vpcC", 1, 0, |output| {
+                    output.extend_from_slice(&record)
+                });
+            }
+        }
+
+        if let Some(color) = format.color {
+            write_box(output, b"colr", |output| {
+                output.extend_from_slice(b"nclx");
+                output.extend_from_slice(&(color.primaries as u16).to_be_bytes());
+                output.extend_from_slice(&(color.transfer as u16).to_be_bytes());
+                output.extend_from_slice(&(color.matrix as u16).to_be_bytes());
+                output.push(if color.full_range { 0x80 } else { 0 });
+            });
+        }
+    });
+}
+
+/// The record of 8 bit 4:2:0 VP8 or VP9, for when the encoder didn't give anything better
+fn default_vp_record(format: &TrackFormat) -> VpCodecConfigurationRecord {
+    VpCodecConfigurationRecord {
+        profile: 0,
+        level: 0,
+        bit_depth: 8,
+        chroma_subsampling: 1,
+        color: format.color.unwrap_or_default(),
+        codec_initialization_data: vec![],
+    }
+}
+
+fn write_audio_sample_entry(output: &mut Vec<u8>, format: &TrackFormat) {
+    let entry_type = match format.mime {
+        "audio/opus" => b"Opus",
+        "audio/flac" => b"fLaC",
+        _ => b"mp4a",
+    };
+
+    write_box(output, entry_type, |output| {
+        // Reserved and data reference index
+        output.extend_from_slice(&[0; 6]);
+        output.extend_from_slice(&1u16.to_be_bytes());
+        output.extend_from_slice(&[0; 8]);
+        output.extend_from_slice(&(format.channel_count as u16).to_be_bytes());
+        output.extend_from_slice(&16u16.to_be_bytes());
+        output.extend_from_slice(&[0; 4]);
+        // 16.16 fixed point, rates that don't fit are left to the codec configuration
+        let sample_rate = if format.sample_rate <= 0xffff {
+            format.sample_rate << 16
+        } else {
+            0
+        };
+        output.extend_from_slice(&sample_rate.to_be_bytes());
+
+        match format.mime {
+            "audio/opus" => {
+                if let Some(head) = OpusHead::parse(&format.codec_private) {
+                    write_box(output, b"dOps", |output| write_dops(output, &head));
+                }
+            }
+            "audio/flac" => write_full_box(output, b"dfLa", 0, 0, |output| {
+                output.extend_from_slice(&format.codec_private)
+            }),
+            _ => write_full_box(output, b"esds", 0, 0, |output| {
+                write_es_descriptor(output, format)
+            }),
+        }
+    });
+}
+
+/// Writes the `dOps` box content, the big endian version of the `OpusHead`
+fn write_dops(output: &mut Vec<u8>, head: &OpusHead) {
+    output.push(0);
+    output.push(head.channel_count);
+    output.extend_from_slice(&head.pre_skip.to_be_bytes());
+    output.extend_from_slice(&head.input_sample_rate.to_be_bytes());
+    output.extend_from_slice(&head.output_gain.to_be_bytes());
+    output.push(head.mapping_family);
+
+    if head.mapping_family != 0 {
+        output.push(head.stream_count);
+        output.push(head.coupled_count);
+        output.extend_from_slice(&head.channel_mapping);
+    }
+}
+
+/// Writes an MPEG-4 descriptor, with the size on 4 bytes
+fn write_descriptor(output: &mut Vec<u8>, tag: u8, content: impl FnOnce(&mut Vec<u8>)) {
+    output.push(tag);
+    let start = output.len();
+    output.extend_from_slice(&[0; 4]);
+
+    content(output);
+
+    let size = output.len() - start - 4;
+    for (index, shift) in [21, 14, 7, 0].iter().enumerate() {
+        let more = if *shift > 0 { 0x80 } else { 0 };
+        output[start + index] = more | ((size >> shift) & 0x7f) as u8;
+    }
+}
+
+/// Writes the ES descriptor of AAC, with the AudioSpecificConfig
+fn write_es_descriptor(output: &mut Vec<u8>, format: &TrackFormat) {
+    write_descriptor(output, 3, |output| {
+        output.extend_from_slice(&(format.track_id as u16).to_be_bytes());
+        output.push(0);
+
+        write_descriptor(output, 4, |output| {
+            // MPEG-4 audio, audio stream
+            output.push(0x40);
+            output.push(0x15);
+            // Buffer size, max and average bitrates
+            output.extend_from_slice(&[0; 11]);
+
+            write_descriptor(output, 5, |output| {
+                output.extend_from_slice(&format.codec_private)
+            });
+        });
+
+        // SL config, predefined for MP4
+        write_descriptor(output, 6, |output| output.push(2));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        Av1CodecConfigurationRecord, HevcDecoderConfigurationRecord, HevcProfileTierLevel,
+        Mp4Demuxer, VideoColorInfo, ANNEXB_START_CODE,
+    };
+
+    /// A 1080p baseline SPS and its PPS
+    const SPS: [u8; 10] = [0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    /// An AAC-LC config, 48 kHz stereo
+    const AAC_CONFIG: [u8; 2] = [0x11, 0x90];
+
+    fn video_track() -> TrackFormat {
+        let mut track = TrackFormat {
+            mime: "video/avc",
+            width: 1920,
+            height: 1080,
+            frame_rate: Some(30.0),
+            ..Default::default()
+        };
+        let config = [&ANNEXB_START_CODE[..], &SPS, &ANNEXB_START_CODE, &PPS].concat();
+        assert!(track.set_codec_config(&config));
+        track
+    }
+
+    fn audio_track() -> TrackFormat {
+        let mut track = TrackFormat {
+            mime: "audio/mp4a-latm",
+            sample_rate: 48000,
+            channel_count: 2,
+            ..Default::default()
+        };
+        assert!(track.set_codec_config(&AAC_CONFIG));
+        track
+    }
+
+    /// The frame numbers of 3 GOPs of 30 frames at 30 fps in decode order, with B-frames and open GOPs: the sync frame
+    /// starting a GOP is followed by the B-frames presented before it
+    fn decode_order() -> Vec<(i64, bool)> {
+        let mut frames = vec![(0, true)];
+        let mut frame = 0;
+        while frame + 3 < 90 {
+            let sync = (frame + 3) % 30 == 0;
+            frames.extend([(frame + 3, sync), (frame + 1, false), (frame + 2, false)]);
+            frame += 3;
+        }
+
+        frames
+    }
+
+    /// Muxes the video frames and AAC frames up to the time of each video frame, returns the init segment and the
+    /// media segments
+    fn mux(frames: &[(i64, bool)]) -> (Vec<u8>, Vec<Fmp4Segment>, usize) {
+        let mut muxer = Fmp4Muxer::new(1_000_000);
+        assert_eq!(muxer.add_track(video_track()), Ok(0));
+        assert_eq!(muxer.add_track(audio_track()), Ok(1));
+        let init = muxer.init_segment().unwrap();
+
+        let mut audio_frames = 0;
+        for (index, &(frame, sync)) in frames.iter().enumerate() {
+            let header = if sync { 0x65 } else { 0x41 };
+            let data = [0, 0, 0, 1, header, frame as u8 | 0x80];
+            muxer
+                .write_sample(0, &data, frame * 1_000_000 / 30, sync)
+                .unwrap();
+
+            while audio_frames * 1024 * 30 < index as i64 * 48000 {
+                let pts_us = audio_frames * 1024 * 1_000_000 / 48000;
+                muxer.write_sample(1, &[0x21, 0x10], pts_us, true).unwrap();
+                audio_frames += 1;
+            }
+        }
+        muxer.finish();
+
+        let segments = std::iter::from_fn(|| muxer.take_segment()).collect();
+        (init, segments, audio_frames as usize)
+    }
+
+    #[test]
+    fn segments() {
+        let frames = decode_order();
+        let (_, segments, _) = mux(&frames);
+
+        // Cut at the sync frames, once a second
+        assert_eq!(segments.len(), 3);
+        for (index, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.sequence_number, index as u32 + 1);
+            assert_eq!(segment.start_us, index as i64 * 1_000_000);
+            assert!(segment.independent);
+            assert_eq!(&segment.data[4..8], b"styp");
+        }
+        assert_eq!(segments[0].duration_us, 1_000_000);
+        assert_eq!(segments[1].duration_us, 1_000_000);
+
+        // Without a sync frame, a segment goes on past the target duration
+        let no_sync: Vec<_> = frames
+            .iter()
+            .map(|&(frame, _)| (frame, frame == 0))
+            .collect();
+        let (_, segments, _) = mux(&no_sync);
+        assert_eq!(segments.len(), 1);
+        assert!(segments[0].duration_us >= 2_900_000);
+    }
+
+    #[test]
+    fn demuxer_round_trip() {
+        let frames = decode_order();
+        let (init, segments, audio_frames) = mux(&frames);
+        let file = [
+            init,
+            segments
+                .into_iter()
+                .flat_map(|segment| segment.data)
+                .collect(),
+        ]
+        .concat();
+
+        let demuxer = Mp4Demuxer::new(Cursor::new(file)).unwrap();
+        assert!(demuxer.is_fragmented());
+        assert_eq!(demuxer.track_count(), 2);
+
+        let video = demuxer.track(0).unwrap();
+        assert_eq!((video.width, video.height), (1920, 1080));
+        assert_eq!(video.timescale, 90_000);
+        assert_eq!(video.codec_string().as_deref(), Some("avc1.42c028"));
+
+        let audio = demuxer.track(1).unwrap();
+        assert_eq!(audio.timescale, 48000);
+        assert_eq!((audio.sample_rate, audio.channel_count), (48000, 2));
+        assert_eq!(audio.codec_private, AAC_CONFIG);
+        assert_eq!(audio.codec_string().as_deref(), Some("mp4a.40.2"));
+
+        // The samples keep the decode order and the presentation times. Each one starts where the previous one ends,
+        // across segments too, even with the B-frames of open GOPs presented before their segment starts
+        let samples = demuxer.samples(0).unwrap();
+        assert_eq!(samples.len(), frames.len());
+        for (sample, &(frame, sync)) in samples.iter().zip(&frames) {
+            assert_eq!(sample.dts + sample.cts_offset as i64, frame * 3000);
+            assert_eq!(sample.sync, sync);
+            assert_eq!(sample.size, 6);
+        }
+        for pair in samples.windows(2) {
+            assert!(pair[0].duration > 0);
+            assert_eq!(pair[0].dts + pair[0].duration as i64, pair[1].dts);
+        }
+
+        let samples = demuxer.samples(1).unwrap();
+        assert_eq!(samples.len(), audio_frames);
+        for (index, sample) in samples.iter().enumerate() {
+            assert_eq!(sample.dts, index as i64 * 1024);
+            assert_eq!((sample.duration, sample.cts_offset), (1024, 0));
+        }
+    }
+
+    #[test]
+    fn codec_strings() {
+        let hevc = HevcDecoderConfigurationRecord {
+            profile_tier_level: HevcProfileTierLevel {
+                profile_space: 0,
+                tier: false,
+                profile_idc: 2,
+                profile_compatibility_flags: 1 << (31 - 2),
+                constraint_indicator_flags: 0x9000_0000_0000,
+                level_idc: 120,
+            },
+            min_spatial_segmentation_idc: 0,
+            parallelism_type: 0,
+            chroma_format: 1,
+            bit_depth_luma: 10,
+            bit_depth_chroma: 10,
+            avg_frame_rate: 0,
+            constant_frame_rate: 0,
+            num_temporal_layers: 1,
+            temporal_id_nested: true,
+            length_size: 4,
+            arrays: vec![],
+        };
+
+        let av1 = Av1CodecConfigurationRecord {
+            seq_profile: 0,
+            seq_level_idx: 8,
+            seq_tier: false,
+            high_bitdepth: true,
+            twelve_bit: false,
+            mono_chrome: false,
+            chroma_subsampling_x: true,
+            chroma_subsampling_y: true,
+            chroma_sample_position: 0,
+            initial_presentation_delay: None,
+            config_obus: vec![],
+        };
+
+        let vp9 = VpCodecConfigurationRecord {
+            profile: 2,
+            level: 31,
+            bit_depth: 10,
+            chroma_subsampling: 1,
+            color: VideoColorInfo {
+                full_range: false,
+                primaries: 9,
+                transfer: 16,
+                matrix: 9,
+            },
+            codec_initialization_data: vec![],
+        };
+
+        let codec_string = |mime, codec_private| {
+            let track = TrackFormat {
+                mime,
+                codec_private,
+                ..Default::default()
+            };
+            track.codec_string()
+        };
+
+        assert_eq!(video_track().codec_string().as_deref(), Some("avc1.42c028"));
+        assert_eq!(
+            codec_string("video/hevc", hevc.to_bytes()).as_deref(),
+            Some("hvc1.2.4.L120.90")
+        );
+        assert_eq!(
+            codec_string("video/av01", av1.to_bytes()).as_deref(),
+            Some("av01.0.08M.10")
+        );
+        assert_eq!(
+            codec_string("video/x-vnd.on2.vp9", vp9.to_bytes()).as_deref(),
+            Some("vp09.02.31.10")
+        );
+        assert_eq!(audio_track().codec_string().as_deref(), Some("mp4a.40.2"));
+        assert_eq!(codec_string("audio/opus", vec![]).as_deref(), Some("opus"));
+
+        // The record is needed
+        assert_eq!(codec_string("video/avc", vec![1, 2]), None);
+    }
+}
@@ -1,11 +1,14 @@
+#[cfg(target_os = "android")]
 use std::{
     ffi::{c_void, CStr, CString},
     os::raw::c_char,
     ptr::null_mut,
 };
 
+#[cfg(target_os = "android")]
 use log::debug;
 
+#[cfg(target_os = "android")]
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct AMediaFormat {
@@ -13,6 +16,7 @@ pub struct AMediaFormat {
     _marker: core::marker::PhantomData<(*mut u8, core::marker::PhantomPinned)>,
 }
 
+#[cfg(target_os = "android")]
 #[link(name = "mediandk")]
 extern "C" {
     /// Available since API level 21.
@@ -129,12 +133,39 @@ extern "C" {
     fn AMediaFormat_copy(to: *mut AMediaFormat, from: *mut AMediaFormat) -> isize;
 }
 
+/// Values of the `color-standard` key
+pub const COLOR_STANDARD_BT709: i32 = 1;
+pub const COLOR_STANDARD_BT601_PAL: i32 = 2;
+pub const COLOR_STANDARD_BT601_NTSC: i32 = 4;
+pub const COLOR_STANDARD_BT2020: i32 = 6;
+
+/// Values of the `color-transfer` key
+pub const COLOR_TRANSFER_LINEAR: i32 = 1;
+pub const COLOR_TRANSFER_SDR_VIDEO: i32 = 3;
+pub const COLOR_TRANSFER_ST2084: i32 = 6;
+pub const COLOR_TRANSFER_HLG: i32 = 7;
+
+/// Values of the `color-range` key
+pub const COLOR_RANGE_FULL: i32 = 1;
+pub const COLOR_RANGE_LIMITED: i32 = 2;
+
+/// Values of the `color-format` key
+pub const COLOR_FORMAT_YUV420_PLANAR: i32 = 19;
+pub const COLOR_FORMAT_YUV420_PACKED_PLANAR: i32 = 20;
+pub const COLOR_FORMAT_YUV420_SEMIPLANAR: i32 = 21;
+pub const COLOR_FORMAT_YUV420_PACKED_SEMIPLANAR: i32 = 39;
+pub const COLOR_FORMAT_YUV_P010: i32 = 54;
+pub const COLOR_FORMAT_SURFACE: i32 = 0x7f000789;
+pub const COLOR_FORMAT_YUV420_FLEXIBLE: i32 = 0x7f420888;
+
 /// This structure stores data in key-value pairs for use in MediaCodec and other places in the NDK
+#[cfg(target_os = "android")]
 #[derive(Debug)]
 pub struct MediaFormat {
     pub(crate) inner: *mut AMediaFormat,
 }
 
+#[cfg(target_os = "android")]
 impl MediaFormat {
     /// Construct a MediaFormat from a raw pointer
     pub fn from_raw(inner: *mut AMediaFormat) -> Self {
@@ -156,7 +187,8 @@ impl MediaFormat {
 
     /// Set a 32-bit integer value
     pub fn set_i32(&mut self, name: &str, value: i32) -> bool {
-        unsafe { AMediaFormat_setInt32(self.inner, name.as_ptr().cast(), value) }
+        let name = CString::new(name).unwrap();
+        unsafe { AMediaFormat_setInt32(self.inner, name.as_ptr(), value) }
     }
 
     /// Get a 32-bit integer value
@@ -244,7 +276,7 @@ impl MediaFormat {
     /// Get a 64-bit floating-point value
     #[cfg(feature = "api28")]
     pub fn get_f64(&self, name: &str) -> Option<f64> {
-        let value = None;
+        let mut value = None;
 
         unsafe {
             let mut v = 0f64;
@@ -279,6 +311,41 @@ impl MediaFormat {
         value
     }
 
+    /// Set a byte buffer value, like the codec specific data (`csd-0`, `csd-1`...).
+    ///
+    /// The data is copied into the format
+    pub fn set_buffer(&mut self, name: &str, value: &[u8]) -> bool {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            AMediaFormat_setBuffer(
+                self.inner,
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+            )
+        }
+    }
+
+    /// Get a byte buffer value
+    pub fn get_buffer(&self, name: &str) -> Option<&[u8]> {
+        let mut value = None;
+
+        unsafe {
+            let mut data = null_mut();
+            let mut size = 0;
+            let name = CString::new(name).unwrap();
+            if AMediaFormat_getBuffer(self.inner, name.as_ptr(), &mut data, &mut size) {
+                value = Some(if data.is_null() || size == 0 {
+                    &[][..]
+                } else {
+                    std::slice::from_raw_parts(data as *const u8, size)
+                });
+            }
+        }
+
+        value
+    }
+
     /// Clear the entire buffer
     #[cfg(feature = "api29")]
     pub fn clear(&mut self) {
@@ -288,6 +355,7 @@ impl MediaFormat {
     }
 }
 
+#[cfg(target_os = "android")]
 impl ToString for MediaFormat {
     fn to_string(&self) -> String {
         unsafe {
@@ -301,6 +369,7 @@ impl ToString for MediaFormat {
     }
 }
 
+#[cfg(target_os = "android")]
 impl Drop for MediaFormat {
     fn drop(&mut self) {
         unsafe {
@@ -309,5 +378,7 @@ impl Drop for MediaFormat {
     }
 }
 
+#[cfg(target_os = "android")]
 unsafe impl Send for MediaFormat {}
+#[cfg(target_os = "android")]
 unsafe impl Sync for MediaFormat {}
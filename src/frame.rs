@@ -0,0 +1,296 @@
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::{PixelFormat, RawVideoFrame, VideoCrop, VideoPlane};
+
+/// The buffers a pool keeps around, shared with the frames taken from it
+#[derive(Debug)]
+struct PoolBuffers {
+    free: Vec<Vec<u8>>,
+    max_free: usize,
+}
+
+/// Recycles the buffers of [VideoFrameBuf]s, so copying decoded frames doesn't allocate once the pool is warm.
+///
+/// A frame gives its buffer back to the pool when it's dropped, on whatever thread that happens.
+/// The pool can be cloned, clones share the same buffers
+#[derive(Debug, Clone)]
+pub struct FramePool {
+    buffers: Arc<Mutex<PoolBuffers>>,
+}
+
+impl FramePool {
+    /// Creates a pool keeping at most `max_free` unused buffers. Frames dropped while the pool is full free their buffer
+    pub fn new(max_free: usize) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(PoolBuffers {
+                free: Vec::with_capacity(max_free),
+                max_free,
+            })),
+        }
+    }
+
+    /// The number of buffers waiting to be reused
+    pub fn free_count(&self) -> usize {
+        self.lock().free.len()
+    }
+
+    /// Copies a frame into a recycled buffer, or a new one if none is free
+    pub fn copy_frame(&self, frame: &RawVideoFrame) -> VideoFrameBuf {
+        let mut data = self.take_buffer(frame.buffer().len());
+        data.extend_from_slice(frame.buffer());
+
+        VideoFrameBuf::from_parts(data, frame, Some(Arc::downgrade(&self.buffers)))
+    }
+
+    /// A free buffer, preferably one that can hold `size` bytes without growing
+    fn take_buffer(&self, size: usize) -> Vec<u8> {
+        let mut buffers = self.lock();
+        let index = buffers
+            .free
+            .iter()
+            .position(|buffer| buffer.capacity() >= size)
+            .or_else(|| buffers.free.len().checked_sub(1));
+
+        match index {
+            Some(index) => buffers.free.swap_remove(index),
+            None => Vec::with_capacity(size),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolBuffers> {
+        // The buffers stay consistent even if a thread panicked while holding the lock
+        self.buffers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// An owned copy of a raw video frame, with its timestamp and rotation.
+///
+/// Unlike [RawVideoFrame], it doesn't borrow the codec output buffer, so the buffer can be released right away
+/// and the frame sent to another thread. The layout of the decoder is kept, padding included
+#[derive(Debug, Clone)]
+pub struct VideoFrameBuf {
+    data: Vec<u8>,
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    stride: usize,
+    slice_height: usize,
+    crop: VideoCrop,
+    pts_us: i64,
+    rotation: i32,
+    pool: Option<Weak<Mutex<PoolBuffers>>>,
+}
+
+impl VideoFrameBuf {
+    fn from_parts(
+        data: Vec<u8>,
+        frame: &RawVideoFrame,
+        pool: Option<Weak<Mutex<PoolBuffers>>>,
+    ) -> Self {
+        Self {
+            data,
+            format: frame.pixel_format(),
+            width: frame.width(),
+            height: frame.height(),
+            stride: frame.stride(),
+            slice_height: frame.slice_height(),
+            crop: frame.crop(),
+            pts_us: 0,
+            rotation: 0,
+            pool,
+        }
+    }
+
+    /// Sets the presentation time of the frame, in microseconds
+    pub fn with_pts_us(mut self, pts_us: i64) -> Self {
+        self.pts_us = pts_us;
+        self
+    }
+
+    /// Sets the clockwise rotation to apply when displaying the frame, in degrees
+    pub fn with_rotation(mut self, rotation: i32) -> Self {
+        self.rotation = rotation.rem_euclid(360);
+        self
+    }
+
+    /// A borrowed view of the frame, to use with everything that takes a [RawVideoFrame]
+    pub fn as_raw(&self) -> RawVideoFrame<'_> {
+        RawVideoFrame::new(&self.data, self.format, self.width, self.height)
+            .with_layout(self.stride, self.slice_height)
+            .with_crop(self.crop)
+    }
+
+    /// Returns the Y, U and V planes, or `None` if the buffer is too small for the frame
+    pub fn planes(&self) -> Option<[VideoPlane<'_>; 3]> {
+        self.as_raw().planes()
+    }
+
+    /// The whole buffer, with the padding
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn slice_height(&self) -> usize {
+        self.slice_height
+    }
+
+    pub fn crop(&self) -> VideoCrop {
+        self.crop
+    }
+
+    pub fn pts_us(&self) -> i64 {
+        self.pts_us
+    }
+
+    pub fn rotation(&self) -> i32 {
+        self.rotation
+    }
+}
+
+impl Drop for VideoFrameBuf {
+    fn drop(&mut self) {
+        let Some(buffers) = self.pool.as_ref().and_then(Weak::upgrade) else {
+            return;
+        };
+        let mut buffers = buffers.lock().unwrap_or_else(|error| error.into_inner());
+
+        if buffers.free.len() < buffers.max_free {
+            let mut data = std::mem::take(&mut self.data);
+            data.clear();
+            buffers.free.push(data);
+        }
+    }
+}
+
+impl RawVideoFrame<'_> {
+    /// Copies the frame into a new [VideoFrameBuf]. Use a [FramePool] to reuse the buffers of dropped frames
+    pub fn to_owned(&self) -> VideoFrameBuf {
+        VideoFrameBuf::from_parts(self.buffer().to_vec(), self, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x4 NV12 frame with a stride of 10 and a slice height of 6, cropped to 6x4
+    fn frame(buffer: &[u8]) -> RawVideoFrame<'_> {
+        let crop = VideoCrop {
+            left: 2,
+            top: 0,
+            right: 7,
+            bottom: 3,
+        };
+        RawVideoFrame::new(buffer, PixelFormat::Nv12, 8, 4)
+            .with_layout(10, 6)
+            .with_crop(crop)
+    }
+
+    fn buffer() -> Vec<u8> {
+        (0..10 * 6 * 3 / 2).map(|byte| byte as u8).collect()
+    }
+
+    #[test]
+    fn to_owned() {
+        let buffer = buffer();
+        let raw = frame(&buffer);
+
+        let owned = raw.to_owned().with_pts_us(42).with_rotation(-90);
+        assert_eq!((owned.pts_us(), owned.rotation()), (42, 270));
+        assert_eq!(owned.pixel_format(), PixelFormat::Nv12);
+        assert_eq!((owned.width(), owned.height()), (8, 4));
+        assert_eq!((owned.stride(), owned.slice_height()), (10, 6));
+        assert_eq!(owned.crop(), raw.crop());
+        // The padding is kept
+        assert_eq!(owned.data(), buffer);
+        assert_ne!(owned.data().as_ptr(), buffer.as_ptr());
+
+        let planes = owned.planes().unwrap();
+        let raw_planes = raw.planes().unwrap();
+        for (plane, raw_plane) in planes.iter().zip(&raw_planes) {
+            assert_eq!(plane.sample(1, 1), raw_plane.sample(1, 1));
+        }
+        assert_eq!(planes[0].sample(3, 2), 23);
+        assert_eq!((planes[1].sample(1, 1), planes[2].sample(1, 1)), (72, 73));
+
+        // The view has the same layout
+        let view = owned.as_raw();
+        assert_eq!((view.stride(), view.slice_height()), (10, 6));
+        assert_eq!(view.crop(), raw.crop());
+        assert_eq!(view.buffer().as_ptr(), owned.data().as_ptr());
+
+        // Clones are independent copies
+        let clone = owned.clone();
+        assert_ne!(clone.data().as_ptr(), owned.data().as_ptr());
+        assert_eq!(clone.data(), owned.data());
+    }
+
+    #[test]
+    fn pool_reuses_buffers() {
+        let buffer = buffer();
+        let raw = frame(&buffer);
+
+        let pool = FramePool::new(2);
+        let first = pool.copy_frame(&raw);
+        let first_buffer = first.data().as_ptr();
+        let second = pool.copy_frame(&raw);
+        let third = pool.copy_frame(&raw);
+        assert_eq!(pool.free_count(), 0);
+        assert_eq!(first.data(), buffer);
+
+        // Frames give their buffer back from any thread
+        std::thread::spawn(move || drop(first)).join().unwrap();
+        assert_eq!(pool.free_count(), 1);
+
+        let fourth = pool.copy_frame(&raw).with_pts_us(10);
+        assert_eq!(pool.free_count(), 0);
+        assert_eq!(fourth.data().as_ptr(), first_buffer);
+        assert_eq!(fourth.data(), buffer);
+        assert_eq!(fourth.pts_us(), 10);
+
+        // The pool keeps at most 2 buffers, clones of the pool share them
+        let clone = pool.clone();
+        drop((second, third, fourth));
+        assert_eq!(pool.free_count(), 2);
+        assert_eq!(clone.free_count(), 2);
+
+        // A buffer large enough is preferred
+        let small = [0; 4];
+        let small = RawVideoFrame::new(&small, PixelFormat::I420, 2, 2);
+        let mut frames: Vec<_> = (0..3).map(|_| clone.copy_frame(&small)).collect();
+        assert_eq!(clone.free_count(), 0);
+        // The last frame got a new buffer, of 4 bytes
+        let reused_buffer = frames[1].data().as_ptr();
+        frames.truncate(1);
+        assert_eq!(clone.free_count(), 2);
+        let large = pool.copy_frame(&raw);
+        assert_eq!(large.data().as_ptr(), reused_buffer);
+        assert_eq!(large.data(), buffer);
+        assert_eq!(pool.free_count(), 1);
+
+        // Copies made with to_owned aren't returned to any pool, and frames outlive their pool
+        drop(raw.to_owned());
+        assert_eq!(pool.free_count(), 1);
+        drop((pool, clone));
+        drop(large);
+        drop(frames);
+    }
+}
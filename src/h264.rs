@@ -0,0 +1,1525 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{
+    annexb_nal_units, bitstream::BitReader, unescape_rbsp, ANNEXB_START_CODE, COLOR_RANGE_FULL,
+    COLOR_RANGE_LIMITED, COLOR_STANDARD_BT2020, COLOR_STANDARD_BT601_NTSC,
+    COLOR_STANDARD_BT601_PAL, COLOR_STANDARD_BT709, COLOR_TRANSFER_HLG, COLOR_TRANSFER_LINEAR,
+    COLOR_TRANSFER_SDR_VIDEO, COLOR_TRANSFER_ST2084,
+};
+
+/// H.264 NAL unit types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvcNalType {
+    Slice,
+    SliceDataA,
+    SliceDataB,
+    SliceDataC,
+    IdrSlice,
+    Sei,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfStream,
+    FillerData,
+    SpsExtension,
+    Prefix,
+    SubsetSps,
+    AuxiliarySlice,
+    SliceExtension,
+    Other(u8),
+}
+
+impl AvcNalType {
+    /// Returns the type of a NAL unit from its first byte
+    pub fn from_header(header: u8) -> Self {
+        match header & 0x1f {
+            1 => Self::Slice,
+            2 => Self::SliceDataA,
+            3 => Self::SliceDataB,
+            4 => Self::SliceDataC,
+            5 => Self::IdrSlice,
+            6 => Self::Sei,
+            7 => Self::Sps,
+            8 => Self::Pps,
+            9 => Self::AccessUnitDelimiter,
+            10 => Self::EndOfSequence,
+            11 => Self::EndOfStream,
+            12 => Self::FillerData,
+            13 => Self::SpsExtension,
+            14 => Self::Prefix,
+            15 => Self::SubsetSps,
+            19 => Self::AuxiliarySlice,
+            20 => Self::SliceExtension,
+            other => Self::Other(other),
+        }
+    }
+
+    /// Whether NAL units of this type carry picture data
+    pub fn is_vcl(&self) -> bool {
+        matches!(
+            self,
+            Self::Slice | Self::SliceDataA | Self::SliceDataB | Self::SliceDataC | Self::IdrSlice
+        )
+    }
+}
+
+/// Color information signalled in the VUI of H.264 and HEVC streams.
+///
+/// The values are the ones of ISO/IEC 23091-2 (the same as the H.273 code points)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoColorInfo {
+    pub full_range: bool,
+    pub primaries: u8,
+    pub transfer: u8,
+    pub matrix: u8,
+}
+
+impl Default for VideoColorInfo {
+    fn default() -> Self {
+        // 2 means "unspecified"
+        Self {
+            full_range: false,
+            primaries: 2,
+            transfer: 2,
+            matrix: 2,
+        }
+    }
+}
+
+impl VideoColorInfo {
+    /// The value of the `color-standard` key for these colors, if there's one
+    pub fn color_standard(&self) -> Option<i32> {
+        match (self.matrix, self.primaries) {
+            (1, _) => Some(COLOR_STANDARD_BT709),
+            (5, _) => Some(COLOR_STANDARD_BT601_PAL),
+            (6, _) => Some(COLOR_STANDARD_BT601_NTSC),
+            (9, _) | (10, _) => Some(COLOR_STANDARD_BT2020),
+            (_, 1) => Some(COLOR_STANDARD_BT709),
+            (_, 5) => Some(COLOR_STANDARD_BT601_PAL),
+            (_, 6) | (_, 7) => Some(COLOR_STANDARD_BT601_NTSC),
+            (_, 9) => Some(COLOR_STANDARD_BT2020),
+            _ => None,
+        }
+    }
+
+    /// The value of the `color-transfer` key for these colors, if there's one
+    pub fn color_transfer(&self) -> Option<i32> {
+        match self.transfer {
+            1 | 6 | 14 | 15 => Some(COLOR_TRANSFER_SDR_VIDEO),
+            8 => Some(COLOR_TRANSFER_LINEAR),
+            16 => Some(COLOR_TRANSFER_ST2084),
+            18 => Some(COLOR_TRANSFER_HLG),
+            _ => None,
+        }
+    }
+
+    /// The value of the `color-range` key for these colors
+    pub fn color_range(&self) -> i32 {
+        if self.full_range {
+            COLOR_RANGE_FULL
+        } else {
+            COLOR_RANGE_LIMITED
+        }
+    }
+
+    /// Sets the color keys of `format` to describe these colors
+    #[cfg(target_os = "android")]
+    pub fn apply_to(&self, format: &mut MediaFormat) {
+        format.set_i32("color-range", self.color_range());
+
+        if let Some(standard) = self.color_standard() {
+            format.set_i32("color-standard", standard);
+        }
+
+        if let Some(transfer) = self.color_transfer() {
+            format.set_i32("color-transfer", transfer);
+        }
+    }
+}
+
+/// The frame cropping of a picture, in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CropRect {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// The sample aspect ratios for `aspect_ratio_idc` 1 to 16
+const SAMPLE_ASPECT_RATIOS: [(u16, u16); 16] = [
+    (1, 1),
+    (12, 11),
+    (10, 11),
+    (16, 11),
+    (40, 33),
+    (24, 11),
+    (20, 11),
+    (32, 11),
+    (80, 33),
+    (18, 11),
+    (15, 11),
+    (64, 33),
+    (160, 99),
+    (4, 3),
+    (3, 2),
+    (2, 1),
+];
+
+/// Reads an `aspect_ratio_info`, returning the sample aspect ratio
+pub(crate) fn read_aspect_ratio(reader: &mut BitReader) -> Option<(u16, u16)> {
+    let idc = reader.read_u8()?;
+    if idc == 255 {
+        return Some((reader.read_u16()?, reader.read_u16()?));
+    }
+
+    Some(
+        SAMPLE_ASPECT_RATIOS
+            .get((idc as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or((1, 1)),
+    )
+}
+
+/// Reads a `video_signal_type` after its present flag (without `video_format`)
+pub(crate) fn read_color_info(reader: &mut BitReader) -> Option<VideoColorInfo> {
+    let mut color = VideoColorInfo {
+        full_range: reader.read_bit()?,
+        ..Default::default()
+    };
+
+    if reader.read_bit()? {
+        color.primaries = reader.read_u8()?;
+        color.transfer = reader.read_u8()?;
+        color.matrix = reader.read_u8()?;
+    }
+
+    Some(color)
+}
+
+/// The VUI parameters of an H.264 sequence parameter set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AvcVui {
+    /// The sample (pixel) aspect ratio
+    pub sample_aspect_ratio: Option<(u16, u16)>,
+    pub color: Option<VideoColorInfo>,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate: bool,
+    pub max_num_reorder_frames: Option<u32>,
+    pub max_dec_frame_buffering: Option<u32>,
+}
+
+impl AvcVui {
+    fn parse(reader: &mut BitReader) -> Option<Self> {
+        let mut vui = Self::default();
+
+        if reader.read_bit()? {
+            vui.sample_aspect_ratio = Some(read_aspect_ratio(reader)?);
+        }
+
+        // overscan_info_present_flag
+        if reader.read_bit()? {
+            reader.skip_bits(1)?;
+        }
+
+        // video_signal_type_present_flag
+        if reader.read_bit()? {
+            // video_format
+            reader.skip_bits(3)?;
+            vui.color = Some(read_color_info(reader)?);
+        }
+
+        // chroma_loc_info_present_flag
+        if reader.read_bit()? {
+            reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        // timing_info_present_flag
+        if reader.read_bit()? {
+            vui.num_units_in_tick = reader.read_bits(32)?;
+            vui.time_scale = reader.read_bits(32)?;
+            vui.fixed_frame_rate = reader.read_bit()?;
+        }
+
+        let nal_hrd = reader.read_bit()?;
+        if nal_hrd {
+            skip_hrd_parameters(reader)?;
+        }
+
+        let vcl_hrd = reader.read_bit()?;
+        if vcl_hrd {
+            skip_hrd_parameters(reader)?;
+        }
+
+        if nal_hrd || vcl_hrd {
+            // low_delay_hrd_flag
+            reader.skip_bits(1)?;
+        }
+
+        // pic_struct_present_flag
+        reader.skip_bits(1)?;
+
+        // bitstream_restriction_flag
+        if reader.read_bit()? {
+            // motion_vectors_over_pic_boundaries_flag
+            reader.skip_bits(1)?;
+            // max_bytes_per_pic_denom, max_bits_per_mb_denom, log2_max_mv_length_horizontal and vertical
+            for _ in 0..4 {
+                reader.read_ue()?;
+            }
+            vui.max_num_reorder_frames = Some(reader.read_ue()?);
+            vui.max_dec_frame_buffering = Some(reader.read_ue()?);
+        }
+
+        Some(vui)
+    }
+
+    /// The frame rate, if the stream signals its timing
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+
+        // Every frame lasts two ticks (one per field)
+        Some(self.time_scale as f64 / (2.0 * self.num_units_in_tick as f64))
+    }
+}
+
+fn skip_hrd_parameters(reader: &mut BitReader) -> Option<()> {
+    let cpb_count = reader.read_ue()? + 1;
+    // bit_rate_scale and cpb_size_scale
+    reader.skip_bits(8)?;
+
+    for _ in 0..cpb_count {
+        reader.read_ue()?;
+        reader.read_ue()?;
+        // cbr_flag
+        reader.skip_bits(1)?;
+    }
+
+    // initial_cpb_removal_delay_length, cpb_removal_delay_length, dpb_output_delay_length and time_offset_length
+    reader.skip_bits(20)
+}
+
+/// The largest width or height of a picture in macroblocks, `sqrt(8 * MaxFS)` for level 6.2 (A.3.1)
+const AVC_MAX_SIZE_IN_MBS: u32 = 1055;
+
+/// An H.264 sequence parameter set
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvcSps {
+    pub profile_idc: u8,
+    /// The `constraint_set` flags, as the byte that follows `profile_idc`
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub sps_id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane: bool,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub log2_max_frame_num: u32,
+    pub pic_order_cnt_type: u32,
+    pub log2_max_pic_order_cnt_lsb: u32,
+    pub delta_pic_order_always_zero: bool,
+    pub max_num_ref_frames: u32,
+    pub frame_mbs_only: bool,
+    /// The width of the decoded picture, before cropping
+    pub coded_width: u32,
+    /// The height of the decoded picture, before cropping
+    pub coded_height: u32,
+    pub crop: CropRect,
+    pub vui: Option<AvcVui>,
+}
+
+impl AvcSps {
+    /// Parses a sequence parameter set NAL unit, including its NAL header byte.
+    ///
+    /// Returns `None` if it's truncated or has values out of the ranges of the specification
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 4 || AvcNalType::from_header(nal[0]) != AvcNalType::Sps {
+            return None;
+        }
+
+        let rbsp = unescape_rbsp(&nal[1..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let profile_idc = reader.read_u8()?;
+        let constraint_flags = reader.read_u8()?;
+        let level_idc = reader.read_u8()?;
+        let sps_id = reader.read_ue().filter(|id| *id <= 31)?;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = reader.read_ue().filter(|idc| *idc <= 3)?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_bit()?;
+            }
+
+            // Up to 14 bits
+            bit_depth_luma = reader.read_ue().filter(|minus8| *minus8 <= 6)? + 8;
+            bit_depth_chroma = reader.read_ue().filter(|minus8| *minus8 <= 6)? + 8;
+
+            // qpprime_y_zero_transform_bypass_flag
+            reader.skip_bits(1)?;
+
+            // seq_scaling_matrix_present_flag
+            if reader.read_bit()? {
+                let count = if chroma_format_idc != 3 { 8 } else { 12 };
+                for index in 0..count {
+                    if reader.read_bit()? {
+                        skip_scaling_list(&mut reader, if index < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        // Both are up to 16 bits
+        let log2_max_frame_num = reader.read_ue().filter(|minus4| *minus4 <= 12)? + 4;
+        let pic_order_cnt_type = reader.read_ue().filter(|value| *value <= 2)?;
+        let mut log2_max_pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_always_zero = false;
+
+        match pic_order_cnt_type {
+            0 => log2_max_pic_order_cnt_lsb = reader.read_ue().filter(|minus4| *minus4 <= 12)? + 4,
+            1 => {
+                delta_pic_order_always_zero = reader.read_bit()?;
+                // offset_for_non_ref_pic and offset_for_top_to_bottom_field
+                reader.read_se()?;
+                reader.read_se()?;
+
+                let cycle = reader.read_ue().filter(|cycle| *cycle <= 255)?;
+                for _ in 0..cycle {
+                    reader.read_se()?;
+                }
+            }
+            _ => {}
+        }
+
+        let max_num_ref_frames = reader.read_ue()?;
+        // gaps_in_frame_num_value_allowed_flag
+        reader.skip_bits(1)?;
+
+        let width_in_mbs = reader.read_ue()? + 1;
+        let height_in_map_units = reader.read_ue()? + 1;
+        let frame_mbs_only = reader.read_bit()?;
+
+        // Field pictures have half the macroblock rows of the frame
+        let field_factor = if frame_mbs_only { 1 } else { 2 };
+        let height_in_mbs = height_in_map_units.checked_mul(field_factor)?;
+        if width_in_mbs > AVC_MAX_SIZE_IN_MBS || height_in_mbs > AVC_MAX_SIZE_IN_MBS {
+            return None;
+        }
+        let (coded_width, coded_height) = (width_in_mbs * 16, height_in_mbs * 16);
+
+        if !frame_mbs_only {
+            // mb_adaptive_frame_field_flag
+            reader.skip_bits(1)?;
+        }
+
+        // direct_8x8_inference_flag
+        reader.skip_bits(1)?;
+
+        let mut crop = CropRect::default();
+        if reader.read_bit()? {
+            crop.left = reader.read_ue()?;
+            crop.right = reader.read_ue()?;
+            crop.top = reader.read_ue()?;
+            crop.bottom = reader.read_ue()?;
+        }
+
+        let vui = if reader.read_bit()? {
+            // Some encoders write truncated VUIs, the rest of the SPS is still useful
+            AvcVui::parse(&mut reader)
+        } else {
+            None
+        };
+
+        // The cropping is in chroma sample units
+        let (crop_unit_x, crop_unit_y) = match (chroma_format_idc, separate_colour_plane) {
+            (0, _) | (3, true) => (1, field_factor),
+            (1, _) => (2, 2 * field_factor),
+            (2, _) => (2, field_factor),
+            _ => (1, field_factor),
+        };
+
+        crop.left = crop.left.checked_mul(crop_unit_x)?;
+        crop.right = crop.right.checked_mul(crop_unit_x)?;
+        crop.top = crop.top.checked_mul(crop_unit_y)?;
+        crop.bottom = crop.bottom.checked_mul(crop_unit_y)?;
+
+        // Something has to be left after cropping
+        if crop.left.checked_add(crop.right)? >= coded_width
+            || crop.top.checked_add(crop.bottom)? >= coded_height
+        {
+            return None;
+        }
+
+        Some(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            sps_id,
+            chroma_format_idc,
+            separate_colour_plane,
+            bit_depth_luma,
+            bit_depth_chroma,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            delta_pic_order_always_zero,
+            max_num_ref_frames,
+            frame_mbs_only,
+            coded_width,
+            coded_height,
+            crop,
+            vui,
+        })
+    }
+
+    /// The width of the picture, after cropping
+    pub fn width(&self) -> u32 {
+        self.coded_width
+            .saturating_sub(self.crop.left.saturating_add(self.crop.right))
+    }
+
+    /// The height of the picture, after cropping
+    pub fn height(&self) -> u32 {
+        self.coded_height
+            .saturating_sub(self.crop.top.saturating_add(self.crop.bottom))
+    }
+
+    /// The frame rate signalled in the VUI, if any
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.vui.as_ref()?.frame_rate()
+    }
+
+    /// The value of the `profile` key in a `MediaFormat` (the `AVCProfile*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_profile(&self) -> Option<i32> {
+        let constrained = self.constraint_flags & 0x40 != 0;
+
+        match self.profile_idc {
+            66 if constrained => Some(0x10000),
+            66 => Some(0x01),
+            77 => Some(0x02),
+            88 => Some(0x04),
+            100 if self.constraint_flags & 0x0c == 0x0c => Some(0x80000),
+            100 => Some(0x08),
+            110 => Some(0x10),
+            122 => Some(0x20),
+            244 => Some(0x40),
+            _ => None,
+        }
+    }
+
+    /// The value of the `level` key in a `MediaFormat` (the `AVCLevel*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_level(&self) -> Option<i32> {
+        // Level 1b is signalled with level 11 and constraint_set3 in the baseline and main profiles
+        let level_1b = self.level_idc == 11
+            && self.constraint_flags & 0x10 != 0
+            && matches!(self.profile_idc, 66 | 77 | 88);
+
+        let level = match self.level_idc {
+            _ if level_1b => 0x02,
+            9 => 0x02,
+            10 => 0x01,
+            11 => 0x04,
+            12 => 0x08,
+            13 => 0x10,
+            20 => 0x20,
+            21 => 0x40,
+            22 => 0x80,
+            30 => 0x100,
+            31 => 0x200,
+            32 => 0x400,
+            40 => 0x800,
+            41 => 0x1000,
+            42 => 0x2000,
+            50 => 0x4000,
+            51 => 0x8000,
+            52 => 0x10000,
+            60 => 0x20000,
+            61 => 0x40000,
+            62 => 0x80000,
+            _ => return None,
+        };
+
+        Some(level)
+    }
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Option<()> {
+    let mut last_scale = 8i32;
+    let mut next_scale = 8i32;
+
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = reader.read_se()?;
+            next_scale = (last_scale + delta + 256) % 256;
+        }
+
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Some(())
+}
+
+/// An H.264 picture parameter set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvcPps {
+    pub pps_id: u32,
+    pub sps_id: u32,
+    /// CABAC is used when set, CAVLC otherwise
+    pub entropy_coding_mode: bool,
+    pub bottom_field_pic_order_in_frame_present: bool,
+    pub num_slice_groups: u32,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub weighted_pred: bool,
+    pub weighted_bipred_idc: u32,
+    /// The initial QP of the slices, before `slice_qp_delta`
+    pub pic_init_qp: i32,
+    pub chroma_qp_index_offset: i32,
+    pub deblocking_filter_control_present: bool,
+    pub constrained_intra_pred: bool,
+    pub redundant_pic_cnt_present: bool,
+    pub transform_8x8_mode: bool,
+}
+
+impl AvcPps {
+    /// Parses a picture parameter set NAL unit, including its NAL header byte.
+    ///
+    /// Returns `None` if it's truncated or has values out of the ranges of the specification. The range of `pic_init_qp`
+    /// depends on the bit depth of the SPS, so it's only checked against 14 bits here, and against the SPS by
+    /// [AvcSliceHeader::parse]
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 2 || AvcNalType::from_header(nal[0]) != AvcNalType::Pps {
+            return None;
+        }
+
+        let rbsp = unescape_rbsp(&nal[1..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let pps_id = reader.read_ue().filter(|id| *id <= 255)?;
+        let sps_id = reader.read_ue().filter(|id| *id <= 31)?;
+        let entropy_coding_mode = reader.read_bit()?;
+        let bottom_field_pic_order_in_frame_present = reader.read_bit()?;
+        let num_slice_groups = reader.read_ue().filter(|minus1| *minus1 <= 7)? + 1;
+
+        if num_slice_groups > 1 {
+            skip_slice_groups(&mut reader, num_slice_groups)?;
+        }
+
+        let num_ref_idx_l0_default_active = reader.read_ue().filter(|minus1| *minus1 <= 31)? + 1;
+        let num_ref_idx_l1_default_active = reader.read_ue().filter(|minus1| *minus1 <= 31)? + 1;
+        let weighted_pred = reader.read_bit()?;
+        let weighted_bipred_idc = reader.read_bits(2)?;
+        let pic_init_qp = 26
+            + reader
+                .read_se()
+                .filter(|minus26| (-(26 + avc_qp_bd_offset(14))..=25).contains(minus26))?;
+        // pic_init_qs
+        reader.read_se()?;
+        let chroma_qp_index_offset = reader
+            .read_se()
+            .filter(|offset| (-12..=12).contains(offset))?;
+        let deblocking_filter_control_present = reader.read_bit()?;
+        let constrained_intra_pred = reader.read_bit()?;
+        let redundant_pic_cnt_present = reader.read_bit()?;
+
+        let transform_8x8_mode = reader.more_rbsp_data() && reader.read_bit()?;
+
+        Some(Self {
+            pps_id,
+            sps_id,
+            entropy_coding_mode,
+            bottom_field_pic_order_in_frame_present,
+            num_slice_groups,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            weighted_pred,
+            weighted_bipred_idc,
+            pic_init_qp,
+            chroma_qp_index_offset,
+            deblocking_filter_control_present,
+            constrained_intra_pred,
+            redundant_pic_cnt_present,
+            transform_8x8_mode,
+        })
+    }
+}
+
+/// The QP range of a bit depth goes down to minus this offset (`QpBdOffsetY`)
+fn avc_qp_bd_offset(bit_depth: u32) -> i32 {
+    6 * (bit_depth as i32 - 8)
+}
+
+fn skip_slice_groups(reader: &mut BitReader, num_slice_groups: u32) -> Option<()> {
+    match reader.read_ue()? {
+        0 => {
+            for _ in 0..num_slice_groups {
+                reader.read_ue()?;
+            }
+        }
+        2 => {
+            for _ in 1..num_slice_groups {
+                reader.read_ue()?;
+                reader.read_ue()?;
+            }
+        }
+        3..=5 => {
+            reader.skip_bits(1)?;
+            reader.read_ue()?;
+        }
+        6 => {
+            let bits = 32 - (num_slice_groups - 1).leading_zeros();
+            let count = reader.read_ue()? + 1;
+            reader.skip_bits(count as usize * bits as usize)?;
+        }
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// The slice types of H.264
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvcSliceType {
+    P,
+    B,
+    I,
+    Sp,
+    Si,
+}
+
+impl AvcSliceType {
+    /// Returns the slice type from its `slice_type` value
+    pub fn from_value(value: u32) -> Option<Self> {
+        match value % 5 {
+            0 => Some(Self::P),
+            1 => Some(Self::B),
+            2 => Some(Self::I),
+            3 => Some(Self::Sp),
+            4 => Some(Self::Si),
+            _ => None,
+        }
+    }
+
+    /// Whether the slice only uses intra prediction
+    pub fn is_intra(&self) -> bool {
+        matches!(self, Self::I | Self::Si)
+    }
+}
+
+/// The slice headers are read from at most this many bytes of the slice
+const AVC_SLICE_HEADER_MAX_SIZE: usize = 1024;
+
+/// The start of an H.264 slice header, up to `slice_qp_delta`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvcSliceHeader {
+    /// Slices with a `nal_ref_idc` of 0 are not used as reference
+    pub nal_ref_idc: u8,
+    pub idr: bool,
+    pub first_mb_in_slice: u32,
+    pub slice_type: AvcSliceType,
+    pub pps_id: u32,
+    pub frame_num: u32,
+    pub field_pic: bool,
+    pub bottom_field: bool,
+    pub idr_pic_id: Option<u32>,
+    pub pic_order_cnt_lsb: Option<u32>,
+    /// The QP of the slice, which is `pic_init_qp + slice_qp_delta`
+    pub qp: i32,
+}
+
+impl AvcSliceHeader {
+    /// Parses the header of a slice NAL unit, including its NAL header byte.
+    ///
+    /// The parameter sets the slice refers to have to be in `sps` and `pps`
+    pub fn parse(nal: &[u8], sps: &[AvcSps], pps: &[AvcPps]) -> Option<Self> {
+        let nal_type = AvcNalType::from_header(*nal.first()?);
+        if !matches!(nal_type, AvcNalType::Slice | AvcNalType::IdrSlice) {
+            return None;
+        }
+
+        let nal_ref_idc = (nal[0] >> 5) & 0x03;
+        let idr = nal_type == AvcNalType::IdrSlice;
+
+        let rbsp = unescape_rbsp(&nal[1..nal.len().min(AVC_SLICE_HEADER_MAX_SIZE)]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let first_mb_in_slice = reader.read_ue()?;
+        let slice_type = AvcSliceType::from_value(reader.read_ue()?)?;
+        let pps_id = reader.read_ue()?;
+
+        let pps = pps.iter().find(|pps| pps.pps_id == pps_id)?;
+        let sps = sps.iter().find(|sps| sps.sps_id == pps.sps_id)?;
+        let qp_range = -avc_qp_bd_offset(sps.bit_depth_luma)..=51;
+        if !qp_range.contains(&pps.pic_init_qp) {
+            return None;
+        }
+
+        if sps.separate_colour_plane {
+            // colour_plane_id
+            reader.skip_bits(2)?;
+        }
+
+        let frame_num = reader.read_bits(sps.log2_max_frame_num)?;
+        let field_pic = !sps.frame_mbs_only && reader.read_bit()?;
+        let bottom_field = field_pic && reader.read_bit()?;
+        let idr_pic_id = if idr { Some(reader.read_ue()?) } else { None };
+
+        let mut pic_order_cnt_lsb = None;
+        if sps.pic_order_cnt_type == 0 {
+            pic_order_cnt_lsb = Some(reader.read_bits(sps.log2_max_pic_order_cnt_lsb)?);
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                // delta_pic_order_cnt_bottom
+                reader.read_se()?;
+            }
+        } else if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero {
+            // delta_pic_order_cnt[0] and [1]
+            reader.read_se()?;
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                reader.read_se()?;
+            }
+        }
+
+        if pps.redundant_pic_cnt_present {
+            // redundant_pic_cnt
+            reader.read_ue()?;
+        }
+
+        let bidirectional = slice_type == AvcSliceType::B;
+        if bidirectional {
+            // direct_spatial_mv_pred_flag
+            reader.skip_bits(1)?;
+        }
+
+        let mut num_ref_idx_l0_active = pps.num_ref_idx_l0_default_active;
+        let mut num_ref_idx_l1_active = pps.num_ref_idx_l1_default_active;
+        if !slice_type.is_intra() {
+            // num_ref_idx_active_override_flag
+            if reader.read_bit()? {
+                num_ref_idx_l0_active = reader.read_ue().filter(|minus1| *minus1 <= 31)? + 1;
+                if bidirectional {
+                    num_ref_idx_l1_active = reader.read_ue().filter(|minus1| *minus1 <= 31)? + 1;
+                }
+            }
+
+            skip_ref_pic_list_modification(&mut reader)?;
+            if bidirectional {
+                skip_ref_pic_list_modification(&mut reader)?;
+            }
+        }
+
+        let explicit_weights = match slice_type {
+            AvcSliceType::P | AvcSliceType::Sp => pps.weighted_pred,
+            AvcSliceType::B => pps.weighted_bipred_idc == 1,
+            _ => false,
+        };
+
+        if explicit_weights {
+            let chroma = !sps.separate_colour_plane && sps.chroma_format_idc != 0;
+
+            // luma_log2_weight_denom and chroma_log2_weight_denom
+            reader.read_ue()?;
+            if chroma {
+                reader.read_ue()?;
+            }
+
+            skip_pred_weights(&mut reader, num_ref_idx_l0_active, chroma)?;
+            if bidirectional {
+                skip_pred_weights(&mut reader, num_ref_idx_l1_active, chroma)?;
+            }
+        }
+
+        if nal_ref_idc != 0 {
+            skip_dec_ref_pic_marking(&mut reader, idr)?;
+        }
+
+        if pps.entropy_coding_mode && !slice_type.is_intra() {
+            // cabac_init_idc
+            reader.read_ue()?;
+        }
+
+        let qp = pps
+            .pic_init_qp
+            .checked_add(reader.read_se()?)
+            .filter(|qp| qp_range.contains(qp))?;
+
+        Some(Self {
+            nal_ref_idc,
+            idr,
+            first_mb_in_slice,
+            slice_type,
+            pps_id,
+            frame_num,
+            field_pic,
+            bottom_field,
+            idr_pic_id,
+            pic_order_cnt_lsb,
+            qp,
+        })
+    }
+}
+
+fn skip_ref_pic_list_modification(reader: &mut BitReader) -> Option<()> {
+    // ref_pic_list_modification_flag
+    if !reader.read_bit()? {
+        return Some(());
+    }
+
+    loop {
+        match reader.read_ue()? {
+            // abs_diff_pic_num_minus1 or long_term_pic_num
+            0..=2 => reader.read_ue()?,
+            3 => return Some(()),
+            _ => return None,
+        };
+    }
+}
+
+fn skip_pred_weights(reader: &mut BitReader, count: u32, chroma: bool) -> Option<()> {
+    for _ in 0..count {
+        // luma_weight_flag, then the weight and offset
+        if reader.read_bit()? {
+            reader.read_se()?;
+            reader.read_se()?;
+        }
+
+        // chroma_weight_flag, then the weights and offsets of both planes
+        if chroma && reader.read_bit()? {
+            for _ in 0..4 {
+                reader.read_se()?;
+            }
+        }
+    }
+
+    Some(())
+}
+
+fn skip_dec_ref_pic_marking(reader: &mut BitReader, idr: bool) -> Option<()> {
+    if idr {
+        // no_output_of_prior_pics_flag and long_term_reference_flag
+        return reader.skip_bits(2);
+    }
+
+    // adaptive_ref_pic_marking_mode_flag
+    if !reader.read_bit()? {
+        return Some(());
+    }
+
+    loop {
+        match reader.read_ue()? {
+            0 => return Some(()),
+            3 => {
+                // difference_of_pic_nums_minus1 and long_term_frame_idx
+                reader.read_ue()?;
+                reader.read_ue()?;
+            }
+            1 | 2 | 4 | 6 => {
+                reader.read_ue()?;
+            }
+            5 => {}
+            _ => return None,
+        }
+    }
+}
+
+/// The avcC record, used as the codec private data of H.264 in MP4 and Matroska
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvcDecoderConfigurationRecord {
+    pub profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub level_indication: u8,
+    /// The size of the NAL unit lengths in the samples, 1, 2 or 4 bytes
+    pub length_size: u8,
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+    /// Only present for the High profiles
+    pub chroma_format: Option<u8>,
+    pub bit_depth_luma: Option<u8>,
+    pub bit_depth_chroma: Option<u8>,
+    pub sps_ext: Vec<Vec<u8>>,
+}
+
+impl AvcDecoderConfigurationRecord {
+    /// Builds a record from the parameter sets (NAL units, without start codes).
+    ///
+    /// Returns `None` if there's no valid SPS
+    pub fn from_parameter_sets<S: AsRef<[u8]>, P: AsRef<[u8]>>(
+        sps: &[S],
+        pps: &[P],
+    ) -> Option<Self> {
+        let first = AvcSps::parse(sps.first()?.as_ref())?;
+        let high = avc_record_has_extension(first.profile_idc);
+
+        Some(Self {
+            profile_indication: first.profile_idc,
+            profile_compatibility: first.constraint_flags,
+            level_indication: first.level_idc,
+            length_size: 4,
+            sps: sps.iter().map(|nal| nal.as_ref().to_vec()).collect(),
+            pps: pps.iter().map(|nal| nal.as_ref().to_vec()).collect(),
+            chroma_format: high.then_some(first.chroma_format_idc as u8),
+            bit_depth_luma: high.then_some(first.bit_depth_luma as u8),
+            bit_depth_chroma: high.then_some(first.bit_depth_chroma as u8),
+            sps_ext: vec![],
+        })
+    }
+
+    /// Builds a record from the parameter sets found in an Annex-B byte stream
+    pub fn from_annexb(data: &[u8]) -> Option<Self> {
+        let mut sps = vec![];
+        let mut pps = vec![];
+
+        for nal in annexb_nal_units(data) {
+            match AvcNalType::from_header(nal[0]) {
+                AvcNalType::Sps if !sps.contains(&nal) => sps.push(nal),
+                AvcNalType::Pps if !pps.contains(&nal) => pps.push(nal),
+                _ => {}
+            }
+        }
+
+        Self::from_parameter_sets(&sps, &pps)
+    }
+
+    /// Builds a record from the `csd-0` (SPS) and `csd-1` (PPS) buffers of a `MediaFormat`
+    pub fn from_csd(csd0: &[u8], csd1: &[u8]) -> Option<Self> {
+        let mut data = csd0.to_vec();
+        data.extend_from_slice(csd1);
+
+        Self::from_annexb(&data)
+    }
+
+    /// Parses an avcC record
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data);
+
+        let version = reader.read_u8()?;
+        if version != 1 {
+            return None;
+        }
+
+        let profile_indication = reader.read_u8()?;
+        let profile_compatibility = reader.read_u8()?;
+        let level_indication = reader.read_u8()?;
+        let length_size = (reader.read_u8()? & 0x03) + 1;
+
+        let sps_count = reader.read_u8()? & 0x1f;
+        let sps = read_parameter_sets(&mut reader, sps_count as usize)?;
+        let pps_count = reader.read_u8()?;
+        let pps = read_parameter_sets(&mut reader, pps_count as usize)?;
+
+        let mut record = Self {
+            profile_indication,
+            profile_compatibility,
+            level_indication,
+            length_size,
+            sps,
+            pps,
+            chroma_format: None,
+            bit_depth_luma: None,
+            bit_depth_chroma: None,
+            sps_ext: vec![],
+        };
+
+        // Plenty of files leave out the extension, even for the High profiles
+        if avc_record_has_extension(profile_indication) && reader.bits_left() >= 32 {
+            record.chroma_format = Some(reader.read_u8()? & 0x03);
+            record.bit_depth_luma = Some((reader.read_u8()? & 0x07) + 8);
+            record.bit_depth_chroma = Some((reader.read_u8()? & 0x07) + 8);
+
+            let ext_count = reader.read_u8()?;
+            record.sps_ext = read_parameter_sets(&mut reader, ext_count as usize)?;
+        }
+
+        Some(record)
+    }
+
+    /// Serializes the record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = vec![
+            1,
+            self.profile_indication,
+            self.profile_compatibility,
+            self.level_indication,
+            0xfc | (self.length_size.clamp(1, 4) - 1),
+            0xe0 | self.sps.len() as u8,
+        ];
+
+        write_parameter_sets(&mut output, &self.sps);
+        output.push(self.pps.len() as u8);
+        write_parameter_sets(&mut output, &self.pps);
+
+        if let (Some(chroma_format), Some(luma), Some(chroma)) = (
+            self.chroma_format,
+            self.bit_depth_luma,
+            self.bit_depth_chroma,
+        ) {
+            output.push(0xfc | chroma_format);
+            output.push(0xf8 | (luma - 8));
+            output.push(0xf8 | (chroma - 8));
+            output.push(self.sps_ext.len() as u8);
+            write_parameter_sets(&mut output, &self.sps_ext);
+        }
+
+        output
+    }
+
+    /// The `csd-0` buffer for MediaCodec: the SPS NAL units, in Annex-B format
+    pub fn csd0(&self) -> Vec<u8> {
+        to_annexb(&self.sps)
+    }
+
+    /// The `csd-1` buffer for MediaCodec: the PPS NAL units, in Annex-B format
+    pub fn csd1(&self) -> Vec<u8> {
+        to_annexb(&self.pps)
+    }
+
+    /// Parses the first SPS of the record
+    pub fn parse_sps(&self) -> Option<AvcSps> {
+        AvcSps::parse(self.sps.first()?)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a `video/avc` decoder.
+    ///
+    /// Sets the dimensions, codec specific data, frame rate, profile, level and colors, as far as the SPS tells us
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let sps = self.parse_sps()?;
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "video/avc");
+        format.set_i32("width", sps.width() as i32);
+        format.set_i32("height", sps.height() as i32);
+        format.set_buffer("csd-0", &self.csd0());
+        format.set_buffer("csd-1", &self.csd1());
+
+        if let Some(frame_rate) = sps.frame_rate() {
+            format.set_f32("frame-rate", frame_rate as f32);
+        }
+
+        if let Some(profile) = sps.media_codec_profile() {
+            format.set_i32("profile", profile);
+        }
+
+        if let Some(level) = sps.media_codec_level() {
+            format.set_i32("level", level);
+        }
+
+        if let Some(color) = sps.vui.as_ref().and_then(|vui| vui.color) {
+            color.apply_to(&mut format);
+        }
+
+        Some(format)
+    }
+}
+
+/// Whether an avcC record has the chroma format, bit depth and SPS extension fields.
+///
+/// ISO/IEC 14496-15 has them for every profile but Baseline, Main and Extended
+fn avc_record_has_extension(profile_indication: u8) -> bool {
+    !matches!(profile_indication, 66 | 77 | 88)
+}
+
+/// Reads `count` parameter sets with 16-bit lengths
+pub(crate) fn read_parameter_sets(reader: &mut BitReader, count: usize) -> Option<Vec<Vec<u8>>> {
+    let mut sets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let length = reader.read_u16()? as usize;
+        let mut nal = Vec::with_capacity(length);
+        for _ in 0..length {
+            nal.push(reader.read_u8()?);
+        }
+        sets.push(nal);
+    }
+
+    Some(sets)
+}
+
+/// Writes parameter sets with 16-bit lengths
+pub(crate) fn write_parameter_sets(output: &mut Vec<u8>, sets: &[Vec<u8>]) {
+    for nal in sets {
+        output.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        output.extend_from_slice(nal);
+    }
+}
+
+/// Joins NAL units into an Annex-B byte stream
+pub(crate) fn to_annexb(units: &[Vec<u8>]) -> Vec<u8> {
+    let mut output = vec![];
+    for nal in units {
+        output.extend_from_slice(&ANNEXB_START_CODE);
+        output.extend_from_slice(nal);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{annexb_to_length_prefixed, bitstream::BitWriter, length_prefixed_to_annexb};
+
+    /// A 1080p SPS (coded as 1088 lines and cropped), with the frame rate in its VUI for the High profile
+    fn sps(profile_idc: u8) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x67, 8);
+        writer.write_bits(profile_idc as u64, 8);
+        writer.write_bits(0x40, 8);
+        writer.write_bits(40, 8);
+        // seq_parameter_set_id
+        writer.write_ue(0);
+
+        let high = avc_record_has_extension(profile_idc);
+        if high {
+            // 4:2:0, 8 bits, no scaling matrices
+            writer.write_ue(1);
+            writer.write_ue(0);
+            writer.write_ue(0);
+            writer.write_bits(0, 2);
+        }
+
+        // log2_max_frame_num_minus4, pic_order_cnt_type 2, max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+        writer.write_ue(0);
+        writer.write_ue(2);
+        writer.write_ue(1);
+        writer.write_bit(false);
+        // 120x68 macroblocks, frame_mbs_only_flag, direct_8x8_inference_flag
+        writer.write_ue(119);
+        writer.write_ue(67);
+        writer.write_bits(0b11, 2);
+        // Crop 8 lines at the bottom
+        writer.write_bit(true);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(4);
+
+        writer.write_bit(high);
+        if high {
+            // Only timing info: 30000/1001 fps
+            writer.write_bits(0, 4);
+            writer.write_bit(true);
+            writer.write_bits(1001, 32);
+            writer.write_bits(60000, 32);
+            writer.write_bit(true);
+            writer.write_bits(0, 4);
+        }
+
+        // rbsp_stop_one_bit
+        writer.write_bit(true);
+        writer.into_bytes()
+    }
+
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    #[test]
+    fn parse_sps() {
+        let sps = AvcSps::parse(&sps(66)).unwrap();
+
+        assert_eq!((sps.coded_width, sps.coded_height), (1920, 1088));
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!(sps.pic_order_cnt_type, 2);
+        assert_eq!(sps.max_num_ref_frames, 1);
+        assert!(sps.frame_mbs_only);
+        assert_eq!(sps.frame_rate(), None);
+        assert_eq!(sps.media_codec_profile(), Some(0x10000));
+        assert_eq!(sps.media_codec_level(), Some(0x800));
+    }
+
+    #[test]
+    fn parse_high_profile_sps() {
+        let sps = AvcSps::parse(&sps(100)).unwrap();
+
+        assert_eq!(sps.chroma_format_idc, 1);
+        assert_eq!((sps.bit_depth_luma, sps.bit_depth_chroma), (8, 8));
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert!((sps.frame_rate().unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(sps.media_codec_profile(), Some(0x08));
+    }
+
+    #[test]
+    fn parse_pps() {
+        let pps = AvcPps::parse(&PPS).unwrap();
+
+        assert_eq!((pps.pps_id, pps.sps_id), (0, 0));
+        assert!(!pps.entropy_coding_mode);
+        assert_eq!(pps.pic_init_qp, 26);
+        assert!(pps.deblocking_filter_control_present);
+    }
+
+    #[test]
+    fn record_from_annexb() {
+        let sps = sps(100);
+        let mut annexb = ANNEXB_START_CODE.to_vec();
+        annexb.extend_from_slice(&sps);
+        annexb.extend_from_slice(&[0, 0, 1]);
+        annexb.extend_from_slice(&PPS);
+        annexb.extend_from_slice(&[0, 0, 1, 0x65, 0x88, 0x84]);
+
+        let record = AvcDecoderConfigurationRecord::from_annexb(&annexb).unwrap();
+        assert_eq!(record.profile_indication, 100);
+        assert_eq!(record.level_indication, 40);
+        assert_eq!(record.length_size, 4);
+        assert_eq!(record.sps.len(), 1);
+        assert_eq!(record.sps[0], sps);
+        assert_eq!(record.pps, [PPS.to_vec()]);
+        assert_eq!(record.chroma_format, Some(1));
+
+        let bytes = record.to_bytes();
+        assert_eq!(bytes[..6], [1, 100, 0x40, 40, 0xff, 0xe1]);
+        assert_eq!(
+            AvcDecoderConfigurationRecord::parse(&bytes),
+            Some(record.clone())
+        );
+
+        let mut csd0 = ANNEXB_START_CODE.to_vec();
+        csd0.extend_from_slice(&sps);
+        assert_eq!(record.csd0(), csd0);
+        assert_eq!(
+            AvcDecoderConfigurationRecord::from_csd(&record.csd0(), &record.csd1()),
+            Some(record)
+        );
+    }
+
+    #[test]
+    fn record_without_high_profile_extension() {
+        let record =
+            AvcDecoderConfigurationRecord::from_parameter_sets(&[sps(66)], &[PPS]).unwrap();
+        let bytes = record.to_bytes();
+
+        // No chroma format and bit depths after the PPS
+        assert_eq!(bytes.len(), 6 + 2 + sps(66).len() + 1 + 2 + PPS.len());
+        assert_eq!(record.parse_sps().unwrap().width(), 1920);
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let mut annexb = ANNEXB_START_CODE.to_vec();
+        annexb.extend_from_slice(&[0x65, 0x88, 0x84, 0x00, 0x10]);
+        annexb.extend_from_slice(&ANNEXB_START_CODE);
+        annexb.extend_from_slice(&[0x01, 0x9a, 0x02]);
+
+        let avcc = annexb_to_length_prefixed(&annexb, 4);
+        assert_eq!(avcc[..4], [0, 0, 0, 5]);
+        assert_eq!(length_prefixed_to_annexb(&avcc, 4), Some(annexb));
+    }
+
+    /// An SPS with pic_order_cnt_type 0, and the fields that have ranges to check. The profile has the chroma format
+    /// and bit depths when `bit_depth_minus8` is set
+    fn custom_sps(
+        bit_depth_minus8: Option<u32>,
+        log2_max_frame_num_minus4: u32,
+        log2_max_pic_order_cnt_lsb_minus4: u32,
+        size_in_mbs_minus1: (u32, u32),
+        crop: [u32; 4],
+    ) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x67, 8);
+        writer.write_bits(if bit_depth_minus8.is_some() { 110 } else { 77 }, 8);
+        writer.write_bits(0, 8);
+        writer.write_bits(40, 8);
+        writer.write_ue(0);
+
+        if let Some(bit_depth_minus8) = bit_depth_minus8 {
+            writer.write_ue(1);
+            writer.write_ue(bit_depth_minus8);
+            writer.write_ue(bit_depth_minus8);
+            writer.write_bits(0, 2);
+        }
+
+        writer.write_ue(log2_max_frame_num_minus4);
+        writer.write_ue(0);
+        writer.write_ue(log2_max_pic_order_cnt_lsb_minus4);
+        writer.write_ue(1);
+        writer.write_bit(false);
+        writer.write_ue(size_in_mbs_minus1.0);
+        writer.write_ue(size_in_mbs_minus1.1);
+        writer.write_bits(0b11, 2);
+
+        writer.write_bit(true);
+        for value in crop {
+            writer.write_ue(value);
+        }
+
+        // No VUI, rbsp_stop_one_bit
+        writer.write_bits(0b01, 2);
+        writer.into_bytes()
+    }
+
+    /// A CAVLC PPS with the given `pic_init_qp_minus26`, as its ue(v) code
+    fn custom_pps(pic_init_qp_minus26_code: u32) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x68, 8);
+        // pps_id, sps_id, entropy_coding_mode_flag, bottom_field_pic_order_in_frame_present_flag, one slice group,
+        // one reference, weighted_pred_flag and weighted_bipred_idc
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_bits(0, 2);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_bits(0, 3);
+        writer.write_ue(pic_init_qp_minus26_code);
+        // pic_init_qs_minus26, chroma_qp_index_offset, then the deblocking flag set and the other two unset
+        writer.write_se(0);
+        writer.write_se(0);
+        writer.write_bits(0b100, 3);
+        writer.write_bit(true);
+        writer.into_bytes()
+    }
+
+    /// An IDR slice header for [custom_sps] and [custom_pps]
+    fn idr_slice(
+        log2_max_frame_num: u32,
+        log2_max_pic_order_cnt_lsb: u32,
+        qp_delta_code: u32,
+    ) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0x65, 8);
+        // first_mb_in_slice, an I slice, pps_id
+        writer.write_ue(0);
+        writer.write_ue(7);
+        writer.write_ue(0);
+        writer.write_bits(0, log2_max_frame_num);
+        // idr_pic_id
+        writer.write_ue(0);
+        writer.write_bits(3, log2_max_pic_order_cnt_lsb);
+        // dec_ref_pic_marking
+        writer.write_bits(0, 2);
+        writer.write_ue(qp_delta_code);
+        writer.write_bits(0x80, 8);
+        writer.into_bytes()
+    }
+
+    /// The ue(v) code of a se(v) value
+    fn se_code(value: i64) -> u32 {
+        if value > 0 {
+            (value * 2 - 1) as u32
+        } else {
+            (-value * 2) as u32
+        }
+    }
+
+    #[test]
+    fn truncated_sps() {
+        for sps in [sps(66), custom_sps(Some(2), 0, 2, (119, 67), [0, 0, 0, 4])] {
+            assert!(AvcSps::parse(&sps).is_some());
+            for size in 0..sps.len() - 1 {
+                assert_eq!(AvcSps::parse(&sps[..size]), None, "{size} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn sps_ranges() {
+        let valid = custom_sps(Some(6), 12, 12, (1054, 1054), [1, 2, 3, 4]);
+        let sps = AvcSps::parse(&valid).unwrap();
+        assert_eq!((sps.bit_depth_luma, sps.bit_depth_chroma), (14, 14));
+        assert_eq!(
+            (sps.log2_max_frame_num, sps.log2_max_pic_order_cnt_lsb),
+            (16, 16)
+        );
+        assert_eq!((sps.coded_width, sps.coded_height), (1055 * 16, 1055 * 16));
+        assert_eq!((sps.width(), sps.height()), (1055 * 16 - 6, 1055 * 16 - 14));
+
+        let max_ue = u32::MAX - 1;
+        for invalid in [
+            custom_sps(Some(7), 0, 0, (0, 0), [0; 4]),
+            custom_sps(Some(max_ue), 0, 0, (0, 0), [0; 4]),
+            custom_sps(None, 13, 0, (0, 0), [0; 4]),
+            custom_sps(None, max_ue, 0, (0, 0), [0; 4]),
+            custom_sps(None, 0, 13, (0, 0), [0; 4]),
+            custom_sps(None, 0, max_ue, (0, 0), [0; 4]),
+            custom_sps(None, 0, 0, (1055, 0), [0; 4]),
+            custom_sps(None, 0, 0, (0, max_ue), [0; 4]),
+            // Cropping everything, or overflowing the crop units
+            custom_sps(None, 0, 0, (0, 0), [4, 4, 0, 0]),
+            custom_sps(None, 0, 0, (0, 0), [0, 0, 0, 8]),
+            custom_sps(None, 0, 0, (9, 9), [max_ue, 0, 0, 0]),
+            custom_sps(None, 0, 0, (9, 9), [0, 0, max_ue / 2, max_ue / 2]),
+        ] {
+            assert_eq!(AvcSps::parse(&invalid), None);
+        }
+
+        // Hand built structures don't overflow either
+        let mut sps = sps;
+        sps.crop.left = u32::MAX;
+        sps.crop.right = u32::MAX;
+        assert_eq!(sps.width(), 0);
+    }
+
+    #[test]
+    fn pps_ranges() {
+        let sps = AvcSps::parse(&custom_sps(None, 12, 12, (9, 9), [0; 4])).unwrap();
+        let high_bit_depth = AvcSps::parse(&custom_sps(Some(2), 12, 12, (9, 9), [0; 4])).unwrap();
+
+        // The QP goes down to -12 with 10 bits
+        let pps = AvcPps::parse(&custom_pps(se_code(-38))).unwrap();
+        assert_eq!(pps.pic_init_qp, -12);
+        let slice = idr_slice(16, 16, se_code(0));
+        assert_eq!(
+            AvcSliceHeader::parse(
+                &slice,
+                std::slice::from_ref(&sps),
+                std::slice::from_ref(&pps)
+            ),
+            None
+        );
+        let header = AvcSliceHeader::parse(&slice, &[high_bit_depth], &[pps]).unwrap();
+        assert_eq!(header.qp, -12);
+        assert_eq!(header.pic_order_cnt_lsb, Some(3));
+
+        let pps = AvcPps::parse(&custom_pps(se_code(25))).unwrap();
+        assert_eq!(pps.pic_init_qp, 51);
+        for qp_delta in [1, i32::MAX as i64, -(i32::MAX as i64)] {
+            let slice = idr_slice(16, 16, se_code(qp_delta));
+            assert_eq!(
+                AvcSliceHeader::parse(
+                    &slice,
+                    std::slice::from_ref(&sps),
+                    std::slice::from_ref(&pps)
+                ),
+                None
+            );
+        }
+        let slice = idr_slice(16, 16, se_code(-51));
+        assert_eq!(AvcSliceHeader::parse(&slice, &[sps], &[pps]).unwrap().qp, 0);
+
+        for minus26 in [26, -63, i32::MAX as i64, -(i32::MAX as i64)] {
+            assert_eq!(AvcPps::parse(&custom_pps(se_code(minus26))), None);
+        }
+    }
+
+    #[test]
+    fn record_extension_profiles() {
+        for (profile_idc, extension) in [
+            (66, false),
+            (77, false),
+            (88, false),
+            (100, true),
+            (244, true),
+            (118, true),
+        ] {
+            let record =
+                AvcDecoderConfigurationRecord::from_parameter_sets(&[sps(profile_idc)], &[PPS])
+                    .unwrap();
+            assert_eq!(record.chroma_format.is_some(), extension);
+
+            let bytes = record.to_bytes();
+            assert_eq!(AvcDecoderConfigurationRecord::parse(&bytes), Some(record));
+        }
+    }
+}
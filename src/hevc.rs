@@ -0,0 +1,1409 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{
+    annexb_nal_units, bitstream::BitReader, h264::read_aspect_ratio, h264::read_color_info,
+    h264::to_annexb, unescape_rbsp, CropRect, VideoColorInfo,
+};
+
+/// HEVC NAL unit types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HevcNalType {
+    TrailN,
+    TrailR,
+    TsaN,
+    TsaR,
+    StsaN,
+    StsaR,
+    RadlN,
+    RadlR,
+    RaslN,
+    RaslR,
+    BlaWLp,
+    BlaWRadl,
+    BlaNLp,
+    IdrWRadl,
+    IdrNLp,
+    Cra,
+    Vps,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfBitstream,
+    FillerData,
+    PrefixSei,
+    SuffixSei,
+    Other(u8),
+}
+
+impl HevcNalType {
+    /// Returns the type of a NAL unit from its first byte
+    pub fn from_header(header: u8) -> Self {
+        match (header >> 1) & 0x3f {
+            0 => Self::TrailN,
+            1 => Self::TrailR,
+            2 => Self::TsaN,
+            3 => Self::TsaR,
+            4 => Self::StsaN,
+            5 => Self::StsaR,
+            6 => Self::RadlN,
+            7 => Self::RadlR,
+            8 => Self::RaslN,
+            9 => Self::RaslR,
+            16 => Self::BlaWLp,
+            17 => Self::BlaWRadl,
+            18 => Self::BlaNLp,
+            19 => Self::IdrWRadl,
+            20 => Self::IdrNLp,
+            21 => Self::Cra,
+            32 => Self::Vps,
+            33 => Self::Sps,
+            34 => Self::Pps,
+            35 => Self::AccessUnitDelimiter,
+            36 => Self::EndOfSequence,
+            37 => Self::EndOfBitstream,
+            38 => Self::FillerData,
+            39 => Self::PrefixSei,
+            40 => Self::SuffixSei,
+            other => Self::Other(other),
+        }
+    }
+
+    /// The numeric value of the type
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::TrailN => 0,
+            Self::TrailR => 1,
+            Self::TsaN => 2,
+            Self::TsaR => 3,
+            Self::StsaN => 4,
+            Self::StsaR => 5,
+            Self::RadlN => 6,
+            Self::RadlR => 7,
+            Self::RaslN => 8,
+            Self::RaslR => 9,
+            Self::BlaWLp => 16,
+            Self::BlaWRadl => 17,
+            Self::BlaNLp => 18,
+            Self::IdrWRadl => 19,
+            Self::IdrNLp => 20,
+            Self::Cra => 21,
+            Self::Vps => 32,
+            Self::Sps => 33,
+            Self::Pps => 34,
+            Self::AccessUnitDelimiter => 35,
+            Self::EndOfSequence => 36,
+            Self::EndOfBitstream => 37,
+            Self::FillerData => 38,
+            Self::PrefixSei => 39,
+            Self::SuffixSei => 40,
+            Self::Other(value) => *value,
+        }
+    }
+
+    /// Whether NAL units of this type carry picture data
+    pub fn is_vcl(&self) -> bool {
+        self.value() < 32
+    }
+
+    /// Whether this is an intra random access point, where decoding can start
+    pub fn is_irap(&self) -> bool {
+        (16..=23).contains(&self.value())
+    }
+
+    pub fn is_idr(&self) -> bool {
+        matches!(self, Self::IdrWRadl | Self::IdrNLp)
+    }
+}
+
+/// Returns the temporal layer of an HEVC NAL unit, from its two byte header
+pub fn hevc_temporal_id(nal: &[u8]) -> Option<u8> {
+    Some((nal.get(1)? & 0x07).saturating_sub(1))
+}
+
+/// The general profile, tier and level of an HEVC stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HevcProfileTierLevel {
+    pub profile_space: u8,
+    /// The high tier when set, the main tier otherwise
+    pub tier: bool,
+    pub profile_idc: u8,
+    pub profile_compatibility_flags: u32,
+    /// The 48 bits starting with `general_progressive_source_flag`
+    pub constraint_indicator_flags: u64,
+    /// The level times 30
+    pub level_idc: u8,
+}
+
+impl HevcProfileTierLevel {
+    fn parse(reader: &mut BitReader, max_sub_layers_minus1: u32) -> Option<Self> {
+        let ptl = Self {
+            profile_space: reader.read_bits(2)? as u8,
+            tier: reader.read_bit()?,
+            profile_idc: reader.read_bits(5)? as u8,
+            profile_compatibility_flags: reader.read_bits(32)?,
+            constraint_indicator_flags: reader.read_bits_u64(48)?,
+            level_idc: reader.read_u8()?,
+        };
+
+        let mut profile_present = vec![];
+        let mut level_present = vec![];
+        for _ in 0..max_sub_layers_minus1 {
+            profile_present.push(reader.read_bit()?);
+            level_present.push(reader.read_bit()?);
+        }
+
+        if max_sub_layers_minus1 > 0 {
+            reader.skip_bits(2 * (8 - max_sub_layers_minus1 as usize))?;
+        }
+
+        for (profile, level) in profile_present.into_iter().zip(level_present) {
+            if profile {
+                reader.skip_bits(88)?;
+            }
+
+            if level {
+                reader.skip_bits(8)?;
+            }
+        }
+
+        Some(ptl)
+    }
+
+    /// The profile the stream conforms to.
+    ///
+    /// The compatibility flags only matter when `general_profile_idc` is 0, the first one set is the profile then.
+    /// Main streams set the Main 10 flag too, as a Main 10 decoder can play them
+    pub fn profile(&self) -> u8 {
+        if self.profile_idc != 0 {
+            return self.profile_idc;
+        }
+
+        (1..32)
+            .find(|profile| self.profile_compatibility_flags & (1 << (31 - profile)) != 0)
+            .unwrap_or(0) as u8
+    }
+
+    /// Whether the stream is profiled as Main 10
+    pub fn is_main10(&self) -> bool {
+        self.profile() == 2
+    }
+
+    /// The value of the `level` key in a `MediaFormat` (the `HEVC*TierLevel*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_level(&self) -> Option<i32> {
+        let main_tier = match self.level_idc {
+            30 => 0x1,
+            60 => 0x4,
+            63 => 0x10,
+            90 => 0x40,
+            93 => 0x100,
+            120 => 0x400,
+            123 => 0x1000,
+            150 => 0x4000,
+            153 => 0x10000,
+            156 => 0x40000,
+            180 => 0x100000,
+            183 => 0x400000,
+            186 => 0x1000000,
+            _ => return None,
+        };
+
+        // The high tier constants are the ones right after the main tier ones
+        Some(if self.tier { main_tier << 1 } else { main_tier })
+    }
+}
+
+/// An HEVC video parameter set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcVps {
+    pub vps_id: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: HevcProfileTierLevel,
+}
+
+impl HevcVps {
+    /// Parses a video parameter set NAL unit, including its two byte NAL header
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 3 || HevcNalType::from_header(nal[0]) != HevcNalType::Vps {
+            return None;
+        }
+
+        let rbsp = unescape_rbsp(&nal[2..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let vps_id = reader.read_bits(4)? as u8;
+        // vps_base_layer_internal_flag, vps_base_layer_available_flag and vps_max_layers_minus1
+        reader.skip_bits(8)?;
+        let max_sub_layers_minus1 = reader.read_bits(3)?;
+        let temporal_id_nesting = reader.read_bit()?;
+        // vps_reserved_0xffff_16bits
+        reader.skip_bits(16)?;
+
+        Some(Self {
+            vps_id,
+            max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+            temporal_id_nesting,
+            profile_tier_level: HevcProfileTierLevel::parse(&mut reader, max_sub_layers_minus1)?,
+        })
+    }
+}
+
+/// The VUI parameters of an HEVC sequence parameter set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HevcVui {
+    /// The sample (pixel) aspect ratio
+    pub sample_aspect_ratio: Option<(u16, u16)>,
+    pub color: Option<VideoColorInfo>,
+    pub field_seq: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+}
+
+impl HevcVui {
+    fn parse(reader: &mut BitReader) -> Option<Self> {
+        let mut vui = Self::default();
+
+        if reader.read_bit()? {
+            vui.sample_aspect_ratio = Some(read_aspect_ratio(reader)?);
+        }
+
+        // overscan_info_present_flag
+        if reader.read_bit()? {
+            reader.skip_bits(1)?;
+        }
+
+        // video_signal_type_present_flag
+        if reader.read_bit()? {
+            // video_format
+            reader.skip_bits(3)?;
+            vui.color = Some(read_color_info(reader)?);
+        }
+
+        // chroma_loc_info_present_flag
+        if reader.read_bit()? {
+            reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        // neutral_chroma_indication_flag
+        reader.skip_bits(1)?;
+        vui.field_seq = reader.read_bit()?;
+        // frame_field_info_present_flag
+        reader.skip_bits(1)?;
+
+        // default_display_window_flag
+        if reader.read_bit()? {
+            for _ in 0..4 {
+                reader.read_ue()?;
+            }
+        }
+
+        // vui_timing_info_present_flag. We stop here, the HRD parameters are of no interest
+        if reader.read_bit()? {
+            vui.num_units_in_tick = reader.read_bits(32)?;
+            vui.time_scale = reader.read_bits(32)?;
+        }
+
+        Some(vui)
+    }
+
+    /// The frame rate, if the stream signals its timing
+    pub fn frame_rate(&self) -> Option<f64> {
+        if self.num_units_in_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+
+        Some(self.time_scale as f64 / self.num_units_in_tick as f64)
+    }
+}
+
+/// An HEVC sequence parameter set
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcSps {
+    pub vps_id: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: HevcProfileTierLevel,
+    pub sps_id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane: bool,
+    /// The width of the decoded picture, before cropping
+    pub coded_width: u32,
+    /// The height of the decoded picture, before cropping
+    pub coded_height: u32,
+    /// The conformance window
+    pub crop: CropRect,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub log2_max_pic_order_cnt_lsb: u32,
+    pub max_num_reorder_pics: u32,
+    pub log2_min_luma_coding_block_size: u32,
+    pub log2_ctb_size: u32,
+    pub num_short_term_ref_pic_sets: u32,
+    /// The number of delta POCs of each short-term reference picture set
+    pub num_delta_pocs: Vec<u32>,
+    pub long_term_ref_pics_present: bool,
+    pub num_long_term_ref_pics_sps: u32,
+    pub temporal_mvp_enabled: bool,
+    pub sample_adaptive_offset_enabled: bool,
+    pub vui: Option<HevcVui>,
+}
+
+impl HevcSps {
+    /// Parses a sequence parameter set NAL unit, including its two byte NAL header
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 3 || HevcNalType::from_header(nal[0]) != HevcNalType::Sps {
+            return None;
+        }
+
+        let rbsp = unescape_rbsp(&nal[2..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let vps_id = reader.read_bits(4)? as u8;
+        let max_sub_layers_minus1 = reader.read_bits(3)?;
+        let temporal_id_nesting = reader.read_bit()?;
+        let profile_tier_level = HevcProfileTierLevel::parse(&mut reader, max_sub_layers_minus1)?;
+
+        let sps_id = reader.read_ue()?;
+        let chroma_format_idc = reader.read_ue()?;
+        let separate_colour_plane = chroma_format_idc == 3 && reader.read_bit()?;
+
+        let coded_width = reader.read_ue()?;
+        let coded_height = reader.read_ue()?;
+
+        let mut crop = CropRect::default();
+        if reader.read_bit()? {
+            // The conformance window is in chroma sample units
+            let (unit_x, unit_y) = match (chroma_format_idc, separate_colour_plane) {
+                (1, false) => (2, 2),
+                (2, false) => (2, 1),
+                _ => (1, 1),
+            };
+
+            crop.left = reader.read_ue()? * unit_x;
+            crop.right = reader.read_ue()? * unit_x;
+            crop.top = reader.read_ue()? * unit_y;
+            crop.bottom = reader.read_ue()? * unit_y;
+        }
+
+        let bit_depth_luma = reader.read_ue()? + 8;
+        let bit_depth_chroma = reader.read_ue()? + 8;
+        let log2_max_pic_order_cnt_lsb = reader.read_ue()? + 4;
+
+        let sub_layer_ordering_info_present = reader.read_bit()?;
+        let first = if sub_layer_ordering_info_present {
+            0
+        } else {
+            max_sub_layers_minus1
+        };
+
+        let mut max_num_reorder_pics = 0;
+        for _ in first..=max_sub_layers_minus1 {
+            // sps_max_dec_pic_buffering_minus1, sps_max_num_reorder_pics and sps_max_latency_increase_plus1
+            reader.read_ue()?;
+            max_num_reorder_pics = reader.read_ue()?;
+            reader.read_ue()?;
+        }
+
+        let log2_min_luma_coding_block_size = reader.read_ue()? + 3;
+        let log2_ctb_size = log2_min_luma_coding_block_size + reader.read_ue()?;
+        // log2_min_luma_transform_block_size_minus2, log2_diff_max_min_luma_transform_block_size,
+        // max_transform_hierarchy_depth_inter and max_transform_hierarchy_depth_intra
+        for _ in 0..4 {
+            reader.read_ue()?;
+        }
+
+        // scaling_list_enabled_flag
+        if reader.read_bit()? {
+            // sps_scaling_list_data_present_flag
+            if reader.read_bit()? {
+                skip_scaling_list_data(&mut reader)?;
+            }
+        }
+
+        // amp_enabled_flag
+        reader.skip_bits(1)?;
+        let sample_adaptive_offset_enabled = reader.read_bit()?;
+
+        // pcm_enabled_flag
+        if reader.read_bit()? {
+            // pcm_sample_bit_depth_luma_minus1 and pcm_sample_bit_depth_chroma_minus1
+            reader.skip_bits(8)?;
+            reader.read_ue()?;
+            reader.read_ue()?;
+            // pcm_loop_filter_disabled_flag
+            reader.skip_bits(1)?;
+        }
+
+        let num_short_term_ref_pic_sets = reader.read_ue()?;
+        if num_short_term_ref_pic_sets > 64 {
+            return None;
+        }
+
+        let mut num_delta_pocs = vec![];
+        for index in 0..num_short_term_ref_pic_sets as usize {
+            let count = read_short_term_ref_pic_set(
+                &mut reader,
+                index,
+                num_short_term_ref_pic_sets as usize,
+                &num_delta_pocs,
+            )?;
+            num_delta_pocs.push(count);
+        }
+
+        let long_term_ref_pics_present = reader.read_bit()?;
+        let mut num_long_term_ref_pics_sps = 0;
+        if long_term_ref_pics_present {
+            num_long_term_ref_pics_sps = reader.read_ue()?;
+            if num_long_term_ref_pics_sps > 32 {
+                return None;
+            }
+
+            for _ in 0..num_long_term_ref_pics_sps {
+                // lt_ref_pic_poc_lsb_sps and used_by_curr_pic_lt_sps_flag
+                reader.skip_bits(log2_max_pic_order_cnt_lsb as usize + 1)?;
+            }
+        }
+
+        let temporal_mvp_enabled = reader.read_bit()?;
+        // strong_intra_smoothing_enabled_flag
+        reader.skip_bits(1)?;
+
+        let vui = if reader.read_bit()? {
+            HevcVui::parse(&mut reader)
+        } else {
+            None
+        };
+
+        Some(Self {
+            vps_id,
+            max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+            temporal_id_nesting,
+            profile_tier_level,
+            sps_id,
+            chroma_format_idc,
+            separate_colour_plane,
+            coded_width,
+            coded_height,
+            crop,
+            bit_depth_luma,
+            bit_depth_chroma,
+            log2_max_pic_order_cnt_lsb,
+            max_num_reorder_pics,
+            log2_min_luma_coding_block_size,
+            log2_ctb_size,
+            num_short_term_ref_pic_sets,
+            num_delta_pocs,
+            long_term_ref_pics_present,
+            num_long_term_ref_pics_sps,
+            temporal_mvp_enabled,
+            sample_adaptive_offset_enabled,
+            vui,
+        })
+    }
+
+    /// The width of the picture, after cropping
+    pub fn width(&self) -> u32 {
+        self.coded_width
+            .saturating_sub(self.crop.left + self.crop.right)
+    }
+
+    /// The height of the picture, after cropping
+    pub fn height(&self) -> u32 {
+        self.coded_height
+            .saturating_sub(self.crop.top + self.crop.bottom)
+    }
+
+    /// The frame rate signalled in the VUI, if any
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.vui.as_ref()?.frame_rate()
+    }
+
+    /// The colors signalled in the VUI, if any
+    pub fn color(&self) -> Option<VideoColorInfo> {
+        self.vui.as_ref()?.color
+    }
+
+    /// Whether the stream has more than 8 bits per sample
+    pub fn is_high_bit_depth(&self) -> bool {
+        self.bit_depth_luma > 8 || self.bit_depth_chroma > 8
+    }
+
+    /// Whether the stream uses an HDR transfer function (PQ or HLG)
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.color().map(|color| color.transfer),
+            Some(16) | Some(18)
+        )
+    }
+
+    /// The value of the `profile` key in a `MediaFormat` (the `HEVCProfile*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_profile(&self) -> Option<i32> {
+        let ptl = &self.profile_tier_level;
+
+        if self.bit_depth_luma == 10 || ptl.is_main10() {
+            // PQ streams need the HDR10 profile, otherwise decoders treat them as SDR
+            let pq = matches!(self.color().map(|color| color.transfer), Some(16));
+            return Some(if pq { 0x1000 } else { 0x2 });
+        }
+
+        match ptl.profile() {
+            1 => Some(0x1),
+            3 => Some(0x4),
+            _ if self.bit_depth_luma == 8 => Some(0x1),
+            _ => None,
+        }
+    }
+}
+
+/// Skips a `short_term_ref_pic_set`, returning its number of delta POCs.
+///
+/// The set at index `num_sets` is the one of a slice header
+fn read_short_term_ref_pic_set(
+    reader: &mut BitReader,
+    index: usize,
+    num_sets: usize,
+    num_delta_pocs: &[u32],
+) -> Option<u32> {
+    let inter_ref_pic_set_prediction = index != 0 && reader.read_bit()?;
+
+    if inter_ref_pic_set_prediction {
+        let delta_idx = if index == num_sets {
+            reader.read_ue()? as usize + 1
+        } else {
+            1
+        };
+
+        // delta_rps_sign and abs_delta_rps_minus1
+        reader.skip_bits(1)?;
+        reader.read_ue()?;
+
+        let reference = *num_delta_pocs.get(index.checked_sub(delta_idx)?)?;
+        let mut count = 0;
+        for _ in 0..=reference {
+            let used_by_curr_pic = reader.read_bit()?;
+            let use_delta = used_by_curr_pic || reader.read_bit()?;
+            if use_delta {
+                count += 1;
+            }
+        }
+
+        return Some(count);
+    }
+
+    let negative = reader.read_ue()?;
+    let positive = reader.read_ue()?;
+    if negative > 16 || positive > 16 {
+        return None;
+    }
+
+    for _ in 0..negative + positive {
+        // delta_poc_minus1 and used_by_curr_pic_flag
+        reader.read_ue()?;
+        reader.skip_bits(1)?;
+    }
+
+    Some(negative + positive)
+}
+
+fn skip_scaling_list_data(reader: &mut BitReader) -> Option<()> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0..6).step_by(step) {
+            // scaling_list_pred_mode_flag
+            if !reader.read_bit()? {
+                // scaling_list_pred_matrix_id_delta
+                reader.read_ue()?;
+                continue;
+            }
+
+            let coefficients = 64.min(1 << (4 + (size_id << 1)));
+            if size_id > 1 {
+                // scaling_list_dc_coef_minus8
+                reader.read_se()?;
+            }
+
+            for _ in 0..coefficients {
+                reader.read_se()?;
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// An HEVC picture parameter set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcPps {
+    pub pps_id: u32,
+    pub sps_id: u32,
+    pub dependent_slice_segments_enabled: bool,
+    pub output_flag_present: bool,
+    pub num_extra_slice_header_bits: u32,
+    pub sign_data_hiding_enabled: bool,
+    pub cabac_init_present: bool,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    /// The initial QP of the slices, before `slice_qp_delta`
+    pub init_qp: i32,
+    pub weighted_pred: bool,
+    pub weighted_bipred: bool,
+    pub tiles_enabled: bool,
+    pub entropy_coding_sync_enabled: bool,
+    pub lists_modification_present: bool,
+}
+
+impl HevcPps {
+    /// Parses a picture parameter set NAL unit, including its two byte NAL header
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 3 || HevcNalType::from_header(nal[0]) != HevcNalType::Pps {
+            return None;
+        }
+
+        let rbsp = unescape_rbsp(&nal[2..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let pps_id = reader.read_ue()?;
+        let sps_id = reader.read_ue()?;
+        let dependent_slice_segments_enabled = reader.read_bit()?;
+        let output_flag_present = reader.read_bit()?;
+        let num_extra_slice_header_bits = reader.read_bits(3)?;
+        let sign_data_hiding_enabled = reader.read_bit()?;
+        let cabac_init_present = reader.read_bit()?;
+        let num_ref_idx_l0_default_active = reader.read_ue()? + 1;
+        let num_ref_idx_l1_default_active = reader.read_ue()? + 1;
+        let init_qp = 26 + reader.read_se()?;
+
+        // constrained_intra_pred_flag and transform_skip_enabled_flag
+        reader.skip_bits(2)?;
+        // cu_qp_delta_enabled_flag
+        if reader.read_bit()? {
+            // diff_cu_qp_delta_depth
+            reader.read_ue()?;
+        }
+
+        // pps_cb_qp_offset and pps_cr_qp_offset
+        reader.read_se()?;
+        reader.read_se()?;
+        // pps_slice_chroma_qp_offsets_present_flag
+        reader.skip_bits(1)?;
+
+        let weighted_pred = reader.read_bit()?;
+        let weighted_bipred = reader.read_bit()?;
+        // transquant_bypass_enabled_flag
+        reader.skip_bits(1)?;
+        let tiles_enabled = reader.read_bit()?;
+        let entropy_coding_sync_enabled = reader.read_bit()?;
+
+        if tiles_enabled {
+            let columns = reader.read_ue()?;
+            let rows = reader.read_ue()?;
+            // uniform_spacing_flag
+            if !reader.read_bit()? {
+                for _ in 0..columns + rows {
+                    reader.read_ue()?;
+                }
+            }
+
+            // loop_filter_across_tiles_enabled_flag
+            reader.skip_bits(1)?;
+        }
+
+        // pps_loop_filter_across_slices_enabled_flag
+        reader.skip_bits(1)?;
+        // deblocking_filter_control_present_flag
+        if reader.read_bit()? {
+            // deblocking_filter_override_enabled_flag
+            reader.skip_bits(1)?;
+            // pps_deblocking_filter_disabled_flag
+            if !reader.read_bit()? {
+                // pps_beta_offset_div2 and pps_tc_offset_div2
+                reader.read_se()?;
+                reader.read_se()?;
+            }
+        }
+
+        // pps_scaling_list_data_present_flag
+        if reader.read_bit()? {
+            skip_scaling_list_data(&mut reader)?;
+        }
+
+        let lists_modification_present = reader.read_bit()?;
+
+        Some(Self {
+            pps_id,
+            sps_id,
+            dependent_slice_segments_enabled,
+            output_flag_present,
+            num_extra_slice_header_bits,
+            sign_data_hiding_enabled,
+            cabac_init_present,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            init_qp,
+            weighted_pred,
+            weighted_bipred,
+            tiles_enabled,
+            entropy_coding_sync_enabled,
+            lists_modification_present,
+        })
+    }
+}
+
+/// The slice types of HEVC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HevcSliceType {
+    B,
+    P,
+    I,
+}
+
+impl HevcSliceType {
+    /// Returns the slice type from its `slice_type` value
+    pub fn from_value(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::B),
+            1 => Some(Self::P),
+            2 => Some(Self::I),
+            _ => None,
+        }
+    }
+}
+
+/// The slice headers are read from at most this many bytes of the slice segment
+const HEVC_SLICE_HEADER_MAX_SIZE: usize = 1024;
+
+/// The start of an HEVC slice segment header, up to `slice_qp_delta`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcSliceHeader {
+    pub nal_type: HevcNalType,
+    pub temporal_id: u8,
+    pub first_slice_segment_in_pic: bool,
+    pub pps_id: u32,
+    pub slice_segment_address: u32,
+    pub slice_type: HevcSliceType,
+    pub pic_output: bool,
+    /// Always 0 in IDR pictures
+    pub pic_order_cnt_lsb: u32,
+    /// The QP of the slice, which is `init_qp + slice_qp_delta`.
+    ///
+    /// This is `None` when the header has reference list modifications or a prediction weight table, which aren't parsed
+    pub qp: Option<i32>,
+}
+
+impl HevcSliceHeader {
+    /// Parses the header of a slice segment NAL unit, including its two byte NAL header.
+    ///
+    /// The parameter sets the slice refers to have to be in `sps` and `pps`.
+    /// Returns `None` for dependent slice segments, which take their header from the previous slice segment
+    pub fn parse(nal: &[u8], sps: &[HevcSps], pps: &[HevcPps]) -> Option<Self> {
+        let nal_type = HevcNalType::from_header(*nal.first()?);
+        if nal.len() < 3 || !nal_type.is_vcl() {
+            return None;
+        }
+
+        let temporal_id = hevc_temporal_id(nal)?;
+        let rbsp = unescape_rbsp(&nal[2..nal.len().min(HEVC_SLICE_HEADER_MAX_SIZE)]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let first_slice_segment_in_pic = reader.read_bit()?;
+        if nal_type.is_irap() {
+            // no_output_of_prior_pics_flag
+            reader.skip_bits(1)?;
+        }
+
+        let pps_id = reader.read_ue()?;
+        let pps = pps.iter().find(|pps| pps.pps_id == pps_id)?;
+        let sps = sps.iter().find(|sps| sps.sps_id == pps.sps_id)?;
+
+        let mut slice_segment_address = 0;
+        if !first_slice_segment_in_pic {
+            // dependent_slice_segment_flag
+            if pps.dependent_slice_segments_enabled && reader.read_bit()? {
+                return None;
+            }
+
+            let ctb_size = 1 << sps.log2_ctb_size;
+            let width = (sps.coded_width + ctb_size - 1) >> sps.log2_ctb_size;
+            let height = (sps.coded_height + ctb_size - 1) >> sps.log2_ctb_size;
+            slice_segment_address = reader.read_bits(ceil_log2(width * height))?;
+        }
+
+        reader.skip_bits(pps.num_extra_slice_header_bits as usize)?;
+        let slice_type = HevcSliceType::from_value(reader.read_ue()?)?;
+        let pic_output = !pps.output_flag_present || reader.read_bit()?;
+
+        if sps.separate_colour_plane {
+            // colour_plane_id
+            reader.skip_bits(2)?;
+        }
+
+        let mut pic_order_cnt_lsb = 0;
+        let mut temporal_mvp = false;
+        if !nal_type.is_idr() {
+            pic_order_cnt_lsb = reader.read_bits(sps.log2_max_pic_order_cnt_lsb)?;
+
+            // short_term_ref_pic_set_sps_flag
+            let sets = sps.num_short_term_ref_pic_sets;
+            if !reader.read_bit()? {
+                read_short_term_ref_pic_set(
+                    &mut reader,
+                    sets as usize,
+                    sets as usize,
+                    &sps.num_delta_pocs,
+                )?;
+            } else {
+                // short_term_ref_pic_set_idx
+                reader.read_bits(ceil_log2(sets))?;
+            }
+
+            if sps.long_term_ref_pics_present {
+                skip_long_term_ref_pics(&mut reader, sps)?;
+            }
+
+            temporal_mvp = sps.temporal_mvp_enabled && reader.read_bit()?;
+        }
+
+        if sps.sample_adaptive_offset_enabled {
+            // slice_sao_luma_flag and slice_sao_chroma_flag
+            let chroma = !sps.separate_colour_plane && sps.chroma_format_idc != 0;
+            reader.skip_bits(1 + chroma as usize)?;
+        }
+
+        let qp = read_slice_qp(&mut reader, slice_type, pps, temporal_mvp);
+
+        Some(Self {
+            nal_type,
+            temporal_id,
+            first_slice_segment_in_pic,
+            pps_id,
+            slice_segment_address,
+            slice_type,
+            pic_output,
+            pic_order_cnt_lsb,
+            qp,
+        })
+    }
+}
+
+/// The number of bits needed to code values up to `value - 1`
+fn ceil_log2(value: u32) -> u32 {
+    if value <= 1 {
+        0
+    } else {
+        32 - (value - 1).leading_zeros()
+    }
+}
+
+fn skip_long_term_ref_pics(reader: &mut BitReader, sps: &HevcSps) -> Option<()> {
+    let num_long_term_sps = if sps.num_long_term_ref_pics_sps > 0 {
+        reader.read_ue()?
+    } else {
+        0
+    };
+    let num_long_term_pics = reader.read_ue()?;
+
+    for index in 0..num_long_term_sps.checked_add(num_long_term_pics)? {
+        if index < num_long_term_sps {
+            // lt_idx_sps
+            reader.read_bits(ceil_log2(sps.num_long_term_ref_pics_sps))?;
+        } else {
+            // poc_lsb_lt and used_by_curr_pic_lt_flag
+            reader.skip_bits(sps.log2_max_pic_order_cnt_lsb as usize + 1)?;
+        }
+
+        // delta_poc_msb_present_flag
+        if reader.read_bit()? {
+            // delta_poc_msb_cycle_lt
+            reader.read_ue()?;
+        }
+    }
+
+    Some(())
+}
+
+/// Reads the end of a slice segment header, from `num_ref_idx_active_override_flag` to `slice_qp_delta`
+fn read_slice_qp(
+    reader: &mut BitReader,
+    slice_type: HevcSliceType,
+    pps: &HevcPps,
+    temporal_mvp: bool,
+) -> Option<i32> {
+    if slice_type != HevcSliceType::I {
+        let bidirectional = slice_type == HevcSliceType::B;
+        let mut num_ref_idx_l0_active = pps.num_ref_idx_l0_default_active;
+        let mut num_ref_idx_l1_active = pps.num_ref_idx_l1_default_active;
+
+        // num_ref_idx_active_override_flag
+        if reader.read_bit()? {
+            num_ref_idx_l0_active = reader.read_ue()? + 1;
+            if bidirectional {
+                num_ref_idx_l1_active = reader.read_ue()? + 1;
+            }
+        }
+
+        // The size of ref_pic_lists_modification depends on the reference picture sets, which aren't tracked
+        if pps.lists_modification_present {
+            return None;
+        }
+
+        if bidirectional {
+            // mvd_l1_zero_flag
+            reader.skip_bits(1)?;
+        }
+
+        if pps.cabac_init_present {
+            // cabac_init_flag
+            reader.skip_bits(1)?;
+        }
+
+        if temporal_mvp {
+            let collocated_from_l0 = !bidirectional || reader.read_bit()?;
+            let active = if collocated_from_l0 {
+                num_ref_idx_l0_active
+            } else {
+                num_ref_idx_l1_active
+            };
+
+            if active > 1 {
+                // collocated_ref_idx
+                reader.read_ue()?;
+            }
+        }
+
+        if (pps.weighted_pred && !bidirectional) || (pps.weighted_bipred && bidirectional) {
+            return None;
+        }
+
+        // five_minus_max_num_merge_cand
+        reader.read_ue()?;
+    }
+
+    Some(pps.init_qp + reader.read_se()?)
+}
+
+/// A group of NAL units of the same type in an hvcC record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcNalArray {
+    /// Whether all the NAL units of this type are in the array (none are in the samples)
+    pub complete: bool,
+    pub nal_type: HevcNalType,
+    pub units: Vec<Vec<u8>>,
+}
+
+/// The hvcC record, used as the codec private data of HEVC in MP4 and Matroska
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcDecoderConfigurationRecord {
+    pub profile_tier_level: HevcProfileTierLevel,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    /// The average frame rate in frames per 256 seconds, 0 when unknown
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    /// The size of the NAL unit lengths in the samples, 1, 2 or 4 bytes
+    pub length_size: u8,
+    pub arrays: Vec<HevcNalArray>,
+}
+
+impl HevcDecoderConfigurationRecord {
+    /// Builds a record from the parameter sets (NAL units, without start codes).
+    ///
+    /// Returns `None` if there's no valid SPS
+    pub fn from_parameter_sets<V: AsRef<[u8]>, S: AsRef<[u8]>, P: AsRef<[u8]>>(
+        vps: &[V],
+        sps: &[S],
+        pps: &[P],
+    ) -> Option<Self> {
+        let first = HevcSps::parse(sps.first()?.as_ref())?;
+
+        let avg_frame_rate = first
+            .frame_rate()
+            .map(|rate| (rate * 256.0).round().min(u16::MAX as f64) as u16)
+            .unwrap_or(0);
+
+        let array = |nal_type: HevcNalType, units: Vec<Vec<u8>>| HevcNalArray {
+            complete: true,
+            nal_type,
+            units,
+        };
+
+        Some(Self {
+            profile_tier_level: first.profile_tier_level,
+            min_spatial_segmentation_idc: 0,
+            parallelism_type: 0,
+            chroma_format: first.chroma_format_idc as u8,
+            bit_depth_luma: first.bit_depth_luma as u8,
+            bit_depth_chroma: first.bit_depth_chroma as u8,
+            avg_frame_rate,
+            constant_frame_rate: 0,
+            num_temporal_layers: first.max_sub_layers,
+            temporal_id_nested: first.temporal_id_nesting,
+            length_size: 4,
+            arrays: vec![
+                array(HevcNalType::Vps, to_vecs(vps)),
+                array(HevcNalType::Sps, to_vecs(sps)),
+                array(HevcNalType::Pps, to_vecs(pps)),
+            ],
+        })
+    }
+
+    /// Builds a record from the parameter sets found in an Annex-B byte stream, like the `csd-0` buffer of a `MediaFormat`
+    pub fn from_annexb(data: &[u8]) -> Option<Self> {
+        let mut vps = vec![];
+        let mut sps = vec![];
+        let mut pps = vec![];
+
+        for nal in annexb_nal_units(data) {
+            let list = match HevcNalType::from_header(nal[0]) {
+                HevcNalType::Vps => &mut vps,
+                HevcNalType::Sps => &mut sps,
+                HevcNalType::Pps => &mut pps,
+                _ => continue,
+            };
+
+            if !list.contains(&nal) {
+                list.push(nal);
+            }
+        }
+
+        Self::from_parameter_sets(&vps, &sps, &pps)
+    }
+
+    /// Parses an hvcC record
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data);
+
+        if reader.read_u8()? != 1 {
+            return None;
+        }
+
+        let profile_tier_level = HevcProfileTierLevel {
+            profile_space: reader.read_bits(2)? as u8,
+            tier: reader.read_bit()?,
+            profile_idc: reader.read_bits(5)? as u8,
+            profile_compatibility_flags: reader.read_bits(32)?,
+            constraint_indicator_flags: reader.read_bits_u64(48)?,
+            level_idc: reader.read_u8()?,
+        };
+
+        reader.skip_bits(4)?;
+        let min_spatial_segmentation_idc = reader.read_bits(12)? as u16;
+        reader.skip_bits(6)?;
+        let parallelism_type = reader.read_bits(2)? as u8;
+        reader.skip_bits(6)?;
+        let chroma_format = reader.read_bits(2)? as u8;
+        reader.skip_bits(5)?;
+        let bit_depth_luma = reader.read_bits(3)? as u8 + 8;
+        reader.skip_bits(5)?;
+        let bit_depth_chroma = reader.read_bits(3)? as u8 + 8;
+        let avg_frame_rate = reader.read_u16()?;
+        let constant_frame_rate = reader.read_bits(2)? as u8;
+        let num_temporal_layers = reader.read_bits(3)? as u8;
+        let temporal_id_nested = reader.read_bit()?;
+        let length_size = reader.read_bits(2)? as u8 + 1;
+
+        let array_count = reader.read_u8()?;
+        let mut arrays = Vec::with_capacity(array_count as usize);
+        for _ in 0..array_count {
+            let complete = reader.read_bit()?;
+            reader.skip_bits(1)?;
+            let nal_type = HevcNalType::from_header((reader.read_bits(6)? as u8) << 1);
+
+            let count = reader.read_u16()?;
+            let mut units = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let length = reader.read_u16()? as usize;
+                let mut nal = Vec::with_capacity(length);
+                for _ in 0..length {
+                    nal.push(reader.read_u8()?);
+                }
+                units.push(nal);
+            }
+
+            arrays.push(HevcNalArray {
+                complete,
+                nal_type,
+                units,
+            });
+        }
+
+        Some(Self {
+            profile_tier_level,
+            min_spatial_segmentation_idc,
+            parallelism_type,
+            chroma_format,
+            bit_depth_luma,
+            bit_depth_chroma,
+            avg_frame_rate,
+            constant_frame_rate,
+            num_temporal_layers,
+            temporal_id_nested,
+            length_size,
+            arrays,
+        })
+    }
+
+    /// Serializes the record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let ptl = &self.profile_tier_level;
+        let mut output = vec![
+            1,
+            (ptl.profile_space << 6) | ((ptl.tier as u8) << 5) | (ptl.profile_idc & 0x1f),
+        ];
+
+        output.extend_from_slice(&ptl.profile_compatibility_flags.to_be_bytes());
+        output.extend_from_slice(&ptl.constraint_indicator_flags.to_be_bytes()[2..]);
+        output.push(ptl.level_idc);
+        output.extend_from_slice(&(0xf000 | self.min_spatial_segmentation_idc).to_be_bytes());
+        output.push(0xfc | self.parallelism_type);
+        output.push(0xfc | self.chroma_format);
+        output.push(0xf8 | (self.bit_depth_luma - 8));
+        output.push(0xf8 | (self.bit_depth_chroma - 8));
+        output.extend_from_slice(&self.avg_frame_rate.to_be_bytes());
+        output.push(
+            (self.constant_frame_rate << 6)
+                | ((self.num_temporal_layers & 0x07) << 3)
+                | ((self.temporal_id_nested as u8) << 2)
+                | (self.length_size.clamp(1, 4) - 1),
+        );
+
+        let arrays: Vec<_> = self
+            .arrays
+            .iter()
+            .filter(|array| !array.units.is_empty())
+            .collect();
+
+        output.push(arrays.len() as u8);
+        for array in arrays {
+            output.push(((array.complete as u8) << 7) | (array.nal_type.value() & 0x3f));
+            output.extend_from_slice(&(array.units.len() as u16).to_be_bytes());
+            for nal in &array.units {
+                output.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+                output.extend_from_slice(nal);
+            }
+        }
+
+        output
+    }
+
+    /// Returns the NAL units of a type
+    pub fn units(&self, nal_type: HevcNalType) -> impl Iterator<Item = &Vec<u8>> {
+        self.arrays
+            .iter()
+            .filter(move |array| array.nal_type == nal_type)
+            .flat_map(|array| array.units.iter())
+    }
+
+    /// The `csd-0` buffer for MediaCodec: the VPS, SPS and PPS NAL units, in Annex-B format
+    pub fn csd0(&self) -> Vec<u8> {
+        let units: Vec<_> = [HevcNalType::Vps, HevcNalType::Sps, HevcNalType::Pps]
+            .into_iter()
+            .flat_map(|nal_type| self.units(nal_type).cloned())
+            .collect();
+
+        to_annexb(&units)
+    }
+
+    /// Parses the first SPS of the record
+    pub fn parse_sps(&self) -> Option<HevcSps> {
+        HevcSps::parse(self.units(HevcNalType::Sps).next()?)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a `video/hevc` decoder.
+    ///
+    /// Sets the dimensions, codec specific data, frame rate, profile, level and colors, as far as the SPS tells us.
+    /// 10-bit streams get the Main10 profile (or Main10 HDR10 for PQ), so the decoder picks a 10-bit output format
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let sps = self.parse_sps()?;
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "video/hevc");
+        format.set_i32("width", sps.width() as i32);
+        format.set_i32("height", sps.height() as i32);
+        format.set_buffer("csd-0", &self.csd0());
+
+        if let Some(frame_rate) = sps.frame_rate() {
+            format.set_f32("frame-rate", frame_rate as f32);
+        }
+
+        if let Some(profile) = sps.media_codec_profile() {
+            format.set_i32("profile", profile);
+        }
+
+        if let Some(level) = sps.profile_tier_level.media_codec_level() {
+            format.set_i32("level", level);
+        }
+
+        if let Some(color) = sps.color() {
+            color.apply_to(&mut format);
+        }
+
+        Some(format)
+    }
+}
+
+fn to_vecs<T: AsRef<[u8]>>(units: &[T]) -> Vec<Vec<u8>> {
+    units.iter().map(|nal| nal.as_ref().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bitstream::BitWriter, escape_rbsp, ANNEXB_START_CODE};
+
+    const MAIN: u32 = 1 << 30;
+    const MAIN_10: u32 = 1 << 29;
+
+    /// A 1080p SPS (coded as 1088 lines and cropped) at 25 fps, with BT.2020 PQ colors
+    fn sps(profile_idc: u8, compatibility_flags: u32, bit_depth: u32) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        // sps_video_parameter_set_id, sps_max_sub_layers_minus1, sps_temporal_id_nesting_flag
+        writer.write_bits(0, 4);
+        writer.write_bits(0, 3);
+        writer.write_bit(true);
+
+        // Main tier, progressive frames, level 5.1
+        writer.write_bits(0, 3);
+        writer.write_bits(profile_idc as u64, 5);
+        writer.write_bits(compatibility_flags as u64, 32);
+        writer.write_bits(0x9000_0000_0000, 48);
+        writer.write_bits(153, 8);
+
+        // sps_seq_parameter_set_id, 4:2:0
+        writer.write_ue(0);
+        writer.write_ue(1);
+        writer.write_ue(1920);
+        writer.write_ue(1088);
+        // Crop 8 lines at the bottom
+        writer.write_bit(true);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(4);
+
+        writer.write_ue(bit_depth - 8);
+        writer.write_ue(bit_depth - 8);
+        // log2_max_pic_order_cnt_lsb_minus4
+        writer.write_ue(4);
+        // Sub layer ordering info
+        writer.write_bit(true);
+        writer.write_ue(4);
+        writer.write_ue(2);
+        writer.write_ue(0);
+
+        // 8x8 to 64x64 coding blocks, transform block sizes and depths
+        writer.write_ue(0);
+        writer.write_ue(3);
+        for _ in 0..4 {
+            writer.write_ue(0);
+        }
+
+        // scaling_list_enabled_flag, amp_enabled_flag, sample_adaptive_offset_enabled_flag, pcm_enabled_flag
+        writer.write_bits(0b0110, 4);
+        // No short or long term reference picture sets
+        writer.write_ue(0);
+        writer.write_bit(false);
+        // sps_temporal_mvp_enabled_flag, strong_intra_smoothing_enabled_flag
+        writer.write_bits(0b11, 2);
+
+        // VUI: only the colors and the timing
+        writer.write_bit(true);
+        writer.write_bits(0b001, 3);
+        writer.write_bits(5, 3);
+        writer.write_bits(0b01, 2);
+        writer.write_bits(9, 8);
+        writer.write_bits(16, 8);
+        writer.write_bits(9, 8);
+        writer.write_bits(0, 5);
+        writer.write_bit(true);
+        writer.write_bits(1, 32);
+        writer.write_bits(25, 32);
+        writer.write_bits(0, 4);
+
+        // sps_extension_present_flag, rbsp_stop_one_bit
+        writer.write_bits(0b01, 2);
+
+        let mut nal = vec![0x42, 0x01];
+        nal.extend(escape_rbsp(&writer.into_bytes()));
+        nal
+    }
+
+    const VPS: [u8; 21] = [
+        0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x02, 0x20, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 0x99, 0x95,
+        0x98, 0x09,
+    ];
+
+    const PPS: [u8; 7] = [0x44, 0x01, 0xc1, 0x72, 0xb4, 0x62, 0x40];
+
+    #[test]
+    fn parse_sps() {
+        let sps = HevcSps::parse(&sps(2, MAIN_10, 10)).unwrap();
+
+        assert_eq!((sps.coded_width, sps.coded_height), (1920, 1088));
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!((sps.bit_depth_luma, sps.bit_depth_chroma), (10, 10));
+        assert_eq!(sps.log2_ctb_size, 6);
+        assert_eq!(sps.max_num_reorder_pics, 2);
+        assert_eq!(sps.frame_rate(), Some(25.0));
+        assert!(sps.is_hdr());
+        assert_eq!(sps.media_codec_profile(), Some(0x1000));
+        assert_eq!(sps.profile_tier_level.media_codec_level(), Some(0x10000));
+    }
+
+    #[test]
+    fn main10_detection() {
+        // Main streams are compatible with Main 10, but are still Main
+        let main = HevcSps::parse(&sps(1, MAIN | MAIN_10, 8)).unwrap();
+        assert!(!main.profile_tier_level.is_main10());
+        assert_eq!(main.media_codec_profile(), Some(0x1));
+
+        let main10 = HevcSps::parse(&sps(2, MAIN_10, 10)).unwrap();
+        assert!(main10.profile_tier_level.is_main10());
+
+        // Without a profile, the compatibility flags tell
+        let flags_only = HevcSps::parse(&sps(0, MAIN_10, 10)).unwrap();
+        assert_eq!(flags_only.profile_tier_level.profile(), 2);
+        assert!(flags_only.profile_tier_level.is_main10());
+
+        let flags_only = HevcSps::parse(&sps(0, MAIN | MAIN_10, 8)).unwrap();
+        assert_eq!(flags_only.profile_tier_level.profile(), 1);
+        assert_eq!(flags_only.media_codec_profile(), Some(0x1));
+    }
+
+    #[test]
+    fn parse_vps_and_pps() {
+        let vps = HevcVps::parse(&VPS).unwrap();
+        assert_eq!(vps.max_sub_layers, 1);
+        assert_eq!(vps.profile_tier_level.profile_idc, 2);
+
+        let pps = HevcPps::parse(&PPS).unwrap();
+        assert_eq!(pps.init_qp, 26);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let sps = sps(2, MAIN_10, 10);
+        let mut annexb = vec![];
+        for nal in [&VPS[..], &sps, &PPS] {
+            annexb.extend_from_slice(&ANNEXB_START_CODE);
+            annexb.extend_from_slice(nal);
+        }
+
+        let record = HevcDecoderConfigurationRecord::from_annexb(&annexb).unwrap();
+        assert_eq!(record.bit_depth_luma, 10);
+        assert_eq!(record.avg_frame_rate, 25 * 256);
+        assert_eq!(record.length_size, 4);
+
+        let parsed = HevcDecoderConfigurationRecord::parse(&record.to_bytes()).unwrap();
+        assert_eq!(parsed, record);
+        assert_eq!(parsed.csd0(), annexb);
+        assert_eq!(parsed.parse_sps().unwrap().width(), 1920);
+    }
+}
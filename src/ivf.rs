@@ -0,0 +1,567 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use log::warn;
+
+use crate::{
+    av1_obus, vp8_is_keyframe, vp8_keyframe_size, Av1CodecConfigurationRecord, Av1ObuType,
+    Av1SequenceHeader, MediaStatus, TrackFormat, Vp9FrameHeader, VpCodecConfigurationRecord,
+    SAMPLE_FLAG_SYNC,
+};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecInputBuffer, CodecOutputBuffer, MediaFormat};
+
+pub const IVF_FOURCC_VP8: [u8; 4] = *b"VP80";
+pub const IVF_FOURCC_VP9: [u8; 4] = *b"VP90";
+pub const IVF_FOURCC_AV1: [u8; 4] = *b"AV01";
+
+const IVF_HEADER_SIZE: usize = 32;
+const IVF_FRAME_HEADER_SIZE: usize = 12;
+
+/// The largest frame we're willing to allocate a buffer for
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// The file header of an IVF file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IvfHeader {
+    pub fourcc: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    /// The time base of the frame timestamps is `timebase_num / timebase_den` seconds
+    pub timebase_den: u32,
+    pub timebase_num: u32,
+    /// The number of frames in the file. Often wrong, or 0 for files that were written as a stream
+    pub frame_count: u32,
+}
+
+impl IvfHeader {
+    /// Creates a header with the usual time base of `1 / frame_rate`
+    pub fn new(fourcc: [u8; 4], width: u16, height: u16, frame_rate: u32) -> Self {
+        Self {
+            fourcc,
+            width,
+            height,
+            timebase_den: frame_rate,
+            timebase_num: 1,
+            frame_count: 0,
+        }
+    }
+
+    /// Parses the 32 byte file header
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let data = data.get(..IVF_HEADER_SIZE)?;
+        if &data[0..4] != b"DKIF" {
+            return None;
+        }
+
+        let le_u16 = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        let le_u32 = |index: usize| u32::from_le_bytes(data[index..index + 4].try_into().unwrap());
+
+        // The header length, which is always 32
+        if le_u16(6) as usize != IVF_HEADER_SIZE {
+            return None;
+        }
+
+        Some(Self {
+            fourcc: data[8..12].try_into().unwrap(),
+            width: le_u16(12),
+            height: le_u16(14),
+            timebase_den: le_u32(16),
+            timebase_num: le_u32(20),
+            frame_count: le_u32(24),
+        })
+    }
+
+    /// Serializes the file header
+    pub fn to_bytes(&self) -> [u8; IVF_HEADER_SIZE] {
+        let mut output = [0; IVF_HEADER_SIZE];
+
+        output[0..4].copy_from_slice(b"DKIF");
+        output[6..8].copy_from_slice(&(IVF_HEADER_SIZE as u16).to_le_bytes());
+        output[8..12].copy_from_slice(&self.fourcc);
+        output[12..14].copy_from_slice(&self.width.to_le_bytes());
+        output[14..16].copy_from_slice(&self.height.to_le_bytes());
+        output[16..20].copy_from_slice(&self.timebase_den.to_le_bytes());
+        output[20..24].copy_from_slice(&self.timebase_num.to_le_bytes());
+        output[24..28].copy_from_slice(&self.frame_count.to_le_bytes());
+
+        output
+    }
+
+    /// The mime type of the codec, if it's one MediaCodec knows about
+    pub fn mime(&self) -> Option<&'static str> {
+        match self.fourcc {
+            IVF_FOURCC_VP8 => Some("video/x-vnd.on2.vp8"),
+            IVF_FOURCC_VP9 => Some("video/x-vnd.on2.vp9"),
+            IVF_FOURCC_AV1 => Some("video/av01"),
+            _ => None,
+        }
+    }
+
+    /// Converts a timestamp in time base units to microseconds
+    pub fn pts_to_us(&self, pts: u64) -> i64 {
+        if self.timebase_den == 0 {
+            return 0;
+        }
+
+        (pts as i128 * self.timebase_num as i128 * 1_000_000 / self.timebase_den as i128) as i64
+    }
+
+    /// Converts a timestamp in microseconds to time base units
+    pub fn us_to_pts(&self, time_us: i64) -> u64 {
+        if self.timebase_num == 0 {
+            return 0;
+        }
+
+        let pts = time_us.max(0) as i128 * self.timebase_den as i128
+            / (self.timebase_num as i128 * 1_000_000);
+        pts as u64
+    }
+
+    /// Returns whether a frame of this stream is a key frame
+    pub fn is_keyframe(&self, frame: &[u8]) -> bool {
+        match self.fourcc {
+            IVF_FOURCC_VP8 => vp8_is_keyframe(frame),
+            IVF_FOURCC_VP9 => Vp9FrameHeader::parse(frame).is_some_and(|header| header.keyframe),
+            IVF_FOURCC_AV1 => av1_is_keyframe(frame),
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether an AV1 temporal unit starts with a key frame
+fn av1_is_keyframe(data: &[u8]) -> bool {
+    let mut reduced_still_picture_header = false;
+
+    for obu in av1_obus(data) {
+        match obu.obu_type {
+            Av1ObuType::SequenceHeader => {
+                reduced_still_picture_header = Av1SequenceHeader::parse(obu.payload)
+                    .is_some_and(|header| header.reduced_still_picture_header);
+            }
+            Av1ObuType::FrameHeader | Av1ObuType::Frame => {
+                if reduced_still_picture_header {
+                    return true;
+                }
+
+                // show_existing_frame, then frame_type, which is 0 for key frames
+                return obu.payload.first().is_some_and(|byte| byte & 0xe0 == 0);
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// A frame of an IVF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IvfFrame {
+    /// The timestamp, in time base units
+    pub pts: u64,
+    pub data: Vec<u8>,
+}
+
+/// Reads the frames of an IVF file (VP8, VP9 or AV1).
+///
+/// The reader works like a [MediaExtractor](crate::MediaExtractor) with a single video track, so it can feed a decoder with [read_next](Self::read_next)
+#[derive(Debug)]
+pub struct IvfReader<R: Read> {
+    reader: R,
+    header: IvfHeader,
+    /// The frame that will be returned next
+    current: Option<IvfFrame>,
+    /// The codec specific data comes from the first frame
+    first_frame: Vec<u8>,
+}
+
+impl IvfReader<BufReader<File>> {
+    /// Opens an IVF file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open IVF file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> IvfReader<R> {
+    /// Creates a reader, reading the file header and the first frame
+    pub fn new(mut reader: R) -> Result<Self, MediaStatus> {
+        let mut header = [0; IVF_HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(|error| {
+            warn!("Could not read the IVF header: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        let header = IvfHeader::parse(&header).ok_or(MediaStatus::ErrorMalformed)?;
+
+        let mut me = Self {
+            reader,
+            header,
+            current: None,
+            first_frame: vec![],
+        };
+        me.current = me.read_frame_inner()?;
+        if let Some(frame) = &me.current {
+            me.first_frame = frame.data.clone();
+        }
+
+        Ok(me)
+    }
+
+    /// The file header
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Returns the description of the stream, from the file header and the first frame.
+    ///
+    /// Returns `None` if the first frame isn't a key frame we can get the stream parameters from
+    pub fn track_format(&self) -> Option<TrackFormat> {
+        let frame = &self.first_frame;
+
+        let (mime, codec_private, width, height) = match self.header.fourcc {
+            IVF_FOURCC_VP8 => {
+                let (width, height) = vp8_keyframe_size(frame)?;
+                ("video/x-vnd.on2.vp8", vec![], width, height)
+            }
+            IVF_FOURCC_VP9 => {
+                let header = Vp9FrameHeader::parse(frame)?;
+                let record = VpCodecConfigurationRecord::from_vp9_header(&header)?;
+                (
+                    "video/x-vnd.on2.vp9",
+                    record.to_bytes(),
+                    header.width,
+                    header.height,
+                )
+            }
+            IVF_FOURCC_AV1 => {
+                let record = Av1CodecConfigurationRecord::from_temporal_unit(frame)?;
+                let header = record.parse_sequence_header()?;
+                let (width, height) = (header.max_frame_width, header.max_frame_height);
+                ("video/av01", record.to_bytes(), width, height)
+            }
+            _ => return None,
+        };
+
+        let mut format = TrackFormat {
+            mime,
+            timescale: 90_000,
+            width,
+            height,
+            codec_private,
+            ..Default::default()
+        };
+
+        // The header has the actual size, where AV1 only has the largest one
+        if self.header.width > 0 && self.header.height > 0 {
+            format.width = self.header.width as u32;
+            format.height = self.header.height as u32;
+        }
+
+        if self.header.timebase_num == 1 && self.header.timebase_den > 0 {
+            format.frame_rate = Some(self.header.timebase_den as f32);
+        }
+
+        Some(format)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a decoder for this stream.
+    ///
+    /// Returns `None` if the first frame isn't a key frame we can get the stream parameters from
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        self.track_format()?.into_media_format()
+    }
+
+    /// Returns whether there are still frames to read
+    pub fn has_next(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the time of the next frame in microseconds, or -1 at the end of the file
+    pub fn sample_time(&self) -> i64 {
+        match &self.current {
+            Some(frame) => self.header.pts_to_us(frame.pts),
+            None => -1,
+        }
+    }
+
+    /// Returns the sample flags of the next frame, like [MediaExtractor::sample_flags](crate::MediaExtractor::sample_flags)
+    pub fn sample_flags(&self) -> u32 {
+        match &self.current {
+            Some(frame) if self.header.is_keyframe(&frame.data) => SAMPLE_FLAG_SYNC,
+            _ => 0,
+        }
+    }
+
+    /// Reads the next frame into `buffer`, without advancing the reader.
+    ///
+    /// Returns the size of the frame, or `None` if there's no frame or `buffer` is too small
+    pub fn read_sample(&self, buffer: &mut [u8]) -> Option<usize> {
+        let data = &self.current.as_ref()?.data;
+        buffer.get_mut(..data.len())?.copy_from_slice(data);
+
+        Some(data.len())
+    }
+
+    /// Read a frame into `buffer` and advance the reader.
+    /// Returns true if there's still more data to read
+    ///
+    /// Frames that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        if self.current.is_none() {
+            return false;
+        }
+
+        let time_us = self.sample_time();
+        let flags = self.sample_flags();
+
+        if let Some(frame) = self.current.as_ref() {
+            if buffer.write_data(&frame.data) {
+                buffer.set_time(time_us as u64);
+                buffer.set_flags(flags);
+            } else {
+                warn!(
+                    "Frame at {time_us}us doesn't fit in the input buffer ({} > {})",
+                    frame.data.len(),
+                    buffer.size()
+                );
+            }
+        }
+
+        self.advance();
+        self.has_next()
+    }
+
+    /// Returns the next frame and advances the reader
+    pub fn read_frame(&mut self) -> Option<IvfFrame> {
+        let frame = self.current.take();
+        if frame.is_some() {
+            self.advance();
+        }
+
+        frame
+    }
+
+    /// Advances to the next frame.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        // Errors in the middle of the file end the stream, like a truncated file
+        self.current = self.read_frame_inner().unwrap_or(None);
+        self.has_next()
+    }
+
+    fn read_frame_inner(&mut self) -> Result<Option<IvfFrame>, MediaStatus> {
+        let mut header = [0; IVF_FRAME_HEADER_SIZE];
+        if self.reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+
+        let size = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let pts = u64::from_le_bytes(header[4..12].try_into().unwrap());
+
+        if size > MAX_FRAME_SIZE {
+            warn!("IVF frame of {size} bytes is too large");
+            return Err(MediaStatus::ErrorMalformed);
+        }
+
+        let mut data = vec![0; size];
+        self.reader.read_exact(&mut data).map_err(|error| {
+            warn!("Truncated IVF frame: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Ok(Some(IvfFrame { pts, data }))
+    }
+}
+
+impl<R: Read> Iterator for IvfReader<R> {
+    type Item = IvfFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame()
+    }
+}
+
+/// Writes encoded VP8, VP9 or AV1 frames into an IVF file
+#[derive(Debug)]
+pub struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    header: IvfHeader,
+    /// AV1 encoders hand out the sequence header as codec config, it has to go in front of the next frame
+    pending_config: Vec<u8>,
+}
+
+impl IvfWriter<BufWriter<File>> {
+    /// Creates an IVF file
+    pub fn create(path: &str, header: IvfHeader) -> Result<Self, MediaStatus> {
+        let file = File::create(path).map_err(|error| {
+            warn!("Could not create IVF file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// Creates a writer, writing the file header. The frame count of `header` is updated by [finish](Self::finish)
+    pub fn new(mut writer: W, mut header: IvfHeader) -> Result<Self, MediaStatus> {
+        header.frame_count = 0;
+        writer.write_all(&header.to_bytes()).map_err(write_error)?;
+
+        Ok(Self {
+            writer,
+            header,
+            pending_config: vec![],
+        })
+    }
+
+    /// The file header, with the number of frames written so far
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Writes a frame, with a timestamp in time base units
+    pub fn write_frame(&mut self, data: &[u8], pts: u64) -> Result<(), MediaStatus> {
+        let size = self.pending_config.len() + data.len();
+        let size = u32::try_from(size).map_err(|_| MediaStatus::ErrorInvalidParameter)?;
+
+        self.writer
+            .write_all(&size.to_le_bytes())
+            .and_then(|_| self.writer.write_all(&pts.to_le_bytes()))
+            .and_then(|_| self.writer.write_all(&self.pending_config))
+            .and_then(|_| self.writer.write_all(data))
+            .map_err(write_error)?;
+
+        self.pending_config.clear();
+        self.header.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Writes an encoder output buffer, converting its presentation time to the time base.
+    ///
+    /// Codec config buffers are kept for the next frame in AV1 streams, and skipped for VP8 and VP9
+    #[cfg(target_os = "android")]
+    pub fn write_buffer(&mut self, buffer: &CodecOutputBuffer) -> Result<(), MediaStatus> {
+        let info = buffer.info();
+        let data = match buffer.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return Ok(()),
+        };
+
+        if BufferFlag::CodecConfig.is_contained_in(info.flags() as i32) {
+            if self.header.fourcc == IVF_FOURCC_AV1 {
+                self.pending_config = data.to_vec();
+            }
+
+            return Ok(());
+        }
+
+        let pts = self.header.us_to_pts(info.presentation_time_us());
+        self.write_frame(data, pts)
+    }
+
+    /// Updates the frame count in the file header and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, MediaStatus> {
+        let count = self.header.frame_count.to_le_bytes();
+
+        self.writer
+            .seek(SeekFrom::Start(24))
+            .and_then(|_| self.writer.write_all(&count))
+            .and_then(|_| self.writer.seek(SeekFrom::End(0)))
+            .and_then(|_| self.writer.flush())
+            .map_err(write_error)?;
+
+        Ok(self.writer)
+    }
+}
+
+fn write_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not write IVF data: {error}");
+    MediaStatus::ErrorIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{bitstream::BitWriter, PacketSource};
+
+    /// The uncompressed header of a 320x240 VP9 key frame
+    fn vp9_keyframe() -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        // frame_marker, profile 0, show_existing_frame, frame_type, show_frame, error_resilient_mode
+        writer.write_bits(0b1000_0010, 8);
+        writer.write_bits(0x498342, 24);
+        // BT.709, limited range
+        writer.write_bits(0b0100, 4);
+        writer.write_bits(319, 16);
+        writer.write_bits(239, 16);
+        writer.into_bytes()
+    }
+
+    fn file(frames: &[(&[u8], u64)]) -> Vec<u8> {
+        let header = IvfHeader::new(IVF_FOURCC_VP9, 320, 240, 30);
+        let mut writer = IvfWriter::new(Cursor::new(vec![]), header).unwrap();
+        for (frame, pts) in frames {
+            writer.write_frame(frame, *pts).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = IvfHeader::new(IVF_FOURCC_AV1, 1920, 1080, 25);
+        assert_eq!(IvfHeader::parse(&header.to_bytes()), Some(header));
+        assert_eq!(header.mime(), Some("video/av01"));
+        assert_eq!(header.pts_to_us(3), 120_000);
+        assert_eq!(header.us_to_pts(120_000), 3);
+    }
+
+    #[test]
+    fn read_frames() {
+        let keyframe = vp9_keyframe();
+        let data = file(&[(&keyframe, 0), (&[0x86, 0], 1)]);
+        assert_eq!(IvfHeader::parse(&data).unwrap().frame_count, 2);
+
+        let mut reader = IvfReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.sample_time(), 0);
+        assert_eq!(reader.sample_flags(), SAMPLE_FLAG_SYNC);
+
+        let mut buffer = [0; 16];
+        assert_eq!(reader.read_sample(&mut buffer), Some(keyframe.len()));
+
+        assert!(reader.advance());
+        assert_eq!(reader.sample_time(), 33333);
+        assert_eq!(reader.sample_flags(), 0);
+        assert!(!reader.advance());
+        assert_eq!(PacketSource::track_index(&reader), -1);
+    }
+
+    #[test]
+    fn track_format() {
+        let reader = IvfReader::new(Cursor::new(file(&[(&vp9_keyframe(), 0)]))).unwrap();
+
+        let format = PacketSource::track_format(&reader, 0).unwrap();
+        assert_eq!(format.mime, "video/x-vnd.on2.vp9");
+        assert_eq!((format.width, format.height), (320, 240));
+        assert_eq!(format.frame_rate, Some(30.0));
+
+        let record = VpCodecConfigurationRecord::parse(&format.codec_private).unwrap();
+        assert_eq!((record.profile, record.bit_depth), (0, 8));
+        assert_eq!(record.color.matrix, 1);
+
+        assert_eq!(PacketSource::track_format(&reader, 1), None);
+    }
+}
@@ -114,20 +114,83 @@
 //! ```
 // #![cfg(os = "android")]
 
+mod aac;
+mod analyzer;
+mod audio;
+mod audio_encoder;
+mod av1;
+mod bitstream;
+mod captions;
 mod codec;
+mod convert;
 mod crypto;
+mod elementary;
 mod error;
 mod extractor;
+mod fmp4;
 mod format;
+mod frame;
+mod h264;
+mod hevc;
+mod ivf;
+mod manifest;
+mod mkv;
+mod mp4;
+#[cfg(target_os = "android")]
 mod muxer;
+mod nal;
+#[cfg(target_os = "android")]
 mod native_window;
+#[cfg(target_os = "android")]
+mod remux;
+mod resample;
+mod rtp;
 mod samples;
+#[cfg(target_os = "android")]
+mod sink;
+mod source;
+mod ts;
+mod vpx;
+mod wav;
+mod xiph;
+mod y4m;
 
+pub use aac::*;
+pub use analyzer::*;
+pub use audio::*;
+pub use audio_encoder::*;
+pub use av1::*;
+pub use captions::*;
 pub use codec::*;
+pub use convert::*;
 pub use crypto::*;
+pub use elementary::*;
 pub use error::*;
 pub use extractor::*;
+pub use fmp4::*;
 pub use format::*;
+pub use frame::*;
+pub use h264::*;
+pub use hevc::*;
+pub use ivf::*;
+pub use manifest::*;
+pub use mkv::*;
+pub use mp4::*;
+#[cfg(target_os = "android")]
 pub use muxer::*;
+pub use nal::*;
+#[cfg(target_os = "android")]
 pub use native_window::*;
+#[cfg(target_os = "android")]
+pub use remux::*;
+pub use resample::*;
+pub use rtp::*;
 pub use samples::*;
+#[cfg(target_os = "android")]
+pub use sink::*;
+pub use source::*;
+pub use ts::*;
+pub use vpx::*;
+pub use wav::*;
+pub use xiph::*;
+pub use y4m::*;
@@ -0,0 +1,585 @@
+use std::fmt::Write;
+
+use crate::{Fmp4Segment, TrackFormat};
+
+/// A media segment listed in a playlist or a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestSegment {
+    pub uri: String,
+    pub start_us: i64,
+    pub duration_us: i64,
+}
+
+impl ManifestSegment {
+    pub fn new(uri: &str, start_us: i64, duration_us: i64) -> Self {
+        Self {
+            uri: uri.to_string(),
+            start_us,
+            duration_us,
+        }
+    }
+
+    /// The entry of a segment written by the [Fmp4Muxer](crate::Fmp4Muxer), stored at `uri`
+    pub fn from_segment(uri: &str, segment: &Fmp4Segment) -> Self {
+        Self::new(uri, segment.start_us, segment.duration_us)
+    }
+}
+
+/// An HLS media playlist of fragmented MP4 segments.
+///
+/// Live playlists can keep a sliding window of the last segments, and get an `#EXT-X-ENDLIST` once [ended](Self::ended) is set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsMediaPlaylist {
+    /// The URI of the init segment, for `#EXT-X-MAP`
+    pub init_uri: String,
+    /// The sequence number of the first segment in the playlist
+    pub media_sequence: u32,
+    pub segments: Vec<ManifestSegment>,
+    /// The most segments kept in the playlist, `None` to keep them all
+    pub window: Option<usize>,
+    pub ended: bool,
+}
+
+impl HlsMediaPlaylist {
+    pub fn new(init_uri: &str) -> Self {
+        Self {
+            init_uri: init_uri.to_string(),
+            media_sequence: 0,
+            segments: vec![],
+            window: None,
+            ended: false,
+        }
+    }
+
+    /// Adds a segment, removing the oldest one if the window is full
+    pub fn push(&mut self, segment: ManifestSegment) {
+        self.segments.push(segment);
+
+        if let Some(window) = self.window {
+            while self.segments.len() > window.max(1) {
+                self.segments.remove(0);
+                self.media_sequence += 1;
+            }
+        }
+    }
+
+    /// The `#EXT-X-TARGETDURATION`: the longest segment, rounded to the nearest second as HLS requires
+    pub fn target_duration(&self) -> u32 {
+        let longest = self
+            .segments
+            .iter()
+            .map(|segment| segment.duration_us)
+            .max()
+            .unwrap_or(0);
+
+        ((longest + 500_000) / 1_000_000).max(1) as u32
+    }
+
+    /// Writes the playlist
+    pub fn to_m3u8(&self) -> String {
+        let mut output = String::new();
+
+        // Version 7 for fragmented MP4 segments with EXT-X-MAP
+        output.push_str("#EXTM3U\n#EXT-X-VERSION:7\n");
+        let _ = writeln!(output, "#EXT-X-TARGETDURATION:{}", self.target_duration());
+        let _ = writeln!(output, "#EXT-X-MEDIA-SEQUENCE:{}", self.media_sequence);
+        if self.ended && self.window.is_none() {
+            output.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+        }
+        output.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+        let _ = writeln!(output, "#EXT-X-MAP:URI=\"{}\"", self.init_uri);
+
+        for segment in &self.segments {
+            let duration = segment.duration_us as f64 / 1_000_000.0;
+            let _ = writeln!(output, "#EXTINF:{duration:.6},\n{}", segment.uri);
+        }
+
+        if self.ended {
+            output.push_str("#EXT-X-ENDLIST\n");
+        }
+
+        output
+    }
+}
+
+/// A variant stream of an HLS master playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlsVariant {
+    /// The URI of the media playlist
+    pub uri: String,
+    /// The peak bitrate, in bits per second
+    pub bandwidth: u32,
+    /// The RFC 6381 codec strings of the tracks
+    pub codecs: Vec<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f32>,
+}
+
+impl HlsVariant {
+    /// Describes a variant with these tracks, taking the codecs, resolution and frame rate from them
+    pub fn new(uri: &str, bandwidth: u32, tracks: &[TrackFormat]) -> Self {
+        let video = tracks.iter().find(|track| track.is_video());
+
+        Self {
+            uri: uri.to_string(),
+            bandwidth,
+            codecs: tracks
+                .iter()
+                .filter_map(|track| track.codec_string())
+                .collect(),
+            resolution: video
+                .filter(|track| track.width > 0 && track.height > 0)
+                .map(|track| (track.width, track.height)),
+            frame_rate: video.and_then(|track| track.frame_rate),
+        }
+    }
+}
+
+/// Writes an HLS master playlist listing the variants
+pub fn hls_master_playlist(variants: &[HlsVariant]) -> String {
+    let mut output = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+    for variant in variants {
+        let _ = write!(output, "#EXT-X-STREAM-INF:BANDWIDTH={}", variant.bandwidth);
+
+        if !variant.codecs.is_empty() {
+            let _ = write!(output, ",CODECS=\"{}\"", variant.codecs.join(","));
+        }
+
+        if let Some((width, height)) = variant.resolution {
+            let _ = write!(output, ",RESOLUTION={width}x{height}");
+        }
+
+        if let Some(frame_rate) = variant.frame_rate {
+            let _ = write!(output, ",FRAME-RATE={frame_rate:.3}");
+        }
+
+        let _ = writeln!(output, "\n{}", variant.uri);
+    }
+
+    output
+}
+
+/// A representation of a DASH manifest: one track, with its init segment and media segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashRepresentation {
+    pub id: String,
+    pub track: TrackFormat,
+    /// The peak bitrate, in bits per second
+    pub bandwidth: u32,
+    pub init_uri: String,
+    /// The URI template of the media segments, with `$Number$` for the segment number
+    pub media_uri: String,
+    /// The number of the first segment
+    pub start_number: u32,
+    pub segments: Vec<ManifestSegment>,
+}
+
+impl DashRepresentation {
+    pub fn new(
+        id: &str,
+        track: TrackFormat,
+        bandwidth: u32,
+        init_uri: &str,
+        media_uri: &str,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            track,
+            bandwidth,
+            init_uri: init_uri.to_string(),
+            media_uri: media_uri.to_string(),
+            start_number: 1,
+            segments: vec![],
+        }
+    }
+
+    /// The total duration of the segments
+    pub fn duration_us(&self) -> i64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.duration_us)
+            .sum()
+    }
+}
+
+/// A DASH manifest (MPD) using segment templates with a segment timeline.
+///
+/// The manifest is static (on demand) unless an availability start time is set, which makes it dynamic (live)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DashManifest {
+    pub representations: Vec<DashRepresentation>,
+    pub min_buffer_time_us: i64,
+    /// The wall clock time of the start of the stream, like `2024-01-01T00:00:00Z`, for live manifests
+    pub availability_start_time: Option<String>,
+}
+
+impl DashManifest {
+    pub fn new() -> Self {
+        Self {
+            min_buffer_time_us: 2_000_000,
+            ..Default::default()
+        }
+    }
+
+    /// Writes the manifest.
+    ///
+    /// Video and audio representations go in separate adaptation sets
+    pub fn to_mpd(&self) -> String {
+        let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+        let _ = write!(
+            output,
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" minBufferTime=\"{}\"",
+            iso_duration(self.min_buffer_time_us)
+        );
+
+        match &self.availability_start_time {
+            Some(start) => {
+                let _ = write!(
+                    output,
+                    " type=\"dynamic\" availabilityStartTime=\"{}\"",
+                    xml_escape(start)
+                );
+            }
+            None => {
+                let duration = self
+                    .representations
+                    .iter()
+                    .map(|representation| representation.duration_us())
+                    .max()
+                    .unwrap_or(0);
+                let _ = write!(
+                    output,
+                    " type=\"static\" mediaPresentationDuration=\"{}\"",
+                    iso_duration(duration)
+                );
+            }
+        }
+        output.push_str(">\n  <Period id=\"0\" start=\"PT0S\">\n");
+
+        for (id, content_type) in ["video", "audio"].iter().enumerate() {
+            let representations: Vec<_> = self
+                .representations
+                .iter()
+                .filter(|representation| representation.track.mime.starts_with(content_type))
+                .collect();
+
+            if representations.is_empty() {
+                continue;
+            }
+
+            let _ = writeln!(
+                output,
+                "    <AdaptationSet id=\"{id}\" contentType=\"{content_type}\" mimeType=\"{content_type}/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">"
+            );
+
+            for representation in representations {
+                write_representation(&mut output, representation);
+            }
+
+            output.push_str("    </AdaptationSet>\n");
+        }
+
+        output.push_str("  </Period>\n</MPD>\n");
+        output
+    }
+}
+
+fn write_representation(output: &mut String, representation: &DashRepresentation) {
+    let track = &representation.track;
+
+    let _ = write!(
+        output,
+        "      <Representation id=\"{}\" bandwidth=\"{}\"",
+        xml_escape(&representation.id),
+        representation.bandwidth
+    );
+
+    if let Some(codecs) = track.codec_string() {
+        let _ = write!(output, " codecs=\"{codecs}\"");
+    }
+
+    if track.is_video() {
+        let _ = write!(
+            output,
+            " width=\"{}\" height=\"{}\"",
+            track.width, track.height
+        );
+        if let Some(frame_rate) = track.frame_rate {
+            let _ = write!(output, " frameRate=\"{}\"", frame_rate_fraction(frame_rate));
+        }
+    } else {
+        let _ = write!(output, " audioSamplingRate=\"{}\"", track.sample_rate);
+    }
+    output.push_str(">\n");
+
+    if track.is_audio() {
+        let _ = writeln!(
+            output,
+            "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>",
+            track.channel_count
+        );
+    }
+
+    let timescale = track.timescale.max(1);
+    let _ = writeln!(
+        output,
+        "        <SegmentTemplate timescale=\"{timescale}\" initialization=\"{}\" media=\"{}\" startNumber=\"{}\">",
+        xml_escape(&representation.init_uri),
+        xml_escape(&representation.media_uri),
+        representation.start_number
+    );
+    output.push_str("          <SegmentTimeline>\n");
+
+    let to_ticks = |time_us: i64| (time_us as i128 * timescale as i128 / 1_000_000) as i64;
+
+    // Segments of the same duration that follow each other are merged with a repeat count
+    let mut index = 0;
+    let segments = &representation.segments;
+    while index < segments.len() {
+        let start = to_ticks(segments[index].start_us);
+        let duration = to_ticks(segments[index].start_us + segments[index].duration_us) - start;
+
+        let mut repeat = 0;
+        let mut end = start + duration;
+        while let Some(next) = segments.get(index + repeat + 1) {
+            let next_start = to_ticks(next.start_us);
+            let next_duration = to_ticks(next.start_us + next.duration_us) - next_start;
+            if next_start != end || next_duration != duration {
+                break;
+            }
+
+            repeat += 1;
+            end += duration;
+        }
+
+        let _ = write!(output, "            <S t=\"{start}\" d=\"{duration}\"");
+        if repeat > 0 {
+            let _ = write!(output, " r=\"{repeat}\"");
+        }
+        output.push_str("/>\n");
+
+        index += repeat + 1;
+    }
+
+    output.push_str(
+        "          </SegmentTimeline>\n        </SegmentTemplate>\n      </Representation>\n",
+    );
+}
+
+/// Formats a duration as an ISO 8601 duration in seconds, like `PT2.500S`
+fn iso_duration(duration_us: i64) -> String {
+    format!("PT{:.3}S", duration_us as f64 / 1_000_000.0)
+}
+
+/// Formats a frame rate, using the NTSC fractions where they apply
+fn frame_rate_fraction(frame_rate: f32) -> String {
+    for base in [24, 30, 60] {
+        let ntsc = base as f32 * 1000.0 / 1001.0;
+        if (frame_rate - ntsc).abs() < 0.01 {
+            return format!("{}/1001", base * 1000);
+        }
+    }
+
+    if (frame_rate - frame_rate.round()).abs() < 0.01 {
+        format!("{}", frame_rate.round() as u32)
+    } else {
+        format!("{frame_rate:.3}")
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1080p baseline SPS and its PPS
+    const SPS: [u8; 10] = [0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    fn video_track() -> TrackFormat {
+        let mut track = TrackFormat {
+            mime: "video/avc",
+            timescale: 90_000,
+            width: 1920,
+            height: 1080,
+            frame_rate: Some(29.97),
+            ..Default::default()
+        };
+        let config = [&[0, 0, 0, 1][..], &SPS, &[0, 0, 0, 1], &PPS].concat();
+        assert!(track.set_codec_config(&config));
+        track
+    }
+
+    fn audio_track() -> TrackFormat {
+        let mut track = TrackFormat {
+            mime: "audio/mp4a-latm",
+            timescale: 48000,
+            sample_rate: 48000,
+            channel_count: 2,
+            ..Default::default()
+        };
+        assert!(track.set_codec_config(&[0x11, 0x90]));
+        track
+    }
+
+    fn segments() -> Vec<ManifestSegment> {
+        [2_000_000, 2_000_000, 2_000_000, 1_500_000]
+            .iter()
+            .scan(0, |start_us, &duration_us| {
+                let segment = ManifestSegment::new(
+                    &format!("segment{}.m4s", *start_us / 2_000_000 + 1),
+                    *start_us,
+                    duration_us,
+                );
+                *start_us += duration_us;
+                Some(segment)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hls_media_playlist() {
+        let mut playlist = HlsMediaPlaylist::new("init.mp4");
+        assert_eq!(playlist.target_duration(), 1);
+
+        for segment in segments() {
+            playlist.push(segment);
+        }
+        playlist.ended = true;
+
+        assert_eq!(
+            playlist.to_m3u8(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-TARGETDURATION:2\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:VOD\n\
+             #EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:2.000000,\nsegment1.m4s\n\
+             #EXTINF:2.000000,\nsegment2.m4s\n\
+             #EXTINF:2.000000,\nsegment3.m4s\n\
+             #EXTINF:1.500000,\nsegment4.m4s\n\
+             #EXT-X-ENDLIST\n"
+        );
+
+        // A live playlist slides over the last segments
+        let mut playlist = HlsMediaPlaylist::new("init.mp4");
+        playlist.window = Some(2);
+        for segment in segments() {
+            playlist.push(segment);
+        }
+
+        let m3u8 = playlist.to_m3u8();
+        assert_eq!(playlist.media_sequence, 2);
+        assert!(m3u8.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        assert!(!m3u8.contains("segment2.m4s"));
+        assert!(m3u8.ends_with("#EXTINF:1.500000,\nsegment4.m4s\n"));
+        assert!(!m3u8.contains("#EXT-X-PLAYLIST-TYPE"));
+
+        // The longest segment is rounded to the nearest second
+        playlist.push(ManifestSegment::new("segment5.m4s", 7_500_000, 2_600_000));
+        assert_eq!(playlist.target_duration(), 3);
+    }
+
+    #[test]
+    fn hls_master() {
+        let variant = HlsVariant::new("video.m3u8", 2_500_000, &[video_track(), audio_track()]);
+        assert_eq!(variant.codecs, ["avc1.42c028", "mp4a.40.2"]);
+
+        let audio_only = HlsVariant::new("audio.m3u8", 128_000, &[audio_track()]);
+        assert_eq!(audio_only.resolution, None);
+
+        assert_eq!(
+            hls_master_playlist(&[variant, audio_only]),
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=2500000,CODECS=\"avc1.42c028,mp4a.40.2\",RESOLUTION=1920x1080,FRAME-RATE=29.970\n\
+             video.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n\
+             audio.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn dash_manifest() {
+        let mut video = DashRepresentation::new(
+            "video",
+            video_track(),
+            2_500_000,
+            "video/init.mp4",
+            "video/$Number$.m4s",
+        );
+        video.segments = segments();
+
+        let mut audio = DashRepresentation::new(
+            "audio&1",
+            audio_track(),
+            128_000,
+            "audio/init.mp4",
+            "audio/$Number$.m4s",
+        );
+        audio.segments = segments();
+
+        let mut manifest = DashManifest::new();
+        manifest.representations = vec![audio, video];
+
+        let mpd = manifest.to_mpd();
+        assert!(mpd.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MPD "));
+        assert!(mpd.contains(
+            " minBufferTime=\"PT2.000S\" type=\"static\" mediaPresentationDuration=\"PT7.500S\">"
+        ));
+
+        // Video comes first, whatever the order of the representations
+        let video_set = mpd
+            .find("<AdaptationSet id=\"0\" contentType=\"video\" mimeType=\"video/mp4\"")
+            .unwrap();
+        let audio_set = mpd
+            .find("<AdaptationSet id=\"1\" contentType=\"audio\" mimeType=\"audio/mp4\"")
+            .unwrap();
+        assert!(video_set < audio_set);
+
+        assert!(mpd.contains(
+            "<Representation id=\"video\" bandwidth=\"2500000\" codecs=\"avc1.42c028\" width=\"1920\" height=\"1080\" frameRate=\"30000/1001\">"
+        ));
+        assert!(mpd.contains(
+            "<Representation id=\"audio&amp;1\" bandwidth=\"128000\" codecs=\"mp4a.40.2\" audioSamplingRate=\"48000\">"
+        ));
+        assert!(mpd.contains("value=\"2\"/>"));
+
+        // Segments of the same duration are merged
+        assert!(mpd.contains(
+            "<SegmentTemplate timescale=\"90000\" initialization=\"video/init.mp4\" media=\"video/$Number$.m4s\" startNumber=\"1\">\n\
+             \x20         <SegmentTimeline>\n\
+             \x20           <S t=\"0\" d=\"180000\" r=\"2\"/>\n\
+             \x20           <S t=\"540000\" d=\"135000\"/>\n"
+        ));
+        assert!(mpd.contains(
+            "<S t=\"0\" d=\"96000\" r=\"2\"/>\n            <S t=\"288000\" d=\"72000\"/>\n"
+        ));
+        assert!(mpd.ends_with("  </Period>\n</MPD>\n"));
+
+        // Live manifests have a start time instead of a duration
+        manifest.availability_start_time = Some("2024-01-01T00:00:00Z".to_string());
+        let mpd = manifest.to_mpd();
+        assert!(mpd.contains(" type=\"dynamic\" availabilityStartTime=\"2024-01-01T00:00:00Z\">"));
+        assert!(!mpd.contains("mediaPresentationDuration"));
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(frame_rate_fraction(23.976), "24000/1001");
+        assert_eq!(frame_rate_fraction(59.94), "60000/1001");
+        assert_eq!(frame_rate_fraction(25.0), "25");
+        assert_eq!(frame_rate_fraction(12.5), "12.500");
+        assert_eq!(iso_duration(2_500_000), "PT2.500S");
+    }
+}
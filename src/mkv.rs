@@ -0,0 +1,1444 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+};
+
+use log::{debug, warn};
+
+#[cfg(target_os = "android")]
+use crate::CodecInputBuffer;
+use crate::{
+    bitstream::ByteReader, length_prefixed_to_annexb, AudioSpecificConfig,
+    AvcDecoderConfigurationRecord, HevcDecoderConfigurationRecord, MediaStatus, SeekMode,
+    TrackFormat, VideoColorInfo, SAMPLE_FLAG_SYNC,
+};
+
+/// The largest header element (like `Tracks` or `Cues`) or block we're willing to read into memory
+const MAX_ELEMENT_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The default `TimecodeScale`, timestamps are in milliseconds
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+// Top level elements
+const EBML: u32 = 0x1a45dfa3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114d9b74;
+const INFO: u32 = 0x1549a966;
+const TRACKS: u32 = 0x1654ae6b;
+const CLUSTER: u32 = 0x1f43b675;
+const CUES: u32 = 0x1c53bb6b;
+const CHAPTERS: u32 = 0x1043a770;
+const TAGS: u32 = 0x1254c367;
+const ATTACHMENTS: u32 = 0x1941a469;
+
+// SeekHead
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+
+// Info
+const TIMECODE_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+
+// Tracks
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const DEFAULT_DURATION: u32 = 0x23e383;
+const LANGUAGE: u32 = 0x22b59c;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const COLOUR: u32 = 0x55b0;
+const MATRIX_COEFFICIENTS: u32 = 0x55b1;
+const RANGE: u32 = 0x55b9;
+const TRANSFER_CHARACTERISTICS: u32 = 0x55ba;
+const PRIMARIES: u32 = 0x55bb;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+const CONTENT_ENCODINGS: u32 = 0x6d80;
+const CONTENT_ENCODING: u32 = 0x6240;
+const CONTENT_COMPRESSION: u32 = 0x5034;
+const CONTENT_COMP_ALGO: u32 = 0x4254;
+const CONTENT_COMP_SETTINGS: u32 = 0x4255;
+const CONTENT_ENCRYPTION: u32 = 0x5035;
+
+// Clusters
+const TIMECODE: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const REFERENCE_BLOCK: u32 = 0xfb;
+
+// Cues
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+/// The `ContentCompAlgo` of header stripping, where a prefix common to all frames is left out of the blocks
+const HEADER_STRIPPING: u64 = 3;
+
+/// Reads an EBML variable size integer, with its length marker removed.
+///
+/// Returns the value and whether all its bits were set, which means "unknown" for element sizes
+fn read_vint(reader: &mut ByteReader) -> Option<(u64, bool)> {
+    let first = reader.read_u8()?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 {
+        return None;
+    }
+
+    let mut value = (first as u64) & (0xff >> length);
+    for _ in 1..length {
+        value = (value << 8) | reader.read_u8()? as u64;
+    }
+
+    Some((value, value == (1 << (7 * length)) - 1))
+}
+
+/// Reads an element ID, which keeps its length marker
+fn read_element_id(reader: &mut ByteReader) -> Option<u32> {
+    let first = reader.read_u8()?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 4 {
+        return None;
+    }
+
+    let mut id = first as u32;
+    for _ in 1..length {
+        id = (id << 8) | reader.read_u8()? as u32;
+    }
+
+    Some(id)
+}
+
+/// Iterator over the child elements of an element read into memory
+struct EbmlElements<'a> {
+    reader: ByteReader<'a>,
+}
+
+fn ebml_elements(data: &[u8]) -> EbmlElements<'_> {
+    EbmlElements {
+        reader: ByteReader::new(data),
+    }
+}
+
+impl<'a> Iterator for EbmlElements<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = read_element_id(&mut self.reader)?;
+        let (size, unknown) = read_vint(&mut self.reader)?;
+
+        let payload = if unknown {
+            self.reader.rest()
+        } else {
+            self.reader.read_bytes(size.try_into().ok()?)?
+        };
+
+        Some((id, payload))
+    }
+}
+
+fn find_element(data: &[u8], id: u32) -> Option<&[u8]> {
+    ebml_elements(data)
+        .find(|(element_id, _)| *element_id == id)
+        .map(|(_, payload)| payload)
+}
+
+fn read_uint(data: &[u8]) -> Option<u64> {
+    if data.len() > 8 {
+        return None;
+    }
+
+    Some(
+        data.iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u64),
+    )
+}
+
+fn read_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    // Strings can be padded with zeros
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// The header of an element read from the file: its ID, the position of its data and its size, `None` when unknown
+#[derive(Debug, Clone, Copy)]
+struct ElementHeader {
+    id: u32,
+    data_position: u64,
+    size: Option<u64>,
+}
+
+impl ElementHeader {
+    fn end(&self) -> Option<u64> {
+        Some(self.data_position + self.size?)
+    }
+}
+
+/// Whether an element can only be at the top level of the segment, which ends a cluster of unknown size
+fn is_top_level(id: u32) -> bool {
+    matches!(
+        id,
+        SEEK_HEAD | INFO | TRACKS | CLUSTER | CUES | CHAPTERS | TAGS | ATTACHMENTS | SEGMENT | EBML
+    )
+}
+
+/// A track of a Matroska file
+#[derive(Debug)]
+struct MkvTrack {
+    number: u64,
+    format: TrackFormat,
+    default_duration_ns: u64,
+    /// The bytes stripped from the start of every frame, with header stripping compression
+    stripped_header: Vec<u8>,
+    selected: bool,
+    /// Frames are skipped until the next keyframe, after a seek
+    needs_keyframe: bool,
+}
+
+/// A frame of a block, ready to be read
+#[derive(Debug)]
+struct MkvSample {
+    track: usize,
+    /// The position of the block in the file and the index of the frame in its lace, the order of the frames
+    position: (u64, usize),
+    time_us: i64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+/// An entry of the `Cues`
+#[derive(Debug, Clone, Copy)]
+struct CuePoint {
+    time_us: i64,
+    track_number: u64,
+    /// The absolute position of the cluster in the file
+    cluster_position: u64,
+}
+
+/// A pure Rust demuxer for Matroska and WebM files, like the ones `MediaMuxer` writes with [OutputFormat::Webm](crate::OutputFormat::Webm).
+///
+/// It works like a [MediaExtractor](crate::MediaExtractor): tracks have to be selected, then the frames of the
+/// selected tracks are returned in file order, with their presentation times in microseconds.
+/// Laced blocks are split into their frames, and AVC and HEVC frames are returned in Annex-B format.
+///
+/// Clusters are read one at a time. Seeking uses the `Cues`, or the start times of the clusters when there are none,
+/// then skips the video frames before the next keyframe
+#[derive(Debug)]
+pub struct MkvDemuxer<R: Read + Seek> {
+    reader: R,
+    doc_type: String,
+    tracks: Vec<MkvTrack>,
+    /// The duration of a timestamp unit, in nanoseconds
+    timecode_scale: u64,
+    duration_us: i64,
+    /// Where the data of the segment starts, positions in the `SeekHead` and the `Cues` are relative to it
+    segment_start: u64,
+    segment_end: u64,
+    cues: Vec<CuePoint>,
+    /// The start times and the positions of the clusters, from the first scan of the file
+    clusters: Vec<(i64, u64)>,
+    first_cluster: Option<u64>,
+    /// The position of the cluster the queued frames come from
+    current_cluster: Option<u64>,
+    /// The position of the next cluster to read, `None` at the end of the file
+    next_cluster: Option<u64>,
+    queue: VecDeque<MkvSample>,
+}
+
+impl MkvDemuxer<BufReader<File>> {
+    /// Opens a Matroska or WebM file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open Matroska file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> MkvDemuxer<R> {
+    /// Creates a demuxer, reading the headers of the segment: `Info`, `Tracks` and `Cues`.
+    ///
+    /// The clusters are only read when their frames are needed
+    pub fn new(mut reader: R) -> Result<Self, MediaStatus> {
+        let file_size = reader.seek(SeekFrom::End(0)).map_err(read_error)?;
+
+        let mut me = Self {
+            reader,
+            doc_type: String::new(),
+            tracks: vec![],
+            timecode_scale: DEFAULT_TIMECODE_SCALE,
+            segment_start: 0,
+            segment_end: file_size,
+            duration_us: 0,
+            cues: vec![],
+            clusters: vec![],
+            first_cluster: None,
+            current_cluster: None,
+            next_cluster: None,
+            queue: VecDeque::new(),
+        };
+
+        let header = me.read_header(0)?.filter(|header| header.id == EBML);
+        let Some(header) = header else {
+            warn!("Not a Matroska file");
+            return Err(MediaStatus::ErrorMalformed);
+        };
+
+        let ebml = me.read_payload(&header)?;
+        me.doc_type = find_element(&ebml, DOC_TYPE)
+            .map(read_string)
+            .unwrap_or_default();
+        if me.doc_type != "matroska" && me.doc_type != "webm" {
+            warn!("Unsupported EBML document type {:?}", me.doc_type);
+            return Err(MediaStatus::ErrorUnsupported);
+        }
+
+        // Skips what comes before the segment, like Void elements
+        let mut position = header.end().unwrap_or(file_size);
+        let segment = loop {
+            match me.read_header(position)? {
+                Some(header) if header.id == SEGMENT => break header,
+                Some(ElementHeader {
+                    size: Some(size),
+                    data_position,
+                    ..
+                }) => position = data_position + size,
+                _ => {
+                    warn!("No segment in the Matroska file");
+                    return Err(MediaStatus::ErrorMalformed);
+                }
+            }
+        };
+
+        me.segment_start = segment.data_position;
+        me.segment_end = segment.end().unwrap_or(file_size).min(file_size);
+        me.read_segment_headers()?;
+
+        if me.tracks.is_empty() {
+            warn!("No supported track in the Matroska file");
+            return Err(MediaStatus::ErrorMalformed);
+        }
+
+        for track in &mut me.tracks {
+            track.format.duration_us = me.duration_us;
+        }
+
+        me.next_cluster = me.first_cluster;
+        Ok(me)
+    }
+
+    /// The EBML document type, `matroska` or `webm`
+    pub fn doc_type(&self) -> &str {
+        &self.doc_type
+    }
+
+    /// The duration of the segment in microseconds, 0 when unknown
+    pub fn duration(&self) -> i64 {
+        self.duration_us
+    }
+
+    /// Returns the number of tracks in the file. Tracks with a codec we don't know about are left out
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns the description of a track. The track id is the Matroska track number
+    pub fn track(&self, index: usize) -> Option<&TrackFormat> {
+        self.tracks.get(index).map(|track| &track.format)
+    }
+
+    /// Returns the format of a track, like [MediaExtractor::track_format](crate::MediaExtractor::track_format).
+    ///
+    /// [into_media_format](TrackFormat::into_media_format) turns it into the `MediaFormat` to create and initialize MediaCodec
+    pub fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        self.track(index).cloned()
+    }
+
+    /// Select this track to be demuxed
+    pub fn select_track(&mut self, index: usize) {
+        let Some(track) = self.tracks.get_mut(index) else {
+            return;
+        };
+        let newly_selected = !track.selected;
+        track.selected = true;
+
+        // The frames of the new track in the current cluster haven't been queued: reads it again from the current frame
+        let front = self.queue.front().map(|sample| sample.position);
+        if let (true, Some(cluster), Some(front)) = (newly_selected, self.current_cluster, front) {
+            self.queue.clear();
+            if self.read_cluster(cluster).is_ok() {
+                self.queue.retain(|sample| sample.position >= front);
+            }
+        }
+
+        self.update_current();
+    }
+
+    /// Unselect this track to be demuxed
+    pub fn unselect_track(&mut self, index: usize) {
+        if let Some(track) = self.tracks.get_mut(index) {
+            track.selected = false;
+        }
+
+        self.queue.retain(|sample| sample.track != index);
+        self.update_current();
+    }
+
+    /// Returns the track index of the current frame, or -1 if there's none
+    pub fn track_index(&self) -> i32 {
+        self.queue.front().map_or(-1, |sample| sample.track as i32)
+    }
+
+    /// Returns the presentation time of the current frame in microseconds, or -1 if there's none
+    pub fn sample_time(&self) -> i64 {
+        self.queue.front().map_or(-1, |sample| sample.time_us)
+    }
+
+    /// Returns the sample flags of the current frame, like [MediaExtractor::sample_flags](crate::MediaExtractor::sample_flags)
+    pub fn sample_flags(&self) -> u32 {
+        match self.queue.front() {
+            Some(sample) if sample.keyframe => SAMPLE_FLAG_SYNC,
+            _ => 0,
+        }
+    }
+
+    /// Returns whether there are still frames to read in the selected tracks
+    pub fn has_next(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Reads the current frame into `buffer`, without advancing.
+    ///
+    /// Returns the size of the frame, or `None` if there's no frame or `buffer` is too small
+    pub fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let data = &self.queue.front()?.data;
+        buffer.get_mut(..data.len())?.copy_from_slice(data);
+
+        Some(data.len())
+    }
+
+    /// Advances to the next frame.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        self.queue.pop_front();
+
+        self.update_current();
+        self.has_next()
+    }
+
+    /// Read a frame into `buffer` and advance the demuxer.
+    /// Returns true if there's still more data to read
+    ///
+    /// Frames that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        let Some(sample) = self.queue.front() else {
+            return false;
+        };
+
+        if buffer.write_data(&sample.data) {
+            buffer.set_time(sample.time_us.max(0) as u64);
+            buffer.set_flags(if sample.keyframe { SAMPLE_FLAG_SYNC } else { 0 });
+        } else {
+            warn!(
+                "Frame at {}us doesn't fit in the input buffer ({} > {})",
+                sample.time_us,
+                sample.data.len(),
+                buffer.size()
+            );
+        }
+
+        self.advance()
+    }
+
+    /// Seeks all selected tracks to `time_us`. Where the tracks land depends on `mode`.
+    ///
+    /// Returns `ErrorUnsupported` if the file has neither `Cues` nor clusters with a known size
+    pub fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        // The cues of the first selected video track, or of the first selected track
+        let reference = self
+            .tracks
+            .iter()
+            .filter(|track| track.selected)
+            .min_by_key(|track| !track.format.is_video())
+            .map(|track| track.number);
+
+        let mut points: Vec<(i64, u64)> = self
+            .cues
+            .iter()
+            .filter(|cue| Some(cue.track_number) == reference)
+            .map(|cue| (cue.time_us, cue.cluster_position))
+            .collect();
+
+        if points.is_empty() {
+            points = self
+                .cues
+                .iter()
+                .map(|cue| (cue.time_us, cue.cluster_position))
+                .collect();
+        }
+
+        if points.is_empty() {
+            points = self.clusters.clone();
+        }
+
+        if points.is_empty() {
+            return Err(MediaStatus::ErrorUnsupported);
+        }
+
+        points.sort_unstable();
+        let next = points.partition_point(|(point_us, _)| *point_us < time_us);
+        let previous = match points.get(next) {
+            Some((point_us, _)) if *point_us == time_us => next,
+            _ => next.saturating_sub(1),
+        };
+
+        let index = match mode {
+            SeekMode::PreviousSync => previous,
+            SeekMode::NextSync => next.min(points.len() - 1),
+            SeekMode::ClosestSync => match points.get(next) {
+                Some((next_us, _))
+                    if next_us.abs_diff(time_us) < time_us.abs_diff(points[previous].0) =>
+                {
+                    next
+                }
+                _ => previous,
+            },
+        };
+
+        self.queue.clear();
+        self.next_cluster = Some(points[index].1);
+        for track in &mut self.tracks {
+            track.needs_keyframe = track.format.is_video();
+        }
+
+        self.update_current();
+        Ok(())
+    }
+
+    /// Reads clusters until there's a frame of a selected track
+    fn update_current(&mut self) {
+        if !self.tracks.iter().any(|track| track.selected) {
+            return;
+        }
+
+        while self.queue.is_empty() {
+            let Some(position) = self.next_cluster else {
+                return;
+            };
+
+            self.current_cluster = Some(position);
+            self.next_cluster = self.read_cluster(position).unwrap_or_default();
+        }
+    }
+
+    /// Reads the header of the element at `position`. Returns `None` at the end of the file
+    fn read_header(&mut self, position: u64) -> Result<Option<ElementHeader>, MediaStatus> {
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map_err(read_error)?;
+
+        // The longest ID is 4 bytes, the longest size 8
+        let mut header = [0; 12];
+        let mut length = 0;
+        while length < header.len() {
+            match self.reader.read(&mut header[length..]) {
+                Ok(0) => break,
+                Ok(read) => length += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(read_error(error)),
+            }
+        }
+
+        let mut reader = ByteReader::new(&header[..length]);
+        let Some(id) = read_element_id(&mut reader) else {
+            return Ok(None);
+        };
+        let Some((size, unknown)) = read_vint(&mut reader) else {
+            return Ok(None);
+        };
+
+        Ok(Some(ElementHeader {
+            id,
+            data_position: position + (length - reader.remaining()) as u64,
+            size: (!unknown).then_some(size),
+        }))
+    }
+
+    /// Reads the data of an element, which must have a known size
+    fn read_payload(&mut self, header: &ElementHeader) -> Result<Vec<u8>, MediaStatus> {
+        let size = match header.size {
+            Some(size) if size <= MAX_ELEMENT_SIZE => size,
+            _ => {
+                warn!("Matroska element {:#x} is too large", header.id);
+                return Err(MediaStatus::ErrorMalformed);
+            }
+        };
+
+        let mut payload = vec![0; size as usize];
+        self.reader
+            .seek(SeekFrom::Start(header.data_position))
+            .and_then(|_| self.reader.read_exact(&mut payload))
+            .map_err(read_error)?;
+
+        Ok(payload)
+    }
+
+    /// Scans the top level elements of the segment, reading the headers and finding the clusters
+    fn read_segment_headers(&mut self) -> Result<(), MediaStatus> {
+        let mut cues_position = None;
+        let mut scanned_to_end = true;
+
+        let mut position = self.segment_start;
+        while position < self.segment_end {
+            let Some(header) = self.read_header(position)? else {
+                break;
+            };
+
+            match header.id {
+                INFO => {
+                    let info = self.read_payload(&header)?;
+                    self.parse_info(&info);
+                }
+                TRACKS => {
+                    let tracks = self.read_payload(&header)?;
+                    self.parse_tracks(&tracks);
+                }
+                CUES => {
+                    let cues = self.read_payload(&header)?;
+                    self.parse_cues(&cues);
+                }
+                SEEK_HEAD => {
+                    let seek_head = self.read_payload(&header)?;
+                    cues_position = cues_position.or(self.parse_seek_head(&seek_head));
+                }
+                CLUSTER => {
+                    self.first_cluster.get_or_insert(position);
+
+                    // The cluster timecode comes first
+                    if let Some(child) = self.read_header(header.data_position)? {
+                        if child.id == TIMECODE && child.size.is_some_and(|size| size <= 8) {
+                            let timecode = read_uint(&self.read_payload(&child)?).unwrap_or(0);
+                            self.clusters.push((self.to_us(timecode as i64), position));
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            match header.end() {
+                Some(end) => position = end,
+                None => {
+                    scanned_to_end = false;
+                    break;
+                }
+            }
+        }
+
+        // Live files have clusters of unknown size, the cues can only be found through the seek head
+        if !scanned_to_end && self.cues.is_empty() {
+            if let Some(position) = cues_position {
+                match self.read_header(position)? {
+                    Some(header) if header.id == CUES => {
+                        let cues = self.read_payload(&header)?;
+                        self.parse_cues(&cues);
+                    }
+                    _ => debug!("No cues where the seek head says"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_us(&self, timecode: i64) -> i64 {
+        (timecode as i128 * self.timecode_scale as i128 / 1000) as i64
+    }
+
+    fn parse_info(&mut self, info: &[u8]) {
+        if let Some(scale) = find_element(info, TIMECODE_SCALE).and_then(read_uint) {
+            if scale > 0 {
+                self.timecode_scale = scale;
+            }
+        }
+
+        if let Some(duration) = find_element(info, DURATION).and_then(read_float) {
+            self.duration_us = (duration * self.timecode_scale as f64 / 1000.0) as i64;
+        }
+    }
+
+    fn parse_seek_head(&self, seek_head: &[u8]) -> Option<u64> {
+        ebml_elements(seek_head)
+            .filter(|(id, _)| *id == SEEK)
+            .find_map(|(_, seek)| {
+                let id = find_element(seek, SEEK_ID).and_then(read_uint)?;
+                let position = find_element(seek, SEEK_POSITION).and_then(read_uint)?;
+                (id == CUES as u64).then_some(self.segment_start + position)
+            })
+    }
+
+    fn parse_tracks(&mut self, tracks: &[u8]) {
+        for (_, entry) in ebml_elements(tracks).filter(|(id, _)| *id == TRACK_ENTRY) {
+            match parse_track_entry(entry) {
+                Some(track) => self.tracks.push(track),
+                None => debug!("Skipping an unsupported Matroska track"),
+            }
+        }
+    }
+
+    fn parse_cues(&mut self, cues: &[u8]) {
+        for (_, point) in ebml_elements(cues).filter(|(id, _)| *id == CUE_POINT) {
+            let Some(time) = find_element(point, CUE_TIME).and_then(read_uint) else {
+                continue;
+            };
+
+            for (_, positions) in ebml_elements(point).filter(|(id, _)| *id == CUE_TRACK_POSITIONS)
+            {
+                let track = find_element(positions, CUE_TRACK).and_then(read_uint);
+                let cluster = find_element(positions, CUE_CLUSTER_POSITION).and_then(read_uint);
+
+                if let (Some(track_number), Some(cluster)) = (track, cluster) {
+                    self.cues.push(CuePoint {
+                        time_us: self.to_us(time as i64),
+                        track_number,
+                        cluster_position: self.segment_start + cluster,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Reads the cluster at `position` (or the next one after it), queueing the frames of the selected tracks.
+    ///
+    /// Returns the position of the next cluster
+    fn read_cluster(&mut self, mut position: u64) -> Result<Option<u64>, MediaStatus> {
+        let cluster = loop {
+            if position >= self.segment_end {
+                return Ok(None);
+            }
+
+            match self.read_header(position)? {
+                Some(header) if header.id == CLUSTER => break header,
+                Some(header) => match header.end() {
+                    Some(end) => position = end,
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
+            }
+        };
+
+        let end = cluster
+            .end()
+            .unwrap_or(self.segment_end)
+            .min(self.segment_end);
+        let mut timecode = 0;
+
+        let mut position = cluster.data_position;
+        while position < end {
+            let Some(child) = self.read_header(position)? else {
+                return Ok(None);
+            };
+
+            // A cluster of unknown size ends at the next top level element
+            if cluster.size.is_none() && is_top_level(child.id) {
+                return Ok(Some(position));
+            }
+
+            let Some(child_end) = child.end() else {
+                warn!(
+                    "Matroska element {:#x} of unknown size in a cluster",
+                    child.id
+                );
+                return Ok(None);
+            };
+
+            match child.id {
+                TIMECODE => timecode = read_uint(&self.read_payload(&child)?).unwrap_or(0),
+                SIMPLE_BLOCK => {
+                    let block = self.read_payload(&child)?;
+                    self.queue_block(&block, position, timecode, None);
+                }
+                BLOCK_GROUP => {
+                    let group = self.read_payload(&child)?;
+                    if let Some(block) = find_element(&group, BLOCK) {
+                        let keyframe = find_element(&group, REFERENCE_BLOCK).is_none();
+                        self.queue_block(block, position, timecode, Some(keyframe));
+                    }
+                }
+                _ => {}
+            }
+
+            position = child_end;
+        }
+
+        Ok(Some(end))
+    }
+
+    /// Splits a block into its frames and queues them, if their track is selected.
+    ///
+    /// The keyframe flag of a `Block` comes from its group, the one of a `SimpleBlock` from its flags
+    fn queue_block(
+        &mut self,
+        block: &[u8],
+        position: u64,
+        cluster_timecode: u64,
+        keyframe: Option<bool>,
+    ) {
+        let mut reader = ByteReader::new(block);
+        let (Some((number, _)), Some(timecode), Some(flags)) =
+            (read_vint(&mut reader), reader.read_i16(), reader.read_u8())
+        else {
+            debug!("Invalid Matroska block");
+            return;
+        };
+
+        let Some(index) = self.tracks.iter().position(|track| track.number == number) else {
+            return;
+        };
+        if !self.tracks[index].selected {
+            return;
+        }
+
+        let keyframe = keyframe.unwrap_or(flags & 0x80 != 0);
+        let Some(frames) = unlace(&mut reader, (flags >> 1) & 0x3) else {
+            debug!("Invalid lacing in a Matroska block of track {number}");
+            return;
+        };
+
+        let time_us = self.to_us(cluster_timecode as i64 + timecode as i64);
+        let track = &mut self.tracks[index];
+
+        if track.needs_keyframe {
+            if !keyframe {
+                return;
+            }
+            track.needs_keyframe = false;
+        }
+
+        for (lace, frame) in frames.into_iter().enumerate() {
+            let mut data = Vec::with_capacity(track.stripped_header.len() + frame.len());
+            data.extend_from_slice(&track.stripped_header);
+            data.extend_from_slice(frame);
+
+            if let Some(length_size) = track.format.nal_length_size {
+                match length_prefixed_to_annexb(&data, length_size as usize) {
+                    Some(annexb) => data = annexb,
+                    None => warn!("Invalid NAL unit lengths in a frame of track {number}"),
+                }
+            }
+
+            // Laced frames follow each other, with the default duration of the track
+            let offset_us = (lace as u64).saturating_mul(track.default_duration_ns) / 1000;
+
+            self.queue.push_back(MkvSample {
+                track: index,
+                position: (position, lace),
+                time_us: time_us.saturating_add_unsigned(offset_us),
+                keyframe,
+                data,
+            });
+        }
+    }
+}
+
+/// Splits the frames of a block, after its header
+fn unlace<'a>(reader: &mut ByteReader<'a>, lacing: u8) -> Option<Vec<&'a [u8]>> {
+    if lacing == 0 {
+        return Some(vec![reader.rest()]);
+    }
+
+    let count = reader.read_u8()? as usize + 1;
+    let mut sizes = Vec::with_capacity(count);
+
+    match lacing {
+        // Xiph lacing
+        1 => {
+            for _ in 0..count - 1 {
+                let mut size = 0;
+                loop {
+                    let byte = reader.read_u8()?;
+                    size += byte as usize;
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        // EBML lacing: the first size, then signed differences with the previous size
+        3 => {
+            let (first, _) = read_vint(reader)?;
+            let mut size = first as i64;
+            sizes.push(size as usize);
+
+            for _ in 1..count - 1 {
+                let before = reader.remaining();
+                let (value, _) = read_vint(reader)?;
+                let length = before - reader.remaining();
+                let bias = (1i64 << (7 * length - 1)) - 1;
+
+                size += value as i64 - bias;
+                sizes.push(usize::try_from(size).ok()?);
+            }
+        }
+        // Fixed size lacing
+        _ => {
+            let size = reader.remaining() / count;
+            sizes.resize(count - 1, size);
+        }
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        frames.push(reader.read_bytes(size)?);
+    }
+    // The last frame takes whatever is left
+    frames.push(reader.rest());
+
+    Some(frames)
+}
+
+/// Parses a `TrackEntry`, returning `None` for the tracks we can't demux
+fn parse_track_entry(entry: &[u8]) -> Option<MkvTrack> {
+    let number = find_element(entry, TRACK_NUMBER).and_then(read_uint)?;
+    let track_type = find_element(entry, TRACK_TYPE).and_then(read_uint)?;
+    let codec_id = read_string(find_element(entry, CODEC_ID)?);
+    let codec_private = find_element(entry, CODEC_PRIVATE).unwrap_or_default();
+    let default_duration_ns = find_element(entry, DEFAULT_DURATION)
+        .and_then(read_uint)
+        .unwrap_or(0);
+
+    let mut format = TrackFormat {
+        mime: codec_mime(&codec_id)?,
+        track_id: number as u32,
+        // Matroska times are converted to microseconds
+        timescale: 1_000_000,
+        language: find_element(entry, LANGUAGE)
+            .map(read_string)
+            .filter(|language| language != "und"),
+        ..Default::default()
+    };
+
+    if (track_type == TRACK_TYPE_VIDEO) != format.is_video()
+        || (track_type == TRACK_TYPE_AUDIO) != format.is_audio()
+    {
+        return None;
+    }
+
+    if let Some(video) = find_element(entry, VIDEO) {
+        let get = |id| find_element(video, id).and_then(read_uint);
+        format.width = get(PIXEL_WIDTH).unwrap_or(0) as u32;
+        format.height = get(PIXEL_HEIGHT).unwrap_or(0) as u32;
+        format.color = find_element(video, COLOUR).map(parse_colour);
+
+        if default_duration_ns > 0 {
+            format.frame_rate = Some((1e9 / default_duration_ns as f64) as f32);
+        }
+    }
+
+    if let Some(audio) = find_element(entry, AUDIO) {
+        format.sample_rate = find_element(audio, SAMPLING_FREQUENCY)
+            .and_then(read_float)
+            .unwrap_or(8000.0) as u32;
+        format.channel_count = find_element(audio, CHANNELS)
+            .and_then(read_uint)
+            .unwrap_or(1) as u32;
+    }
+
+    match format.mime {
+        "video/avc" => {
+            let record = AvcDecoderConfigurationRecord::parse(codec_private)?;
+            format.nal_length_size = Some(record.length_size);
+            format.codec_private = codec_private.to_vec();
+        }
+        "video/hevc" => {
+            let record = HevcDecoderConfigurationRecord::parse(codec_private)?;
+            format.nal_length_size = Some(record.length_size);
+            format.codec_private = codec_private.to_vec();
+        }
+        // The codec private data of VP8 and VP9 isn't a vpcC record
+        "video/x-vnd.on2.vp8" | "video/x-vnd.on2.vp9" => {}
+        "audio/mp4a-latm" if codec_private.is_empty() => {
+            let object_type = match codec_id.as_str() {
+                "A_AAC/MPEG2/MAIN" | "A_AAC/MPEG4/MAIN" => 1,
+                "A_AAC/MPEG2/SSR" | "A_AAC/MPEG4/SSR" => 3,
+                "A_AAC/MPEG4/LTP" => 4,
+                _ => 2,
+            };
+
+            let config = AudioSpecificConfig::new(
+                object_type,
+                format.sample_rate,
+                format.channel_count.min(7) as u8,
+            );
+            format.codec_private = config.to_bytes();
+        }
+        _ => format.codec_private = codec_private.to_vec(),
+    }
+
+    let mut stripped_header = vec![];
+    for (_, encoding) in find_element(entry, CONTENT_ENCODINGS)
+        .map(ebml_elements)
+        .into_iter()
+        .flatten()
+        .filter(|(id, _)| *id == CONTENT_ENCODING)
+    {
+        if find_element(encoding, CONTENT_ENCRYPTION).is_some() {
+            format.encrypted = true;
+        }
+
+        if let Some(compression) = find_element(encoding, CONTENT_COMPRESSION) {
+            let algorithm = find_element(compression, CONTENT_COMP_ALGO).and_then(read_uint);
+            if algorithm != Some(HEADER_STRIPPING) {
+                warn!("Unsupported compression of Matroska track {number}");
+                return None;
+            }
+
+            stripped_header = find_element(compression, CONTENT_COMP_SETTINGS)
+                .unwrap_or_default()
+                .to_vec();
+        }
+    }
+
+    Some(MkvTrack {
+        number,
+        format,
+        default_duration_ns,
+        stripped_header,
+        selected: false,
+        needs_keyframe: false,
+    })
+}
+
+/// The mime type of a Matroska codec ID
+fn codec_mime(codec_id: &str) -> Option<&'static str> {
+    let mime = match codec_id {
+        "V_MPEG4/ISO/AVC" => "video/avc",
+        "V_MPEGH/ISO/HEVC" => "video/hevc",
+        "V_AV1" => "video/av01",
+        "V_VP8" => "video/x-vnd.on2.vp8",
+        "V_VP9" => "video/x-vnd.on2.vp9",
+        "A_OPUS" => "audio/opus",
+        "A_VORBIS" => "audio/vorbis",
+        "A_FLAC" => "audio/flac",
+        "A_MPEG/L3" => "audio/mpeg",
+        "A_AC3" => "audio/ac3",
+        "A_EAC3" => "audio/eac3",
+        id if id.starts_with("A_AAC") => "audio/mp4a-latm",
+        _ => return None,
+    };
+
+    Some(mime)
+}
+
+/// Reads the `Colour` element of a video track, which uses the code points of ISO/IEC 23091-2 like [VideoColorInfo]
+fn parse_colour(colour: &[u8]) -> VideoColorInfo {
+    let get = |id| {
+        find_element(colour, id)
+            .and_then(read_uint)
+            .map(|value| value as u8)
+    };
+    let default = VideoColorInfo::default();
+
+    VideoColorInfo {
+        // 1 is broadcast range, 2 full range
+        full_range: get(RANGE) == Some(2),
+        primaries: get(PRIMARIES).unwrap_or(default.primaries),
+        transfer: get(TRANSFER_CHARACTERISTICS).unwrap_or(default.transfer),
+        matrix: get(MATRIX_COEFFICIENTS).unwrap_or(default.matrix),
+    }
+}
+
+fn read_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not read the Matroska file: {error}");
+    MediaStatus::ErrorIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::PacketSource;
+
+    /// A 1080p baseline SPS and its PPS
+    const SPS: [u8; 10] = [0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    /// An element with an 8 byte size
+    fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let skip = id.iter().position(|&byte| byte != 0).unwrap();
+
+        let mut output = id[skip..].to_vec();
+        output.push(0x01);
+        output.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+        output.extend_from_slice(payload);
+        output
+    }
+
+    fn uint(id: u32, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    fn block(track: u8, timecode: i16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut output = vec![0x80 | track];
+        output.extend(timecode.to_be_bytes());
+        output.push(flags);
+        output.extend_from_slice(payload);
+        output
+    }
+
+    /// A length-prefixed slice of `size` bytes, after its 2 byte header
+    fn frame(keyframe: bool, size: usize) -> Vec<u8> {
+        let header = if keyframe { 0x65 } else { 0x41 };
+        let mut output = vec![0, 0, 0, (size + 2) as u8, header, 0x88];
+        output.extend((1..=size).map(|byte| byte as u8));
+        output
+    }
+
+    fn tracks() -> Vec<u8> {
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(&[SPS], &[PPS]).unwrap();
+        let colour = [
+            uint(RANGE, 2),
+            uint(PRIMARIES, 1),
+            uint(TRANSFER_CHARACTERISTICS, 1),
+            uint(MATRIX_COEFFICIENTS, 1),
+        ];
+        let video = [
+            uint(TRACK_NUMBER, 1),
+            uint(TRACK_TYPE, 1),
+            element(CODEC_ID, b"V_MPEG4/ISO/AVC"),
+            element(CODEC_PRIVATE, &record.to_bytes()),
+            uint(DEFAULT_DURATION, 33_333_333),
+            element(LANGUAGE, b"eng\0"),
+            element(
+                VIDEO,
+                &[
+                    uint(PIXEL_WIDTH, 1920),
+                    uint(PIXEL_HEIGHT, 1080),
+                    element(COLOUR, &colour.concat()),
+                ]
+                .concat(),
+            ),
+        ];
+
+        // Without CodecPrivate, the AudioSpecificConfig comes from the codec ID
+        let aac = [
+            uint(TRACK_NUMBER, 2),
+            uint(TRACK_TYPE, 2),
+            element(CODEC_ID, b"A_AAC/MPEG4/LC"),
+            uint(DEFAULT_DURATION, 21_333_333),
+            element(
+                AUDIO,
+                &[
+                    element(SAMPLING_FREQUENCY, &48000.0f32.to_be_bytes()),
+                    uint(CHANNELS, 2),
+                ]
+                .concat(),
+            ),
+        ];
+
+        // The MP3 frame headers are stripped with header compression
+        let compression = [
+            uint(CONTENT_COMP_ALGO, 3),
+            element(CONTENT_COMP_SETTINGS, &[0xff, 0xfb]),
+        ];
+        let mp3 = [
+            uint(TRACK_NUMBER, 3),
+            uint(TRACK_TYPE, 2),
+            element(CODEC_ID, b"A_MPEG/L3"),
+            uint(DEFAULT_DURATION, 24_000_000),
+            element(
+                AUDIO,
+                &[
+                    element(SAMPLING_FREQUENCY, &44100.0f64.to_be_bytes()),
+                    uint(CHANNELS, 1),
+                ]
+                .concat(),
+            ),
+            element(
+                CONTENT_ENCODINGS,
+                &element(
+                    CONTENT_ENCODING,
+                    &element(CONTENT_COMPRESSION, &compression.concat()),
+                ),
+            ),
+        ];
+
+        let subtitles = [
+            uint(TRACK_NUMBER, 4),
+            uint(TRACK_TYPE, 17),
+            element(CODEC_ID, b"S_TEXT/UTF8"),
+        ];
+
+        let entries = [
+            video.concat(),
+            aac.concat(),
+            mp3.concat(),
+            subtitles.concat(),
+        ];
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|entry| element(TRACK_ENTRY, entry))
+            .collect();
+        element(TRACKS, &entries.concat())
+    }
+
+    fn clusters() -> [Vec<u8>; 3] {
+        // Xiph lacing of 3, 256 and 5 bytes
+        let mut xiph = vec![2, 3, 255, 1];
+        xiph.extend_from_slice(&[0xa1; 3]);
+        xiph.extend_from_slice(&[0xa2; 256]);
+        xiph.extend_from_slice(&[0xa3; 5]);
+
+        // EBML lacing of 4, 6 and 3 bytes: the first size, then the difference
+        let mut ebml = vec![2, 0x84, 0xc1];
+        ebml.extend_from_slice(&[1; 4]);
+        ebml.extend_from_slice(&[2; 6]);
+        ebml.extend_from_slice(&[3; 3]);
+
+        // Fixed-size lacing of two 4 byte frames
+        let mut fixed = vec![1];
+        fixed.extend_from_slice(&[7; 8]);
+
+        let first = [
+            uint(TIMECODE, 0),
+            element(SIMPLE_BLOCK, &block(1, 0, 0x80, &frame(true, 10))),
+            element(SIMPLE_BLOCK, &block(2, 0, 0x82, &xiph)),
+            element(SIMPLE_BLOCK, &block(3, 5, 0x86, &ebml)),
+            element(SIMPLE_BLOCK, &block(4, 5, 0x80, b"hello")),
+            element(
+                BLOCK_GROUP,
+                &[
+                    element(BLOCK, &block(1, 33, 0, &frame(false, 20))),
+                    element(REFERENCE_BLOCK, &[0xdf]),
+                ]
+                .concat(),
+            ),
+            element(SIMPLE_BLOCK, &block(2, 64, 0x84, &fixed)),
+            // A block without a reference is a key frame
+            element(
+                BLOCK_GROUP,
+                &element(BLOCK, &block(1, 67, 0, &frame(false, 5))),
+            ),
+        ];
+        let second = [
+            uint(TIMECODE, 1000),
+            element(SIMPLE_BLOCK, &block(1, 0, 0x80, &frame(true, 7))),
+            element(SIMPLE_BLOCK, &block(1, 33, 0, &frame(false, 3))),
+            element(SIMPLE_BLOCK, &block(2, 10, 0x80, &[9, 9])),
+        ];
+        let third = [
+            uint(TIMECODE, 1500),
+            element(SIMPLE_BLOCK, &block(1, 0, 0, &frame(false, 1))),
+            element(SIMPLE_BLOCK, &block(1, 33, 0x80, &frame(true, 2))),
+        ];
+
+        [first.concat(), second.concat(), third.concat()].map(|cluster| element(CLUSTER, &cluster))
+    }
+
+    fn file(with_cues: bool) -> Vec<u8> {
+        let header = element(EBML, &element(DOC_TYPE, b"webm"));
+        let info = element(
+            INFO,
+            &[
+                uint(TIMECODE_SCALE, 1_000_000),
+                element(DURATION, &2000.0f64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let tracks = tracks();
+        let clusters = clusters();
+
+        let mut segment = [info, tracks].concat();
+        let mut cue_points = vec![];
+        for (cluster, time) in clusters.iter().zip([0, 1000, 1533]) {
+            let positions = [
+                uint(CUE_TRACK, 1),
+                uint(CUE_CLUSTER_POSITION, segment.len() as u64),
+            ];
+            cue_points.extend(element(
+                CUE_POINT,
+                &[
+                    uint(CUE_TIME, time),
+                    element(CUE_TRACK_POSITIONS, &positions.concat()),
+                ]
+                .concat(),
+            ));
+            segment.extend_from_slice(cluster);
+        }
+
+        if with_cues {
+            segment.extend(element(CUES, &cue_points));
+        }
+
+        [header, element(SEGMENT, &segment)].concat()
+    }
+
+    fn read_all(demuxer: &mut MkvDemuxer<Cursor<Vec<u8>>>) -> Vec<(i32, i64, u32, Vec<u8>)> {
+        let mut samples = vec![];
+        let mut buffer = vec![0; 4096];
+        while demuxer.has_next() {
+            let size = demuxer.read_sample(&mut buffer).unwrap();
+            samples.push((
+                demuxer.track_index(),
+                demuxer.sample_time(),
+                demuxer.sample_flags(),
+                buffer[..size].to_vec(),
+            ));
+            demuxer.advance();
+        }
+
+        samples
+    }
+
+    #[test]
+    fn track_formats() {
+        let demuxer = MkvDemuxer::new(Cursor::new(file(true))).unwrap();
+        assert_eq!(demuxer.doc_type(), "webm");
+        assert_eq!(demuxer.duration(), 2_000_000);
+        // The subtitles are skipped
+        assert_eq!(demuxer.track_count(), 3);
+
+        let video = PacketSource::track_format(&demuxer, 0).unwrap();
+        assert_eq!(video.mime, "video/avc");
+        assert_eq!(video.track_id, 1);
+        assert_eq!((video.width, video.height), (1920, 1080));
+        assert_eq!(video.nal_length_size, Some(4));
+        assert_eq!(video.language.as_deref(), Some("eng"));
+        assert_eq!(video.duration_us, 2_000_000);
+        let color = VideoColorInfo {
+            full_range: true,
+            primaries: 1,
+            transfer: 1,
+            matrix: 1,
+        };
+        assert_eq!(video.color, Some(color));
+
+        let aac = PacketSource::track_format(&demuxer, 1).unwrap();
+        assert_eq!(aac.mime, "audio/mp4a-latm");
+        assert_eq!((aac.sample_rate, aac.channel_count), (48000, 2));
+        assert_eq!(
+            AudioSpecificConfig::parse(&aac.codec_private)
+                .unwrap()
+                .sample_rate,
+            48000
+        );
+
+        let mp3 = PacketSource::track_format(&demuxer, 2).unwrap();
+        assert_eq!(
+            (mp3.mime, mp3.sample_rate, mp3.channel_count),
+            ("audio/mpeg", 44100, 1)
+        );
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let mut demuxer = MkvDemuxer::new(Cursor::new(file(true))).unwrap();
+        assert!(!demuxer.has_next());
+
+        for index in 0..3 {
+            demuxer.select_track(index);
+        }
+
+        let samples = read_all(&mut demuxer);
+        let summary: Vec<_> = samples
+            .iter()
+            .map(|(track, time, flags, data)| (*track, *time, *flags, data.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, 0, 1, 16),
+                (1, 0, 1, 3),
+                (1, 21333, 1, 256),
+                (1, 42666, 1, 5),
+                (2, 5000, 1, 6),
+                (2, 29000, 1, 8),
+                (2, 53000, 1, 5),
+                (0, 33000, 0, 26),
+                (1, 64000, 1, 4),
+                (1, 85333, 1, 4),
+                (0, 67000, 1, 11),
+                (0, 1_000_000, 1, 13),
+                (0, 1_033_000, 0, 9),
+                (1, 1_010_000, 1, 2),
+                (0, 1_500_000, 0, 7),
+                (0, 1_533_000, 1, 8),
+            ]
+        );
+
+        // Start codes replace the NAL unit lengths, and the stripped headers are put back
+        assert_eq!(samples[0].3[..6], [0, 0, 0, 1, 0x65, 0x88]);
+        assert_eq!(samples[4].3, [0xff, 0xfb, 1, 1, 1, 1]);
+        assert_eq!(samples[6].3, [0xff, 0xfb, 3, 3, 3]);
+
+        demuxer.seek_to(1_100_000, SeekMode::PreviousSync).unwrap();
+        assert_eq!(
+            (demuxer.track_index(), demuxer.sample_time()),
+            (0, 1_000_000)
+        );
+
+        // The last cue points at a cluster that starts with a non key frame
+        demuxer.seek_to(1_100_000, SeekMode::NextSync).unwrap();
+        assert_eq!(
+            (demuxer.track_index(), demuxer.sample_time()),
+            (0, 1_533_000)
+        );
+
+        demuxer.seek_to(900_000, SeekMode::ClosestSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 1_000_000);
+
+        // Times far away from the cues
+        demuxer.seek_to(i64::MIN, SeekMode::ClosestSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 0);
+        demuxer.seek_to(i64::MAX, SeekMode::ClosestSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 1_533_000);
+
+        demuxer.seek_to(0, SeekMode::ClosestSync).unwrap();
+        assert_eq!(read_all(&mut demuxer).len(), samples.len());
+
+        demuxer.unselect_track(0);
+        demuxer.unselect_track(2);
+        demuxer.seek_to(0, SeekMode::PreviousSync).unwrap();
+        assert!(read_all(&mut demuxer).iter().all(|sample| sample.0 == 1));
+    }
+
+    #[test]
+    fn seek_without_cues() {
+        let mut demuxer = MkvDemuxer::new(Cursor::new(file(false))).unwrap();
+        demuxer.select_track(0);
+
+        demuxer.seek_to(1_200_000, SeekMode::PreviousSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 1_000_000);
+
+        assert!(MkvDemuxer::new(Cursor::new(vec![0; 64])).is_err());
+    }
+}
@@ -0,0 +1,2009 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    bitstream::ByteReader, length_prefixed_to_annexb, AudioSpecificConfig,
+    Av1CodecConfigurationRecord, AvcDecoderConfigurationRecord, FlacStreamInfo,
+    HevcDecoderConfigurationRecord, MediaStatus, OpusHead, SeekMode, VideoColorInfo,
+    VpCodecConfigurationRecord, FLAC_MAGIC, SAMPLE_FLAG_ENCRYPTED, SAMPLE_FLAG_SYNC,
+};
+#[cfg(target_os = "android")]
+use crate::{CodecInputBuffer, MediaFormat, VorbisHeaders, OPUS_DEFAULT_SEEK_PRE_ROLL_NS};
+
+/// The largest `moov` or `moof` box we're willing to read into memory
+const MAX_HEADER_BOX_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The largest sample we're willing to allocate a buffer for
+const MAX_SAMPLE_SIZE: u32 = 64 * 1024 * 1024;
+
+// tfhd flags
+const TFHD_BASE_DATA_OFFSET: u32 = 0x1;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x2;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x8;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x10;
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x20000;
+
+// trun flags
+const TRUN_DATA_OFFSET: u32 = 0x1;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x4;
+const TRUN_SAMPLE_DURATION: u32 = 0x100;
+const TRUN_SAMPLE_SIZE: u32 = 0x200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x400;
+const TRUN_SAMPLE_CTS_OFFSET: u32 = 0x800;
+
+/// The `sample_is_non_sync_sample` bit of the sample flags of fragments
+const SAMPLE_IS_NON_SYNC: u32 = 0x10000;
+
+/// A box (or atom) of an ISO-BMFF file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp4Box<'a> {
+    pub box_type: [u8; 4],
+    /// The content of the box, after its header
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the boxes in a buffer, like the content of a container box.
+///
+/// Iteration stops at the first box that doesn't fit in the buffer
+#[derive(Debug, Clone)]
+pub struct Mp4Boxes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Returns an iterator over the boxes in `data`
+pub fn mp4_boxes(data: &[u8]) -> Mp4Boxes<'_> {
+    Mp4Boxes { data, position: 0 }
+}
+
+impl<'a> Iterator for Mp4Boxes<'a> {
+    type Item = Mp4Box<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut reader = ByteReader::new(self.data.get(self.position..)?);
+
+        let size = reader.read_u32()? as u64;
+        let box_type = reader.read_array()?;
+        let (size, header_size) = match size {
+            0 => ((self.data.len() - self.position) as u64, 8),
+            1 => (reader.read_u64()?, 16),
+            size => (size, 8),
+        };
+
+        if size < header_size || size > reader.remaining() as u64 + header_size {
+            self.position = self.data.len();
+            return None;
+        }
+
+        let start = self.position + header_size as usize;
+        let end = self.position + size as usize;
+        self.position = end;
+
+        Some(Mp4Box {
+            box_type,
+            payload: &self.data[start..end],
+        })
+    }
+}
+
+/// Returns the payload of the first box of type `box_type` in `data`
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_boxes(data)
+        .find(|mp4_box| &mp4_box.box_type == box_type)
+        .map(|mp4_box| mp4_box.payload)
+}
+
+/// Follows a path of nested boxes, like `[b"mdia", b"minf", b"stbl"]`
+fn find_path<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter()
+        .try_fold(data, |data, box_type| find_box(data, box_type))
+}
+
+/// Reads the version and flags of a full box
+fn read_full_box_header(reader: &mut ByteReader) -> Option<(u8, u32)> {
+    let value = reader.read_u32()?;
+    Some(((value >> 24) as u8, value & 0xff_ffff))
+}
+
+/// Converts a time in `timescale` units to microseconds
+fn ticks_to_us(ticks: i64, timescale: u32) -> i64 {
+    if timescale == 0 {
+        return 0;
+    }
+
+    (ticks as i128 * 1_000_000 / timescale as i128) as i64
+}
+
+/// A sample of an MP4 track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp4Sample {
+    /// The position of the sample in the file
+    pub offset: u64,
+    pub size: u32,
+    /// The decode time, in track timescale units
+    pub dts: i64,
+    /// The presentation time minus the decode time, in track timescale units
+    pub cts_offset: i32,
+    pub duration: u32,
+    pub sync: bool,
+}
+
+/// The description of an MP4 track, what `MediaExtractor` would put in its `MediaFormat`.
+///
+/// This doesn't need the NDK, [into_media_format](Self::into_media_format) does the conversion on Android
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackFormat {
+    /// The mime type of the codec, like `video/avc` or `audio/mp4a-latm`
+    pub mime: &'static str,
+    pub track_id: u32,
+    /// The number of time units per second of the sample times
+    pub timescale: u32,
+    pub duration_us: i64,
+    /// The ISO 639-2 language code, `None` when undetermined
+    pub language: Option<String>,
+    /// The size of the largest sample, as returned by the demuxer
+    pub max_sample_size: usize,
+    /// The samples are encrypted (Common Encryption), `mime` is the one of the original format
+    pub encrypted: bool,
+
+    pub width: u32,
+    pub height: u32,
+    /// The clockwise rotation from the track matrix, in degrees
+    pub rotation: i32,
+    /// The average frame rate, from the sample count and the duration
+    pub frame_rate: Option<f32>,
+    /// The colors from the `colr` box
+    pub color: Option<VideoColorInfo>,
+
+    pub sample_rate: u32,
+    pub channel_count: u32,
+
+    /// The decoder configuration of the sample entry: the avcC, hvcC, av1C or vpcC record, the AAC AudioSpecificConfig,
+    /// the FLAC metadata blocks, an `OpusHead` for Opus, or the Xiph laced headers for Vorbis
+    pub codec_private: Vec<u8>,
+    /// The size of the NAL unit lengths of AVC and HEVC samples, which the demuxer replaces with start codes
+    pub nal_length_size: Option<u8>,
+}
+
+impl TrackFormat {
+    pub fn is_video(&self) -> bool {
+        self.mime.starts_with("video/")
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.mime.starts_with("audio/")
+    }
+
+    /// Creates a `MediaFormat` with the keys `MediaExtractor` would set for this track
+    #[cfg(target_os = "android")]
+    pub fn into_media_format(self) -> Option<MediaFormat> {
+        let mut format = match self.codec_media_format() {
+            Some(format) => format,
+            None => self.basic_media_format()?,
+        };
+
+        if self.is_video() && self.width > 0 && self.height > 0 {
+            format.set_i32("width", self.width as i32);
+            format.set_i32("height", self.height as i32);
+        }
+
+        if self.duration_us > 0 {
+            format.set_i64("durationUs", self.duration_us);
+        }
+
+        if self.max_sample_size > 0 {
+            format.set_i32("max-input-size", self.max_sample_size as i32);
+        }
+
+        if let Some(language) = &self.language {
+            format.set_string("language", language);
+        }
+
+        if self.rotation != 0 {
+            format.set_i32("rotation-degrees", self.rotation);
+        }
+
+        if let Some(frame_rate) = self.frame_rate {
+            format.set_f32("frame-rate", frame_rate);
+        }
+
+        if let Some(color) = self.color {
+            color.apply_to(&mut format);
+        }
+
+        Some(format)
+    }
+
+    /// The format built from the codec private data, with things like the profile and level
+    #[cfg(target_os = "android")]
+    fn codec_media_format(&self) -> Option<MediaFormat> {
+        let data = &self.codec_private;
+
+        match self.mime {
+            "video/avc" => AvcDecoderConfigurationRecord::parse(data)?.media_format(),
+            "video/hevc" => HevcDecoderConfigurationRecord::parse(data)?.media_format(),
+            "video/av01" => Av1CodecConfigurationRecord::parse(data)?.media_format(),
+            "video/x-vnd.on2.vp8" | "video/x-vnd.on2.vp9" => {
+                let mut format = VpCodecConfigurationRecord::parse(data)?
+                    .media_format(self.width, self.height)?;
+                format.set_string("mime", self.mime);
+                Some(format)
+            }
+            "audio/mp4a-latm" => AudioSpecificConfig::parse(data)?.media_format(),
+            "audio/opus" => OpusHead::parse(data)?.media_format(OPUS_DEFAULT_SEEK_PRE_ROLL_NS),
+            "audio/flac" => FlacStreamInfo::from_codec_private(data)?.media_format(),
+            "audio/vorbis" => VorbisHeaders::from_codec_private(data)?.media_format(),
+            _ => None,
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    fn basic_media_format(&self) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", self.mime);
+
+        if self.is_audio() {
+            format.set_i32("sample-rate", self.sample_rate as i32);
+            format.set_i32("channel-count", self.channel_count as i32);
+        }
+
+        if !self.codec_private.is_empty() {
+            format.set_buffer("csd-0", &self.codec_private);
+        }
+
+        Some(format)
+    }
+
+    /// Describes the track of a `MediaFormat`, like the output format of an encoder.
+    ///
+    /// The codec private data is built from the `csd-*` buffers. The timescale is 90kHz for video and the sample rate for audio
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(format: &MediaFormat) -> Option<Self> {
+        let mime = format.get_string("mime")?;
+        let mime = *MP4_MIMES.iter().find(|known| **known == mime)?;
+
+        let get_u32 = |name: &str| format.get_i32(name).unwrap_or(0).max(0) as u32;
+
+        let mut track = Self {
+            mime,
+            duration_us: format.get_i64("durationUs").unwrap_or(0),
+            language: format
+                .get_string("language")
+                .filter(|language| language != "und"),
+            ..Default::default()
+        };
+
+        if track.is_video() {
+            track.width = get_u32("width");
+            track.height = get_u32("height");
+            track.rotation = format.get_i32("rotation-degrees").unwrap_or(0);
+            track.frame_rate = format
+                .get_f32("frame-rate")
+                .or_else(|| format.get_i32("frame-rate").map(|rate| rate as f32));
+            track.timescale = 90_000;
+        } else {
+            track.sample_rate = get_u32("sample-rate");
+            track.channel_count = get_u32("channel-count");
+            track.timescale = track.sample_rate;
+        }
+
+        // H.264 has the SPS and the PPS in separate buffers
+        let mut config = format.get_buffer("csd-0").unwrap_or_default().to_vec();
+        if mime == "video/avc" {
+            config.extend_from_slice(format.get_buffer("csd-1").unwrap_or_default());
+        }
+
+        if !config.is_empty() && !track.set_codec_config(&config) {
+            warn!("Invalid codec specific data for {mime}");
+        }
+
+        Some(track)
+    }
+
+    /// Sets the codec private data from a codec config buffer, as an encoder hands them out.
+    ///
+    /// AVC and HEVC parameter sets are turned into a record for 4 byte NAL unit lengths, AV1 can be an av1C record or OBUs,
+    /// and Opus can be an `OpusHead` or the marked-up config of the Android encoder. Returns false if the data isn't valid
+    pub fn set_codec_config(&mut self, data: &[u8]) -> bool {
+        let codec_private = match self.mime {
+            "video/avc" => AvcDecoderConfigurationRecord::from_annexb(data)
+                .or_else(|| AvcDecoderConfigurationRecord::parse(data))
+                .map(|mut record| {
+                    record.length_size = 4;
+                    record.to_bytes()
+                }),
+            "video/hevc" => HevcDecoderConfigurationRecord::from_annexb(data)
+                .or_else(|| HevcDecoderConfigurationRecord::parse(data))
+                .map(|mut record| {
+                    record.length_size = 4;
+                    record.to_bytes()
+                }),
+            "video/av01" => Av1CodecConfigurationRecord::parse(data)
+                .or_else(|| Av1CodecConfigurationRecord::from_temporal_unit(data))
+                .map(|record| record.to_bytes()),
+            "video/x-vnd.on2.vp8" | "video/x-vnd.on2.vp9" => {
+                VpCodecConfigurationRecord::parse(data).map(|record| record.to_bytes())
+            }
+            "audio/mp4a-latm" => AudioSpecificConfig::parse(data).map(|_| data.to_vec()),
+            "audio/opus" => data
+                .windows(8)
+                .position(|window| window == b"OpusHead")
+                .and_then(|start| OpusHead::parse(&data[start..]))
+                .map(|head| head.to_bytes()),
+            "audio/flac" => FlacStreamInfo::from_codec_private(data)
+                .map(|_| data.strip_prefix(FLAC_MAGIC).unwrap_or(data).to_vec()),
+            _ => Some(data.to_vec()),
+        };
+
+        let Some(codec_private) = codec_private else {
+            return false;
+        };
+
+        if matches!(self.mime, "video/avc" | "video/hevc") {
+            self.nal_length_size = Some(4);
+        }
+        self.codec_private = codec_private;
+
+        true
+    }
+
+    /// The RFC 6381 codec string of the track, like `avc1.64001f` or `mp4a.40.2`, for HLS and DASH manifests.
+    ///
+    /// Returns `None` when the codec private data needed to build it is missing
+    pub fn codec_string(&self) -> Option<String> {
+        let data = &self.codec_private;
+
+        let codec = match self.mime {
+            "video/avc" => {
+                let record = AvcDecoderConfigurationRecord::parse(data)?;
+                format!(
+                    "avc1.{:02x}{:02x}{:02x}",
+                    record.profile_indication,
+                    record.profile_compatibility,
+                    record.level_indication
+                )
+            }
+            "video/hevc" => {
+                let ptl = HevcDecoderConfigurationRecord::parse(data)?.profile_tier_level;
+                let profile_space = ["", "A", "B", "C"][ptl.profile_space as usize & 3];
+                let tier = if ptl.tier { 'H' } else { 'L' };
+
+                let mut codec = format!(
+                    "hvc1.{profile_space}{}.{:x}.{tier}{}",
+                    ptl.profile_idc,
+                    ptl.profile_compatibility_flags.reverse_bits(),
+                    ptl.level_idc
+                );
+
+                // The constraint bytes, without the trailing zero ones
+                let constraints = &ptl.constraint_indicator_flags.to_be_bytes()[2..];
+                let count = constraints
+                    .iter()
+                    .rposition(|&byte| byte != 0)
+                    .map_or(0, |last| last + 1);
+                for byte in &constraints[..count] {
+                    codec.push_str(&format!(".{byte:x}"));
+                }
+
+                codec
+            }
+            "video/av01" => {
+                let record = Av1CodecConfigurationRecord::parse(data)?;
+                let bit_depth = match (record.high_bitdepth, record.twelve_bit) {
+                    (true, true) => 12,
+                    (true, false) => 10,
+                    _ => 8,
+                };
+                let tier = if record.seq_tier { 'H' } else { 'M' };
+
+                format!(
+                    "av01.{}.{:02}{tier}.{bit_depth:02}",
+                    record.seq_profile, record.seq_level_idx
+                )
+            }
+            "video/x-vnd.on2.vp9" => {
+                let record = VpCodecConfigurationRecord::parse(data)?;
+                format!(
+                    "vp09.{:02}.{:02}.{:02}",
+                    record.profile, record.level, record.bit_depth
+                )
+            }
+            "video/x-vnd.on2.vp8" => "vp8".to_string(),
+            "audio/mp4a-latm" => {
+                let config = AudioSpecificConfig::parse(data)?;
+                // HE-AAC is signalled with its own object types
+                let object_type = match (config.sbr, config.ps) {
+                    (_, true) => 29,
+                    (true, false) => 5,
+                    _ => config.object_type,
+                };
+                format!("mp4a.40.{object_type}")
+            }
+            "audio/mpeg" => "mp4a.40.34".to_string(),
+            "audio/opus" => "opus".to_string(),
+            "audio/flac" => "fLaC".to_string(),
+            "audio/ac3" => "ac-3".to_string(),
+            "audio/eac3" => "ec-3".to_string(),
+            _ => return None,
+        };
+
+        Some(codec)
+    }
+}
+
+/// The mime types of the codecs we know how to put in MP4
+#[cfg(target_os = "android")]
+const MP4_MIMES: &[&str] = &[
+    "video/avc",
+    "video/hevc",
+    "video/av01",
+    "video/x-vnd.on2.vp8",
+    "video/x-vnd.on2.vp9",
+    "video/mp4v-es",
+    "video/3gpp",
+    "audio/mp4a-latm",
+    "audio/mpeg",
+    "audio/opus",
+    "audio/flac",
+    "audio/ac3",
+    "audio/eac3",
+];
+
+/// The `trex` defaults of a track, used by fragments that don't have their own
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackDefaults {
+    sample_duration: u32,
+    sample_size: u32,
+    sample_flags: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Mp4Track {
+    format: TrackFormat,
+    samples: Vec<Mp4Sample>,
+    /// Added to the sample times to apply the edit list, in timescale units
+    time_offset: i64,
+    defaults: TrackDefaults,
+    /// The decode time of the next fragment sample, for fragments without `tfdt`
+    next_dts: i64,
+    selected: bool,
+    /// The index of the next sample to read
+    position: usize,
+}
+
+impl Mp4Track {
+    fn current(&self) -> Option<&Mp4Sample> {
+        if self.selected {
+            self.samples.get(self.position)
+        } else {
+            None
+        }
+    }
+
+    fn dts_us(&self, sample: &Mp4Sample) -> i64 {
+        ticks_to_us(sample.dts + self.time_offset, self.format.timescale)
+    }
+
+    fn pts_us(&self, sample: &Mp4Sample) -> i64 {
+        ticks_to_us(
+            sample.dts + sample.cts_offset as i64 + self.time_offset,
+            self.format.timescale,
+        )
+    }
+
+    /// The index of the sample a seek to `time_us` lands on
+    fn seek_position(&self, time_us: i64, mode: SeekMode) -> usize {
+        let mut previous = None;
+        let mut next = None;
+
+        for (index, sample) in self.samples.iter().enumerate() {
+            if !sample.sync {
+                continue;
+            }
+
+            let pts_us = self.pts_us(sample);
+            if pts_us <= time_us {
+                previous = Some((index, pts_us));
+            } else {
+                next = Some((index, pts_us));
+                break;
+            }
+        }
+
+        let end = self.samples.len();
+        match mode {
+            SeekMode::PreviousSync => previous.or(next).map_or(end, |(index, _)| index),
+            SeekMode::NextSync => match previous {
+                Some((index, pts_us)) if pts_us == time_us => index,
+                _ => next.map_or(end, |(index, _)| index),
+            },
+            SeekMode::ClosestSync => match (previous, next) {
+                (Some((previous, previous_us)), Some((next, next_us))) => {
+                    if time_us - previous_us <= next_us - time_us {
+                        previous
+                    } else {
+                        next
+                    }
+                }
+                (Some((index, _)), None) | (None, Some((index, _))) => index,
+                (None, None) => end,
+            },
+        }
+    }
+
+    /// Fills in the parts of the format that depend on all the samples being known
+    fn finish(&mut self, duration: u64) {
+        let format = &mut self.format;
+
+        let samples_duration = self
+            .samples
+            .last()
+            .map_or(0, |last| last.dts + last.duration as i64);
+        let duration = match duration {
+            0 | 0xffff_ffff | u64::MAX => samples_duration,
+            duration => duration as i64,
+        };
+        format.duration_us = ticks_to_us(duration, format.timescale);
+
+        if format.is_video() && self.samples.len() > 1 && samples_duration > 0 {
+            let frame_rate =
+                self.samples.len() as f64 * format.timescale as f64 / samples_duration as f64;
+            format.frame_rate = Some(frame_rate as f32);
+        }
+
+        let max_size = self.samples.iter().map(|sample| sample.size).max();
+        let max_size = max_size.unwrap_or(0) as usize;
+        format.max_sample_size = match format.nal_length_size {
+            // Each NAL unit takes at least one byte more than its length, and grows by the difference with the start code
+            Some(length_size @ 1..=3) => {
+                max_size + max_size / (length_size as usize + 1) * (4 - length_size as usize)
+            }
+            _ => max_size,
+        };
+    }
+}
+
+/// A pure Rust demuxer for MP4 (ISO-BMFF) and QuickTime files, regular or fragmented.
+///
+/// It works like a [MediaExtractor](crate::MediaExtractor): tracks have to be selected, then the samples of the
+/// selected tracks are returned interleaved in decode order, with their presentation times in microseconds.
+/// Like `MediaExtractor`, AVC and HEVC samples are returned in Annex-B format.
+///
+/// The edit list is applied to the sample times, but samples aren't dropped because of it
+#[derive(Debug)]
+pub struct Mp4Demuxer<R: Read + Seek> {
+    reader: R,
+    tracks: Vec<Mp4Track>,
+    /// The index of the track of the current sample
+    current: Option<usize>,
+    fragmented: bool,
+}
+
+impl Mp4Demuxer<BufReader<File>> {
+    /// Opens an MP4 file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open MP4 file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> Mp4Demuxer<R> {
+    /// Creates a demuxer, reading the `moov` box and the `moof` boxes of fragmented files.
+    ///
+    /// The samples themselves are only read when they are needed
+    pub fn new(mut reader: R) -> Result<Self, MediaStatus> {
+        let io_error = |error: std::io::Error| {
+            warn!("Could not read the MP4 file: {error}");
+            MediaStatus::ErrorIO
+        };
+
+        let file_size = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        let mut moov = None;
+        let mut fragments = vec![];
+
+        let mut position = 0;
+        while position + 8 <= file_size {
+            reader.seek(SeekFrom::Start(position)).map_err(io_error)?;
+
+            let mut header = [0; 8];
+            reader.read_exact(&mut header).map_err(io_error)?;
+            let box_type: [u8; 4] = header[4..8].try_into().unwrap();
+
+            let (size, header_size) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+                0 => (file_size - position, 8),
+                1 => {
+                    let mut size = [0; 8];
+                    reader.read_exact(&mut size).map_err(io_error)?;
+                    (u64::from_be_bytes(size), 16)
+                }
+                size => (size as u64, 8),
+            };
+
+            if size < header_size {
+                warn!("Invalid size for MP4 box at {position}");
+                break;
+            }
+
+            if &box_type == b"moov" || &box_type == b"moof" {
+                let payload_size = size - header_size;
+                if payload_size > MAX_HEADER_BOX_SIZE {
+                    warn!("MP4 box of {payload_size} bytes is too large");
+                    return Err(MediaStatus::ErrorMalformed);
+                }
+
+                let mut payload = vec![0; payload_size as usize];
+                reader.read_exact(&mut payload).map_err(io_error)?;
+
+                if &box_type == b"moov" {
+                    moov = Some(payload);
+                } else {
+                    fragments.push((position, payload));
+                }
+            }
+
+            position = position.saturating_add(size);
+        }
+
+        let moov = moov.ok_or_else(|| {
+            warn!("No moov box in the MP4 file");
+            MediaStatus::ErrorMalformed
+        })?;
+
+        let (mut tracks, durations) = parse_moov(&moov);
+        for (offset, moof) in &fragments {
+            parse_moof(&mut tracks, moof, *offset);
+        }
+
+        for (track, duration) in tracks.iter_mut().zip(durations) {
+            track.finish(duration);
+        }
+
+        let mut me = Self {
+            reader,
+            tracks,
+            current: None,
+            fragmented: !fragments.is_empty(),
+        };
+        me.update_current();
+
+        Ok(me)
+    }
+
+    /// Returns whether the file has movie fragments
+    pub fn is_fragmented(&self) -> bool {
+        self.fragmented
+    }
+
+    /// Returns the number of tracks in the file. Tracks with a codec we don't know about are left out
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns the description of a track
+    pub fn track(&self, index: usize) -> Option<&TrackFormat> {
+        self.tracks.get(index).map(|track| &track.format)
+    }
+
+    /// Returns the samples of a track, in decode order
+    pub fn samples(&self, index: usize) -> Option<&[Mp4Sample]> {
+        self.tracks.get(index).map(|track| track.samples.as_slice())
+    }
+
+    /// Returns the format of a track, like [MediaExtractor::track_format](crate::MediaExtractor::track_format).
+    ///
+    /// [into_media_format](TrackFormat::into_media_format) turns it into the `MediaFormat` to create and initialize MediaCodec
+    pub fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        self.track(index).cloned()
+    }
+
+    /// Select this track to be demuxed
+    pub fn select_track(&mut self, index: usize) {
+        if let Some(track) = self.tracks.get_mut(index) {
+            track.selected = true;
+        }
+
+        self.update_current();
+    }
+
+    /// Unselect this track to be demuxed
+    pub fn unselect_track(&mut self, index: usize) {
+        if let Some(track) = self.tracks.get_mut(index) {
+            track.selected = false;
+        }
+
+        self.update_current();
+    }
+
+    /// Returns the track index of the current sample, or -1 if there's none
+    pub fn track_index(&self) -> i32 {
+        self.current.map_or(-1, |index| index as i32)
+    }
+
+    /// Returns the presentation time of the current sample in microseconds, or -1 if there's none
+    pub fn sample_time(&self) -> i64 {
+        match self.current_sample() {
+            Some((track, sample)) => track.pts_us(sample),
+            None => -1,
+        }
+    }
+
+    /// Returns the sample flags of the current sample, like [MediaExtractor::sample_flags](crate::MediaExtractor::sample_flags)
+    pub fn sample_flags(&self) -> u32 {
+        let Some((track, sample)) = self.current_sample() else {
+            return 0;
+        };
+
+        let mut flags = 0;
+        if sample.sync {
+            flags |= SAMPLE_FLAG_SYNC;
+        }
+        if track.format.encrypted {
+            flags |= SAMPLE_FLAG_ENCRYPTED;
+        }
+
+        flags
+    }
+
+    /// Returns whether there are still samples to read in the selected tracks
+    pub fn has_next(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Reads the current sample into `buffer`, without advancing.
+    ///
+    /// Returns the size of the sample, or `None` if there's no sample or `buffer` is too small
+    pub fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let data = self.read_current()?;
+        buffer.get_mut(..data.len())?.copy_from_slice(&data);
+
+        Some(data.len())
+    }
+
+    /// Advances to the next sample.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        if let Some(index) = self.current {
+            self.tracks[index].position += 1;
+        }
+
+        self.update_current();
+        self.has_next()
+    }
+
+    /// Read a sample into `buffer` and advance the demuxer.
+    /// Returns true if there's still more data to read
+    ///
+    /// Samples that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        if self.current.is_none() {
+            return false;
+        }
+
+        let time_us = self.sample_time();
+        let flags = self.sample_flags();
+
+        if let Some(data) = self.read_current() {
+            if buffer.write_data(&data) {
+                buffer.set_time(time_us.max(0) as u64);
+                buffer.set_flags(flags);
+            } else {
+                warn!(
+                    "Sample at {time_us}us doesn't fit in the input buffer ({} > {})",
+                    data.len(),
+                    buffer.size()
+                );
+            }
+        }
+
+        self.advance()
+    }
+
+    /// Seeks all selected tracks to `time_us`. Where the tracks land depends on `mode`
+    pub fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        for track in self.tracks.iter_mut().filter(|track| track.selected) {
+            track.position = track.seek_position(time_us, mode);
+        }
+
+        self.update_current();
+        Ok(())
+    }
+
+    fn current_sample(&self) -> Option<(&Mp4Track, &Mp4Sample)> {
+        let track = &self.tracks[self.current?];
+        Some((track, track.current()?))
+    }
+
+    /// Picks the selected track whose next sample has the earliest decode time
+    fn update_current(&mut self) {
+        self.current = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, track)| Some((track.dts_us(track.current()?), index)))
+            .min()
+            .map(|(_, index)| index);
+    }
+
+    /// Reads the current sample from the file, converting AVC and HEVC samples to Annex-B
+    fn read_current(&mut self) -> Option<Vec<u8>> {
+        let (track, sample) = self.current_sample()?;
+        let (offset, size) = (sample.offset, sample.size);
+        let nal_length_size = track.format.nal_length_size;
+
+        if size > MAX_SAMPLE_SIZE {
+            warn!("MP4 sample of {size} bytes is too large");
+            return None;
+        }
+
+        let mut data = vec![0; size as usize];
+        let result = self
+            .reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.reader.read_exact(&mut data));
+        if let Err(error) = result {
+            warn!("Could not read the MP4 sample at {offset}: {error}");
+            return None;
+        }
+
+        match nal_length_size {
+            Some(length_size) => match length_prefixed_to_annexb(&data, length_size as usize) {
+                Some(annexb) => Some(annexb),
+                None => {
+                    warn!("Invalid NAL unit lengths in the MP4 sample at {offset}");
+                    Some(data)
+                }
+            },
+            None => Some(data),
+        }
+    }
+}
+
+/// Parses the tracks of the `moov` box, returning them with their duration from `mdhd`
+fn parse_moov(moov: &[u8]) -> (Vec<Mp4Track>, Vec<u64>) {
+    let movie_timescale = find_box(moov, b"mvhd")
+        .and_then(|mvhd| {
+            let mut reader = ByteReader::new(mvhd);
+            let (version, _) = read_full_box_header(&mut reader)?;
+            reader.skip(if version == 1 { 16 } else { 8 })?;
+            reader.read_u32()
+        })
+        .unwrap_or(0);
+
+    let mut tracks = vec![];
+    let mut durations = vec![];
+
+    for trak in mp4_boxes(moov).filter(|mp4_box| &mp4_box.box_type == b"trak") {
+        match parse_trak(trak.payload, movie_timescale) {
+            Some((track, duration)) => {
+                tracks.push(track);
+                durations.push(duration);
+            }
+            None => debug!("Skipping an MP4 track we don't support"),
+        }
+    }
+
+    if let Some(mvex) = find_box(moov, b"mvex") {
+        for trex in mp4_boxes(mvex).filter(|mp4_box| &mp4_box.box_type == b"trex") {
+            let mut reader = ByteReader::new(trex.payload);
+            let defaults = read_full_box_header(&mut reader).and_then(|_| {
+                let track_id = reader.read_u32()?;
+                reader.skip(4)?;
+
+                let defaults = TrackDefaults {
+                    sample_duration: reader.read_u32()?,
+                    sample_size: reader.read_u32()?,
+                    sample_flags: reader.read_u32()?,
+                };
+                Some((track_id, defaults))
+            });
+
+            if let Some((track_id, defaults)) = defaults {
+                for track in tracks.iter_mut() {
+                    if track.format.track_id == track_id {
+                        track.defaults = defaults;
+                    }
+                }
+            }
+        }
+    }
+
+    (tracks, durations)
+}
+
+fn parse_trak(trak: &[u8], movie_timescale: u32) -> Option<(Mp4Track, u64)> {
+    let mdia = find_box(trak, b"mdia")?;
+
+    let handler = {
+        let mut reader = ByteReader::new(find_box(mdia, b"hdlr")?);
+        reader.skip(8)?;
+        reader.read_array::<4>()?
+    };
+    if &handler != b"vide" && &handler != b"soun" {
+        return None;
+    }
+
+    let stsd = find_path(mdia, &[b"minf", b"stbl", b"stsd"])?;
+    let mut reader = ByteReader::new(stsd);
+    reader.skip(8)?;
+    let entry = mp4_boxes(reader.rest()).next()?;
+
+    let mut format = match &handler {
+        b"vide" => parse_visual_sample_entry(entry)?,
+        _ => parse_audio_sample_entry(entry)?,
+    };
+
+    let (track_id, rotation) = parse_tkhd(find_box(trak, b"tkhd")?)?;
+    format.track_id = track_id;
+    format.rotation = rotation;
+
+    let (timescale, duration, language) = parse_mdhd(find_box(mdia, b"mdhd")?)?;
+    format.timescale = timescale;
+    format.language = language;
+
+    let samples = parse_sample_table(find_path(mdia, &[b"minf", b"stbl"])?)?;
+    let next_dts = samples
+        .last()
+        .map_or(0, |last| last.dts + last.duration as i64);
+
+    let time_offset = find_path(trak, &[b"edts", b"elst"])
+        .and_then(|elst| edit_list_offset(elst, movie_timescale, timescale))
+        .unwrap_or(0);
+
+    let track = Mp4Track {
+        format,
+        samples,
+        time_offset,
+        defaults: TrackDefaults::default(),
+        next_dts,
+        selected: false,
+        position: 0,
+    };
+
+    Some((track, duration))
+}
+
+/// Reads the track id and the rotation of the track matrix
+fn parse_tkhd(tkhd: &[u8]) -> Option<(u32, i32)> {
+    let mut reader = ByteReader::new(tkhd);
+    let (version, _) = read_full_box_header(&mut reader)?;
+
+    reader.skip(if version == 1 { 16 } else { 8 })?;
+    let track_id = reader.read_u32()?;
+    reader.skip(if version == 1 { 12 } else { 8 })?;
+
+    // Reserved, layer, alternate group, volume and reserved, then the matrix
+    reader.skip(16)?;
+    let a = reader.read_i32()?;
+    let b = reader.read_i32()?;
+    reader.skip(4)?;
+    let c = reader.read_i32()?;
+    let d = reader.read_i32()?;
+
+    // The values are 16.16 fixed point
+    const ONE: i32 = 0x10000;
+    const MINUS_ONE: i32 = -0x10000;
+    let rotation = match (a, b, c, d) {
+        (0, ONE, MINUS_ONE, 0) => 90,
+        (MINUS_ONE, 0, 0, MINUS_ONE) => 180,
+        (0, MINUS_ONE, ONE, 0) => 270,
+        _ => 0,
+    };
+
+    Some((track_id, rotation))
+}
+
+/// Reads the timescale, the duration and the language of a track
+fn parse_mdhd(mdhd: &[u8]) -> Option<(u32, u64, Option<String>)> {
+    let mut reader = ByteReader::new(mdhd);
+    let (version, _) = read_full_box_header(&mut reader)?;
+
+    let (timescale, duration) = if version == 1 {
+        reader.skip(16)?;
+        (reader.read_u32()?, reader.read_u64()?)
+    } else {
+        reader.skip(8)?;
+        (reader.read_u32()?, reader.read_u32()? as u64)
+    };
+
+    // Three letters of 5 bits, offset by 0x60
+    let code = reader.read_u16()?;
+    let language: String = [10, 5, 0]
+        .iter()
+        .map(|shift| (((code >> shift) & 0x1f) as u8 + 0x60) as char)
+        .collect();
+    let language = match language.as_str() {
+        "und" => None,
+        _ if !language.chars().all(|c| c.is_ascii_lowercase()) => None,
+        _ => Some(language),
+    };
+
+    Some((timescale, duration, language))
+}
+
+/// Computes what to add to the sample times to apply the edit list: the empty edits delay the track,
+/// and the media time of the first actual edit is where the presentation starts
+fn edit_list_offset(elst: &[u8], movie_timescale: u32, timescale: u32) -> Option<i64> {
+    let mut reader = ByteReader::new(elst);
+    let (version, _) = read_full_box_header(&mut reader)?;
+    let count = reader.read_u32()?;
+
+    let mut delay = 0i64;
+    for _ in 0..count {
+        let (segment_duration, media_time) = if version == 1 {
+            (reader.read_u64()? as i64, reader.read_i64()?)
+        } else {
+            (reader.read_u32()? as i64, reader.read_i32()? as i64)
+        };
+        reader.skip(4)?;
+
+        if media_time == -1 {
+            delay += segment_duration;
+            continue;
+        }
+
+        let delay = if movie_timescale > 0 {
+            (delay as i128 * timescale as i128 / movie_timescale as i128) as i64
+        } else {
+            0
+        };
+        return Some(delay - media_time);
+    }
+
+    None
+}
+
+/// Skips the fields of a sample entry before its child boxes
+fn sample_entry_children<'a>(
+    reader: &mut ByteReader<'a>,
+    entry: &Mp4Box<'a>,
+) -> Option<([u8; 4], &'a [u8])> {
+    let children = reader.rest();
+
+    // Encrypted entries keep the original type in sinf/frma
+    let entry_type = match &entry.box_type {
+        b"encv" | b"enca" => find_path(children, &[b"sinf", b"frma"])?
+            .get(..4)?
+            .try_into()
+            .ok()?,
+        entry_type => *entry_type,
+    };
+
+    Some((entry_type, children))
+}
+
+fn parse_visual_sample_entry(entry: Mp4Box) -> Option<TrackFormat> {
+    let mut reader = ByteReader::new(entry.payload);
+
+    // Reserved, data reference index and pre-defined fields
+    reader.skip(24)?;
+    let width = reader.read_u16()? as u32;
+    let height = reader.read_u16()? as u32;
+    // Resolutions, frame count, compressor name, depth and pre-defined
+    reader.skip(50)?;
+
+    let (entry_type, children) = sample_entry_children(&mut reader, &entry)?;
+
+    let mut format = TrackFormat {
+        width,
+        height,
+        encrypted: &entry.box_type == b"encv",
+        color: find_box(children, b"colr").and_then(parse_colr),
+        ..Default::default()
+    };
+
+    match &entry_type {
+        b"avc1" | b"avc3" => {
+            format.mime = "video/avc";
+            format.codec_private = find_box(children, b"avcC")?.to_vec();
+            let record = AvcDecoderConfigurationRecord::parse(&format.codec_private)?;
+            format.nal_length_size = Some(record.length_size);
+        }
+        b"hvc1" | b"hev1" => {
+            format.mime = "video/hevc";
+            format.codec_private = find_box(children, b"hvcC")?.to_vec();
+            let record = HevcDecoderConfigurationRecord::parse(&format.codec_private)?;
+            format.nal_length_size = Some(record.length_size);
+        }
+        b"vp08" | b"vp09" => {
+            format.mime = match &entry_type {
+                b"vp08" => "video/x-vnd.on2.vp8",
+                _ => "video/x-vnd.on2.vp9",
+            };
+            if let Some(vpcc) = find_box(children, b"vpcC") {
+                format.codec_private = vpcc.get(4..)?.to_vec();
+            }
+        }
+        b"av01" => {
+            format.mime = "video/av01";
+            format.codec_private = find_box(children, b"av1C")?.to_vec();
+        }
+        b"mp4v" => {
+            format.mime = "video/mp4v-es";
+            if let Some(descriptor) = find_box(children, b"esds").and_then(parse_esds) {
+                format.codec_private = descriptor.decoder_specific_info;
+            }
+        }
+        b"s263" | b"h263" => format.mime = "video/3gpp",
+        _ => return None,
+    }
+
+    Some(format)
+}
+
+fn parse_audio_sample_entry(entry: Mp4Box) -> Option<TrackFormat> {
+    let mut reader = ByteReader::new(entry.payload);
+
+    // Reserved and data reference index
+    reader.skip(8)?;
+    let version = reader.read_u16()?;
+    reader.skip(6)?;
+    let mut channel_count = reader.read_u16()? as u32;
+    reader.skip(6)?;
+    let mut sample_rate = reader.read_u32()? >> 16;
+
+    // The QuickTime sound description versions have extra fields
+    match version {
+        1 => reader.skip(16)?,
+        2 => {
+            reader.skip(4)?;
+            sample_rate = f64::from_bits(reader.read_u64()?) as u32;
+            channel_count = reader.read_u32()?;
+            reader.skip(20)?;
+        }
+        _ => {}
+    }
+
+    let (entry_type, children) = sample_entry_children(&mut reader, &entry)?;
+
+    let mut format = TrackFormat {
+        sample_rate,
+        channel_count,
+        encrypted: &entry.box_type == b"enca",
+        ..Default::default()
+    };
+
+    match &entry_type {
+        b"mp4a" => {
+            let descriptor = find_box(children, b"esds").and_then(parse_esds);
+            format.mime = match descriptor.as_ref().map(|descriptor| descriptor.object_type) {
+                Some(0x69) | Some(0x6b) => "audio/mpeg",
+                Some(0xa5) => "audio/ac3",
+                Some(0xa6) => "audio/eac3",
+                _ => "audio/mp4a-latm",
+            };
+            if let Some(descriptor) = descriptor {
+                format.codec_private = descriptor.decoder_specific_info;
+            }
+        }
+        b".mp3" => format.mime = "audio/mpeg",
+        b"Opus" => {
+            format.mime = "audio/opus";
+            format.codec_private = parse_dops(find_box(children, b"dOps")?)?.to_bytes();
+        }
+        b"fLaC" => {
+            format.mime = "audio/flac";
+            format.codec_private = find_box(children, b"dfLa")?.get(4..)?.to_vec();
+        }
+        b"ac-3" => format.mime = "audio/ac3",
+        b"ec-3" => format.mime = "audio/eac3",
+        _ => return None,
+    }
+
+    Some(format)
+}
+
+/// Reads the colors of an `nclx` (or QuickTime `nclc`) `colr` box
+fn parse_colr(colr: &[u8]) -> Option<VideoColorInfo> {
+    let mut reader = ByteReader::new(colr);
+    let colour_type = reader.read_array::<4>()?;
+    if &colour_type != b"nclx" && &colour_type != b"nclc" {
+        return None;
+    }
+
+    let primaries = reader.read_u16()? as u8;
+    let transfer = reader.read_u16()? as u8;
+    let matrix = reader.read_u16()? as u8;
+    let full_range = &colour_type == b"nclx" && reader.read_u8()? & 0x80 != 0;
+
+    Some(VideoColorInfo {
+        full_range,
+        primaries,
+        transfer,
+        matrix,
+    })
+}
+
+/// The part of an MPEG-4 elementary stream descriptor we care about
+struct EsDescriptor {
+    object_type: u8,
+    decoder_specific_info: Vec<u8>,
+}
+
+/// Reads the tag and the payload of an MPEG-4 descriptor
+fn read_descriptor<'a>(reader: &mut ByteReader<'a>) -> Option<(u8, &'a [u8])> {
+    let tag = reader.read_u8()?;
+
+    // The size has 7 bits per byte, the high bit says if there's more
+    let mut size = 0usize;
+    for _ in 0..4 {
+        let byte = reader.read_u8()?;
+        size = (size << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Some((tag, reader.read_bytes(size)?))
+}
+
+fn parse_esds(esds: &[u8]) -> Option<EsDescriptor> {
+    let mut reader = ByteReader::new(esds);
+    read_full_box_header(&mut reader)?;
+
+    let (tag, es_descriptor) = read_descriptor(&mut reader)?;
+    if tag != 3 {
+        return None;
+    }
+
+    let mut reader = ByteReader::new(es_descriptor);
+    reader.skip(2)?;
+    let flags = reader.read_u8()?;
+    if flags & 0x80 != 0 {
+        reader.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        let length = reader.read_u8()? as usize;
+        reader.skip(length)?;
+    }
+    if flags & 0x20 != 0 {
+        reader.skip(2)?;
+    }
+
+    let (tag, decoder_config) = read_descriptor(&mut reader)?;
+    if tag != 4 {
+        return None;
+    }
+
+    let mut reader = ByteReader::new(decoder_config);
+    let object_type = reader.read_u8()?;
+    // Stream type, buffer size and bitrates
+    reader.skip(12)?;
+
+    let decoder_specific_info = match read_descriptor(&mut reader) {
+        Some((5, info)) => info.to_vec(),
+        _ => vec![],
+    };
+
+    Some(EsDescriptor {
+        object_type,
+        decoder_specific_info,
+    })
+}
+
+/// Converts the `dOps` box of Opus in MP4 to an `OpusHead`, which is big endian where `OpusHead` is little endian
+fn parse_dops(dops: &[u8]) -> Option<OpusHead> {
+    let mut reader = ByteReader::new(dops);
+    reader.skip(1)?;
+
+    let channel_count = reader.read_u8()?;
+    let pre_skip = reader.read_u16()?;
+    let input_sample_rate = reader.read_u32()?;
+
+    let mut head = OpusHead::new(channel_count, pre_skip, input_sample_rate);
+    head.output_gain = reader.read_i16()?;
+    head.mapping_family = reader.read_u8()?;
+
+    if head.mapping_family != 0 {
+        head.stream_count = reader.read_u8()?;
+        head.coupled_count = reader.read_u8()?;
+        head.channel_mapping = reader.read_bytes(channel_count as usize)?.to_vec();
+    }
+
+    Some(head)
+}
+
+/// Builds the samples of a track from the sample table boxes
+fn parse_sample_table(stbl: &[u8]) -> Option<Vec<Mp4Sample>> {
+    let chunk_offsets = match (find_box(stbl, b"stco"), find_box(stbl, b"co64")) {
+        (Some(stco), _) => read_table(stco, 4, |reader| reader.read_u32().map(u64::from))?,
+        (None, Some(co64)) => read_table(co64, 8, |reader| reader.read_u64())?,
+        (None, None) => vec![],
+    };
+
+    let chunks = match find_box(stbl, b"stsc") {
+        Some(stsc) => read_table(stsc, 12, |reader| {
+            let first_chunk = reader.read_u32()?;
+            let samples_per_chunk = reader.read_u32()?;
+            reader.skip(4)?;
+            Some((first_chunk as usize, samples_per_chunk))
+        })?,
+        None => vec![],
+    };
+
+    let sizes = match (find_box(stbl, b"stsz"), find_box(stbl, b"stz2")) {
+        (Some(stsz), _) => parse_stsz(stsz, chunk_sample_count(&chunks, chunk_offsets.len()))?,
+        (None, Some(stz2)) => parse_stz2(stz2)?,
+        // Fragmented files have no samples in the moov
+        (None, None) => return Some(vec![]),
+    };
+
+    let mut samples = Vec::with_capacity(sizes.len());
+
+    'chunks: for (index, &(first_chunk, samples_per_chunk)) in chunks.iter().enumerate() {
+        let end_chunk = match chunks.get(index + 1) {
+            Some(&(next_chunk, _)) => next_chunk.min(chunk_offsets.len() + 1),
+            None => chunk_offsets.len() + 1,
+        };
+
+        for chunk in first_chunk.max(1)..end_chunk {
+            let mut offset = chunk_offsets[chunk - 1];
+
+            for _ in 0..samples_per_chunk {
+                let Some(&size) = sizes.get(samples.len()) else {
+                    break 'chunks;
+                };
+
+                samples.push(Mp4Sample {
+                    offset,
+                    size,
+                    dts: 0,
+                    cts_offset: 0,
+                    duration: 0,
+                    sync: true,
+                });
+                offset += size as u64;
+            }
+        }
+    }
+
+    if samples.len() < sizes.len() {
+        warn!(
+            "The MP4 chunks only have {} of the {} samples",
+            samples.len(),
+            sizes.len()
+        );
+    }
+
+    if let Some(stts) = find_box(stbl, b"stts") {
+        let entries = read_table(stts, 8, |reader| {
+            Some((reader.read_u32()?, reader.read_u32()?))
+        })?;
+        let durations = entries
+            .iter()
+            .flat_map(|&(count, delta)| std::iter::repeat_n(delta, count as usize));
+
+        let mut dts = 0i64;
+        for (sample, duration) in samples.iter_mut().zip(durations) {
+            sample.dts = dts;
+            sample.duration = duration;
+            dts += duration as i64;
+        }
+    }
+
+    if let Some(ctts) = find_box(stbl, b"ctts") {
+        let entries = read_table(ctts, 8, |reader| {
+            Some((reader.read_u32()?, reader.read_i32()?))
+        })?;
+        let offsets = entries
+            .iter()
+            .flat_map(|&(count, offset)| std::iter::repeat_n(offset, count as usize));
+
+        for (sample, offset) in samples.iter_mut().zip(offsets) {
+            sample.cts_offset = offset;
+        }
+    }
+
+    // Without stss, every sample is a sync sample
+    if let Some(stss) = find_box(stbl, b"stss") {
+        for sample in samples.iter_mut() {
+            sample.sync = false;
+        }
+
+        for number in read_table(stss, 4, |reader| reader.read_u32())? {
+            if let Some(sample) = samples.get_mut((number as usize).wrapping_sub(1)) {
+                sample.sync = true;
+            }
+        }
+    }
+
+    Some(samples)
+}
+
+/// Reads the entries of a full box that starts with an entry count, like `stts` or `stco`
+fn read_table<T>(
+    data: &[u8],
+    entry_size: usize,
+    mut read_entry: impl FnMut(&mut ByteReader) -> Option<T>,
+) -> Option<Vec<T>> {
+    let mut reader = ByteReader::new(data);
+    read_full_box_header(&mut reader)?;
+    let count = reader.read_u32()? as usize;
+
+    if reader.remaining() / entry_size < count {
+        warn!("MP4 table with {count} entries is truncated");
+        return None;
+    }
+
+    (0..count).map(|_| read_entry(&mut reader)).collect()
+}
+
+/// Returns the number of samples the `stsc` entries put in `chunk_count` chunks
+fn chunk_sample_count(chunks: &[(usize, u32)], chunk_count: usize) -> usize {
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, &(first_chunk, samples_per_chunk))| {
+            let end_chunk = match chunks.get(index + 1) {
+                Some(&(next_chunk, _)) => next_chunk.min(chunk_count + 1),
+                None => chunk_count + 1,
+            };
+
+            end_chunk
+                .saturating_sub(first_chunk.max(1))
+                .saturating_mul(samples_per_chunk as usize)
+        })
+        .fold(0, usize::saturating_add)
+}
+
+/// Parses the sample sizes of `stsz`, which can't be more than the `max_count` samples of the chunks
+fn parse_stsz(stsz: &[u8], max_count: usize) -> Option<Vec<u32>> {
+    let mut reader = ByteReader::new(stsz);
+    read_full_box_header(&mut reader)?;
+    let sample_size = reader.read_u32()?;
+
+    let count = reader.read_u32()? as usize;
+
+    if sample_size != 0 {
+        // The count isn't backed by any table, don't trust it further than the chunks
+        if count > max_count {
+            warn!("MP4 sample size box has {count} samples, but the chunks only have {max_count}");
+            return None;
+        }
+
+        return Some(vec![sample_size; count]);
+    }
+
+    if reader.remaining() / 4 < count {
+        warn!("MP4 sample size table with {count} entries is truncated");
+        return None;
+    }
+
+    (0..count).map(|_| reader.read_u32()).collect()
+}
+
+fn parse_stz2(stz2: &[u8]) -> Option<Vec<u32>> {
+    let mut reader = ByteReader::new(stz2);
+    read_full_box_header(&mut reader)?;
+    reader.skip(3)?;
+    let field_size = reader.read_u8()?;
+    let count = reader.read_u32()? as usize;
+
+    if reader.remaining() * 8 / (field_size.max(1) as usize) < count {
+        return None;
+    }
+
+    let mut sizes = Vec::with_capacity(count);
+    match field_size {
+        4 => {
+            let bytes = reader.read_bytes(count.div_ceil(2))?;
+            for index in 0..count {
+                let byte = bytes[index / 2];
+                let size = if index & 1 == 0 {
+                    byte >> 4
+                } else {
+                    byte & 0x0f
+                };
+                sizes.push(size as u32);
+            }
+        }
+        8 => sizes.extend(reader.read_bytes(count)?.iter().map(|&size| size as u32)),
+        16 => {
+            for _ in 0..count {
+                sizes.push(reader.read_u16()? as u32);
+            }
+        }
+        _ => return None,
+    }
+
+    Some(sizes)
+}
+
+/// Adds the samples of the fragments of a `moof` box to their tracks
+fn parse_moof(tracks: &mut [Mp4Track], moof: &[u8], moof_offset: u64) {
+    // Without an explicit base, a track fragment's data follows the one of the previous track fragment
+    let mut previous_end = moof_offset;
+
+    for traf in mp4_boxes(moof).filter(|mp4_box| &mp4_box.box_type == b"traf") {
+        match parse_traf(tracks, traf.payload, moof_offset, previous_end) {
+            Some(end) => previous_end = end,
+            None => warn!("Invalid MP4 track fragment in the moof at {moof_offset}"),
+        }
+    }
+}
+
+/// Adds the samples of a track fragment, returning where its data ends
+fn parse_traf(
+    tracks: &mut [Mp4Track],
+    traf: &[u8],
+    moof_offset: u64,
+    previous_end: u64,
+) -> Option<u64> {
+    let mut reader = ByteReader::new(find_box(traf, b"tfhd")?);
+    let (_, flags) = read_full_box_header(&mut reader)?;
+    let track_id = reader.read_u32()?;
+
+    let Some(track) = tracks
+        .iter_mut()
+        .find(|track| track.format.track_id == track_id)
+    else {
+        // A track we skipped
+        return Some(previous_end);
+    };
+
+    let base_offset = if flags & TFHD_BASE_DATA_OFFSET != 0 {
+        reader.read_u64()?
+    } else if flags & TFHD_DEFAULT_BASE_IS_MOOF != 0 {
+        moof_offset
+    } else {
+        previous_end
+    };
+    if flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+        reader.skip(4)?;
+    }
+
+    let mut defaults = track.defaults;
+    if flags & TFHD_DEFAULT_SAMPLE_DURATION != 0 {
+        defaults.sample_duration = reader.read_u32()?;
+    }
+    if flags & TFHD_DEFAULT_SAMPLE_SIZE != 0 {
+        defaults.sample_size = reader.read_u32()?;
+    }
+    if flags & TFHD_DEFAULT_SAMPLE_FLAGS != 0 {
+        defaults.sample_flags = reader.read_u32()?;
+    }
+
+    if let Some(tfdt) = find_box(traf, b"tfdt") {
+        let mut reader = ByteReader::new(tfdt);
+        let (version, _) = read_full_box_header(&mut reader)?;
+        track.next_dts = match version {
+            1 => reader.read_u64()? as i64,
+            _ => reader.read_u32()? as i64,
+        };
+    }
+
+    // Audio samples are all sync samples, whatever the fragments say
+    let all_sync = track.format.is_audio();
+
+    let mut offset = base_offset;
+    for trun in mp4_boxes(traf).filter(|mp4_box| &mp4_box.box_type == b"trun") {
+        let mut reader = ByteReader::new(trun.payload);
+        let (_, flags) = read_full_box_header(&mut reader)?;
+        let count = reader.read_u32()?;
+
+        if flags & TRUN_DATA_OFFSET != 0 {
+            offset = base_offset.checked_add_signed(reader.read_i32()? as i64)?;
+        }
+        let first_sample_flags = match flags & TRUN_FIRST_SAMPLE_FLAGS {
+            0 => None,
+            _ => Some(reader.read_u32()?),
+        };
+
+        for index in 0..count {
+            let mut read_field = |flag: u32, default: u32| match flags & flag {
+                0 => Some(default),
+                _ => reader.read_u32(),
+            };
+
+            let duration = read_field(TRUN_SAMPLE_DURATION, defaults.sample_duration)?;
+            let size = read_field(TRUN_SAMPLE_SIZE, defaults.sample_size)?;
+            let mut sample_flags = read_field(TRUN_SAMPLE_FLAGS, defaults.sample_flags)?;
+            // Version 0 has unsigned offsets, but negative ones written as version 0 are common enough
+            let cts_offset = read_field(TRUN_SAMPLE_CTS_OFFSET, 0)? as i32;
+
+            if index == 0 {
+                if let Some(first_sample_flags) = first_sample_flags {
+                    sample_flags = first_sample_flags;
+                }
+            }
+
+            track.samples.push(Mp4Sample {
+                offset,
+                size,
+                dts: track.next_dts,
+                cts_offset,
+                duration,
+                sync: all_sync || sample_flags & SAMPLE_IS_NON_SYNC == 0,
+            });
+
+            offset += size as u64;
+            track.next_dts += duration as i64;
+        }
+    }
+
+    Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::PacketSource;
+
+    /// A 1080p baseline SPS and its PPS
+    const SPS: [u8; 10] = [0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut output = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        output.extend_from_slice(box_type);
+        output.extend_from_slice(payload);
+        output
+    }
+
+    fn full_box(box_type: &[u8; 4], version_and_flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = version_and_flags.to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+        mp4_box(box_type, &data)
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    /// A table box: its entry count, then the entries
+    fn table(box_type: &[u8; 4], entry_size: usize, values: &[u32]) -> Vec<u8> {
+        let mut data = u32s(&[(values.len() / entry_size) as u32]);
+        data.extend(u32s(values));
+        full_box(box_type, 0, &data)
+    }
+
+    fn mvhd() -> Vec<u8> {
+        let mut data = vec![0; 8];
+        data.extend(u32s(&[1000, 0]));
+        data.extend_from_slice(&[0; 80]);
+        full_box(b"mvhd", 0, &data)
+    }
+
+    fn tkhd(track_id: u32, rotated: bool) -> Vec<u8> {
+        let one = 0x10000;
+        let matrix = if rotated {
+            [0, one, 0, -0x10000i32 as u32, 0, 0, 0, 0, 0x40000000]
+        } else {
+            [one, 0, 0, 0, one, 0, 0, 0, 0x40000000]
+        };
+
+        let mut data = vec![0; 8];
+        data.extend(u32s(&[track_id, 0, 0, 0, 0, 0, 0]));
+        data.extend(u32s(&matrix));
+        data.extend(u32s(&[0, 0]));
+        full_box(b"tkhd", 0, &data)
+    }
+
+    /// A `mdhd` for English, and the handler of the track
+    fn mdhd_and_hdlr(timescale: u32, handler: &[u8; 4]) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        data.extend(u32s(&[timescale, 0]));
+        let language = b"eng"
+            .iter()
+            .fold(0u16, |code, letter| (code << 5) | (letter - 0x60) as u16);
+        data.extend(language.to_be_bytes());
+        data.extend_from_slice(&[0, 0]);
+        let mut output = full_box(b"mdhd", 0, &data);
+
+        let mut data = vec![0; 4];
+        data.extend_from_slice(handler);
+        data.extend_from_slice(&[0; 13]);
+        output.extend(full_box(b"hdlr", 0, &data));
+        output
+    }
+
+    fn avc_entry() -> Vec<u8> {
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(&[SPS], &[PPS]).unwrap();
+
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(&[0; 16]);
+        data.extend(1920u16.to_be_bytes());
+        data.extend(1080u16.to_be_bytes());
+        data.extend_from_slice(&[0; 50]);
+        data.extend(mp4_box(b"avcC", &record.to_bytes()));
+        // BT.709, full range
+        data.extend(mp4_box(b"colr", b"nclx\x00\x01\x00\x01\x00\x01\x80"));
+        mp4_box(b"avc1", &data)
+    }
+
+    fn aac_entry() -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(&[0; 8]);
+        data.extend(2u16.to_be_bytes());
+        data.extend(16u16.to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend((44100u32 << 16).to_be_bytes());
+
+        // ES_Descriptor > DecoderConfigDescriptor > DecoderSpecificInfo with an AAC-LC config
+        let mut decoder_config = vec![4, 17, 0x40, 0x15];
+        decoder_config.extend_from_slice(&[0; 11]);
+        decoder_config.extend_from_slice(&[5, 2, 0x12, 0x10]);
+        let mut descriptor = vec![3, 0x80, 0x80, 0x80, 3 + decoder_config.len() as u8, 0, 2, 0];
+        descriptor.extend(decoder_config);
+        data.extend(full_box(b"esds", 0, &descriptor));
+        mp4_box(b"mp4a", &data)
+    }
+
+    struct SampleTable<'a> {
+        stts: &'a [u32],
+        ctts: &'a [u32],
+        stss: &'a [u32],
+        stsc: &'a [u32],
+        stco: &'a [u32],
+        sizes: &'a [u32],
+    }
+
+    fn stbl(entry: Vec<u8>, samples: SampleTable) -> Vec<u8> {
+        let mut stsd = u32s(&[1]);
+        stsd.extend(entry);
+
+        let mut data = full_box(b"stsd", 0, &stsd);
+        data.extend(table(b"stts", 2, samples.stts));
+        if !samples.ctts.is_empty() {
+            data.extend(table(b"ctts", 2, samples.ctts));
+        }
+        if !samples.stss.is_empty() {
+            data.extend(table(b"stss", 1, samples.stss));
+        }
+        data.extend(table(b"stsc", 3, samples.stsc));
+        data.extend(table(b"stco", 1, samples.stco));
+
+        let mut stsz = u32s(&[0, samples.sizes.len() as u32]);
+        stsz.extend(u32s(samples.sizes));
+        data.extend(full_box(b"stsz", 0, &stsz));
+        mp4_box(b"stbl", &data)
+    }
+
+    fn trak(
+        track_id: u32,
+        rotated: bool,
+        media_time: Option<u32>,
+        mdia: Vec<u8>,
+        stbl: Vec<u8>,
+    ) -> Vec<u8> {
+        let mut data = tkhd(track_id, rotated);
+        if let Some(media_time) = media_time {
+            let elst = full_box(b"elst", 0, &u32s(&[1, 1000, media_time, 0x10000]));
+            data.extend(mp4_box(b"edts", &elst));
+        }
+
+        let mut mdia = mdia;
+        mdia.extend(mp4_box(b"minf", &stbl));
+        data.extend(mp4_box(b"mdia", &mdia));
+        mp4_box(b"trak", &data)
+    }
+
+    /// A file with 3 video frames (I, P and B, with an edit list skipping the first frame's delay) and 2 AAC frames
+    fn movie() -> Vec<u8> {
+        let moov = |mdat_start: u32| {
+            let video = stbl(
+                avc_entry(),
+                SampleTable {
+                    stts: &[3, 3000],
+                    ctts: &[1, 3000, 1, 9000, 1, 3000],
+                    stss: &[1],
+                    stsc: &[1, 3, 1],
+                    stco: &[mdat_start],
+                    sizes: &[7, 6, 6],
+                },
+            );
+            let audio = stbl(
+                aac_entry(),
+                SampleTable {
+                    stts: &[2, 1024],
+                    ctts: &[],
+                    stss: &[],
+                    stsc: &[1, 2, 1],
+                    stco: &[mdat_start + 19],
+                    sizes: &[4, 4],
+                },
+            );
+
+            let mut data = mvhd();
+            data.extend(trak(
+                1,
+                true,
+                Some(3000),
+                mdhd_and_hdlr(90000, b"vide"),
+                video,
+            ));
+            data.extend(trak(2, false, None, mdhd_and_hdlr(44100, b"soun"), audio));
+            mp4_box(b"moov", &data)
+        };
+
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        let mdat_start = (file.len() + moov(0).len() + 8) as u32;
+        file.extend(moov(mdat_start));
+
+        let mut mdat = vec![
+            0, 0, 0, 3, 0x65, 0x88, 0x84, 0, 0, 0, 2, 0x41, 0x9a, 0, 0, 0, 2, 0x41, 0x9b,
+        ];
+        mdat.extend_from_slice(&[1, 1, 1, 1, 2, 2, 2, 2]);
+        file.extend(mp4_box(b"mdat", &mdat));
+        file
+    }
+
+    #[test]
+    fn track_formats() {
+        let demuxer = Mp4Demuxer::new(Cursor::new(movie())).unwrap();
+        assert_eq!(demuxer.track_count(), 2);
+
+        let video = PacketSource::track_format(&demuxer, 0).unwrap();
+        assert_eq!(video.mime, "video/avc");
+        assert_eq!((video.width, video.height), (1920, 1080));
+        assert_eq!(video.rotation, 90);
+        assert_eq!(video.timescale, 90000);
+        assert_eq!(video.duration_us, 100_000);
+        assert_eq!(video.language.as_deref(), Some("eng"));
+        assert_eq!(video.nal_length_size, Some(4));
+        assert!(video.color.unwrap().full_range);
+        assert!((video.frame_rate.unwrap() - 30.0).abs() < 0.01);
+
+        let audio = PacketSource::track_format(&demuxer, 1).unwrap();
+        assert_eq!(audio.mime, "audio/mp4a-latm");
+        assert_eq!((audio.sample_rate, audio.channel_count), (44100, 2));
+        assert_eq!(audio.codec_private, [0x12, 0x10]);
+        assert_eq!(audio.codec_string().as_deref(), Some("mp4a.40.2"));
+
+        assert_eq!(PacketSource::track_format(&demuxer, 2), None);
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let mut demuxer = Mp4Demuxer::new(Cursor::new(movie())).unwrap();
+        assert!(!demuxer.has_next());
+        assert_eq!(demuxer.track_index(), -1);
+
+        demuxer.select_track(0);
+        demuxer.select_track(1);
+
+        let mut samples = vec![];
+        let mut buffer = [0; 64];
+        while demuxer.has_next() {
+            let size = demuxer.read_sample(&mut buffer).unwrap();
+            samples.push((
+                demuxer.track_index(),
+                demuxer.sample_time(),
+                demuxer.sample_flags(),
+                buffer[..size].to_vec(),
+            ));
+            demuxer.advance();
+        }
+
+        // The samples are interleaved by time, and NAL unit lengths are replaced with start codes
+        let times: Vec<_> = samples.iter().map(|sample| (sample.0, sample.1)).collect();
+        assert_eq!(
+            times,
+            [(0, 0), (0, 100_000), (1, 0), (1, 23219), (0, 66666)]
+        );
+        assert_eq!(samples[0].2, SAMPLE_FLAG_SYNC);
+        assert_eq!(samples[0].3, [0, 0, 0, 1, 0x65, 0x88, 0x84]);
+        assert_eq!(samples[1].2, 0);
+        assert_eq!(samples[2].3, [1, 1, 1, 1]);
+
+        demuxer.seek_to(40_000, SeekMode::PreviousSync).unwrap();
+        assert_eq!((demuxer.track_index(), demuxer.sample_time()), (0, 0));
+
+        demuxer.seek_to(40_000, SeekMode::NextSync).unwrap();
+        assert_eq!(demuxer.track_index(), -1);
+
+        demuxer.unselect_track(0);
+        demuxer.seek_to(10_000, SeekMode::ClosestSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 0);
+        demuxer.seek_to(15_000, SeekMode::ClosestSync).unwrap();
+        assert_eq!(demuxer.sample_time(), 23219);
+    }
+
+    #[test]
+    fn fragmented() {
+        let mut stbl = u32s(&[1]);
+        stbl.extend(avc_entry());
+        let mut stbl = full_box(b"stsd", 0, &stbl);
+        stbl.extend(table(b"stts", 2, &[]));
+        stbl.extend(table(b"stsc", 3, &[]));
+        stbl.extend(full_box(b"stsz", 0, &u32s(&[0, 0])));
+        stbl.extend(table(b"stco", 1, &[]));
+
+        let mut moov = mvhd();
+        moov.extend(trak(
+            1,
+            false,
+            None,
+            mdhd_and_hdlr(90000, b"vide"),
+            mp4_box(b"stbl", &stbl),
+        ));
+        moov.extend(mp4_box(
+            b"mvex",
+            &full_box(b"trex", 0, &u32s(&[1, 1, 3000, 0, 0x10000])),
+        ));
+
+        let mut file = mp4_box(b"ftyp", b"iso6\0\0\0\0iso6");
+        file.extend(mp4_box(b"moov", &moov));
+
+        // Two fragments of a sync and a non-sync sample, with the default flags of trex
+        for (sequence_number, decode_time) in [(1, 0), (2, 6000)] {
+            let moof = |data_offset: u32| {
+                let mut traf = full_box(b"tfhd", TFHD_DEFAULT_BASE_IS_MOOF, &u32s(&[1]));
+                traf.extend(full_box(b"tfdt", 0, &u32s(&[decode_time])));
+                traf.extend(full_box(
+                    b"trun",
+                    0x205,
+                    &u32s(&[2, data_offset, 0x2000000, 7, 6]),
+                ));
+
+                let mut moof = full_box(b"mfhd", 0, &u32s(&[sequence_number]));
+                moof.extend(mp4_box(b"traf", &traf));
+                mp4_box(b"moof", &moof)
+            };
+
+            let size = moof(0).len() as u32;
+            file.extend(moof(size + 8));
+            file.extend(mp4_box(
+                b"mdat",
+                &[0, 0, 0, 3, 0x65, 0x88, 0x84, 0, 0, 0, 2, 0x41, 0x9a],
+            ));
+        }
+
+        let mut demuxer = Mp4Demuxer::new(Cursor::new(file)).unwrap();
+        assert!(demuxer.is_fragmented());
+        assert_eq!(demuxer.samples(0).unwrap().len(), 4);
+        assert_eq!(demuxer.track(0).unwrap().duration_us, 133_333);
+
+        demuxer.select_track(0);
+
+        let mut samples = vec![];
+        let mut buffer = [0; 16];
+        while demuxer.has_next() {
+            let size = demuxer.read_sample(&mut buffer).unwrap();
+            samples.push((demuxer.sample_time(), demuxer.sample_flags(), size));
+            demuxer.advance();
+        }
+
+        assert_eq!(
+            samples,
+            [(0, 1, 7), (33333, 0, 6), (66666, 1, 7), (100_000, 0, 6)]
+        );
+    }
+
+    #[test]
+    fn constant_sample_size_count() {
+        // 3 chunks of 2 samples, then 1 chunk of 1 sample
+        let chunks = [(1, 2), (4, 1)];
+        assert_eq!(chunk_sample_count(&chunks, 4), 7);
+        assert_eq!(chunk_sample_count(&chunks, 2), 4);
+        assert_eq!(
+            chunk_sample_count(&[(1, u32::MAX)], usize::MAX - 1),
+            usize::MAX
+        );
+
+        let stsz = |count: u32| u32s(&[0, 100, count]);
+        assert_eq!(parse_stsz(&stsz(7), 7), Some(vec![100; 7]));
+        // A huge count with a few bytes of box
+        assert_eq!(parse_stsz(&stsz(u32::MAX), 7), None);
+
+        // The sizes are in the box, so the count is bounded by its size
+        assert_eq!(parse_stsz(&u32s(&[0, 0, 2, 5, 6]), 0), Some(vec![5, 6]));
+        assert_eq!(parse_stsz(&u32s(&[0, 0, u32::MAX, 5]), usize::MAX), None);
+    }
+}
@@ -1,4 +1,12 @@
-use log::warn;
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom},
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+use log::{debug, warn};
 
 use crate::{AMediaFormat, BufferInfo, MediaFormat, MediaStatus};
 
@@ -10,11 +18,65 @@ pub struct AMediaMuxer {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
     Mpeg4 = 0,
     Webm = 1,
     ThreeGpp = 2,
+    /// Since: API 28
+    #[cfg(feature = "api28")]
+    Heif = 3,
+    /// Since: API 29
+    #[cfg(feature = "api29")]
+    Ogg = 4,
+}
+
+impl OutputFormat {
+    /// Returns whether a track with this mime type is known to be writable into this container.
+    ///
+    /// `AMediaMuxer` rejects unsupported tracks with an opaque error, so this can be used to check beforehand.
+    /// This is only a hint: newer Android releases accept more codecs than listed here, so a `false` doesn't mean
+    /// the muxer will refuse the track
+    pub fn supports_mime(&self, mime: &str) -> bool {
+        match self {
+            OutputFormat::Mpeg4 => {
+                #[cfg(feature = "api29")]
+                if matches!(mime, "video/x-vnd.on2.vp9" | "audio/opus") {
+                    return true;
+                }
+
+                matches!(
+                    mime,
+                    "video/avc"
+                        | "video/hevc"
+                        | "video/mp4v-es"
+                        | "video/3gpp"
+                        | "video/av01"
+                        | "video/dolby-vision"
+                        | "audio/mp4a-latm"
+                        | "audio/3gpp"
+                        | "audio/amr-wb"
+                ) || mime.starts_with("application/")
+            }
+            OutputFormat::ThreeGpp => matches!(
+                mime,
+                "video/avc"
+                    | "video/mp4v-es"
+                    | "video/3gpp"
+                    | "audio/mp4a-latm"
+                    | "audio/3gpp"
+                    | "audio/amr-wb"
+            ),
+            OutputFormat::Webm => matches!(
+                mime,
+                "video/x-vnd.on2.vp8" | "video/x-vnd.on2.vp9" | "audio/vorbis" | "audio/opus"
+            ),
+            #[cfg(feature = "api28")]
+            OutputFormat::Heif => matches!(mime, "image/vnd.android.heic" | "video/hevc"),
+            #[cfg(feature = "api29")]
+            OutputFormat::Ogg => mime == "audio/opus",
+        }
+    }
 }
 
 // FFI FUNCTIONS
@@ -63,6 +125,95 @@ enum MuxerState {
     Started,
 }
 
+/// What `MediaMuxer` should do with a sample whose presentation time is not after the previous sample of the same track.
+///
+/// `AMediaMuxer` aborts the whole process on some of these, so it's better to deal with them before they get that far.
+///
+/// Samples are written in decoding order, so the presentation times of a video track with B-frames go back and forth.
+/// Only [Passthrough](TimestampPolicy::Passthrough) handles such tracks, the other policies are meant for audio and
+/// video without frame reordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// Move the timestamp to 1us after the previous sample of the track (the default).
+    ///
+    /// Not safe for reordered video: every B-frame would get the wrong presentation time
+    #[default]
+    Fix,
+    /// Drop the sample silently.
+    ///
+    /// Not safe for reordered video: every B-frame would be dropped, and the frames referencing it would break
+    Drop,
+    /// Refuse the sample with `MediaStatus::ErrorInvalidParameter`
+    Error,
+    /// Write the sample as it is, unless its timestamp is negative: that is refused like with [Error](TimestampPolicy::Error).
+    ///
+    /// Needed for video tracks with B-frames, as their presentation times are not in decoding order
+    Passthrough,
+}
+
+/// A sample waiting in the interleaving queue
+#[derive(Debug)]
+struct QueuedSample {
+    data: Vec<u8>,
+    info: BufferInfo,
+}
+
+/// Writes samples from all tracks in timestamp order.
+///
+/// Every track has its own queue, so the order of samples within a track never changes.
+/// A sample is only held back as long as it's within `window_us` of the newest sample seen
+#[derive(Debug)]
+struct Interleaver {
+    window_us: i64,
+    queues: Vec<VecDeque<QueuedSample>>,
+    newest_us: i64,
+}
+
+impl Interleaver {
+    fn new(window_us: i64) -> Self {
+        Self {
+            window_us,
+            queues: vec![],
+            newest_us: i64::MIN,
+        }
+    }
+
+    fn push(&mut self, track_index: usize, data: &[u8], info: BufferInfo) {
+        if self.queues.len() <= track_index {
+            self.queues.resize_with(track_index + 1, VecDeque::new);
+        }
+
+        self.newest_us = self.newest_us.max(info.presentation_time_us());
+        self.queues[track_index].push_back(QueuedSample {
+            data: data.to_vec(),
+            info,
+        });
+    }
+
+    /// Pops the queued sample with the lowest timestamp.
+    ///
+    /// Unless `drain` is set, samples within the window are kept in the queue
+    fn pop(&mut self, drain: bool) -> Option<(usize, QueuedSample)> {
+        let (track_index, time) = self
+            .queues
+            .iter()
+            .enumerate()
+            .filter_map(|(index, queue)| {
+                queue
+                    .front()
+                    .map(|sample| (index, sample.info.presentation_time_us()))
+            })
+            .min_by_key(|(_, time)| *time)?;
+
+        if !drain && self.newest_us - time <= self.window_us {
+            return None;
+        }
+
+        let sample = self.queues[track_index].pop_front()?;
+        Some((track_index, sample))
+    }
+}
+
 /// The Type-Safe wrapper for `AMediaMuxer`.
 ///
 /// Ensures memory safety and frees resources when it's supposed to.
@@ -75,16 +226,86 @@ pub struct MediaMuxer {
     longitude: f32,
     orientation_hint: i32,
     track_formats: Vec<MediaFormat>,
+    last_timestamps: Vec<Option<i64>>,
+    timestamp_policy: TimestampPolicy,
+    interleaver: Option<Interleaver>,
     state: MuxerState,
+    output_format: OutputFormat,
+    /// The output file, if the muxer owns it
+    output: Option<File>,
+    /// Whether the output is an in-memory file
+    in_memory: bool,
 }
 
 impl MediaMuxer {
     /// Creates a new MediaMuxer instance
     ///
-    /// `fd` is the file descriptor to write data to
+    /// `fd` is the file descriptor to write data to. It is not closed by the muxer, so it must stay open until the muxer is dropped
     ///
     /// `output_format` is the container format for the output
     pub fn new(fd: i32, output_format: OutputFormat) -> Option<Self> {
+        Self::with_output(fd, output_format, None, false)
+    }
+
+    /// Creates a MediaMuxer writing to the file at `path`.
+    ///
+    /// The file is created if it does not exist, and truncated if it does
+    pub fn create(path: &str, output_format: OutputFormat) -> Result<Self, MediaStatus> {
+        // The muxer needs to seek around, and some containers are read back while writing
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|error| {
+                warn!("Could not create muxer output {path}: {error}");
+                MediaStatus::ErrorIO
+            })?;
+
+        Self::from_file(file, output_format)
+    }
+
+    /// Creates a MediaMuxer writing to `file`.
+    ///
+    /// The muxer takes ownership of the file, and closes it when dropped
+    pub fn from_file(file: File, output_format: OutputFormat) -> Result<Self, MediaStatus> {
+        Self::with_output(file.as_raw_fd(), output_format, Some(file), false)
+            .ok_or(MediaStatus::ErrorUnknown)
+    }
+
+    /// Creates a MediaMuxer writing to an anonymous in-memory file.
+    ///
+    /// Nothing touches the storage, use [stop_into_bytes](Self::stop_into_bytes) to get the finished container
+    pub fn in_memory(output_format: OutputFormat) -> Result<Self, MediaStatus> {
+        let name = CString::new("mediamuxer").unwrap();
+
+        // memfd_create only got a libc wrapper in API 30, so call it directly
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                name.as_ptr(),
+                libc::MFD_CLOEXEC as libc::c_uint,
+            )
+        };
+
+        if fd < 0 {
+            warn!("memfd_create failed: {}", std::io::Error::last_os_error());
+            return Err(MediaStatus::ErrorIO);
+        }
+
+        let file = unsafe { File::from_raw_fd(fd as i32) };
+
+        Self::with_output(file.as_raw_fd(), output_format, Some(file), true)
+            .ok_or(MediaStatus::ErrorUnknown)
+    }
+
+    fn with_output(
+        fd: i32,
+        output_format: OutputFormat,
+        output: Option<File>,
+        in_memory: bool,
+    ) -> Option<Self> {
         let value = unsafe { AMediaMuxer_new(fd, output_format) };
 
         if value.is_null() {
@@ -97,7 +318,13 @@ impl MediaMuxer {
             longitude: 0f32,
             orientation_hint: 0,
             track_formats: vec![],
+            last_timestamps: vec![],
+            timestamp_policy: TimestampPolicy::default(),
+            interleaver: None,
             state: MuxerState::Uninitialized,
+            output_format,
+            output,
+            in_memory,
         })
     }
 
@@ -142,16 +369,59 @@ impl MediaMuxer {
         self
     }
 
+    /// Sets what happens to samples that are not in chronological order within their track.
+    ///
+    /// The default is [TimestampPolicy::Fix](TimestampPolicy::Fix). Video with B-frames needs
+    /// [TimestampPolicy::Passthrough](TimestampPolicy::Passthrough), like when remuxing.
+    /// Negative timestamps are treated the same way as out of order ones
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) -> &mut Self {
+        self.timestamp_policy = policy;
+        self
+    }
+
+    /// Enables or disables the interleaving queue.
+    ///
+    /// When enabled, samples are queued and written out in timestamp order across all tracks, holding each sample back for at most `window_us` microseconds of media time.
+    /// This keeps the output file nicely interleaved, even when encoders deliver their output in bursts.
+    ///
+    /// Pass `None` to write samples out as soon as they arrive (the default). This can only be changed before the muxer is started
+    pub fn set_interleaving(&mut self, window_us: Option<i64>) -> &mut Self {
+        if let MuxerState::Started = self.state {
+            warn!("Can't change interleaving after MediaMuxer was started");
+            return self;
+        }
+
+        self.interleaver = window_us.map(|window| Interleaver::new(window.max(0)));
+        self
+    }
+
+    /// Returns the container format this muxer writes
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
     /// Adds a track with the specified format.
     ///
     /// Returns the index of the new track or a `MediaStatus` in case of failure.
+    /// A warning is logged for tracks the container is not known to hold, see [OutputFormat::supports_mime](OutputFormat::supports_mime).
+    /// They are still handed to `AMediaMuxer`, which has the final say
     pub fn add_track(&mut self, format: MediaFormat) -> Result<isize, MediaStatus> {
+        if let Some(mime) = format.get_string("mime") {
+            if !self.output_format.supports_mime(&mime) {
+                warn!(
+                    "A {mime} track might not be supported in {:?}",
+                    self.output_format
+                );
+            }
+        }
+
         let result = unsafe { AMediaMuxer_addTrack(self.inner, format.inner) };
 
         let result = MediaStatus::make_result(result)?;
 
         // Keep the format, the user might need it
         self.track_formats.push(format);
+        self.last_timestamps.push(None);
 
         Ok(result)
     }
@@ -200,11 +470,45 @@ impl MediaMuxer {
     /// Once the muxer stops, it cannot be restarted, and therefore this function takes ownership
     /// of the muxer instance
     pub fn stop(mut self) -> Result<(), MediaStatus> {
+        self.stop_muxer()
+    }
+
+    /// Stops a muxer created with [in_memory](Self::in_memory) and returns the finished container.
+    ///
+    /// Returns `MediaStatus::ErrorInvalidOperation` if the muxer is not writing to memory
+    pub fn stop_into_bytes(mut self) -> Result<Vec<u8>, MediaStatus> {
+        if !self.in_memory {
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        self.stop_muxer()?;
+
+        let file = self.output.as_mut().unwrap();
+
+        let mut bytes = vec![];
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.read_to_end(&mut bytes))
+            .map_err(|error| {
+                warn!("Could not read back the muxer output: {error}");
+                MediaStatus::ErrorIO
+            })?;
+
+        Ok(bytes)
+    }
+
+    fn stop_muxer(&mut self) -> Result<(), MediaStatus> {
         if let MuxerState::Uninitialized = self.state {
             // Don't return unnecessary errors. Let them do rubbish :)
             return Ok(());
         }
 
+        // Write out whatever is still waiting to be interleaved
+        if let Some(mut interleaver) = self.interleaver.take() {
+            while let Some((track_index, sample)) = interleaver.pop(true) {
+                Self::write_raw(self.inner, track_index, &sample.data, &sample.info)?;
+            }
+        }
+
         // In case our user is a crazy person, and managed to bypass the Rust system
         self.state = MuxerState::Uninitialized;
 
@@ -213,9 +517,10 @@ impl MediaMuxer {
 
     /// Writes an encoded sample into the muxer.
     ///
-    /// The application needs to make sure that the samples are written into the right tracks.
+    /// The track index must be one returned by `add_track`, and the samples for each track should be written in chronological order (e.g. in the order they are provided by the encoder).
+    /// Samples that are out of order are handled according to the [TimestampPolicy](TimestampPolicy) of the muxer.
     ///
-    /// Also, it needs to make sure the samples for each track are written in chronological order (e.g. in the order they are provided by the encoder)
+    /// If interleaving is enabled, the sample is copied and might be written at a later time
     pub fn write_sample_data(
         &mut self,
         track_index: usize,
@@ -225,7 +530,80 @@ impl MediaMuxer {
         if let MuxerState::Uninitialized = self.state {
             return Err(MediaStatus::ErrorInvalidOperation);
         }
-        unsafe { AMediaMuxer_writeSampleData(self.inner, track_index, data.as_ptr(), buffer_info) }
+
+        if track_index >= self.track_formats.len() {
+            warn!("Invalid track index {track_index} passed to MediaMuxer");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let offset = buffer_info.offset().max(0) as usize;
+        if offset + buffer_info.size().max(0) as usize > data.len() {
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let buffer_info = match self.check_timestamp(track_index, buffer_info)? {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+
+        if let Some(interleaver) = self.interleaver.as_mut() {
+            interleaver.push(track_index, data, buffer_info);
+
+            while let Some((track_index, sample)) = interleaver.pop(false) {
+                Self::write_raw(self.inner, track_index, &sample.data, &sample.info)?;
+            }
+
+            return Ok(());
+        }
+
+        Self::write_raw(self.inner, track_index, data, &buffer_info)
+    }
+
+    /// Validates the timestamp of a sample against the previous one of the same track.
+    ///
+    /// Returns the buffer info to write, or `None` if the sample should be dropped
+    fn check_timestamp(
+        &mut self,
+        track_index: usize,
+        buffer_info: &BufferInfo,
+    ) -> Result<Option<BufferInfo>, MediaStatus> {
+        let time = buffer_info.presentation_time_us();
+        let last = self.last_timestamps[track_index];
+
+        let in_order = time >= 0 && last.map(|last| time > last).unwrap_or(true);
+
+        let time = match self.timestamp_policy {
+            _ if in_order => time,
+            TimestampPolicy::Passthrough if time >= 0 => time,
+            TimestampPolicy::Fix => last.map(|last| last + 1).unwrap_or(0),
+            TimestampPolicy::Drop => {
+                debug!("Dropping sample with timestamp {time} on track {track_index}");
+                return Ok(None);
+            }
+            TimestampPolicy::Error | TimestampPolicy::Passthrough => {
+                warn!("Sample with timestamp {time} on track {track_index} is out of order (previous: {last:?})");
+                return Err(MediaStatus::ErrorInvalidParameter);
+            }
+        };
+
+        self.last_timestamps[track_index] = Some(last.map(|last| last.max(time)).unwrap_or(time));
+
+        Ok(Some(BufferInfo::new(
+            buffer_info.offset(),
+            buffer_info.size(),
+            time,
+            buffer_info.flags(),
+        )))
+    }
+
+    /// Hands a sample over to `AMediaMuxer`
+    fn write_raw(
+        inner: *mut AMediaMuxer,
+        track_index: usize,
+        data: &[u8],
+        buffer_info: &BufferInfo,
+    ) -> Result<(), MediaStatus> {
+        unsafe { AMediaMuxer_writeSampleData(inner, track_index, data.as_ptr(), buffer_info) }
             .result()?;
 
         Ok(())
@@ -238,6 +616,7 @@ unsafe impl Sync for MediaMuxer {}
 
 impl Drop for MediaMuxer {
     fn drop(&mut self) {
+        // The output file (if we own one) is closed after this, when the fields get dropped
         unsafe {
             AMediaMuxer_delete(self.inner);
         }
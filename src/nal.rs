@@ -0,0 +1,383 @@
+use crate::{AvcDecoderConfigurationRecord, HevcDecoderConfigurationRecord};
+
+/// The start code used when writing Annex-B data.
+///
+/// Elementary streams and things like RTSP use the Annex-B format, where every NAL unit is preceded by a start code (`00 00 01` or `00 00 00 01`).
+/// MP4 and Matroska use length-prefixed NAL units instead, with the size of the length described by the avcC or hvcC record
+pub const ANNEXB_START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Iterator over the NAL units of an Annex-B byte stream.
+///
+/// The NAL units are returned without their start codes. Anything before the first start code is skipped
+#[derive(Debug, Clone)]
+pub struct AnnexBNalUnits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Returns an iterator over the NAL units of an Annex-B byte stream
+pub fn annexb_nal_units(data: &[u8]) -> AnnexBNalUnits<'_> {
+    let position = find_start_code(data, 0)
+        .map(|(_, end)| end)
+        .unwrap_or(data.len());
+
+    AnnexBNalUnits { data, position }
+}
+
+impl<'a> Iterator for AnnexBNalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position < self.data.len() {
+            let start = self.position;
+            let (end, next) =
+                find_start_code(self.data, start).unwrap_or((self.data.len(), self.data.len()));
+            self.position = next;
+
+            // Trailing zeros belong to the next start code (or are just padding)
+            let mut nal = &self.data[start..end];
+            while let Some((0, rest)) = nal.split_last() {
+                nal = rest;
+            }
+
+            if !nal.is_empty() {
+                return Some(nal);
+            }
+        }
+
+        None
+    }
+}
+
+/// Finds the next `00 00 01` start code at or after `from`.
+///
+/// Returns the position of the start code and the position right after it
+pub(crate) fn find_start_code(data: &[u8], from: usize) -> Option<(usize, usize)> {
+    if data.len() < 3 {
+        return None;
+    }
+
+    let mut index = from;
+    while index + 2 < data.len() {
+        if data[index + 2] > 1 {
+            // Can't be part of a start code that ends before index + 3
+            index += 3;
+        } else if data[index] == 0 && data[index + 1] == 0 && data[index + 2] == 1 {
+            return Some((index, index + 3));
+        } else {
+            index += 1;
+        }
+    }
+
+    None
+}
+
+/// Iterator over length-prefixed NAL units, like the samples of an MP4 file.
+///
+/// Stops at the first NAL unit whose length runs past the end of the data
+#[derive(Debug, Clone)]
+pub struct LengthPrefixedNalUnits<'a> {
+    data: &'a [u8],
+    length_size: usize,
+    position: usize,
+}
+
+/// Returns an iterator over length-prefixed NAL units. `length_size` is 1, 2 or 4 bytes, the iterator is empty for any other size
+pub fn length_prefixed_nal_units(data: &[u8], length_size: usize) -> LengthPrefixedNalUnits<'_> {
+    LengthPrefixedNalUnits {
+        data,
+        length_size,
+        position: 0,
+    }
+}
+
+impl<'a> LengthPrefixedNalUnits<'a> {
+    /// Returns whether all the data was consumed without errors
+    pub fn is_complete(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
+
+impl<'a> Iterator for LengthPrefixedNalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !matches!(self.length_size, 1 | 2 | 4) {
+            return None;
+        }
+
+        let header_end = self.position + self.length_size;
+        let header = self.data.get(self.position..header_end)?;
+        let length = header
+            .iter()
+            .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+
+        let nal = self.data.get(header_end..header_end + length)?;
+        self.position = header_end + length;
+
+        Some(nal)
+    }
+}
+
+/// Converts an Annex-B byte stream into length-prefixed NAL units (the avcC / hvcC sample format)
+pub fn annexb_to_length_prefixed(data: &[u8], length_size: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + 16);
+    for nal in annexb_nal_units(data) {
+        write_length(&mut output, nal.len(), length_size);
+        output.extend_from_slice(nal);
+    }
+
+    output
+}
+
+/// Converts length-prefixed NAL units (the avcC / hvcC sample format) into an Annex-B byte stream.
+///
+/// Returns `None` if the data is truncated
+pub fn length_prefixed_to_annexb(data: &[u8], length_size: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len() + 16);
+    let mut units = length_prefixed_nal_units(data, length_size);
+
+    for nal in &mut units {
+        output.extend_from_slice(&ANNEXB_START_CODE);
+        output.extend_from_slice(nal);
+    }
+
+    if !units.is_complete() {
+        return None;
+    }
+
+    Some(output)
+}
+
+/// How the NAL units of H.264 and HEVC samples are delimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NalFormat {
+    /// Start codes, like MediaCodec and MediaExtractor output, elementary streams and the demuxers of this crate
+    #[default]
+    AnnexB,
+    /// Big endian lengths of the given size (1, 2 or 4 bytes), like the samples of MP4 and Matroska files
+    LengthPrefixed(usize),
+}
+
+impl NalFormat {
+    /// Returns the format of the samples that go with a codec config buffer.
+    ///
+    /// An avcC or hvcC record gives the length size of its samples, anything else (like the Annex-B `csd-0` of MediaCodec) means Annex-B
+    pub fn from_codec_config(mime: &str, data: &[u8]) -> Self {
+        let length_size = match mime {
+            "video/avc" => {
+                AvcDecoderConfigurationRecord::parse(data).map(|record| record.length_size)
+            }
+            "video/hevc" => {
+                HevcDecoderConfigurationRecord::parse(data).map(|record| record.length_size)
+            }
+            _ => None,
+        };
+
+        match length_size {
+            Some(length_size) => Self::LengthPrefixed(length_size as usize),
+            None => Self::AnnexB,
+        }
+    }
+
+    /// Returns the non-empty NAL units of a sample in this format
+    pub fn nal_units<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let units: Vec<&[u8]> = match *self {
+            Self::AnnexB => annexb_nal_units(data).collect(),
+            Self::LengthPrefixed(length_size) => {
+                length_prefixed_nal_units(data, length_size).collect()
+            }
+        };
+
+        units.into_iter().filter(|nal| !nal.is_empty()).collect()
+    }
+}
+
+/// Writes `length` as a big endian integer of `length_size` bytes
+pub(crate) fn write_length(output: &mut Vec<u8>, length: usize, length_size: usize) {
+    for index in (0..length_size).rev() {
+        output.push((length >> (index * 8)) as u8);
+    }
+}
+
+/// Removes the emulation prevention bytes (the `03` in `00 00 03`) from a NAL unit, so it can be parsed
+pub fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        output.push(byte);
+    }
+
+    output
+}
+
+/// Inserts emulation prevention bytes into a raw byte sequence payload, so it can be stored in a NAL unit
+pub fn escape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 64);
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte <= 3 {
+            output.push(3);
+            zeros = 0;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        output.push(byte);
+    }
+
+    output
+}
+
+/// The `user_data_registered_itu_t_t35` SEI payload type, which carries closed captions and HDR metadata
+pub const SEI_USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+
+/// A message of an H.264 or HEVC SEI NAL unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeiMessage<'a> {
+    pub payload_type: u32,
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the messages of an SEI payload.
+///
+/// Stops at the first message whose size runs past the end of the data
+#[derive(Debug, Clone)]
+pub struct SeiMessages<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Returns an iterator over the messages of an SEI, from its RBSP (after the NAL header, and without emulation prevention bytes)
+pub fn sei_messages(rbsp: &[u8]) -> SeiMessages<'_> {
+    SeiMessages {
+        data: rbsp,
+        position: 0,
+    }
+}
+
+impl<'a> SeiMessages<'a> {
+    /// Reads a value coded as a run of `ff` bytes followed by a last byte
+    fn read_value(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+
+        loop {
+            let byte = *self.data.get(self.position)?;
+            self.position += 1;
+            value = value.checked_add(byte as u32)?;
+
+            if byte != 0xff {
+                return Some(value);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SeiMessages<'a> {
+    type Item = SeiMessage<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Only the rbsp_trailing_bits are left
+        let rest = self.data.get(self.position..)?;
+        if rest.is_empty() || rest == [0x80] {
+            return None;
+        }
+
+        let payload_type = self.read_value()?;
+        let size = self.read_value()? as usize;
+        let payload = self.data.get(self.position..self.position + size)?;
+        self.position += size;
+
+        Some(SeiMessage {
+            payload_type,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annexb_units() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 0, 1, 0x65, 4,
+        ];
+        let units: Vec<_> = annexb_nal_units(&data).collect();
+
+        assert_eq!(units, [&[0x67, 1, 2][..], &[0x68, 3], &[0x65, 4]]);
+    }
+
+    #[test]
+    fn length_prefixed_round_trip() {
+        let annexb = [0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3];
+
+        for length_size in [1, 2, 4] {
+            let prefixed = annexb_to_length_prefixed(&annexb, length_size);
+            assert_eq!(prefixed.len(), 5 + 2 * length_size);
+
+            let units: Vec<_> = length_prefixed_nal_units(&prefixed, length_size).collect();
+            assert_eq!(units, [&[0x67, 1, 2][..], &[0x68, 3]]);
+
+            let back = length_prefixed_to_annexb(&prefixed, length_size).unwrap();
+            assert_eq!(back, [0, 0, 0, 1, 0x67, 1, 2, 0, 0, 0, 1, 0x68, 3]);
+        }
+    }
+
+    #[test]
+    fn nal_formats() {
+        // The length of a 256 byte NAL unit looks like a start code
+        let mut prefixed = vec![0, 0, 1, 0, 0x65];
+        prefixed.resize(260, 0x80);
+
+        let units = NalFormat::LengthPrefixed(4).nal_units(&prefixed);
+        assert_eq!(units, [&prefixed[4..]]);
+
+        let annexb = length_prefixed_to_annexb(&prefixed, 4).unwrap();
+        assert_eq!(NalFormat::AnnexB.nal_units(&annexb), [&prefixed[4..]]);
+    }
+
+    #[test]
+    fn truncated_length_prefixed() {
+        assert_eq!(length_prefixed_to_annexb(&[0, 0, 0, 5, 0x65, 1], 4), None);
+    }
+
+    #[test]
+    fn invalid_length_size() {
+        let data = [0, 0, 0, 2, 0x65, 1];
+
+        for length_size in [0, 3, 5] {
+            assert_eq!(length_prefixed_nal_units(&data, length_size).count(), 0);
+            assert_eq!(length_prefixed_to_annexb(&data, length_size), None);
+        }
+    }
+
+    #[test]
+    fn rbsp_escaping() {
+        assert_eq!(escape_rbsp(&[0, 0, 1, 0, 0, 0]), [0, 0, 3, 1, 0, 0, 3, 0]);
+        assert_eq!(unescape_rbsp(&[0, 0, 3, 1, 0, 0, 3, 0]), [0, 0, 1, 0, 0, 0]);
+
+        let data = [0, 0, 0, 0, 3, 0, 0, 2, 0xff];
+        assert_eq!(unescape_rbsp(&escape_rbsp(&data)), data);
+    }
+
+    #[test]
+    fn sei_message_list() {
+        let rbsp = [4, 3, 0xb5, 0, 0x31, 5, 1, 0xaa, 0x80];
+        let messages: Vec<_> = sei_messages(&rbsp).collect();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload_type, SEI_USER_DATA_REGISTERED_ITU_T_T35);
+        assert_eq!(messages[0].payload, [0xb5, 0, 0x31]);
+        assert_eq!(messages[1].payload_type, 5);
+        assert_eq!(messages[1].payload, [0xaa]);
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt;
+
+use log::{debug, warn};
+
+use crate::{
+    BufferInfo, MediaExtractor, MediaMuxer, MediaStatus, SeekMode, TimestampPolicy,
+    SAMPLE_FLAG_SYNC,
+};
+
+/// The buffer size used when a track doesn't tell us its largest sample size
+const DEFAULT_SAMPLE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The largest sample we're willing to allocate a buffer for
+const MAX_SAMPLE_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// How many samples a video frame can come before the frames displayed ahead of it, in decoding order.
+/// This is the largest decoded picture buffer allowed by H.264 and HEVC
+const MAX_REORDER_DEPTH: usize = 16;
+
+/// Options for [remux](remux)
+///
+/// By default, all tracks are copied as they are, and timestamps are shifted to start at zero
+pub struct RemuxOptions<'a> {
+    start_us: Option<i64>,
+    end_us: Option<i64>,
+    tracks: Option<Vec<usize>>,
+    drop_audio: bool,
+    drop_video: bool,
+    shift_timestamps: bool,
+    orientation_hint: Option<i32>,
+    location: Option<(f32, f32)>,
+    progress: Option<Box<dyn FnMut(f32) + 'a>>,
+}
+
+impl<'a> RemuxOptions<'a> {
+    /// Creates the default options
+    pub fn new() -> Self {
+        Self {
+            start_us: None,
+            end_us: None,
+            tracks: None,
+            drop_audio: false,
+            drop_video: false,
+            shift_timestamps: true,
+            orientation_hint: None,
+            location: None,
+            progress: None,
+        }
+    }
+
+    /// Only copy the media between `start_us` and `end_us`.
+    ///
+    /// The start is moved back to the sync sample at or before `start_us`, so the output starts with a keyframe.
+    /// Video frames displayed after `end_us` are still copied when a frame displayed before it depends on them.
+    /// `None` means the start or the end of the source respectively
+    pub fn trim(mut self, start_us: Option<i64>, end_us: Option<i64>) -> Self {
+        self.start_us = start_us;
+        self.end_us = end_us;
+        self
+    }
+
+    /// Only copy the tracks with these indices in the source
+    pub fn tracks(mut self, tracks: &[usize]) -> Self {
+        self.tracks = Some(tracks.to_vec());
+        self
+    }
+
+    /// Don't copy any audio tracks
+    pub fn drop_audio(mut self) -> Self {
+        self.drop_audio = true;
+        self
+    }
+
+    /// Don't copy any video tracks
+    pub fn drop_video(mut self) -> Self {
+        self.drop_video = true;
+        self
+    }
+
+    /// Whether the output timestamps should start at zero. Enabled by default
+    pub fn shift_timestamps(mut self, shift: bool) -> Self {
+        self.shift_timestamps = shift;
+        self
+    }
+
+    /// Overrides the orientation hint of the output. By default, the rotation of the source video track is kept
+    pub fn orientation_hint(mut self, degrees: i32) -> Self {
+        self.orientation_hint = Some(degrees);
+        self
+    }
+
+    /// Overrides the location stored in the output. By default, the location of the source is kept where it's available (API 28)
+    pub fn location(mut self, latitude: f32, longitude: f32) -> Self {
+        self.location = Some((latitude, longitude));
+        self
+    }
+
+    /// Sets a callback that receives the progress of the operation, from 0.0 to 1.0
+    pub fn progress(mut self, callback: impl FnMut(f32) + 'a) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    fn report_progress(&mut self, progress: f32) {
+        if let Some(callback) = self.progress.as_mut() {
+            callback(progress.clamp(0.0, 1.0));
+        }
+    }
+}
+
+impl Default for RemuxOptions<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RemuxOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemuxOptions")
+            .field("start_us", &self.start_us)
+            .field("end_us", &self.end_us)
+            .field("tracks", &self.tracks)
+            .field("drop_audio", &self.drop_audio)
+            .field("drop_video", &self.drop_video)
+            .field("shift_timestamps", &self.shift_timestamps)
+            .field("orientation_hint", &self.orientation_hint)
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+/// A source track that gets copied
+#[derive(Debug)]
+struct RemuxTrack {
+    muxer_index: usize,
+    is_video: bool,
+    /// Video tracks are only copied from their first sync sample
+    seen_sync: bool,
+    /// Video samples displayed after the end of the trim range, held back in case a frame displayed before the end
+    /// comes after them in decoding order and references them
+    pending: Vec<PendingSample>,
+    /// The decoding time of the track went past the end of the trim range
+    done: bool,
+}
+
+/// A sample read from the source, but not written yet
+#[derive(Debug)]
+struct PendingSample {
+    data: Vec<u8>,
+    time_us: i64,
+    flags: u32,
+}
+
+/// Copies tracks from `source` into `dest` without re-encoding them.
+///
+/// `dest` must be a fresh muxer, with no tracks added yet. It is started by this function, but not stopped,
+/// so call `stop` (or `stop_into_bytes`) on it afterwards.
+///
+/// `source` should have no tracks selected, the tracks to copy are selected here
+pub fn remux(
+    source: &mut MediaExtractor,
+    dest: &mut MediaMuxer,
+    mut options: RemuxOptions,
+) -> Result<(), MediaStatus> {
+    if dest.track_count() != 0 {
+        return Err(MediaStatus::ErrorInvalidOperation);
+    }
+
+    let mut tracks: Vec<Option<RemuxTrack>> = (0..source.track_count()).map(|_| None).collect();
+    let mut buffer_size = 0;
+    let mut duration_us = 0;
+    let mut rotation = None;
+
+    for (index, slot) in tracks.iter_mut().enumerate() {
+        if let Some(keep) = &options.tracks {
+            if !keep.contains(&index) {
+                continue;
+            }
+        }
+
+        let format = source
+            .track_format(index)
+            .ok_or(MediaStatus::ErrorMalformed)?;
+
+        let is_video = format.is_video();
+        if (is_video && options.drop_video) || (format.is_audio() && options.drop_audio) {
+            continue;
+        }
+
+        buffer_size = buffer_size.max(format.get_i32("max-input-size").unwrap_or(0) as usize);
+        duration_us = duration_us.max(format.get_i64("durationUs").unwrap_or(0));
+
+        if is_video && rotation.is_none() {
+            rotation = format.get_i32("rotation-degrees");
+        }
+
+        let muxer_index = dest.add_track(format)? as usize;
+        source.select_track(index);
+
+        *slot = Some(RemuxTrack {
+            muxer_index,
+            is_video,
+            seen_sync: !is_video,
+            pending: vec![],
+            done: false,
+        });
+    }
+
+    if dest.track_count() == 0 {
+        warn!("No tracks left to remux");
+        return Err(MediaStatus::ErrorInvalidOperation);
+    }
+
+    // Video with B-frames comes in decoding order, so its presentation times aren't increasing
+    dest.set_timestamp_policy(TimestampPolicy::Passthrough);
+
+    if let Some(degrees) = options.orientation_hint.or(rotation) {
+        dest.set_orientation_hint(degrees);
+    }
+
+    if let Some((latitude, longitude)) = options.location.or_else(|| source_location(source)) {
+        dest.set_location(latitude, longitude);
+    }
+
+    let cut_us = match options.start_us {
+        Some(start_us) => seek_to_sync(source, &tracks, start_us)?,
+        None => 0,
+    };
+
+    let end_us = options.end_us.unwrap_or(i64::MAX);
+    let range_us = if end_us != i64::MAX {
+        end_us - cut_us
+    } else {
+        duration_us - cut_us
+    };
+
+    // Without a trim, the output starts at the first sample we see
+    let mut offset_us = options.start_us.map(|_| cut_us);
+
+    dest.start()?;
+
+    let buffer_size = if buffer_size > 0 {
+        buffer_size
+    } else {
+        DEFAULT_SAMPLE_BUFFER_SIZE
+    };
+    let mut buffer = vec![0u8; buffer_size];
+
+    options.report_progress(0.0);
+
+    while source.has_next() {
+        let index = source.track_index();
+        let time_us = source.sample_time();
+        let flags = source.sample_flags();
+
+        let track = match usize::try_from(index)
+            .ok()
+            .and_then(|index| tracks.get_mut(index))
+            .and_then(|track| track.as_mut())
+        {
+            Some(track) => track,
+            None => {
+                source.advance();
+                continue;
+            }
+        };
+
+        if track.done || time_us < cut_us {
+            source.advance();
+            continue;
+        }
+
+        if !track.seen_sync {
+            if flags & SAMPLE_FLAG_SYNC == 0 {
+                source.advance();
+                continue;
+            }
+
+            track.seen_sync = true;
+        }
+
+        // Frames displayed before the end can come after frames displayed past it, as long as they are still being decoded.
+        // Nothing after a sync sample depends on what came before it, and no frame comes later than the reorder depth
+        let past_end = time_us >= end_us;
+        if past_end
+            && (!track.is_video
+                || flags & SAMPLE_FLAG_SYNC != 0
+                || track.pending.len() >= MAX_REORDER_DEPTH)
+        {
+            track.done = true;
+            track.pending.clear();
+
+            if tracks.iter().flatten().all(|track| track.done) {
+                break;
+            }
+
+            source.advance();
+            continue;
+        }
+
+        let size = loop {
+            if let Some(size) = source.read_sample(&mut buffer) {
+                break size;
+            }
+
+            // The track lied about its largest sample, try again with a bigger buffer
+            if buffer.len() >= MAX_SAMPLE_BUFFER_SIZE {
+                warn!("Could not read the sample at {time_us} on track {index}");
+                return Err(MediaStatus::ErrorIO);
+            }
+
+            buffer.resize(buffer.len() * 2, 0);
+        };
+
+        if past_end {
+            track.pending.push(PendingSample {
+                data: buffer[..size].to_vec(),
+                time_us,
+                flags,
+            });
+
+            source.advance();
+            continue;
+        }
+
+        // The held back samples come first in decoding order, and this one might reference them
+        for sample in track.pending.drain(..) {
+            write_sample(
+                dest,
+                track.muxer_index,
+                &sample.data,
+                sample.time_us,
+                sample.flags,
+                &mut offset_us,
+                options.shift_timestamps,
+            )?;
+        }
+
+        write_sample(
+            dest,
+            track.muxer_index,
+            &buffer[..size],
+            time_us,
+            flags,
+            &mut offset_us,
+            options.shift_timestamps,
+        )?;
+
+        if range_us > 0 {
+            let written_us = source.sample_time() - cut_us;
+            options.report_progress(written_us as f32 / range_us as f32);
+        }
+
+        source.advance();
+    }
+
+    options.report_progress(1.0);
+
+    Ok(())
+}
+
+/// Writes a sample into `dest`, moving its timestamp by the start of the output if needed
+fn write_sample(
+    dest: &mut MediaMuxer,
+    muxer_index: usize,
+    data: &[u8],
+    time_us: i64,
+    flags: u32,
+    offset_us: &mut Option<i64>,
+    shift_timestamps: bool,
+) -> Result<(), MediaStatus> {
+    let offset = *offset_us.get_or_insert(time_us);
+    let time_us = if shift_timestamps {
+        time_us - offset
+    } else {
+        time_us
+    };
+
+    // Samples from before the start of the output can't be played anyway
+    if time_us < 0 {
+        debug!("Dropping sample at {time_us} on track {muxer_index}");
+        return Ok(());
+    }
+
+    let info = BufferInfo::new(0, data.len() as i32, time_us, flags & SAMPLE_FLAG_SYNC);
+    dest.write_sample_data(muxer_index, data, &info)
+}
+
+/// Seeks `source` to the sync sample at or before `start_us`, and returns its time.
+///
+/// Video tracks decide where the output starts, as they can only start on a sync sample. Every other track is cut at the same point
+fn seek_to_sync(
+    source: &mut MediaExtractor,
+    tracks: &[Option<RemuxTrack>],
+    start_us: i64,
+) -> Result<i64, MediaStatus> {
+    let has_video = tracks.iter().flatten().any(|track| track.is_video);
+
+    if !has_video {
+        source.seek_to(start_us, SeekMode::PreviousSync)?;
+        return Ok(start_us);
+    }
+
+    // Find the sync sample with only the video tracks selected
+    for (index, track) in tracks.iter().enumerate() {
+        if let Some(track) = track {
+            if !track.is_video {
+                source.unselect_track(index);
+            }
+        }
+    }
+
+    source.seek_to(start_us, SeekMode::PreviousSync)?;
+    let cut_us = source.sample_time().max(0);
+
+    for (index, track) in tracks.iter().enumerate() {
+        if let Some(track) = track {
+            if !track.is_video {
+                source.select_track(index);
+            }
+        }
+    }
+
+    source.seek_to(cut_us, SeekMode::PreviousSync)?;
+
+    debug!("Trimming from {cut_us}us (requested {start_us}us)");
+
+    Ok(cut_us)
+}
+
+/// Returns the location the source was recorded at, if the container has one
+#[cfg(feature = "api28")]
+fn source_location(source: &MediaExtractor) -> Option<(f32, f32)> {
+    let location = source.file_format()?.get_string("location")?;
+    parse_iso6709(&location)
+}
+
+#[cfg(not(feature = "api28"))]
+fn source_location(_source: &MediaExtractor) -> Option<(f32, f32)> {
+    None
+}
+
+/// Parses the latitude and longitude out of an ISO-6709 string, like `+37.4220-122.0841/`
+#[cfg_attr(not(feature = "api28"), allow(dead_code))]
+fn parse_iso6709(value: &str) -> Option<(f32, f32)> {
+    let value = value.trim_end_matches('/');
+    let mut parts = vec![];
+    let mut start = 0;
+
+    for (index, c) in value.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            parts.push(&value[start..index]);
+            start = index;
+        }
+    }
+    parts.push(&value[start..]);
+
+    if parts.len() < 2 {
+        return None;
+    }
+
+    let latitude = parts[0].parse().ok()?;
+    let longitude = parts[1].parse().ok()?;
+
+    Some((latitude, longitude))
+}
@@ -0,0 +1,882 @@
+use log::warn;
+
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{
+    AudioBuffer, AudioFrame, AudioSamples, ChannelLayout, ChannelPosition, MediaStatus, SampleType,
+};
+
+/// The most phases of the filter bank. Ratios needing more use the nearest phase below
+const MAX_PHASES: usize = 1024;
+
+/// The trade-off between the quality and the cost of resampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    /// 16 taps, good enough for voice
+    Fast,
+    /// 32 taps
+    #[default]
+    Medium,
+    /// 64 taps, for music that is going to be listened to carefully
+    High,
+}
+
+impl ResampleQuality {
+    /// The number of taps on each side of the filter, the Kaiser window beta and the part of the band kept
+    fn parameters(&self) -> (usize, f64, f64) {
+        match self {
+            Self::Fast => (8, 6.0, 0.90),
+            Self::Medium => (16, 8.0, 0.94),
+            Self::High => (32, 10.0, 0.96),
+        }
+    }
+}
+
+/// Converts the sample rate of a stream of audio buffers with a polyphase windowed sinc filter.
+///
+/// Buffers can be of any size, the filter state is kept between them. The timestamps of the output follow
+/// the ones of the input, and [flush](Self::flush) returns the samples held back at the end of the stream
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    channels: u32,
+    /// The input advances by `step` for `phase_count` output samples
+    phase_count: u64,
+    step: u64,
+    half_taps: usize,
+    /// `phases` rows of `2 * half_taps` coefficients
+    filter: Vec<f32>,
+    phases: usize,
+    /// The pending input of each channel
+    history: Vec<Vec<f32>>,
+    /// The position of the next output sample in the history, in `1 / phase_count` input samples
+    position: u64,
+    /// The presentation time of the first sample of the history
+    history_pts_us: f64,
+    /// The layout of the last input
+    layout: Option<ChannelLayout>,
+    input_count: u64,
+    output_count: u64,
+}
+
+impl Resampler {
+    pub fn new(
+        input_rate: u32,
+        output_rate: u32,
+        channels: u32,
+        quality: ResampleQuality,
+    ) -> Result<Self, MediaStatus> {
+        if input_rate == 0 || output_rate == 0 || channels == 0 {
+            warn!("Can't resample {channels} channels from {input_rate} Hz to {output_rate} Hz");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let divisor = gcd(input_rate as u64, output_rate as u64);
+        let phase_count = output_rate as u64 / divisor;
+        let step = input_rate as u64 / divisor;
+
+        // When downsampling, the filter gets wider to cut everything above the new Nyquist frequency
+        let (taps, beta, rolloff) = quality.parameters();
+        let ratio = (output_rate as f64 / input_rate as f64).min(1.0);
+        let half_taps = (taps as f64 / ratio).ceil() as usize;
+        let cutoff = 0.5 * ratio * rolloff;
+        let phases = (phase_count as usize).min(MAX_PHASES);
+
+        let mut filter = Vec::with_capacity(phases * 2 * half_taps);
+        for phase in 0..phases {
+            let fraction = phase as f64 / phases as f64;
+            let start = filter.len();
+
+            for tap in 0..2 * half_taps {
+                // The distance between the input sample and the output sample
+                let t = tap as f64 + 1.0 - half_taps as f64 - fraction;
+                let window = (1.0 - (t / half_taps as f64).powi(2)).max(0.0).sqrt();
+                filter.push(sinc(2.0 * cutoff * t) * bessel_i0(beta * window));
+            }
+
+            // Each phase has a gain of 1, so silence and constants stay the same
+            let sum: f64 = filter[start..].iter().sum();
+            filter[start..]
+                .iter_mut()
+                .for_each(|coefficient| *coefficient /= sum);
+        }
+
+        let mut me = Self {
+            input_rate,
+            output_rate,
+            channels,
+            phase_count,
+            step,
+            half_taps,
+            filter: filter.into_iter().map(|value| value as f32).collect(),
+            phases,
+            history: vec![Vec::new(); channels as usize],
+            position: 0,
+            history_pts_us: 0.0,
+            layout: None,
+            input_count: 0,
+            output_count: 0,
+        };
+        me.reset();
+
+        Ok(me)
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// Forgets the pending samples, to start a new stream (after a seek for example)
+    pub fn reset(&mut self) {
+        // The first output sample is centered on the first input sample, the filter sees silence before it
+        let leading = self.half_taps - 1;
+        for history in &mut self.history {
+            history.clear();
+            history.resize(leading, 0.0);
+        }
+
+        self.position = leading as u64 * self.phase_count;
+        self.input_count = 0;
+        self.output_count = 0;
+    }
+
+    /// Resamples a buffer. The output has float samples, and as many as the input allows so far
+    pub fn process(&mut self, buffer: &AudioBuffer) -> Result<AudioBuffer, MediaStatus> {
+        if buffer.sample_rate() != self.input_rate || buffer.channels() != self.channels {
+            warn!(
+                "The resampler expects {} channels at {} Hz, got {} channels at {} Hz",
+                self.channels,
+                self.input_rate,
+                buffer.channels(),
+                buffer.sample_rate()
+            );
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        // The history ends where the buffer starts
+        let pending = self.history[0].len() as f64;
+        self.history_pts_us = buffer.pts_us() as f64 - pending * 1e6 / self.input_rate as f64;
+
+        let channels = self.channels as usize;
+        for (index, sample) in buffer.samples().to_f32().into_iter().enumerate() {
+            self.history[index % channels].push(sample);
+        }
+        self.input_count += buffer.nb_samples() as u64;
+        self.layout = buffer.layout();
+
+        self.resample(u64::MAX)
+            .ok_or(MediaStatus::ErrorInvalidParameter)
+    }
+
+    /// Resamples an [AudioFrame] starting at `pts_us`
+    pub fn process_frame(
+        &mut self,
+        frame: &AudioFrame,
+        pts_us: i64,
+    ) -> Result<AudioBuffer, MediaStatus> {
+        let Some(buffer) = frame.to_owned(self.input_rate) else {
+            warn!("The audio frame isn't a whole number of samples per channel");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        self.process(&buffer.with_pts_us(pts_us))
+    }
+
+    /// Returns the samples held back by the filter at the end of a stream, and resets the resampler
+    pub fn flush(&mut self) -> Option<AudioBuffer> {
+        let expected = (self.input_count * self.phase_count).div_ceil(self.step);
+        let missing = expected.saturating_sub(self.output_count);
+
+        for history in &mut self.history {
+            history.resize(history.len() + 2 * self.half_taps, 0.0);
+        }
+
+        let output = self.resample(missing);
+        self.reset();
+
+        output.filter(|output| output.nb_samples() > 0)
+    }
+
+    /// Produces up to `limit` output samples from the history
+    fn resample(&mut self, limit: u64) -> Option<AudioBuffer> {
+        let taps = 2 * self.half_taps;
+        let channels = self.channels as usize;
+        let available = self.history[0].len();
+        let pts_us = self.history_pts_us
+            + self.position as f64 / self.phase_count as f64 * 1e6 / self.input_rate as f64;
+
+        let mut output = Vec::new();
+        let mut produced = 0;
+        loop {
+            let index = (self.position / self.phase_count) as usize;
+            let start = index + 1 - self.half_taps;
+            if start + taps > available || produced >= limit {
+                break;
+            }
+
+            let phase =
+                (self.position % self.phase_count * self.phases as u64 / self.phase_count) as usize;
+            let coefficients = &self.filter[phase * taps..(phase + 1) * taps];
+
+            for history in &self.history {
+                let samples = &history[start..start + taps];
+                output.push(
+                    samples
+                        .iter()
+                        .zip(coefficients)
+                        .map(|(sample, coefficient)| sample * coefficient)
+                        .sum::<f32>(),
+                );
+            }
+
+            self.position += self.step;
+            produced += 1;
+        }
+        self.output_count += produced;
+
+        // Drop the samples the next outputs don't need
+        let needed = (self.position / self.phase_count) as usize + 1 - self.half_taps;
+        let consumed = needed.min(available);
+        for history in &mut self.history {
+            history.drain(..consumed);
+        }
+        self.position -= consumed as u64 * self.phase_count;
+        self.history_pts_us += consumed as f64 * 1e6 / self.input_rate as f64;
+
+        let buffer =
+            AudioBuffer::new(AudioSamples::F32(output), channels as u32, self.output_rate)?
+                .with_pts_us(pts_us.round() as i64);
+
+        Some(match self.layout {
+            Some(layout) => buffer.with_layout(layout),
+            None => buffer,
+        })
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = std::f64::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+/// The modified Bessel function of the first kind, for the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+
+    sum
+}
+
+/// Converts audio between channel layouts, with the usual downmix coefficients.
+///
+/// Channels missing from the output are folded into the nearest ones (center into left and right at -3 dB,
+/// surrounds into the sides or fronts...), the LFE channel is dropped. Mono is copied to both fronts when upmixing.
+/// The coefficients are scaled down so a full scale input can't clip
+#[derive(Debug, Clone)]
+pub struct ChannelMixer {
+    input: ChannelLayout,
+    output: ChannelLayout,
+    /// One row of input coefficients per output channel
+    matrix: Vec<Vec<f32>>,
+}
+
+impl ChannelMixer {
+    pub fn new(input: ChannelLayout, output: ChannelLayout) -> Result<Self, MediaStatus> {
+        if input.channels() == 0 || output.channels() == 0 {
+            warn!(
+                "Can't mix from channel mask {:#x} to channel mask {:#x}",
+                input.mask(),
+                output.mask()
+            );
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let mut matrix = match output.channels() {
+            // Mono gets the average of the stereo downmix
+            1 => {
+                let stereo = Self::mix_matrix(input, ChannelLayout::STEREO);
+                let mono = stereo[0]
+                    .iter()
+                    .zip(&stereo[1])
+                    .map(|(left, right)| 0.5 * (left + right))
+                    .collect();
+                vec![mono]
+            }
+            _ => Self::mix_matrix(input, output),
+        };
+
+        let loudest = matrix
+            .iter()
+            .map(|row| row.iter().sum::<f32>())
+            .fold(1.0, f32::max);
+        matrix
+            .iter_mut()
+            .flatten()
+            .for_each(|value| *value /= loudest);
+
+        Ok(Self {
+            input,
+            output,
+            matrix,
+        })
+    }
+
+    /// A mixer between the default layouts of two channel counts
+    pub fn from_channels(input: u32, output: u32) -> Result<Self, MediaStatus> {
+        let (Some(input), Some(output)) = (
+            ChannelLayout::default_for(input),
+            ChannelLayout::default_for(output),
+        ) else {
+            warn!("No default channel layout to mix {input} channels into {output}");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        Self::new(input, output)
+    }
+
+    pub fn input(&self) -> ChannelLayout {
+        self.input
+    }
+
+    pub fn output(&self) -> ChannelLayout {
+        self.output
+    }
+
+    /// The coefficient of an input channel in an output channel
+    pub fn coefficient(&self, output: usize, input: usize) -> Option<f32> {
+        self.matrix.get(output)?.get(input).copied()
+    }
+
+    /// Mixes a buffer with the input layout. The output has float samples and the same timestamp
+    pub fn process(&self, buffer: &AudioBuffer) -> Result<AudioBuffer, MediaStatus> {
+        let input_channels = self.input.channels() as usize;
+        if buffer.channels() as usize != input_channels {
+            warn!(
+                "The mixer expects {input_channels} channels, got {}",
+                buffer.channels()
+            );
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let samples = buffer.samples().to_f32();
+        let mut output = Vec::with_capacity(buffer.nb_samples() * self.matrix.len());
+        for frame in samples.chunks_exact(input_channels) {
+            for row in &self.matrix {
+                output.push(row.iter().zip(frame).map(|(c, sample)| c * sample).sum());
+            }
+        }
+
+        let mixed = AudioBuffer::new(
+            AudioSamples::F32(output),
+            self.output.channels(),
+            buffer.sample_rate(),
+        )
+        .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        Ok(mixed.with_layout(self.output).with_pts_us(buffer.pts_us()))
+    }
+
+    /// Mixes an [AudioFrame] with the input layout
+    pub fn process_frame(&self, frame: &AudioFrame) -> Result<AudioBuffer, MediaStatus> {
+        // The sample rate doesn't matter to the mixer
+        let Some(buffer) = frame.to_owned(1) else {
+            warn!("The audio frame isn't a whole number of samples per channel");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        self.process(&buffer)
+    }
+
+    fn mix_matrix(input: ChannelLayout, output: ChannelLayout) -> Vec<Vec<f32>> {
+        let input_positions = input.positions();
+        let mut matrix = vec![vec![0.0; input_positions.len()]; output.channels() as usize];
+
+        for (column, position) in input_positions.into_iter().enumerate() {
+            for (target, weight) in Self::targets(position, input, output) {
+                if let Some(row) = output.index_of(target) {
+                    matrix[row][column] += weight;
+                }
+            }
+        }
+
+        matrix
+    }
+
+    /// The output positions an input position goes to, with their weight
+    fn targets(
+        position: ChannelPosition,
+        input: ChannelLayout,
+        output: ChannelLayout,
+    ) -> Vec<(ChannelPosition, f32)> {
+        use ChannelPosition::*;
+
+        const HALF: f32 = 0.5;
+        const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+        // A mono input is heard on both sides
+        if input.channels() == 1 && output.contains(FrontLeft) && output.contains(FrontRight) {
+            return vec![(FrontLeft, 1.0), (FrontRight, 1.0)];
+        }
+
+        if output.contains(position) {
+            return vec![(position, 1.0)];
+        }
+
+        // The first alternative that the output has all the positions of
+        let alternatives: &[&[(ChannelPosition, f32)]] = match position {
+            FrontLeft => &[&[(FrontCenter, MINUS_3DB)]],
+            FrontRight => &[&[(FrontCenter, MINUS_3DB)]],
+            FrontCenter => &[&[(FrontLeft, MINUS_3DB), (FrontRight, MINUS_3DB)]],
+            LowFrequency => &[],
+            BackLeft => &[&[(SideLeft, 1.0)], &[(FrontLeft, MINUS_3DB)]],
+            BackRight => &[&[(SideRight, 1.0)], &[(FrontRight, MINUS_3DB)]],
+            SideLeft => &[&[(BackLeft, 1.0)], &[(FrontLeft, MINUS_3DB)]],
+            SideRight => &[&[(BackRight, 1.0)], &[(FrontRight, MINUS_3DB)]],
+            FrontLeftOfCenter => &[&[(FrontLeft, 1.0)]],
+            FrontRightOfCenter => &[&[(FrontRight, 1.0)]],
+            BackCenter => &[
+                &[(BackLeft, MINUS_3DB), (BackRight, MINUS_3DB)],
+                &[(SideLeft, MINUS_3DB), (SideRight, MINUS_3DB)],
+                &[(FrontLeft, HALF), (FrontRight, HALF)],
+            ],
+            TopFrontLeft => &[&[(FrontLeft, MINUS_3DB)]],
+            TopFrontRight => &[&[(FrontRight, MINUS_3DB)]],
+            TopFrontCenter => &[
+                &[(FrontCenter, MINUS_3DB)],
+                &[(FrontLeft, HALF), (FrontRight, HALF)],
+            ],
+            TopBackLeft => &[
+                &[(BackLeft, MINUS_3DB)],
+                &[(SideLeft, MINUS_3DB)],
+                &[(FrontLeft, HALF)],
+            ],
+            TopBackRight => &[
+                &[(BackRight, MINUS_3DB)],
+                &[(SideRight, MINUS_3DB)],
+                &[(FrontRight, HALF)],
+            ],
+            TopCenter | TopBackCenter => &[&[(FrontLeft, HALF), (FrontRight, HALF)]],
+        };
+
+        alternatives
+            .iter()
+            .find(|targets| targets.iter().all(|(target, _)| output.contains(*target)))
+            .map_or_else(Vec::new, |targets| targets.to_vec())
+    }
+}
+
+/// Converts audio of any sample rate and channel layout to a fixed one, by chaining a [ChannelMixer] and a [Resampler].
+///
+/// They're set up from the first buffer, and again when the input format changes, dropping the samples
+/// the resampler held back
+#[derive(Debug, Clone)]
+pub struct AudioConverter {
+    sample_rate: u32,
+    layout: ChannelLayout,
+    quality: ResampleQuality,
+    /// The input format the stages are set up for
+    input: Option<(u32, ChannelLayout)>,
+    mixer: Option<ChannelMixer>,
+    resampler: Option<Resampler>,
+}
+
+impl AudioConverter {
+    pub fn new(sample_rate: u32, layout: ChannelLayout, quality: ResampleQuality) -> Self {
+        Self {
+            sample_rate,
+            layout,
+            quality,
+            input: None,
+            mixer: None,
+            resampler: None,
+        }
+    }
+
+    /// Converts to the `sample-rate`, `channel-count` and `channel-mask` of a format, like the one an encoder is configured with
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(
+        format: &MediaFormat,
+        quality: ResampleQuality,
+    ) -> Result<Self, MediaStatus> {
+        let sample_rate = format.get_i32("sample-rate").filter(|rate| *rate > 0);
+        let layout = ChannelLayout::from_media_format(format);
+
+        let (Some(sample_rate), Some(layout)) = (sample_rate, layout) else {
+            warn!("The format needs a sample-rate and a channel-count");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        Ok(Self::new(sample_rate as u32, layout, quality))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    /// Converts a buffer. The output has float samples. Buffers without a layout use the default for their channel count
+    pub fn process(&mut self, buffer: &AudioBuffer) -> Result<AudioBuffer, MediaStatus> {
+        let Some(layout) = buffer
+            .layout()
+            .or_else(|| ChannelLayout::default_for(buffer.channels()))
+        else {
+            warn!("Unknown layout for {} channels", buffer.channels());
+            return Err(MediaStatus::ErrorInvalidParameter);
+        };
+
+        if self.input != Some((buffer.sample_rate(), layout)) {
+            self.setup(buffer.sample_rate(), layout)?;
+        }
+
+        // Mixing down is done first, so there are fewer channels to resample
+        let mix_first = self.layout.channels() < layout.channels();
+        let mut output = None;
+
+        if let (true, Some(mixer)) = (mix_first, &self.mixer) {
+            output = Some(mixer.process(buffer)?);
+        }
+        if let Some(resampler) = &mut self.resampler {
+            output = Some(resampler.process(output.as_ref().unwrap_or(buffer))?);
+        }
+        if let (false, Some(mixer)) = (mix_first, &self.mixer) {
+            output = Some(mixer.process(output.as_ref().unwrap_or(buffer))?);
+        }
+
+        Ok(output.unwrap_or_else(|| buffer.convert(SampleType::F32).with_layout(layout)))
+    }
+
+    /// Returns the samples held back at the end of a stream
+    pub fn flush(&mut self) -> Result<Option<AudioBuffer>, MediaStatus> {
+        let Some(output) = self.resampler.as_mut().and_then(Resampler::flush) else {
+            return Ok(None);
+        };
+
+        match &self.mixer {
+            Some(mixer) if mixer.input().channels() == output.channels() => {
+                mixer.process(&output).map(Some)
+            }
+            _ => Ok(Some(output)),
+        }
+    }
+
+    fn setup(&mut self, sample_rate: u32, layout: ChannelLayout) -> Result<(), MediaStatus> {
+        self.mixer = match layout == self.layout {
+            true => None,
+            false => Some(ChannelMixer::new(layout, self.layout)?),
+        };
+
+        // The resampler works on the smaller number of channels
+        let channels = layout.channels().min(self.layout.channels());
+        self.resampler = match sample_rate == self.sample_rate {
+            true => None,
+            false => Some(Resampler::new(
+                sample_rate,
+                self.sample_rate,
+                channels,
+                self.quality,
+            )?),
+        };
+
+        self.input = Some((sample_rate, layout));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    /// `count` samples of a sine of amplitude 0.5, the same on every channel
+    fn sine(sample_rate: u32, frequency: f64, count: usize, channels: usize) -> Vec<f32> {
+        (0..count)
+            .flat_map(|index| {
+                let t = index as f64 / sample_rate as f64;
+                let sample = (0.5 * (2.0 * std::f64::consts::PI * frequency * t).sin()) as f32;
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect()
+    }
+
+    fn buffer(samples: Vec<f32>, channels: u32, sample_rate: u32, pts_us: i64) -> AudioBuffer {
+        AudioBuffer::new(AudioSamples::F32(samples), channels, sample_rate)
+            .unwrap()
+            .with_pts_us(pts_us)
+    }
+
+    /// Resamples the input in chunks of `chunk` samples per channel, and flushes
+    fn resample_chunks(
+        resampler: &mut Resampler,
+        input: &[f32],
+        chunk: usize,
+        pts_us: i64,
+    ) -> Vec<AudioBuffer> {
+        let channels = resampler.channels() as usize;
+        let rate = resampler.input_rate();
+        let mut outputs: Vec<_> = input
+            .chunks(chunk * channels)
+            .enumerate()
+            .map(|(index, samples)| {
+                let pts = pts_us + (index * chunk) as i64 * 1_000_000 / rate as i64;
+                let input = buffer(samples.to_vec(), channels as u32, rate, pts);
+                resampler.process(&input).unwrap()
+            })
+            .collect();
+        outputs.extend(resampler.flush());
+        outputs
+    }
+
+    #[test]
+    fn output_counts() {
+        let rates = [(48000, 44100), (44100, 48000), (8000, 48000), (48000, 8000)];
+        for (input_rate, output_rate) in rates {
+            for count in [1, 100, 441, 4800, 12345] {
+                for chunk in [1, 333, count] {
+                    let mut resampler =
+                        Resampler::new(input_rate, output_rate, 2, ResampleQuality::Fast).unwrap();
+                    let input = sine(input_rate, 440.0, count, 2);
+                    let outputs = resample_chunks(&mut resampler, &input, chunk, 0);
+
+                    let total: usize = outputs.iter().map(AudioBuffer::nb_samples).sum();
+                    let expected = (count as u64 * output_rate as u64).div_ceil(input_rate as u64);
+                    assert_eq!(
+                        total as u64, expected,
+                        "{count} samples from {input_rate} Hz to {output_rate} Hz"
+                    );
+                    // Nothing is left after a flush
+                    assert!(resampler.flush().is_none());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sine_accuracy() {
+        let cases = [
+            (44100, 48000, ResampleQuality::High, 2e-4),
+            (48000, 44100, ResampleQuality::High, 2e-4),
+            (8000, 48000, ResampleQuality::High, 2e-4),
+            (44100, 48000, ResampleQuality::Medium, 2e-3),
+            (44100, 48000, ResampleQuality::Fast, 2e-2),
+        ];
+        for (input_rate, output_rate, quality, tolerance) in cases {
+            let mut resampler = Resampler::new(input_rate, output_rate, 2, quality).unwrap();
+            let input = sine(input_rate, 1000.0, input_rate as usize, 2);
+            let output: Vec<f32> = resample_chunks(&mut resampler, &input, 1000, 0)
+                .iter()
+                .flat_map(|output| output.samples().to_f32())
+                .collect();
+            let expected = sine(output_rate, 1000.0, output_rate as usize, 2);
+            assert_eq!(output.len(), expected.len());
+
+            // The edges of the stream are filtered against silence
+            let edge = 2 * 200;
+            let error = output[edge..output.len() - edge]
+                .iter()
+                .zip(&expected[edge..])
+                .map(|(sample, expected)| (sample - expected).abs())
+                .fold(0.0, f32::max);
+            assert!(
+                error < tolerance,
+                "{quality:?} from {input_rate} Hz to {output_rate} Hz: {error}"
+            );
+        }
+
+        // Downsampling removes what is above the new Nyquist frequency, and keeps what is below
+        for (frequency, peak) in [(10000.0, 0.0), (3000.0, 0.5)] {
+            let mut resampler = Resampler::new(48000, 16000, 1, ResampleQuality::Medium).unwrap();
+            let input = buffer(sine(48000, frequency, 48000, 1), 1, 48000, 0);
+            let output = resampler.process(&input).unwrap().samples().to_f32();
+            let measured = output[100..15000]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            assert!((measured - peak).abs() < 0.01, "{frequency} Hz: {measured}");
+        }
+
+        // A ratio with more phases than the filter bank has
+        let mut resampler = Resampler::new(44100, 47999, 1, ResampleQuality::Fast).unwrap();
+        let input = AudioBuffer::new(AudioSamples::S16(vec![8192; 4410]), 1, 44100).unwrap();
+        let output = resampler.process(&input).unwrap().samples().to_f32();
+        assert!(output[20..4000]
+            .iter()
+            .all(|sample| (sample - 0.25).abs() < 1e-3));
+    }
+
+    #[test]
+    fn continuous_pts() {
+        for (input_rate, output_rate) in [(44100, 48000), (48000, 44100), (8000, 48000)] {
+            let input = sine(input_rate, 1000.0, input_rate as usize / 2, 2);
+
+            let mut resampler =
+                Resampler::new(input_rate, output_rate, 2, ResampleQuality::Medium).unwrap();
+            let whole = resample_chunks(&mut resampler, &input, input.len(), 1000);
+            assert_eq!(whole[0].pts_us(), 1000);
+
+            let mut resampler =
+                Resampler::new(input_rate, output_rate, 2, ResampleQuality::Medium).unwrap();
+            let chunks = resample_chunks(&mut resampler, &input, 333, 1000);
+
+            // Every output starts where the previous one ended, the flushed samples too
+            let mut produced = 0;
+            for output in chunks.iter().filter(|output| output.nb_samples() > 0) {
+                let expected = 1000 + produced * 1_000_000 / output_rate as i64;
+                assert!(
+                    (output.pts_us() - expected).abs() <= 1,
+                    "{} instead of {expected}",
+                    output.pts_us()
+                );
+                produced += output.nb_samples() as i64;
+            }
+
+            // Chunks of any size give the same samples
+            let samples = |outputs: &[AudioBuffer]| -> Vec<f32> {
+                outputs
+                    .iter()
+                    .flat_map(|output| output.samples().to_f32())
+                    .collect()
+            };
+            let (whole, chunks) = (samples(&whole), samples(&chunks));
+            assert_eq!(whole.len(), chunks.len());
+            assert!(whole
+                .iter()
+                .zip(&chunks)
+                .all(|(whole, chunk)| (whole - chunk).abs() < 1e-6));
+        }
+
+        // The flush resets the resampler for the next stream
+        let mut resampler = Resampler::new(44100, 48000, 1, ResampleQuality::Fast).unwrap();
+        let input = buffer(vec![0.0; 441], 1, 44100, 5000);
+        resampler.process(&input).unwrap();
+        resampler.flush().unwrap();
+        assert_eq!(resampler.process(&input).unwrap().pts_us(), 5000);
+
+        let stereo = buffer(vec![0.0; 2], 2, 44100, 0);
+        assert!(resampler.process(&stereo).is_err());
+        assert!(Resampler::new(0, 48000, 1, ResampleQuality::Fast).is_err());
+    }
+
+    #[test]
+    fn mixer_coefficients() {
+        // 5.1 to stereo: the center at -3 dB, the backs at -3 dB on their side, no LFE
+        let mixer = ChannelMixer::new(ChannelLayout::SURROUND_5_1, ChannelLayout::STEREO).unwrap();
+        let scale = 1.0 + 2.0 * FRAC_1_SQRT_2;
+        let expected = [
+            [1.0, 0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2, 0.0],
+            [0.0, 1.0, FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2],
+        ];
+        for (output, row) in expected.iter().enumerate() {
+            for (input, coefficient) in row.iter().enumerate() {
+                let actual = mixer.coefficient(output, input).unwrap();
+                assert!(
+                    (actual - coefficient / scale).abs() < 1e-6,
+                    "{output} {input}: {actual}"
+                );
+            }
+        }
+        assert_eq!(mixer.coefficient(2, 0), None);
+        assert_eq!(mixer.coefficient(0, 6), None);
+
+        // A full scale input doesn't clip
+        let loud = buffer(vec![1.0, -1.0, 1.0, 1.0, 1.0, -1.0], 6, 48000, 0);
+        let output = mixer.process(&loud).unwrap().samples().to_f32();
+        assert!((output[0] - 1.0).abs() < 1e-6);
+
+        // Mono to stereo is a copy
+        let mixer = ChannelMixer::from_channels(1, 2).unwrap();
+        let output = mixer.process(&buffer(vec![0.5], 1, 8000, 0)).unwrap();
+        assert_eq!(output.samples(), &AudioSamples::F32(vec![0.5, 0.5]));
+        assert_eq!(output.layout(), Some(ChannelLayout::STEREO));
+
+        // Stereo to mono is the average
+        let mixer = ChannelMixer::from_channels(2, 1).unwrap();
+        assert_eq!(mixer.coefficient(0, 0), Some(0.5));
+        assert_eq!(mixer.coefficient(0, 1), Some(0.5));
+        let input = AudioBuffer::new(AudioSamples::S16(vec![1000, 3000]), 2, 8000)
+            .unwrap()
+            .with_pts_us(5);
+        let output = mixer.process(&input).unwrap();
+        assert_eq!(output.samples().to_f32()[0] * 32768.0, 2000.0);
+        assert_eq!(output.pts_us(), 5);
+        assert_eq!(output.layout(), Some(ChannelLayout::MONO));
+
+        // Stereo to 5.1 leaves the other speakers silent
+        let mixer = ChannelMixer::from_channels(2, 6).unwrap();
+        let output = mixer.process(&buffer(vec![0.5, 0.25], 2, 8000, 0)).unwrap();
+        assert_eq!(
+            output.samples(),
+            &AudioSamples::F32(vec![0.5, 0.25, 0.0, 0.0, 0.0, 0.0])
+        );
+
+        // 7.1 to 5.1 folds the sides into the backs
+        let mixer =
+            ChannelMixer::new(ChannelLayout::SURROUND_7_1, ChannelLayout::SURROUND_5_1).unwrap();
+        assert_eq!(mixer.coefficient(4, 6), mixer.coefficient(4, 4));
+        assert_eq!(mixer.coefficient(5, 7), mixer.coefficient(5, 5));
+
+        assert!(mixer.process(&buffer(vec![0.0; 6], 6, 8000, 0)).is_err());
+        assert!(ChannelMixer::from_channels(2, 12).is_err());
+        assert!(ChannelMixer::new(ChannelLayout::from_mask(0), ChannelLayout::STEREO).is_err());
+    }
+
+    #[test]
+    fn converter() {
+        let mut converter =
+            AudioConverter::new(48000, ChannelLayout::STEREO, ResampleQuality::Fast);
+        let input = AudioBuffer::new(AudioSamples::S16(vec![1000; 6 * 441]), 6, 44100)
+            .unwrap()
+            .with_pts_us(7);
+        let output = converter.process(&input).unwrap();
+        assert_eq!(output.channels(), 2);
+        assert_eq!(output.sample_rate(), 48000);
+        assert_eq!(output.pts_us(), 7);
+        let tail = converter.flush().unwrap().unwrap();
+        assert_eq!(output.nb_samples() + tail.nb_samples(), 480);
+
+        // Upmixing is done after resampling, the flushed samples are upmixed too
+        let input = AudioBuffer::new(AudioSamples::S16(vec![1000; 441]), 1, 44100).unwrap();
+        let output = converter.process(&input).unwrap();
+        assert_eq!(output.channels(), 2);
+        let tail = converter.flush().unwrap().unwrap();
+        assert_eq!(tail.channels(), 2);
+        assert_eq!(output.nb_samples() + tail.nb_samples(), 480);
+
+        // The same format only converts the samples
+        let input = AudioBuffer::new(AudioSamples::S16(vec![1000; 480]), 2, 48000).unwrap();
+        assert_eq!(
+            converter.process(&input).unwrap().samples(),
+            &input.samples().convert(SampleType::F32)
+        );
+        assert!(converter.flush().unwrap().is_none());
+    }
+}
@@ -0,0 +1,1153 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use log::{debug, warn};
+
+use crate::{AvcNalType, HevcNalType, NalFormat, ANNEXB_START_CODE};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecInputBuffer, CodecOutputBuffer, SAMPLE_FLAG_SYNC};
+
+/// The size of an RTP header without CSRCs or extension
+const RTP_HEADER_SIZE: usize = 12;
+
+/// The packet size used by default, which stays below the MTU of most networks, tunnels included
+pub const RTP_DEFAULT_MTU: usize = 1200;
+
+// H.264 packet types (RFC 6184)
+const AVC_STAP_A: u8 = 24;
+const AVC_FU_A: u8 = 28;
+
+// HEVC packet types (RFC 7798)
+const HEVC_AP: u8 = 48;
+const HEVC_FU: u8 = 49;
+
+// The start and end bits of fragmentation unit headers
+const FU_START: u8 = 0x80;
+const FU_END: u8 = 0x40;
+
+/// The number of samples of the AAC frames of a RTP stream
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// The largest AAC frame a RTP packet can carry, the AU headers have 13 bits for its size
+pub const AAC_MAX_FRAME_SIZE: usize = (1 << 13) - 1;
+
+/// The number of packets a [RtpJitterBuffer](RtpJitterBuffer) holds at most while waiting for a missing one
+const MAX_JITTER_PACKETS: usize = 1024;
+
+/// An RTP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    pub payload_type: u8,
+    /// The end of an access unit for video, the start of a talkspurt for audio
+    pub marker: bool,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: Vec<u8>,
+}
+
+impl RtpPacket {
+    /// Parses an RTP packet, like a UDP datagram. CSRCs and header extensions are skipped
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < RTP_HEADER_SIZE || data[0] >> 6 != 2 {
+            return None;
+        }
+
+        let padding = data[0] & 0x20 != 0;
+        let extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0xf) as usize;
+
+        let mut start = RTP_HEADER_SIZE + 4 * csrc_count;
+        if extension {
+            let length = u16::from_be_bytes([*data.get(start + 2)?, *data.get(start + 3)?]);
+            start += 4 + 4 * length as usize;
+        }
+
+        let mut end = data.len();
+        if padding {
+            end = end.checked_sub(*data.last()? as usize)?;
+        }
+
+        Some(Self {
+            payload_type: data[1] & 0x7f,
+            marker: data[1] & 0x80 != 0,
+            sequence_number: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes(data[4..8].try_into().ok()?),
+            ssrc: u32::from_be_bytes(data[8..12].try_into().ok()?),
+            payload: data.get(start..end)?.to_vec(),
+        })
+    }
+
+    /// Serializes the packet, with a 12 byte header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(RTP_HEADER_SIZE + self.payload.len());
+
+        output.push(0x80);
+        output.push((self.marker as u8) << 7 | (self.payload_type & 0x7f));
+        output.extend_from_slice(&self.sequence_number.to_be_bytes());
+        output.extend_from_slice(&self.timestamp.to_be_bytes());
+        output.extend_from_slice(&self.ssrc.to_be_bytes());
+        output.extend_from_slice(&self.payload);
+
+        output
+    }
+}
+
+/// The payload formats of [RtpPayloader](RtpPayloader) and [RtpDepayloader](RtpDepayloader)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpCodec {
+    /// H.264 in non-interleaved mode (RFC 6184), with single NAL unit, STAP-A and FU-A packets
+    H264,
+    /// HEVC without DONL fields (RFC 7798), with single NAL unit, aggregation and fragmentation packets
+    Hevc,
+    /// Opus (RFC 7587), one frame per packet
+    Opus,
+    /// AAC in the `AAC-hbr` mode of RFC 3640: 13 bit sizes and 3 bit indices in the AU headers.
+    /// The clock rate is the sample rate
+    Aac { sample_rate: u32 },
+}
+
+impl RtpCodec {
+    /// The rate of the RTP timestamps
+    pub fn clock_rate(&self) -> u32 {
+        match self {
+            Self::H264 | Self::Hevc => 90_000,
+            Self::Opus => 48_000,
+            Self::Aac { sample_rate } => *sample_rate,
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, Self::H264 | Self::Hevc)
+    }
+
+    /// AAC needs a sample rate, it's the clock rate
+    fn is_valid(&self) -> bool {
+        !matches!(self, Self::Aac { sample_rate: 0 })
+    }
+
+    /// The size of the NAL unit headers
+    fn nal_header_size(&self) -> usize {
+        match self {
+            Self::Hevc => 2,
+            _ => 1,
+        }
+    }
+
+    fn is_parameter_set(&self, nal: &[u8]) -> bool {
+        match self {
+            Self::H264 => matches!(
+                AvcNalType::from_header(nal[0]),
+                AvcNalType::Sps | AvcNalType::Pps
+            ),
+            Self::Hevc => matches!(
+                HevcNalType::from_header(nal[0]),
+                HevcNalType::Vps | HevcNalType::Sps | HevcNalType::Pps
+            ),
+            _ => false,
+        }
+    }
+
+    fn is_sync(&self, nal: &[u8]) -> bool {
+        match self {
+            Self::H264 => AvcNalType::from_header(nal[0]) == AvcNalType::IdrSlice,
+            Self::Hevc => HevcNalType::from_header(nal[0]).is_irap(),
+            _ => true,
+        }
+    }
+}
+
+/// Splits encoded frames into RTP packets.
+///
+/// H.264 and HEVC access units are split into NAL units: small ones are aggregated, large ones fragmented to fit the MTU.
+/// The parameter sets from codec config buffers are sent again in front of every keyframe that doesn't have them,
+/// so receivers can join at any keyframe.
+///
+/// AAC frames larger than a packet are fragmented as RFC 3640 allows; Opus frames always fit.
+/// For all codecs, the marker bit is set on the last packet of every frame
+#[derive(Debug)]
+pub struct RtpPayloader {
+    codec: RtpCodec,
+    payload_type: u8,
+    ssrc: u32,
+    mtu: usize,
+    sequence_number: u16,
+    timestamp_offset: u32,
+    nal_format: NalFormat,
+    parameter_sets: Vec<Vec<u8>>,
+}
+
+impl RtpPayloader {
+    /// Creates a payloader for a stream. The sequence numbers and the timestamps start at 0,
+    /// [set_initial_state](Self::set_initial_state) can make them random as RFC 3550 recommends.
+    ///
+    /// Returns `None` for AAC with a sample rate of 0
+    pub fn new(codec: RtpCodec, payload_type: u8, ssrc: u32) -> Option<Self> {
+        if !codec.is_valid() {
+            warn!("Invalid RTP codec {codec:?}");
+            return None;
+        }
+
+        Some(Self {
+            codec,
+            payload_type,
+            ssrc,
+            mtu: RTP_DEFAULT_MTU,
+            sequence_number: 0,
+            timestamp_offset: 0,
+            nal_format: NalFormat::AnnexB,
+            parameter_sets: vec![],
+        })
+    }
+
+    pub fn codec(&self) -> RtpCodec {
+        self.codec
+    }
+
+    /// Sets the largest size of the packets, header included. The default is [RTP_DEFAULT_MTU](RTP_DEFAULT_MTU)
+    pub fn set_mtu(&mut self, mtu: usize) {
+        // Room for the header and the fragmentation headers
+        self.mtu = mtu.max(RTP_HEADER_SIZE + 16);
+    }
+
+    /// Sets the sequence number of the next packet, and the RTP timestamp of a presentation time of 0
+    pub fn set_initial_state(&mut self, sequence_number: u16, timestamp_offset: u32) {
+        self.sequence_number = sequence_number;
+        self.timestamp_offset = timestamp_offset;
+    }
+
+    /// Sets how the NAL units of AVC and HEVC access units are delimited. The default is Annex-B, like encoder output
+    pub fn set_nal_format(&mut self, nal_format: NalFormat) {
+        self.nal_format = nal_format;
+    }
+
+    /// The sequence number of the next packet
+    pub fn sequence_number(&self) -> u16 {
+        self.sequence_number
+    }
+
+    /// Sets the parameter sets to send in front of keyframes, from a codec config buffer in Annex-B format
+    pub fn set_codec_config(&mut self, data: &[u8]) {
+        if self.codec.is_video() {
+            let header_size = self.codec.nal_header_size();
+            self.parameter_sets = NalFormat::AnnexB
+                .nal_units(data)
+                .into_iter()
+                .filter(|nal| nal.len() >= header_size)
+                .map(|nal| nal.to_vec())
+                .collect();
+        }
+    }
+
+    /// Packetizes an encoder output buffer. Codec config buffers update the parameter sets and produce no packets
+    #[cfg(target_os = "android")]
+    pub fn write(&mut self, buffer: &CodecOutputBuffer) -> Vec<RtpPacket> {
+        let info = *buffer.info();
+        let flags = info.flags() as i32;
+
+        let data = match buffer.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return vec![],
+        };
+
+        if BufferFlag::CodecConfig.is_contained_in(flags) {
+            self.set_codec_config(data);
+            return vec![];
+        }
+
+        let sync = BufferFlag::Encode.is_contained_in(flags);
+        self.packetize(data, info.presentation_time_us(), sync)
+    }
+
+    /// Packetizes a frame. AVC and HEVC access units are in Annex-B unless [set_nal_format](Self::set_nal_format) says otherwise.
+    ///
+    /// AAC frames of [AAC_MAX_FRAME_SIZE](AAC_MAX_FRAME_SIZE) bytes or more don't fit in the AU header, they produce no packets
+    pub fn packetize(&mut self, data: &[u8], pts_us: i64, sync: bool) -> Vec<RtpPacket> {
+        let clock_rate = self.codec.clock_rate() as i64;
+        let timestamp = self
+            .timestamp_offset
+            .wrapping_add((pts_us * clock_rate + 500_000).div_euclid(1_000_000) as u32);
+
+        let payloads = match self.codec {
+            RtpCodec::H264 | RtpCodec::Hevc => self.video_payloads(data, sync),
+            RtpCodec::Opus => vec![data.to_vec()],
+            RtpCodec::Aac { .. } => self.aac_payloads(data),
+        };
+
+        let count = payloads.len();
+        payloads
+            .into_iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let packet = RtpPacket {
+                    payload_type: self.payload_type,
+                    marker: index + 1 == count,
+                    sequence_number: self.sequence_number,
+                    timestamp,
+                    ssrc: self.ssrc,
+                    payload,
+                };
+
+                self.sequence_number = self.sequence_number.wrapping_add(1);
+                packet
+            })
+            .collect()
+    }
+
+    fn max_payload_size(&self) -> usize {
+        self.mtu - RTP_HEADER_SIZE
+    }
+
+    fn video_payloads(&self, data: &[u8], sync: bool) -> Vec<Vec<u8>> {
+        let header_size = self.codec.nal_header_size();
+        let mut units = self.nal_format.nal_units(data);
+        // Too short to even have a NAL unit header
+        units.retain(|nal| nal.len() >= header_size);
+
+        if sync && !units.iter().any(|nal| self.codec.is_parameter_set(nal)) {
+            let parameter_sets = self.parameter_sets.iter().map(|nal| nal.as_slice());
+            units.splice(0..0, parameter_sets);
+        }
+
+        let max_size = self.max_payload_size();
+        let mut payloads = vec![];
+
+        let mut index = 0;
+        while index < units.len() {
+            let nal = units[index];
+            if nal.len() > max_size {
+                self.fragment(nal, &mut payloads);
+                index += 1;
+                continue;
+            }
+
+            // Aggregate the following NAL units while they fit, each with a 16 bit size
+            let mut end = index + 1;
+            let mut size = header_size + 2 + nal.len();
+            while let Some(next) = units.get(end) {
+                if size + 2 + next.len() > max_size {
+                    break;
+                }
+
+                size += 2 + next.len();
+                end += 1;
+            }
+
+            if end - index == 1 {
+                payloads.push(nal.to_vec());
+            } else {
+                payloads.push(self.aggregate(&units[index..end], size));
+            }
+
+            index = end;
+        }
+
+        payloads
+    }
+
+    /// Builds a STAP-A or an aggregation packet
+    fn aggregate(&self, units: &[&[u8]], size: usize) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(size);
+
+        match self.codec {
+            RtpCodec::H264 => {
+                // The F bit if any NAL unit has it, and the highest NRI
+                let header = units.iter().fold(0, |header, nal| header | (nal[0] & 0x80));
+                let nri = units.iter().map(|nal| nal[0] & 0x60).max().unwrap_or(0);
+                payload.push(header | nri | AVC_STAP_A);
+            }
+            _ => {
+                // The F bit if any NAL unit has it, the lowest layer id and temporal id
+                let forbidden = units.iter().fold(0, |header, nal| header | (nal[0] & 0x80));
+                let layer_id = units
+                    .iter()
+                    .map(|nal| ((nal[0] & 1) << 5) | (nal[1] >> 3))
+                    .min()
+                    .unwrap_or(0);
+                let temporal_id = units.iter().map(|nal| nal[1] & 0x7).min().unwrap_or(1);
+
+                payload.push(forbidden | (HEVC_AP << 1) | (layer_id >> 5));
+                payload.push((layer_id << 3) | temporal_id);
+            }
+        }
+
+        for nal in units {
+            payload.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            payload.extend_from_slice(nal);
+        }
+
+        payload
+    }
+
+    /// Splits a NAL unit into FU-A or fragmentation unit packets
+    fn fragment(&self, nal: &[u8], payloads: &mut Vec<Vec<u8>>) {
+        let header_size = self.codec.nal_header_size();
+
+        let (header, nal_type): (Vec<u8>, u8) = match self.codec {
+            RtpCodec::H264 => (vec![(nal[0] & 0xe0) | AVC_FU_A], nal[0] & 0x1f),
+            _ => (
+                vec![(nal[0] & 0x81) | (HEVC_FU << 1), nal[1]],
+                (nal[0] >> 1) & 0x3f,
+            ),
+        };
+
+        let chunk_size = self.max_payload_size() - header.len() - 1;
+        let chunks: Vec<&[u8]> = nal[header_size..].chunks(chunk_size).collect();
+        let count = chunks.len();
+
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let mut fu_header = nal_type;
+            if index == 0 {
+                fu_header |= FU_START;
+            }
+            if index + 1 == count {
+                fu_header |= FU_END;
+            }
+
+            let mut payload = Vec::with_capacity(header.len() + 1 + chunk.len());
+            payload.extend_from_slice(&header);
+            payload.push(fu_header);
+            payload.extend_from_slice(chunk);
+            payloads.push(payload);
+        }
+    }
+
+    /// Builds AAC-hbr payloads: one access unit, fragmented if it doesn't fit in a packet
+    fn aac_payloads(&self, frame: &[u8]) -> Vec<Vec<u8>> {
+        if frame.len() > AAC_MAX_FRAME_SIZE {
+            warn!(
+                "AAC frame of {} bytes is too large for RTP, dropping it",
+                frame.len()
+            );
+            return vec![];
+        }
+
+        // AU-headers-length in bits, then an AU header with the size and an index of 0
+        let mut header = vec![0, 16];
+        header.extend_from_slice(&((frame.len() as u16) << 3).to_be_bytes());
+
+        let chunk_size = self.max_payload_size() - header.len();
+        frame
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let mut payload = header.clone();
+                payload.extend_from_slice(chunk);
+                payload
+            })
+            .collect()
+    }
+}
+
+/// A frame rebuilt from RTP packets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpFrame {
+    /// The frame, an access unit in Annex-B format for H.264 and HEVC
+    pub data: Vec<u8>,
+    /// The RTP timestamp of the frame
+    pub timestamp: u32,
+    /// The presentation time, from the first RTP timestamp of the stream
+    pub pts_us: i64,
+    /// The access unit has an IDR (H.264) or IRAP (HEVC) picture. Always true for audio
+    pub sync: bool,
+}
+
+impl RtpFrame {
+    /// Copies the frame into a decoder input buffer, with its presentation time and flags.
+    ///
+    /// Returns false (and writes nothing) if the frame doesn't fit
+    #[cfg(target_os = "android")]
+    pub fn write_to(&self, buffer: &mut CodecInputBuffer) -> bool {
+        if !buffer.write_data(&self.data) {
+            warn!(
+                "RTP frame at {}us doesn't fit in the input buffer ({} > {})",
+                self.pts_us,
+                self.data.len(),
+                buffer.size()
+            );
+            return false;
+        }
+
+        buffer.set_time(self.pts_us.max(0) as u64);
+        buffer.set_flags(if self.sync { SAMPLE_FLAG_SYNC } else { 0 });
+        true
+    }
+}
+
+/// Rebuilds frames from RTP packets, in sequence number order.
+///
+/// Video access units and fragmented AAC frames end with the marker bit. Video access units also end when the timestamp
+/// changes, if the packet with the marker bit was lost.
+/// NAL units that lost a fragment are dropped, the rest of their access unit is kept
+#[derive(Debug)]
+pub struct RtpDepayloader {
+    codec: RtpCodec,
+    last_sequence_number: Option<u16>,
+    first_timestamp: Option<i64>,
+    /// The last timestamp, extended to 64 bits
+    last_timestamp: Option<i64>,
+    /// The access unit being assembled, and its timestamp
+    access_unit: Option<(u32, Vec<u8>)>,
+    /// The NAL unit (or the AAC frame) being reassembled from fragments
+    fragment: Option<Vec<u8>>,
+}
+
+impl RtpDepayloader {
+    /// Returns `None` for AAC with a sample rate of 0
+    pub fn new(codec: RtpCodec) -> Option<Self> {
+        if !codec.is_valid() {
+            warn!("Invalid RTP codec {codec:?}");
+            return None;
+        }
+
+        Some(Self {
+            codec,
+            last_sequence_number: None,
+            first_timestamp: None,
+            last_timestamp: None,
+            access_unit: None,
+            fragment: None,
+        })
+    }
+
+    pub fn codec(&self) -> RtpCodec {
+        self.codec
+    }
+
+    /// Handles the next packet of the stream. Returns the frames it completes
+    pub fn push(&mut self, packet: &RtpPacket) -> Vec<RtpFrame> {
+        let lost = self
+            .last_sequence_number
+            .is_some_and(|last| packet.sequence_number != last.wrapping_add(1));
+        self.last_sequence_number = Some(packet.sequence_number);
+
+        if lost && self.fragment.take().is_some() {
+            debug!(
+                "Dropping a fragmented unit, packets before {} were lost",
+                packet.sequence_number
+            );
+        }
+
+        let mut frames = vec![];
+
+        match self.codec {
+            RtpCodec::H264 | RtpCodec::Hevc => {
+                if self
+                    .access_unit
+                    .as_ref()
+                    .is_some_and(|(timestamp, _)| *timestamp != packet.timestamp)
+                {
+                    frames.extend(self.flush());
+                }
+
+                self.push_video(packet);
+
+                if packet.marker {
+                    frames.extend(self.flush());
+                }
+            }
+            RtpCodec::Opus => {
+                if !packet.payload.is_empty() {
+                    frames.push(self.frame(packet.timestamp, packet.payload.clone(), true));
+                }
+            }
+            RtpCodec::Aac { .. } => self.push_aac(packet, &mut frames),
+        }
+
+        frames
+    }
+
+    /// Returns the access unit being assembled, at the end of the stream
+    pub fn flush(&mut self) -> Option<RtpFrame> {
+        let (timestamp, data) = self.access_unit.take()?;
+        if data.is_empty() {
+            return None;
+        }
+
+        let sync = NalFormat::AnnexB
+            .nal_units(&data)
+            .iter()
+            .any(|nal| self.codec.is_sync(nal));
+
+        Some(self.frame(timestamp, data, sync))
+    }
+
+    fn frame(&mut self, timestamp: u32, data: Vec<u8>, sync: bool) -> RtpFrame {
+        // Extends the timestamp, so wrapping around doesn't make time go back
+        let extended = match self.last_timestamp {
+            Some(last) => last + timestamp.wrapping_sub(last as u32) as i32 as i64,
+            None => timestamp as i64,
+        };
+        self.last_timestamp = Some(extended);
+
+        let first = *self.first_timestamp.get_or_insert(extended);
+        let pts_us = (extended - first) * 1_000_000 / self.codec.clock_rate() as i64;
+
+        RtpFrame {
+            data,
+            timestamp,
+            pts_us,
+            sync,
+        }
+    }
+
+    fn push_video(&mut self, packet: &RtpPacket) {
+        let payload = &packet.payload;
+        let header_size = self.codec.nal_header_size();
+        if payload.len() < header_size {
+            return;
+        }
+
+        let (nal_type, aggregation, fragmentation) = match self.codec {
+            RtpCodec::H264 => (payload[0] & 0x1f, AVC_STAP_A, AVC_FU_A),
+            _ => ((payload[0] >> 1) & 0x3f, HEVC_AP, HEVC_FU),
+        };
+
+        let (_, access_unit) = self
+            .access_unit
+            .get_or_insert_with(|| (packet.timestamp, vec![]));
+
+        if nal_type == aggregation {
+            let mut units = &payload[header_size..];
+
+            while units.len() >= 2 {
+                let size = u16::from_be_bytes([units[0], units[1]]) as usize;
+                let Some(nal) = units.get(2..2 + size) else {
+                    debug!("Truncated aggregation packet {}", packet.sequence_number);
+                    break;
+                };
+
+                access_unit.extend_from_slice(&ANNEXB_START_CODE);
+                access_unit.extend_from_slice(nal);
+                units = &units[2 + size..];
+            }
+        } else if nal_type == fragmentation {
+            let Some(&fu_header) = payload.get(header_size) else {
+                return;
+            };
+            let data = &payload[header_size + 1..];
+
+            if fu_header & FU_START != 0 {
+                let original_type = fu_header & 0x3f;
+                let header = match self.codec {
+                    RtpCodec::H264 => vec![(payload[0] & 0xe0) | (original_type & 0x1f)],
+                    _ => vec![(payload[0] & 0x81) | (original_type << 1), payload[1]],
+                };
+
+                let mut nal = header;
+                nal.extend_from_slice(data);
+                self.fragment = Some(nal);
+            } else if let Some(nal) = &mut self.fragment {
+                nal.extend_from_slice(data);
+            }
+
+            if fu_header & FU_END != 0 {
+                if let Some(nal) = self.fragment.take() {
+                    access_unit.extend_from_slice(&ANNEXB_START_CODE);
+                    access_unit.extend_from_slice(&nal);
+                }
+            }
+        } else if nal_type < aggregation {
+            access_unit.extend_from_slice(&ANNEXB_START_CODE);
+            access_unit.extend_from_slice(payload);
+        } else {
+            debug!("Unsupported RTP packet type {nal_type}");
+        }
+    }
+
+    fn push_aac(&mut self, packet: &RtpPacket, frames: &mut Vec<RtpFrame>) {
+        let payload = &packet.payload;
+        let Some(length) = payload
+            .get(..2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        else {
+            return;
+        };
+
+        // 16 bit AU headers
+        let header_count = length as usize / 16;
+        let Some(headers) = payload.get(2..2 + 2 * header_count) else {
+            return;
+        };
+        let mut data = &payload[2 + 2 * header_count..];
+
+        let sizes: Vec<usize> = headers
+            .chunks_exact(2)
+            .map(|header| (u16::from_be_bytes([header[0], header[1]]) >> 3) as usize)
+            .collect();
+
+        // A fragment of a frame that spans several packets
+        if let [size] = sizes[..] {
+            if self.fragment.is_some() || size > data.len() {
+                let fragment = self.fragment.get_or_insert_with(Vec::new);
+                fragment.extend_from_slice(data);
+
+                // The last fragment has the marker bit
+                if packet.marker {
+                    let frame = self.fragment.take().unwrap_or_default();
+                    if frame.len() == size {
+                        frames.push(self.frame(packet.timestamp, frame, true));
+                    } else {
+                        debug!(
+                            "Dropping an AAC frame of {} bytes instead of {size}",
+                            frame.len()
+                        );
+                    }
+                }
+
+                return;
+            }
+        }
+
+        let mut timestamp = packet.timestamp;
+        for size in sizes {
+            let Some(frame) = data.get(..size) else {
+                debug!("Truncated AAC packet {}", packet.sequence_number);
+                break;
+            };
+
+            frames.push(self.frame(timestamp, frame.to_vec(), true));
+            data = &data[size..];
+            timestamp = timestamp.wrapping_add(AAC_FRAME_SAMPLES);
+        }
+    }
+}
+
+/// Reorders received RTP packets by sequence number, and rebuilds the frames with a [RtpDepayloader](RtpDepayloader).
+///
+/// Packets are released as soon as they're in sequence. When one is missing, the buffer waits for it until it holds
+/// `latency_ms` worth of later packets (by RTP timestamp), then gives up on it. Packets arriving after that are dropped.
+/// Nothing is released before the first packet has waited for the latency either, so reordered packets at the start aren't lost
+#[derive(Debug)]
+pub struct RtpJitterBuffer {
+    depayloader: RtpDepayloader,
+    /// The latency in RTP timestamp units
+    latency: u32,
+    /// The packets waiting, by extended sequence number
+    packets: BTreeMap<i64, RtpPacket>,
+    /// The extended sequence number of the next packet to release
+    next_sequence_number: Option<i64>,
+    highest_sequence_number: Option<i64>,
+    lost_packets: u64,
+    frames: VecDeque<RtpFrame>,
+}
+
+impl RtpJitterBuffer {
+    /// Returns `None` for AAC with a sample rate of 0
+    pub fn new(codec: RtpCodec, latency_ms: u32) -> Option<Self> {
+        Some(Self {
+            depayloader: RtpDepayloader::new(codec)?,
+            latency: (codec.clock_rate() as u64 * latency_ms as u64 / 1000) as u32,
+            packets: BTreeMap::new(),
+            next_sequence_number: None,
+            highest_sequence_number: None,
+            lost_packets: 0,
+            frames: VecDeque::new(),
+        })
+    }
+
+    /// The number of packets that never arrived, or arrived too late
+    pub fn lost_packets(&self) -> u64 {
+        self.lost_packets
+    }
+
+    /// Adds a received packet, like a UDP datagram. Returns false if it isn't a valid RTP packet
+    pub fn push_datagram(&mut self, data: &[u8]) -> bool {
+        match RtpPacket::parse(data) {
+            Some(packet) => {
+                self.push(packet);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a received packet
+    pub fn push(&mut self, packet: RtpPacket) {
+        // Extends the sequence number, from the highest one so far
+        let sequence_number = match self.highest_sequence_number {
+            Some(highest) => {
+                highest + packet.sequence_number.wrapping_sub(highest as u16) as i16 as i64
+            }
+            None => packet.sequence_number as i64,
+        };
+
+        if self
+            .next_sequence_number
+            .is_some_and(|next| sequence_number < next)
+        {
+            debug!(
+                "Dropping RTP packet {}, it's too late",
+                packet.sequence_number
+            );
+            return;
+        }
+
+        self.highest_sequence_number = Some(
+            self.highest_sequence_number
+                .map_or(sequence_number, |highest| highest.max(sequence_number)),
+        );
+        self.packets.insert(sequence_number, packet);
+
+        self.release(false);
+    }
+
+    /// Returns the next complete frame
+    pub fn pop_frame(&mut self) -> Option<RtpFrame> {
+        self.frames.pop_front()
+    }
+
+    /// Writes the next complete frame into a decoder input buffer.
+    ///
+    /// Returns false if no frame is ready. Frames that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        match self.pop_frame() {
+            Some(frame) => {
+                frame.write_to(buffer);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Releases all the packets without waiting for the missing ones, and the access unit being assembled,
+    /// like at the end of the stream
+    pub fn flush(&mut self) {
+        self.release(true);
+        self.frames.extend(self.depayloader.flush());
+    }
+
+    fn release(&mut self, force: bool) {
+        while let Some(&sequence_number) = self.packets.keys().next() {
+            if self.next_sequence_number != Some(sequence_number) {
+                if !force && !self.waited_enough() {
+                    break;
+                }
+
+                if let Some(next) = self.next_sequence_number {
+                    let lost = sequence_number - next;
+                    debug!("Giving up on {lost} RTP packets");
+                    self.lost_packets += lost as u64;
+                }
+            }
+
+            let Some((_, packet)) = self.packets.pop_first() else {
+                break;
+            };
+            self.next_sequence_number = Some(sequence_number + 1);
+            self.frames.extend(self.depayloader.push(&packet));
+        }
+    }
+
+    /// Whether the first packet waiting has waited for the latency
+    fn waited_enough(&self) -> bool {
+        let (Some((_, first)), Some((_, last))) = (
+            self.packets.first_key_value(),
+            self.packets.last_key_value(),
+        ) else {
+            return false;
+        };
+
+        let waited = last.timestamp.wrapping_sub(first.timestamp) as i32;
+        waited >= self.latency as i32 || self.packets.len() >= MAX_JITTER_PACKETS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    /// An Annex-B NAL unit with the given header and `size` bytes of payload without start codes
+    fn nal(header: &[u8], size: usize) -> Vec<u8> {
+        let mut nal = ANNEXB_START_CODE.to_vec();
+        nal.extend_from_slice(header);
+        nal.extend((0..size).map(|byte| (byte % 250) as u8 + 1));
+        nal
+    }
+
+    /// Sends the packets over UDP on the loopback interface, in the given order
+    fn loopback(packets: &[RtpPacket], order: &[usize]) -> Vec<Vec<u8>> {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().unwrap();
+
+        let mut datagrams = vec![];
+        let mut buffer = [0; 2048];
+        for &index in order {
+            sender.send_to(&packets[index].to_bytes(), address).unwrap();
+            let size = receiver.recv(&mut buffer).unwrap();
+            datagrams.push(buffer[..size].to_vec());
+        }
+
+        datagrams
+    }
+
+    fn flush_frames(jitter_buffer: &mut RtpJitterBuffer) -> Vec<RtpFrame> {
+        jitter_buffer.flush();
+        std::iter::from_fn(|| jitter_buffer.pop_frame()).collect()
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = RtpPacket {
+            payload_type: 96,
+            marker: true,
+            sequence_number: 0xfffe,
+            timestamp: 0x1234_5678,
+            ssrc: 0xdead_beef,
+            payload: vec![1, 2, 3],
+        };
+
+        let data = packet.to_bytes();
+        assert_eq!(data.len(), RTP_HEADER_SIZE + 3);
+        assert_eq!(RtpPacket::parse(&data), Some(packet));
+        assert_eq!(RtpPacket::parse(&data[..RTP_HEADER_SIZE - 1]), None);
+    }
+
+    #[test]
+    fn avc_loopback() {
+        let mut payloader = RtpPayloader::new(RtpCodec::H264, 96, 0x1234).unwrap();
+        payloader.set_initial_state(65530, 0xffff_fff0);
+        let config = [nal(&[0x67], 10), nal(&[0x68], 3)].concat();
+        payloader.set_codec_config(&config);
+
+        let mut packets = vec![];
+        let mut expected = vec![];
+        for index in 0..10 {
+            let sync = index % 5 == 0;
+            let frame = if sync {
+                nal(&[0x65, 0x88], 5000)
+            } else {
+                [nal(&[0x06], 4), nal(&[0x41, 0x9a], 100 + index as usize)].concat()
+            };
+
+            let pts_us = index * 33_333;
+            let frame_packets = payloader.packetize(&frame, pts_us, sync);
+            assert!(frame_packets
+                .iter()
+                .all(|packet| packet.to_bytes().len() <= RTP_DEFAULT_MTU));
+            assert!(frame_packets.last().unwrap().marker);
+            assert!(frame_packets
+                .iter()
+                .rev()
+                .skip(1)
+                .all(|packet| !packet.marker));
+            packets.extend(frame_packets);
+
+            // The parameter sets are sent again in front of keyframes
+            let frame = if sync {
+                [config.clone(), frame].concat()
+            } else {
+                frame
+            };
+            expected.push((frame, pts_us, sync));
+        }
+        assert_eq!(
+            payloader.sequence_number(),
+            65530u16.wrapping_add(packets.len() as u16)
+        );
+
+        // Some packets arrive out of order
+        let mut order: Vec<usize> = (0..packets.len()).collect();
+        order.swap(3, 5);
+        order.swap(10, 11);
+
+        let mut jitter_buffer = RtpJitterBuffer::new(RtpCodec::H264, 100).unwrap();
+        for datagram in loopback(&packets, &order) {
+            assert!(jitter_buffer.push_datagram(&datagram));
+        }
+
+        let frames = flush_frames(&mut jitter_buffer);
+        assert_eq!(frames.len(), expected.len());
+        for (frame, (data, pts_us, sync)) in frames.iter().zip(&expected) {
+            assert_eq!(&frame.data, data);
+            // The 90 kHz clock rounds the times
+            assert!((frame.pts_us - pts_us).abs() < 20);
+            assert_eq!(frame.sync, *sync);
+        }
+        assert_eq!(jitter_buffer.lost_packets(), 0);
+        assert!(!jitter_buffer.push_datagram(&[0x80; 4]));
+
+        // A lost fragment drops its NAL unit, the parameter sets of the keyframe are kept
+        let mut jitter_buffer = RtpJitterBuffer::new(RtpCodec::H264, 100).unwrap();
+        for (index, packet) in packets.iter().enumerate() {
+            if index != 2 {
+                jitter_buffer.push(packet.clone());
+            }
+        }
+
+        let frames = flush_frames(&mut jitter_buffer);
+        assert_eq!(frames.len(), expected.len());
+        assert_eq!(frames[0].data, config);
+        assert!(!frames[0].sync);
+        assert_eq!(frames[1].data, expected[1].0);
+        assert_eq!(jitter_buffer.lost_packets(), 1);
+    }
+
+    #[test]
+    fn hevc_aggregation_and_fragmentation() {
+        let mut payloader = RtpPayloader::new(RtpCodec::Hevc, 97, 1).unwrap();
+        payloader.set_mtu(500);
+
+        let frame = [
+            nal(&[0x40, 0x01], 20),
+            nal(&[0x42, 0x01], 30),
+            nal(&[0x44, 0x01], 5),
+            nal(&[0x26, 0x01], 3000),
+            nal(&[0x02, 0x01], 50),
+        ]
+        .concat();
+        let packets = payloader.packetize(&frame, 0, true);
+        assert!(packets.iter().all(|packet| packet.to_bytes().len() <= 500));
+
+        // The parameter sets are aggregated, the slice is fragmented
+        assert_eq!(packets[0].payload[0] >> 1, HEVC_AP);
+        assert_eq!(packets[1].payload[0] >> 1, HEVC_FU);
+
+        let mut depayloader = RtpDepayloader::new(RtpCodec::Hevc).unwrap();
+        let frames: Vec<RtpFrame> = packets
+            .iter()
+            .flat_map(|packet| depayloader.push(packet))
+            .collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, frame);
+        assert!(frames[0].sync);
+    }
+
+    #[test]
+    fn short_nal_units() {
+        let mut payloader = RtpPayloader::new(RtpCodec::Hevc, 97, 1).unwrap();
+        // A one byte NAL unit among the parameter sets
+        payloader.set_codec_config(&[nal(&[0x40, 0x01], 20), nal(&[0x42], 0)].concat());
+
+        // And one in the middle of the NAL units aggregated together
+        let slices = [nal(&[0x26, 0x01], 30), nal(&[0x02, 0x01], 50)];
+        let frame = [slices[0].clone(), nal(&[0x02], 0), slices[1].clone()].concat();
+        let packets = payloader.packetize(&frame, 0, true);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload[0] >> 1, HEVC_AP);
+
+        let mut depayloader = RtpDepayloader::new(RtpCodec::Hevc).unwrap();
+        let frames = depayloader.push(&packets[0]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].data,
+            [nal(&[0x40, 0x01], 20), slices.concat()].concat()
+        );
+    }
+
+    #[test]
+    fn aac_limits() {
+        assert!(RtpPayloader::new(RtpCodec::Aac { sample_rate: 0 }, 98, 2).is_none());
+        assert!(RtpDepayloader::new(RtpCodec::Aac { sample_rate: 0 }).is_none());
+        assert!(RtpJitterBuffer::new(RtpCodec::Aac { sample_rate: 0 }, 100).is_none());
+
+        // The size of the frame has 13 bits in the AU header
+        let mut payloader = RtpPayloader::new(RtpCodec::Aac { sample_rate: 48000 }, 98, 2).unwrap();
+        assert!(payloader
+            .packetize(&[0; AAC_MAX_FRAME_SIZE + 1], 0, true)
+            .is_empty());
+
+        let packets = payloader.packetize(&[0; AAC_MAX_FRAME_SIZE], 0, true);
+        assert_eq!(
+            packets.len(),
+            AAC_MAX_FRAME_SIZE.div_ceil(RTP_DEFAULT_MTU - RTP_HEADER_SIZE - 4)
+        );
+        assert_eq!(packets[0].payload[2..4], [0xff, 0xf8]);
+        assert_eq!(payloader.sequence_number(), packets.len() as u16);
+    }
+
+    #[test]
+    fn aac_fragmentation() {
+        let codec = RtpCodec::Aac { sample_rate: 48000 };
+        let mut payloader = RtpPayloader::new(codec, 98, 2).unwrap();
+        payloader.set_mtu(300);
+        let mut jitter_buffer = RtpJitterBuffer::new(codec, 0).unwrap();
+
+        for index in 0..5 {
+            let frame: Vec<u8> = (0..100 + index * 200).map(|byte| byte as u8).collect();
+            let pts_us = index as i64 * 1024 * 1_000_000 / 48000;
+
+            let packets = payloader.packetize(&frame, pts_us, true);
+            assert_eq!(packets.len(), 1 + (frame.len() + 4) / 296);
+            assert!(packets.last().unwrap().marker);
+            assert!(packets.iter().rev().skip(1).all(|packet| !packet.marker));
+
+            for packet in packets {
+                jitter_buffer.push(packet);
+            }
+
+            let received = jitter_buffer.pop_frame().unwrap();
+            assert_eq!(received.data, frame);
+            assert_eq!(received.pts_us, pts_us);
+            assert!(jitter_buffer.pop_frame().is_none());
+        }
+    }
+
+    #[test]
+    fn aac_lost_fragment() {
+        let codec = RtpCodec::Aac { sample_rate: 48000 };
+        let mut payloader = RtpPayloader::new(codec, 98, 2).unwrap();
+        payloader.set_mtu(300);
+
+        let first: Vec<u8> = (0..1000).map(|byte| byte as u8).collect();
+        let mut packets = payloader.packetize(&first, 0, true);
+        packets.remove(1);
+        packets.extend(payloader.packetize(&[1, 2, 3], 21_333, true));
+
+        // The frame missing a fragment is dropped, the next one is kept
+        let mut depayloader = RtpDepayloader::new(codec).unwrap();
+        let frames: Vec<RtpFrame> = packets
+            .iter()
+            .flat_map(|packet| depayloader.push(packet))
+            .collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, [1, 2, 3]);
+        assert_eq!(frames[0].timestamp, 1024);
+    }
+
+    #[test]
+    fn aac_access_units() {
+        // Two frames of 3 and 2 bytes in one packet
+        let packet = RtpPacket {
+            payload_type: 98,
+            marker: true,
+            sequence_number: 1,
+            timestamp: 1000,
+            ssrc: 3,
+            payload: vec![0, 32, 0, 3 << 3, 0, 2 << 3, 1, 2, 3, 4, 5],
+        };
+
+        let mut depayloader = RtpDepayloader::new(RtpCodec::Aac { sample_rate: 48000 }).unwrap();
+        let frames = depayloader.push(&RtpPacket::parse(&packet.to_bytes()).unwrap());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (&frames[0].data[..], frames[0].timestamp),
+            (&[1, 2, 3][..], 1000)
+        );
+        assert_eq!(
+            (&frames[1].data[..], frames[1].timestamp),
+            (&[4, 5][..], 2024)
+        );
+        assert_eq!(frames[1].pts_us, 1024 * 1_000_000 / 48000);
+    }
+
+    #[test]
+    fn opus() {
+        let mut payloader = RtpPayloader::new(RtpCodec::Opus, 111, 2).unwrap();
+        let mut depayloader = RtpDepayloader::new(RtpCodec::Opus).unwrap();
+
+        for index in 0..3 {
+            let packets = payloader.packetize(&[1, 2, index as u8], index * 20_000, true);
+            assert_eq!(packets.len(), 1);
+            assert!(packets[0].marker);
+            assert_eq!(packets[0].timestamp, index as u32 * 960);
+
+            let frames = depayloader.push(&packets[0]);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(frames[0].data, [1, 2, index as u8]);
+            assert_eq!(frames[0].pts_us, index * 20_000);
+        }
+    }
+}
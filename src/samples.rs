@@ -1,3 +1,12 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+#[cfg(target_os = "android")]
+use crate::COLOR_FORMAT_YUV420_FLEXIBLE;
+use crate::{
+    COLOR_FORMAT_YUV420_PACKED_PLANAR, COLOR_FORMAT_YUV420_PACKED_SEMIPLANAR,
+    COLOR_FORMAT_YUV420_PLANAR, COLOR_FORMAT_YUV420_SEMIPLANAR, COLOR_FORMAT_YUV_P010,
+};
+
 /// Represents a codec frame (either audio or video)
 #[derive(Debug)]
 pub enum Frame<'a> {
@@ -6,7 +15,10 @@ pub enum Frame<'a> {
 }
 
 pub const ENCODING_PCM_16BIT: usize = 2;
+pub const ENCODING_PCM_8BIT: usize = 3;
 pub const ENCODING_PCM_FLOAT: usize = 4;
+pub const ENCODING_PCM_24BIT_PACKED: usize = 21;
+pub const ENCODING_PCM_32BIT: usize = 22;
 
 /// Represents an audio sample format, and contains the samples buffer
 #[derive(Debug)]
@@ -16,8 +28,8 @@ pub enum SampleFormat<'a> {
 }
 
 impl SampleFormat<'_> {
-    /// Returns the number of samples contained by this format
-    fn samples(&self, channels: u32) -> usize {
+    /// Returns the number of samples per channel contained by this format
+    pub fn samples(&self, channels: u32) -> usize {
         match self {
             SampleFormat::S16(value) => value.len() / channels as usize,
             SampleFormat::F32(value) => value.len() / channels as usize,
@@ -25,7 +37,7 @@ impl SampleFormat<'_> {
     }
 
     /// Returns the size of one sample represented by this format
-    fn sample_size(&self) -> usize {
+    pub fn sample_size(&self) -> usize {
         match self {
             SampleFormat::S16(_) => std::mem::size_of::<i16>(),
             SampleFormat::F32(_) => std::mem::size_of::<f32>(),
@@ -33,7 +45,7 @@ impl SampleFormat<'_> {
     }
 
     /// Returns the size of one frame represented by this format. It needs the number of channels stored in this buffer to determine the value
-    fn frame_size(&self, channels: u32) -> usize {
+    pub fn frame_size(&self, channels: u32) -> usize {
         self.sample_size() * channels as usize
     }
 }
@@ -57,7 +69,7 @@ impl<'a> AudioFrame<'a> {
     }
 
     /// Returns the sample format for this frame
-    pub fn format(&self) -> &SampleFormat {
+    pub fn format(&self) -> &SampleFormat<'_> {
         &self.format
     }
 
@@ -65,6 +77,21 @@ impl<'a> AudioFrame<'a> {
     pub fn nb_samples(&self) -> usize {
         self.format.samples(self.channels)
     }
+
+    /// Returns the frame without its first `count` samples (per channel)
+    pub fn skip_samples(self, count: usize) -> Self {
+        let start = count.saturating_mul(self.channels as usize);
+
+        let format = match self.format {
+            SampleFormat::S16(value) => SampleFormat::S16(&value[start.min(value.len())..]),
+            SampleFormat::F32(value) => SampleFormat::F32(&value[start.min(value.len())..]),
+        };
+
+        Self {
+            format,
+            channels: self.channels,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -77,8 +104,470 @@ pub enum VideoFrame<'a> {
     RawFrame(RawVideoFrame<'a>),
 }
 
+/// The layout of the samples of a raw YUV 4:2:0 video frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// Planar: the Y plane, then the U plane and the V plane
+    I420,
+    /// Planar: the Y plane, then the V plane and the U plane
+    Yv12,
+    /// Semi planar: the Y plane, then a plane of interleaved U and V samples
+    Nv12,
+    /// Semi planar: the Y plane, then a plane of interleaved V and U samples
+    Nv21,
+    /// Like NV12, with 16 bit little endian samples holding 10 bit values in their high bits
+    P010,
+}
+
+impl PixelFormat {
+    /// The pixel format of a `color-format` value.
+    ///
+    /// The flexible format doesn't tell the layout of the buffers, so it returns `None`.
+    /// [RawVideoFrame::with_flexible_layout] works it out from the samples
+    pub fn from_color_format(color_format: i32) -> Option<Self> {
+        match color_format {
+            COLOR_FORMAT_YUV420_PLANAR | COLOR_FORMAT_YUV420_PACKED_PLANAR => Some(Self::I420),
+            COLOR_FORMAT_YUV420_SEMIPLANAR | COLOR_FORMAT_YUV420_PACKED_SEMIPLANAR => {
+                Some(Self::Nv12)
+            }
+            COLOR_FORMAT_YUV_P010 => Some(Self::P010),
+            _ => None,
+        }
+    }
+
+    /// The `color-format` value of this pixel format, if MediaCodec has one
+    pub fn color_format(&self) -> Option<i32> {
+        match self {
+            Self::I420 => Some(COLOR_FORMAT_YUV420_PLANAR),
+            Self::Nv12 => Some(COLOR_FORMAT_YUV420_SEMIPLANAR),
+            Self::P010 => Some(COLOR_FORMAT_YUV_P010),
+            Self::Yv12 | Self::Nv21 => None,
+        }
+    }
+
+    /// The size of one sample, in bytes
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            Self::P010 => 2,
+            _ => 1,
+        }
+    }
+
+    /// The size of a frame without any padding, in bytes
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+
+        (width * height + 2 * chroma) * self.bytes_per_sample()
+    }
+}
+
+/// The visible rectangle of a video frame.
+///
+/// Like the `crop-*` keys of `MediaFormat`, the right and bottom edges are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoCrop {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl VideoCrop {
+    /// The whole frame
+    pub fn full(width: u32, height: u32) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            right: width.saturating_sub(1),
+            bottom: height.saturating_sub(1),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.right + 1 - self.left
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bottom + 1 - self.top
+    }
+}
+
+/// A plane of a raw video frame
+#[derive(Debug, Clone, Copy)]
+pub struct VideoPlane<'a> {
+    /// The data of the plane, starting with its first sample
+    pub data: &'a [u8],
+    /// The distance between two rows, in bytes
+    pub row_stride: usize,
+    /// The distance between two samples of a row, in bytes
+    pub pixel_stride: usize,
+    /// The size of a sample, 2 for little endian 16 bit samples
+    pub bytes_per_sample: usize,
+}
+
+impl VideoPlane<'_> {
+    /// Returns the sample at column `x` of row `y`, or 0 if it's out of the buffer
+    pub fn sample(&self, x: usize, y: usize) -> u16 {
+        let index = y * self.row_stride + x * self.pixel_stride;
+
+        match self.bytes_per_sample {
+            2 => self
+                .data
+                .get(index..index + 2)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]])),
+            _ => self.data.get(index).map_or(0, |byte| *byte as u16),
+        }
+    }
+
+    /// Returns `count` samples of row `y` starting at column `x`, when they are contiguous 8 bit samples
+    pub fn row(&self, x: usize, y: usize, count: usize) -> Option<&[u8]> {
+        if self.pixel_stride != 1 {
+            return None;
+        }
+
+        let start = y * self.row_stride + x;
+        self.data.get(start..start + count)
+    }
+}
+
 /// A raw video frame with pixel format and a byte buffer to read the data
 #[derive(Debug)]
 pub struct RawVideoFrame<'a> {
     buffer: &'a [u8],
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    /// The distance between two rows of the Y plane, in bytes
+    stride: usize,
+    /// The number of rows of the Y plane, the chroma planes start after them
+    slice_height: usize,
+    crop: VideoCrop,
+}
+
+impl<'a> RawVideoFrame<'a> {
+    /// Describes a frame without padding
+    pub fn new(buffer: &'a [u8], format: PixelFormat, width: u32, height: u32) -> Self {
+        Self {
+            buffer,
+            format,
+            width,
+            height,
+            stride: width as usize * format.bytes_per_sample(),
+            slice_height: height as usize,
+            crop: VideoCrop::full(width, height),
+        }
+    }
+
+    /// Sets the distance between two rows of the Y plane in bytes, and the number of rows of the Y plane
+    pub fn with_layout(mut self, stride: usize, slice_height: usize) -> Self {
+        self.stride = stride;
+        self.slice_height = slice_height;
+        self
+    }
+
+    /// Sets the visible rectangle of the frame. It's limited to the size of the frame
+    pub fn with_crop(mut self, crop: VideoCrop) -> Self {
+        let right = crop.right.min(self.width.saturating_sub(1));
+        let bottom = crop.bottom.min(self.height.saturating_sub(1));
+
+        self.crop = VideoCrop {
+            left: crop.left.min(right),
+            top: crop.top.min(bottom),
+            right,
+            bottom,
+        };
+        self
+    }
+
+    /// Picks the layout of a frame in `COLOR_FORMAT_YUV420_FLEXIBLE`, which ByteBuffer output doesn't tell.
+    ///
+    /// Decoders fill these buffers as I420 or NV12, with the same stride and slice height, so the chroma samples
+    /// start at the same offset either way. Interleaved U and V samples differ more from the next sample than from
+    /// the one after it, while planar rows change smoothly. Frames without any chroma detail are taken as NV12
+    pub fn with_flexible_layout(mut self) -> Self {
+        let luma_size = self.stride * self.slice_height;
+        let row_size = (self.width.div_ceil(2) * 2) as usize;
+        // The rows at the start of the U plane of I420 and of the UV plane of NV12
+        let rows = (self.height.div_ceil(2) / 2) as usize;
+
+        let (mut next, mut second) = (0u64, 0u64);
+        for row in 0..rows {
+            let start = luma_size + row * self.stride;
+            let Some(row) = self.buffer.get(start..start + row_size) else {
+                break;
+            };
+
+            for samples in row.windows(3) {
+                next += samples[0].abs_diff(samples[1]) as u64;
+                second += samples[0].abs_diff(samples[2]) as u64;
+            }
+        }
+
+        self.format = if next < second {
+            PixelFormat::I420
+        } else {
+            PixelFormat::Nv12
+        };
+        self
+    }
+
+    /// Describes a decoder output buffer, from the `width`, `height`, `color-format`, `stride`, `slice-height`
+    /// and `crop-*` keys of the output format.
+    ///
+    /// The layout of `COLOR_FORMAT_YUV420_FLEXIBLE` buffers comes from [with_flexible_layout](Self::with_flexible_layout)
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(buffer: &'a [u8], format: &MediaFormat) -> Option<Self> {
+        let width = format.get_i32("width").filter(|width| *width > 0)? as u32;
+        let height = format.get_i32("height").filter(|height| *height > 0)? as u32;
+        let color_format = format.get_i32("color-format")?;
+        let flexible = color_format == COLOR_FORMAT_YUV420_FLEXIBLE;
+        // Both layouts of the flexible format have 8 bit samples, so the default stride is the same
+        let pixel_format = if flexible {
+            PixelFormat::Nv12
+        } else {
+            PixelFormat::from_color_format(color_format)?
+        };
+
+        let frame = Self::new(buffer, pixel_format, width, height);
+        let stride = format
+            .get_i32("stride")
+            .filter(|stride| *stride as usize >= frame.stride)
+            .map_or(frame.stride, |stride| stride as usize);
+        let slice_height = format
+            .get_i32("slice-height")
+            .filter(|slice_height| *slice_height as u32 >= height)
+            .map_or(frame.slice_height, |slice_height| slice_height as usize);
+
+        let full = VideoCrop::full(width, height);
+        let crop = |key: &str, default: u32| {
+            format
+                .get_i32(key)
+                .filter(|value| *value >= 0)
+                .map_or(default, |value| value as u32)
+        };
+
+        let frame = frame
+            .with_layout(stride, slice_height)
+            .with_crop(VideoCrop {
+                left: crop("crop-left", full.left),
+                top: crop("crop-top", full.top),
+                right: crop("crop-right", full.right),
+                bottom: crop("crop-bottom", full.bottom),
+            });
+
+        Some(if flexible {
+            frame.with_flexible_layout()
+        } else {
+            frame
+        })
+    }
+
+    /// The whole buffer, with the padding
+    pub fn buffer(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn slice_height(&self) -> usize {
+        self.slice_height
+    }
+
+    pub fn crop(&self) -> VideoCrop {
+        self.crop
+    }
+
+    /// Returns the Y, U and V planes, or `None` if the buffer is too small for the frame or the frame is empty
+    pub fn planes(&self) -> Option<[VideoPlane<'a>; 3]> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let luma_size = self.stride * self.slice_height;
+        let chroma_height = self.height.div_ceil(2) as usize;
+        let chroma_width = self.width.div_ceil(2) as usize;
+
+        // The offsets of U and V, their row stride and pixel stride
+        let (u, v, row_stride, pixel_stride) = match self.format {
+            PixelFormat::I420 | PixelFormat::Yv12 => {
+                let chroma_stride = self.stride.div_ceil(2);
+                let first = luma_size;
+                let second = luma_size + chroma_stride * self.slice_height.div_ceil(2);
+
+                match self.format {
+                    PixelFormat::I420 => (first, second, chroma_stride, 1),
+                    _ => (second, first, chroma_stride, 1),
+                }
+            }
+            // The interleaved chroma rows of odd widths are a sample longer than the luma rows
+            PixelFormat::Nv12 => (luma_size, luma_size + 1, self.stride.next_multiple_of(2), 2),
+            PixelFormat::Nv21 => (luma_size + 1, luma_size, self.stride.next_multiple_of(2), 2),
+            PixelFormat::P010 => (luma_size, luma_size + 2, self.stride.next_multiple_of(4), 4),
+        };
+
+        // The last samples of the planes must be in the buffer, the padding after them doesn't
+        let end =
+            |offset: usize, row_stride: usize, pixel_stride: usize, width: usize, height: usize| {
+                offset + (height - 1) * row_stride + (width - 1) * pixel_stride + bytes_per_sample
+            };
+        let needed = end(
+            0,
+            self.stride,
+            bytes_per_sample,
+            self.width as usize,
+            self.height as usize,
+        )
+        .max(end(
+            u,
+            row_stride,
+            pixel_stride,
+            chroma_width,
+            chroma_height,
+        ))
+        .max(end(
+            v,
+            row_stride,
+            pixel_stride,
+            chroma_width,
+            chroma_height,
+        ));
+
+        if self.buffer.len() < needed {
+            return None;
+        }
+
+        let plane = |offset: usize, row_stride: usize, pixel_stride: usize| VideoPlane {
+            data: &self.buffer[offset..],
+            row_stride,
+            pixel_stride,
+            bytes_per_sample,
+        };
+
+        Some([
+            plane(0, self.stride, bytes_per_sample),
+            plane(u, row_stride, pixel_stride),
+            plane(v, row_stride, pixel_stride),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::COLOR_FORMAT_YUV420_FLEXIBLE;
+
+    /// A 16x8 frame with a stride of 20 and a slice height of 10, its chroma in `format` (I420 or NV12).
+    /// U and V are smooth gradients, far from each other
+    fn yuv420(format: PixelFormat) -> Vec<u8> {
+        let (stride, slice_height) = (20, 10);
+        let mut buffer = vec![0; stride * slice_height * 3 / 2];
+        for y in 0..8 {
+            for x in 0..16 {
+                buffer[y * stride + x] = (y * 16 + x) as u8;
+            }
+        }
+
+        let luma_size = stride * slice_height;
+        for y in 0..4 {
+            for x in 0..8 {
+                let (u, v) = (40 + (y * 8 + x) as u8, 220 - (y * 8 + x) as u8);
+                match format {
+                    PixelFormat::I420 => {
+                        buffer[luma_size + y * stride / 2 + x] = u;
+                        buffer[luma_size + stride * slice_height / 4 + y * stride / 2 + x] = v;
+                    }
+                    _ => {
+                        buffer[luma_size + y * stride + 2 * x] = u;
+                        buffer[luma_size + y * stride + 2 * x + 1] = v;
+                    }
+                }
+            }
+        }
+
+        buffer
+    }
+
+    #[test]
+    fn planes() {
+        for format in [PixelFormat::I420, PixelFormat::Nv12] {
+            let buffer = yuv420(format);
+            let frame = RawVideoFrame::new(&buffer, format, 16, 8).with_layout(20, 10);
+
+            let [y, u, v] = frame.planes().unwrap();
+            assert_eq!(y.sample(3, 2), 35);
+            assert_eq!(y.row(0, 7, 16).unwrap()[15], 127);
+            assert_eq!((u.sample(5, 3), v.sample(5, 3)), (69, 191));
+            assert_eq!(u.row(0, 0, 2).is_some(), format == PixelFormat::I420);
+        }
+
+        // The padding after the last samples can be missing, not the samples
+        let buffer = yuv420(PixelFormat::Nv12);
+        let needed = 20 * 10 + 3 * 20 + 16;
+        let frame = |size| RawVideoFrame::new(&buffer[..size], PixelFormat::Nv12, 16, 8);
+        assert!(frame(needed).with_layout(20, 10).planes().is_some());
+        assert!(frame(needed - 1).with_layout(20, 10).planes().is_none());
+    }
+
+    #[test]
+    fn empty_frames() {
+        for (width, height) in [(0, 0), (0, 8), (16, 0)] {
+            let frame = RawVideoFrame::new(&[], PixelFormat::I420, width, height);
+            assert!(frame.planes().is_none());
+
+            let frame = frame
+                .with_crop(VideoCrop::full(0, 0))
+                .with_flexible_layout();
+            assert!(frame.planes().is_none());
+        }
+    }
+
+    #[test]
+    fn flexible_layout() {
+        assert_eq!(
+            PixelFormat::from_color_format(COLOR_FORMAT_YUV420_FLEXIBLE),
+            None
+        );
+
+        for format in [PixelFormat::I420, PixelFormat::Nv12] {
+            let buffer = yuv420(format);
+            for guess in [PixelFormat::I420, PixelFormat::Nv12] {
+                let frame = RawVideoFrame::new(&buffer, guess, 16, 8)
+                    .with_layout(20, 10)
+                    .with_flexible_layout();
+                assert_eq!(frame.pixel_format(), format);
+            }
+        }
+
+        // Without chroma detail, both layouts give the same picture
+        let buffer = vec![128; 16 * 8 * 3 / 2];
+        let frame = RawVideoFrame::new(&buffer, PixelFormat::I420, 16, 8).with_flexible_layout();
+        assert_eq!(frame.pixel_format(), PixelFormat::Nv12);
+    }
+
+    #[test]
+    fn frame_sizes() {
+        assert_eq!(PixelFormat::I420.frame_size(5, 3), 15 + 2 * 6);
+        assert_eq!(PixelFormat::P010.frame_size(4, 2), 2 * (8 + 2 * 2));
+        assert_eq!(
+            PixelFormat::from_color_format(COLOR_FORMAT_YUV420_SEMIPLANAR),
+            Some(PixelFormat::Nv12)
+        );
+        assert_eq!(PixelFormat::Nv21.color_format(), None);
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+use log::{debug, warn};
+
+use crate::{BufferFlag, BufferInfo, CodecOutputBuffer, MediaFormat, MediaMuxer, MediaStatus};
+
+/// A sample that arrived before the muxer could be started
+#[derive(Debug)]
+struct PendingSample {
+    muxer_index: usize,
+    data: Vec<u8>,
+    info: BufferInfo,
+}
+
+#[derive(Debug, Default)]
+struct SinkTrack {
+    /// The muxer track index, once the output format of the encoder is known
+    muxer_index: Option<usize>,
+    /// Whether the encoder signalled end of stream on this track
+    ended: bool,
+}
+
+/// Connects one or more encoders to a [MediaMuxer](MediaMuxer).
+///
+/// Encoder output buffers are handed over as they are dequeued. The sink takes care of the usual chores:
+/// - Codec config buffers are skipped, the muxer gets the codec specific data from the track format instead
+/// - A track is added to the muxer when its first sample arrives, using the encoder's output format at that time
+/// - The muxer is only started once the output format of every track is known
+/// - Samples that arrive before the muxer is started are queued (copied), and written as soon as it starts
+///
+/// Once the muxer is started, samples are written straight out of the codec output buffers, without any copying.
+/// Encoders that emit B-frames need [TimestampPolicy::Passthrough](crate::TimestampPolicy::Passthrough) on the muxer,
+/// see [muxer_mut](Self::muxer_mut).
+#[derive(Debug)]
+pub struct EncoderMuxerSink {
+    muxer: MediaMuxer,
+    tracks: Vec<SinkTrack>,
+    pending: VecDeque<PendingSample>,
+    started: bool,
+}
+
+impl EncoderMuxerSink {
+    /// Creates a sink writing into `muxer`.
+    ///
+    /// `track_count` is the number of encoders that will write into this sink. Each of them is identified by an index in `0..track_count`
+    pub fn new(muxer: MediaMuxer, track_count: usize) -> Self {
+        Self {
+            muxer,
+            tracks: (0..track_count).map(|_| SinkTrack::default()).collect(),
+            pending: VecDeque::new(),
+            started: false,
+        }
+    }
+
+    /// Returns the underlying muxer. Useful to set the location or orientation hint before the muxer gets started
+    pub fn muxer_mut(&mut self) -> &mut MediaMuxer {
+        &mut self.muxer
+    }
+
+    /// Returns the number of tracks handled by this sink
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns whether the muxer has been started
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns the number of samples waiting for the muxer to start
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns whether every track has signalled end of stream
+    pub fn is_finished(&self) -> bool {
+        self.tracks.iter().all(|track| track.ended)
+    }
+
+    /// Sets the output format of a track explicitly.
+    ///
+    /// This is not needed in most cases, as the sink fetches the format from the encoder when the first sample arrives.
+    /// It is useful when the format is known ahead of time, or comes from somewhere else entirely
+    pub fn set_format(&mut self, track: usize, format: MediaFormat) -> Result<(), MediaStatus> {
+        let entry = self
+            .tracks
+            .get(track)
+            .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        if entry.muxer_index.is_some() {
+            // The format can't change after the track was added to the muxer
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        let index = self.muxer.add_track(format)?;
+        self.tracks[track].muxer_index = Some(index as usize);
+
+        self.start_if_ready()
+    }
+
+    /// Writes an encoder output buffer for `track` into the muxer.
+    ///
+    /// Codec config buffers and empty buffers are skipped. The buffer can be released as soon as this function returns
+    pub fn write(&mut self, track: usize, buffer: &CodecOutputBuffer) -> Result<(), MediaStatus> {
+        if track >= self.tracks.len() {
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let info = *buffer.info();
+        let flags = info.flags() as i32;
+
+        if BufferFlag::EndOfStream.is_contained_in(flags) {
+            self.tracks[track].ended = true;
+        }
+
+        if BufferFlag::CodecConfig.is_contained_in(flags) {
+            // The codec specific data is part of the output format, MediaMuxer doesn't want it as a sample
+            return Ok(());
+        }
+
+        let data = match buffer.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return Ok(()),
+        };
+
+        if self.tracks[track].muxer_index.is_none() {
+            let format = buffer
+                .codec_output_format()
+                .ok_or(MediaStatus::ErrorInvalidOperation)?;
+
+            debug!(
+                "Output format for track {track} is known: {}",
+                format.to_string()
+            );
+            self.set_format(track, format)?;
+        }
+
+        // The data slice already starts at the buffer offset
+        let info = BufferInfo::new(
+            0,
+            data.len() as i32,
+            info.presentation_time_us(),
+            info.flags(),
+        );
+
+        let muxer_index = self.tracks[track].muxer_index.unwrap();
+
+        if self.started {
+            return self.muxer.write_sample_data(muxer_index, data, &info);
+        }
+
+        self.pending.push_back(PendingSample {
+            muxer_index,
+            data: data.to_vec(),
+            info,
+        });
+
+        Ok(())
+    }
+
+    /// Stops the muxer and finalizes the output.
+    ///
+    /// If some tracks never produced a sample, the muxer is started with the tracks that did, so the output is still usable
+    pub fn finish(mut self) -> Result<(), MediaStatus> {
+        self.start_remaining()?;
+        self.muxer.stop()
+    }
+
+    /// Same as [finish](Self::finish), for a muxer created with `MediaMuxer::in_memory`.
+    ///
+    /// Returns the finished container
+    pub fn finish_into_bytes(mut self) -> Result<Vec<u8>, MediaStatus> {
+        self.start_remaining()?;
+        self.muxer.stop_into_bytes()
+    }
+
+    /// Starts the muxer with whatever tracks are known, if it didn't start yet
+    fn start_remaining(&mut self) -> Result<(), MediaStatus> {
+        if self.started {
+            return Ok(());
+        }
+
+        if self.tracks.iter().all(|track| track.muxer_index.is_none()) {
+            warn!("No samples were written to the sink!");
+            return Ok(());
+        }
+
+        warn!("Starting the muxer without all tracks, some encoders produced no output");
+        self.start()
+    }
+
+    /// Starts the muxer if the output formats of all tracks are known
+    fn start_if_ready(&mut self) -> Result<(), MediaStatus> {
+        if self.started || self.tracks.iter().any(|track| track.muxer_index.is_none()) {
+            return Ok(());
+        }
+
+        self.start()
+    }
+
+    /// Starts the muxer and writes out every queued sample
+    fn start(&mut self) -> Result<(), MediaStatus> {
+        self.muxer.start()?;
+        self.started = true;
+
+        while let Some(sample) = self.pending.pop_front() {
+            self.muxer
+                .write_sample_data(sample.muxer_index, &sample.data, &sample.info)?;
+        }
+
+        Ok(())
+    }
+}
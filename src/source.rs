@@ -0,0 +1,311 @@
+use std::io::{Read, Seek};
+
+#[cfg(target_os = "android")]
+use crate::{CodecInputBuffer, MediaExtractor};
+use crate::{IvfReader, MediaStatus, MkvDemuxer, Mp4Demuxer, SeekMode, TrackFormat, TsDemuxer};
+
+/// A source of demuxed packets, that can feed one decoder per track.
+///
+/// This is the interface of [MediaExtractor](MediaExtractor), so code written against it works the same
+/// with the pure Rust readers of this crate (like [Mp4Demuxer](Mp4Demuxer), [TsDemuxer](TsDemuxer), [MkvDemuxer](MkvDemuxer), [IvfReader](IvfReader) or [ElementaryStreamReader](crate::ElementaryStreamReader))
+pub trait PacketSource {
+    /// Returns the number of tracks in the source
+    fn track_count(&self) -> usize;
+
+    /// Returns the format of a track.
+    ///
+    /// On Android, [into_media_format](TrackFormat::into_media_format) turns it into the `MediaFormat` to create and initialize MediaCodec
+    fn track_format(&self, index: usize) -> Option<TrackFormat>;
+
+    /// Select this track to be demuxed
+    fn select_track(&mut self, index: usize);
+
+    /// Unselect this track to be demuxed
+    fn unselect_track(&mut self, index: usize);
+
+    /// Returns the track index of the current packet, or -1 if there's none
+    fn track_index(&self) -> i32;
+
+    /// Returns the time of the current packet in microseconds, or -1 if there's none
+    fn sample_time(&self) -> i64;
+
+    /// Returns the sample flags of the current packet (like [SAMPLE_FLAG_SYNC](crate::SAMPLE_FLAG_SYNC))
+    fn sample_flags(&self) -> u32;
+
+    /// Returns whether there are still packets to read
+    fn has_next(&self) -> bool;
+
+    /// Reads the current packet into `buffer`, without advancing.
+    ///
+    /// Returns the number of bytes read, or `None` if there's no packet or `buffer` is too small
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize>;
+
+    /// Advances to the next packet.
+    /// Returns true if there's still more data to read
+    fn advance(&mut self) -> bool;
+
+    /// Read a packet into `buffer` and advance the source.
+    /// Returns true if there's still more data to read
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool;
+
+    /// Seeks all selected tracks to `time_us`. Where the tracks land depends on `mode`.
+    ///
+    /// Sources that can't seek return `ErrorUnsupported`
+    fn seek_to(&mut self, _time_us: i64, _mode: SeekMode) -> Result<(), MediaStatus> {
+        Err(MediaStatus::ErrorUnsupported)
+    }
+}
+
+#[cfg(target_os = "android")]
+impl PacketSource for MediaExtractor {
+    fn track_count(&self) -> usize {
+        MediaExtractor::track_count(self)
+    }
+
+    /// Tracks with a codec [TrackFormat](TrackFormat) can't describe return `None`
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        TrackFormat::from_media_format(&MediaExtractor::track_format(self, index)?)
+    }
+
+    fn select_track(&mut self, index: usize) {
+        MediaExtractor::select_track(self, index)
+    }
+
+    fn unselect_track(&mut self, index: usize) {
+        MediaExtractor::unselect_track(self, index)
+    }
+
+    fn track_index(&self) -> i32 {
+        MediaExtractor::track_index(self)
+    }
+
+    fn sample_time(&self) -> i64 {
+        MediaExtractor::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        MediaExtractor::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        MediaExtractor::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        MediaExtractor::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        MediaExtractor::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        MediaExtractor::read_next(self, buffer)
+    }
+
+    fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        MediaExtractor::seek_to(self, time_us, mode)
+    }
+}
+
+impl<R: Read + Seek> PacketSource for Mp4Demuxer<R> {
+    fn track_count(&self) -> usize {
+        Mp4Demuxer::track_count(self)
+    }
+
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        Mp4Demuxer::track_format(self, index)
+    }
+
+    fn select_track(&mut self, index: usize) {
+        Mp4Demuxer::select_track(self, index)
+    }
+
+    fn unselect_track(&mut self, index: usize) {
+        Mp4Demuxer::unselect_track(self, index)
+    }
+
+    fn track_index(&self) -> i32 {
+        Mp4Demuxer::track_index(self)
+    }
+
+    fn sample_time(&self) -> i64 {
+        Mp4Demuxer::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        Mp4Demuxer::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        Mp4Demuxer::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        Mp4Demuxer::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        Mp4Demuxer::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        Mp4Demuxer::read_next(self, buffer)
+    }
+
+    fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        Mp4Demuxer::seek_to(self, time_us, mode)
+    }
+}
+
+impl<R: Read + Seek> PacketSource for MkvDemuxer<R> {
+    fn track_count(&self) -> usize {
+        MkvDemuxer::track_count(self)
+    }
+
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        MkvDemuxer::track_format(self, index)
+    }
+
+    fn select_track(&mut self, index: usize) {
+        MkvDemuxer::select_track(self, index)
+    }
+
+    fn unselect_track(&mut self, index: usize) {
+        MkvDemuxer::unselect_track(self, index)
+    }
+
+    fn track_index(&self) -> i32 {
+        MkvDemuxer::track_index(self)
+    }
+
+    fn sample_time(&self) -> i64 {
+        MkvDemuxer::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        MkvDemuxer::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        MkvDemuxer::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        MkvDemuxer::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        MkvDemuxer::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        MkvDemuxer::read_next(self, buffer)
+    }
+
+    fn seek_to(&mut self, time_us: i64, mode: SeekMode) -> Result<(), MediaStatus> {
+        MkvDemuxer::seek_to(self, time_us, mode)
+    }
+}
+
+impl<R: Read> PacketSource for TsDemuxer<R> {
+    fn track_count(&self) -> usize {
+        TsDemuxer::track_count(self)
+    }
+
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        TsDemuxer::track_format(self, index)
+    }
+
+    fn select_track(&mut self, index: usize) {
+        TsDemuxer::select_track(self, index)
+    }
+
+    fn unselect_track(&mut self, index: usize) {
+        TsDemuxer::unselect_track(self, index)
+    }
+
+    fn track_index(&self) -> i32 {
+        TsDemuxer::track_index(self)
+    }
+
+    fn sample_time(&self) -> i64 {
+        TsDemuxer::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        TsDemuxer::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        TsDemuxer::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        TsDemuxer::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        TsDemuxer::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        TsDemuxer::read_next(self, buffer)
+    }
+}
+
+impl<R: Read> PacketSource for IvfReader<R> {
+    fn track_count(&self) -> usize {
+        1
+    }
+
+    fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        if index != 0 {
+            return None;
+        }
+
+        IvfReader::track_format(self)
+    }
+
+    fn select_track(&mut self, _index: usize) {}
+
+    fn unselect_track(&mut self, _index: usize) {}
+
+    fn track_index(&self) -> i32 {
+        if self.has_next() {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn sample_time(&self) -> i64 {
+        IvfReader::sample_time(self)
+    }
+
+    fn sample_flags(&self) -> u32 {
+        IvfReader::sample_flags(self)
+    }
+
+    fn has_next(&self) -> bool {
+        IvfReader::has_next(self)
+    }
+
+    fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        IvfReader::read_sample(self, buffer)
+    }
+
+    fn advance(&mut self) -> bool {
+        IvfReader::advance(self)
+    }
+
+    #[cfg(target_os = "android")]
+    fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        IvfReader::read_next(self, buffer)
+    }
+}
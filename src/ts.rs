@@ -0,0 +1,1702 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    adts_frames, bitstream::ByteReader, AdtsHeader, AudioSpecificConfig,
+    AvcDecoderConfigurationRecord, AvcNalType, HevcDecoderConfigurationRecord, HevcNalType,
+    MediaStatus, NalFormat, TrackFormat, ANNEXB_START_CODE, SAMPLE_FLAG_SYNC,
+};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecInputBuffer, CodecOutputBuffer, MediaFormat};
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+const PAT_PID: u16 = 0;
+const NULL_PID: u16 = 0x1fff;
+
+const PAT_TABLE_ID: u8 = 0;
+const PMT_TABLE_ID: u8 = 2;
+
+/// PES timestamps and the PCR base are in units of a 90kHz clock, and wrap around after 33 bits
+const TS_CLOCK_RATE: i64 = 90_000;
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+/// How much of the stream is read to find the programs and the format of every stream
+const PROBE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The largest PES packet we're willing to buffer
+const MAX_PES_SIZE: usize = 64 * 1024 * 1024;
+
+/// How far the muxer puts the timestamps ahead of the PCR, to leave decoders some time to buffer
+const MUX_DELAY: i64 = 63_000;
+
+// stream_type values of the PMT
+const STREAM_TYPE_MPEG1_AUDIO: u8 = 0x03;
+const STREAM_TYPE_MPEG2_AUDIO: u8 = 0x04;
+const STREAM_TYPE_PRIVATE_DATA: u8 = 0x06;
+const STREAM_TYPE_AAC_ADTS: u8 = 0x0f;
+const STREAM_TYPE_AVC: u8 = 0x1b;
+const STREAM_TYPE_HEVC: u8 = 0x24;
+const STREAM_TYPE_AC3: u8 = 0x81;
+const STREAM_TYPE_EAC3: u8 = 0x87;
+
+// Descriptor tags of the PMT
+const REGISTRATION_DESCRIPTOR: u8 = 0x05;
+const LANGUAGE_DESCRIPTOR: u8 = 0x0a;
+const DVB_AC3_DESCRIPTOR: u8 = 0x6a;
+const DVB_EAC3_DESCRIPTOR: u8 = 0x7a;
+
+/// The number of channels of the AC-3 audio coding modes, without the LFE channel
+const AC3_ACMOD_CHANNELS: [u32; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+
+/// An elementary stream of the program, and the PES packet being reassembled
+#[derive(Debug)]
+struct TsStream {
+    pid: u16,
+    stream_type: u8,
+    format: TrackFormat,
+    /// The format has been completed from the stream data, like the SPS or an ADTS header
+    configured: bool,
+    selected: bool,
+    /// The PES packet being reassembled, `None` until the start of one is seen
+    pes: Option<Vec<u8>>,
+    continuity_counter: Option<u8>,
+    /// The last timestamp, with the wrap arounds undone
+    last_timestamp: Option<i64>,
+    /// The time of the next audio frame, for PES packets without a timestamp
+    next_time_us: Option<i64>,
+}
+
+impl TsStream {
+    fn new(pid: u16, stream_type: u8, mime: &'static str, language: Option<String>) -> Self {
+        Self {
+            pid,
+            stream_type,
+            format: TrackFormat {
+                mime,
+                track_id: pid as u32,
+                timescale: TS_CLOCK_RATE as u32,
+                language,
+                ..Default::default()
+            },
+            configured: false,
+            selected: false,
+            pes: None,
+            continuity_counter: None,
+            last_timestamp: None,
+            next_time_us: None,
+        }
+    }
+
+    /// Checks the continuity counter of a packet with a payload.
+    ///
+    /// Returns `None` for a duplicate packet, and false if packets were lost
+    fn check_continuity(&mut self, counter: u8, discontinuity: bool) -> Option<bool> {
+        let previous = self.continuity_counter.replace(counter);
+
+        match previous {
+            Some(previous) if !discontinuity => {
+                if counter == previous {
+                    return None;
+                }
+
+                Some(counter == (previous + 1) & 0xf)
+            }
+            _ => Some(true),
+        }
+    }
+
+    /// Completes the format from the start of the stream. Returns false if `data` doesn't tell enough yet
+    fn configure(&mut self, data: &[u8]) -> bool {
+        let format = &mut self.format;
+
+        match format.mime {
+            "video/avc" => {
+                let Some(record) = AvcDecoderConfigurationRecord::from_annexb(data) else {
+                    return false;
+                };
+
+                if let Some(sps) = record.parse_sps() {
+                    format.width = sps.width();
+                    format.height = sps.height();
+                    format.frame_rate = sps.frame_rate().map(|rate| rate as f32);
+                }
+                format.codec_private = record.to_bytes();
+            }
+            "video/hevc" => {
+                let Some(record) = HevcDecoderConfigurationRecord::from_annexb(data) else {
+                    return false;
+                };
+
+                if let Some(sps) = record.parse_sps() {
+                    format.width = sps.width();
+                    format.height = sps.height();
+                    format.frame_rate = sps.frame_rate().map(|rate| rate as f32);
+                }
+                format.codec_private = record.to_bytes();
+            }
+            "audio/mp4a-latm" => {
+                let Some(frame) = adts_frames(data).next() else {
+                    return false;
+                };
+
+                let config = frame.header.audio_specific_config();
+                format.sample_rate = config.sample_rate;
+                format.channel_count = config.channel_count();
+                format.codec_private = config.to_bytes();
+            }
+            "audio/ac3" | "audio/eac3" => {
+                let Some((sample_rate, channel_count)) = parse_ac3_header(data) else {
+                    return false;
+                };
+
+                format.sample_rate = sample_rate;
+                format.channel_count = channel_count;
+            }
+            _ => {
+                let Some((mime, sample_rate, channel_count)) = parse_mpeg_audio_header(data) else {
+                    return false;
+                };
+
+                format.mime = mime;
+                format.sample_rate = sample_rate;
+                format.channel_count = channel_count;
+            }
+        }
+
+        self.configured = true;
+        true
+    }
+
+    /// Whether an access unit of this stream has a picture decoders can start from
+    fn is_sync(&self, data: &[u8]) -> bool {
+        let units = NalFormat::AnnexB.nal_units(data);
+
+        match self.format.mime {
+            "video/avc" => units
+                .iter()
+                .any(|nal| AvcNalType::from_header(nal[0]) == AvcNalType::IdrSlice),
+            "video/hevc" => units
+                .iter()
+                .any(|nal| HevcNalType::from_header(nal[0]).is_irap()),
+            _ => true,
+        }
+    }
+}
+
+/// A demuxed sample, waiting to be read
+#[derive(Debug)]
+struct TsSample {
+    pid: u16,
+    time_us: i64,
+    sync: bool,
+    data: Vec<u8>,
+}
+
+/// A pure Rust demuxer for MPEG-2 transport streams, like `.ts` captures of a broadcast or an HLS segment.
+///
+/// The first program of the PAT is demuxed. Its H.264, HEVC, AAC (ADTS), MPEG audio, AC-3 and E-AC-3 streams become tracks,
+/// once their format has been found in the first megabytes of the stream. Streams of other types are skipped.
+///
+/// It works like a [MediaExtractor](crate::MediaExtractor): tracks have to be selected, then the samples of the
+/// selected tracks are returned in stream order. Video samples are whole PES packets in Annex-B format,
+/// ADTS frames are returned without their header with an AudioSpecificConfig as `csd-0`.
+/// Times are in microseconds from the first timestamp of the program.
+///
+/// Lost packets are detected with the continuity counters: the PES packet they belonged to is dropped.
+/// Transport streams have no index, so the demuxer can't seek
+#[derive(Debug)]
+pub struct TsDemuxer<R: Read> {
+    reader: R,
+    pmt_pid: Option<u16>,
+    pcr_pid: Option<u16>,
+    /// The last program clock reference, in 27MHz units
+    pcr: Option<u64>,
+    /// The PSI sections being reassembled, by PID
+    sections: HashMap<u16, Vec<u8>>,
+    streams: Vec<TsStream>,
+    queue: VecDeque<TsSample>,
+    first_timestamp: Option<i64>,
+    /// The streams are still being added and configured
+    probing: bool,
+    bytes_read: u64,
+    continuity_errors: u64,
+    eof: bool,
+}
+
+impl TsDemuxer<BufReader<File>> {
+    /// Opens a transport stream file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open transport stream {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> TsDemuxer<R> {
+    /// Creates a demuxer, reading the start of the stream to find the tracks.
+    ///
+    /// The samples read meanwhile are kept, nothing is lost
+    pub fn new(reader: R) -> Result<Self, MediaStatus> {
+        let mut me = Self {
+            reader,
+            pmt_pid: None,
+            pcr_pid: None,
+            pcr: None,
+            sections: HashMap::new(),
+            streams: vec![],
+            queue: VecDeque::new(),
+            first_timestamp: None,
+            probing: true,
+            bytes_read: 0,
+            continuity_errors: 0,
+            eof: false,
+        };
+
+        while me.streams.is_empty() || me.streams.iter().any(|stream| !stream.configured) {
+            if me.bytes_read >= PROBE_SIZE || !me.read_packet()? {
+                break;
+            }
+        }
+
+        for stream in me.streams.iter().filter(|stream| !stream.configured) {
+            warn!(
+                "Skipping stream {:#x} of type {:#x}, its format wasn't found",
+                stream.pid, stream.stream_type
+            );
+        }
+        me.streams.retain(|stream| stream.configured);
+
+        if me.streams.is_empty() {
+            warn!("No supported stream in the transport stream");
+            return Err(MediaStatus::ErrorMalformed);
+        }
+
+        me.probing = false;
+        Ok(me)
+    }
+
+    /// Returns the number of tracks in the program
+    pub fn track_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Returns the description of a track. The track id is the PID of the stream
+    pub fn track(&self, index: usize) -> Option<&TrackFormat> {
+        self.streams.get(index).map(|stream| &stream.format)
+    }
+
+    /// Returns the PID of the elementary stream of a track
+    pub fn track_pid(&self, index: usize) -> Option<u16> {
+        self.streams.get(index).map(|stream| stream.pid)
+    }
+
+    /// Returns the format of a track, like [MediaExtractor::track_format](crate::MediaExtractor::track_format).
+    ///
+    /// [into_media_format](TrackFormat::into_media_format) turns it into the `MediaFormat` to create and initialize MediaCodec
+    pub fn track_format(&self, index: usize) -> Option<TrackFormat> {
+        self.track(index).cloned()
+    }
+
+    /// Returns the last program clock reference, in microseconds since the start of the PCR clock
+    pub fn pcr_us(&self) -> Option<i64> {
+        self.pcr.map(|pcr| (pcr / 27) as i64)
+    }
+
+    /// Returns the number of times packets were missing, according to the continuity counters
+    pub fn continuity_errors(&self) -> u64 {
+        self.continuity_errors
+    }
+
+    /// Select this track to be demuxed
+    pub fn select_track(&mut self, index: usize) {
+        if let Some(stream) = self.streams.get_mut(index) {
+            stream.selected = true;
+        }
+
+        self.update_current();
+    }
+
+    /// Unselect this track to be demuxed
+    pub fn unselect_track(&mut self, index: usize) {
+        if let Some(stream) = self.streams.get_mut(index) {
+            stream.selected = false;
+            stream.pes = None;
+        }
+
+        self.update_current();
+    }
+
+    /// Returns the track index of the current sample, or -1 if there's none
+    pub fn track_index(&self) -> i32 {
+        self.current()
+            .and_then(|sample| self.stream_index(sample.pid))
+            .map_or(-1, |index| index as i32)
+    }
+
+    /// Returns the presentation time of the current sample in microseconds, or -1 if there's none
+    pub fn sample_time(&self) -> i64 {
+        self.current().map_or(-1, |sample| sample.time_us)
+    }
+
+    /// Returns the sample flags of the current sample, like [MediaExtractor::sample_flags](crate::MediaExtractor::sample_flags)
+    pub fn sample_flags(&self) -> u32 {
+        match self.current() {
+            Some(sample) if sample.sync => SAMPLE_FLAG_SYNC,
+            _ => 0,
+        }
+    }
+
+    /// Returns whether there are still samples to read in the selected tracks
+    pub fn has_next(&self) -> bool {
+        self.current().is_some()
+    }
+
+    /// Reads the current sample into `buffer`, without advancing.
+    ///
+    /// Returns the size of the sample, or `None` if there's no sample or `buffer` is too small
+    pub fn read_sample(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let data = &self.current()?.data;
+        buffer.get_mut(..data.len())?.copy_from_slice(data);
+
+        Some(data.len())
+    }
+
+    /// Advances to the next sample.
+    /// Returns true if there's still more data to read
+    pub fn advance(&mut self) -> bool {
+        if self.current().is_some() {
+            self.queue.pop_front();
+        }
+
+        self.update_current();
+        self.has_next()
+    }
+
+    /// Read a sample into `buffer` and advance the demuxer.
+    /// Returns true if there's still more data to read
+    ///
+    /// Samples that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        let Some(sample) = self.current() else {
+            return false;
+        };
+
+        if buffer.write_data(&sample.data) {
+            buffer.set_time(sample.time_us.max(0) as u64);
+            buffer.set_flags(if sample.sync { SAMPLE_FLAG_SYNC } else { 0 });
+        } else {
+            warn!(
+                "Sample at {}us doesn't fit in the input buffer ({} > {})",
+                sample.time_us,
+                sample.data.len(),
+                buffer.size()
+            );
+        }
+
+        self.advance()
+    }
+
+    fn current(&self) -> Option<&TsSample> {
+        self.queue.front().filter(|sample| {
+            self.stream_index(sample.pid)
+                .is_some_and(|index| self.streams[index].selected)
+        })
+    }
+
+    fn stream_index(&self, pid: u16) -> Option<usize> {
+        self.streams.iter().position(|stream| stream.pid == pid)
+    }
+
+    /// Reads until the first queued sample belongs to a selected track, dropping the samples of the other tracks
+    fn update_current(&mut self) {
+        if !self.streams.iter().any(|stream| stream.selected) {
+            return;
+        }
+
+        loop {
+            while let Some(sample) = self.queue.front() {
+                if self
+                    .stream_index(sample.pid)
+                    .is_some_and(|index| self.streams[index].selected)
+                {
+                    return;
+                }
+
+                self.queue.pop_front();
+            }
+
+            if self.eof || !self.read_packet().unwrap_or(false) {
+                return;
+            }
+        }
+    }
+
+    /// Reads and handles the next transport packet. Returns false at the end of the stream
+    fn read_packet(&mut self) -> Result<bool, MediaStatus> {
+        let mut packet = [0; TS_PACKET_SIZE];
+
+        let mut skipped = 0;
+        loop {
+            if !self.read_exact(&mut packet[..1])? {
+                return Ok(self.finish());
+            }
+
+            if packet[0] == TS_SYNC_BYTE {
+                break;
+            }
+            skipped += 1;
+        }
+
+        if skipped > 0 {
+            debug!("Skipped {skipped} bytes to find the next transport packet");
+        }
+
+        if !self.read_exact(&mut packet[1..])? {
+            return Ok(self.finish());
+        }
+
+        self.bytes_read += (skipped + TS_PACKET_SIZE) as u64;
+        self.handle_packet(&packet);
+
+        Ok(true)
+    }
+
+    /// Fills `buffer`, returning false at the end of the stream
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<bool, MediaStatus> {
+        match self.reader.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => {
+                warn!("Could not read the transport stream: {error}");
+                self.eof = true;
+                Err(MediaStatus::ErrorIO)
+            }
+        }
+    }
+
+    /// Flushes the PES packets that are still being reassembled at the end of the stream
+    fn finish(&mut self) -> bool {
+        if !self.eof {
+            self.eof = true;
+
+            for index in 0..self.streams.len() {
+                self.flush_pes(index);
+            }
+        }
+
+        false
+    }
+
+    fn handle_packet(&mut self, packet: &[u8; TS_PACKET_SIZE]) {
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        if pid == NULL_PID {
+            return;
+        }
+
+        let stream_index = self.stream_index(pid);
+
+        // transport_error_indicator
+        if packet[1] & 0x80 != 0 {
+            debug!("Corrupted transport packet in PID {pid:#x}");
+            if let Some(index) = stream_index {
+                self.continuity_errors += 1;
+                self.streams[index].pes = None;
+            }
+            return;
+        }
+
+        let unit_start = packet[1] & 0x40 != 0;
+        let has_adaptation_field = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        let counter = packet[3] & 0xf;
+
+        let mut payload = &packet[4..];
+        let mut discontinuity = false;
+
+        if has_adaptation_field {
+            let length = payload[0] as usize;
+            let Some(field) = payload.get(1..1 + length) else {
+                debug!("Invalid adaptation field in PID {pid:#x}");
+                return;
+            };
+
+            if let Some(&flags) = field.first() {
+                discontinuity = flags & 0x80 != 0;
+
+                if flags & 0x10 != 0 && Some(pid) == self.pcr_pid && field.len() >= 7 {
+                    let base = u64::from_be_bytes([
+                        0, 0, 0, field[1], field[2], field[3], field[4], field[5],
+                    ]) >> 7;
+                    let extension = u16::from_be_bytes([field[5] & 1, field[6]]) as u64;
+                    self.pcr = Some(base * 300 + extension);
+                }
+            }
+
+            payload = &payload[1 + length..];
+        }
+
+        if !has_payload {
+            return;
+        }
+
+        if pid == PAT_PID || Some(pid) == self.pmt_pid {
+            self.handle_psi(pid, unit_start, payload);
+            return;
+        }
+
+        let Some(index) = stream_index else {
+            return;
+        };
+
+        match self.streams[index].check_continuity(counter, discontinuity) {
+            None => return,
+            Some(false) => {
+                warn!("Packets lost in PID {pid:#x}, dropping the current PES packet");
+                self.continuity_errors += 1;
+                self.streams[index].pes = None;
+            }
+            Some(true) => {}
+        }
+
+        let stream = &mut self.streams[index];
+        if !self.probing && !stream.selected {
+            return;
+        }
+
+        if unit_start {
+            self.flush_pes(index);
+            self.streams[index].pes = Some(payload.to_vec());
+        } else if let Some(pes) = &mut stream.pes {
+            if pes.len() + payload.len() > MAX_PES_SIZE {
+                warn!("PES packet of PID {pid:#x} is too large, dropping it");
+                stream.pes = None;
+                return;
+            }
+
+            pes.extend_from_slice(payload);
+        }
+
+        // A PES packet with a length is complete without waiting for the next one
+        if let Some(pes) = &self.streams[index].pes {
+            let length = pes
+                .get(4..6)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]));
+            if length.is_some_and(|length| length > 0 && pes.len() >= 6 + length as usize) {
+                self.flush_pes(index);
+            }
+        }
+    }
+
+    /// Adds the payload of a PAT or PMT packet, parsing the section once it's complete
+    fn handle_psi(&mut self, pid: u16, unit_start: bool, payload: &[u8]) {
+        let section = self.sections.entry(pid).or_default();
+
+        if unit_start {
+            let pointer = payload[0] as usize;
+            section.clear();
+            section.extend_from_slice(payload.get(1 + pointer..).unwrap_or_default());
+        } else if !section.is_empty() {
+            section.extend_from_slice(payload);
+        }
+
+        let Some(length) = section
+            .get(1..3)
+            .map(|bytes| u16::from_be_bytes([bytes[0] & 0xf, bytes[1]]) as usize)
+        else {
+            return;
+        };
+
+        if section.len() < 3 + length {
+            return;
+        }
+
+        let section = std::mem::take(section);
+        let section = &section[..3 + length];
+
+        if length < 9 || mpeg_crc32(section) != 0 {
+            warn!("Invalid PSI section in PID {pid:#x}");
+            return;
+        }
+
+        match section[0] {
+            PAT_TABLE_ID if pid == PAT_PID => self.parse_pat(section),
+            PMT_TABLE_ID => self.parse_pmt(section),
+            _ => {}
+        }
+    }
+
+    fn parse_pat(&mut self, section: &[u8]) {
+        // The header is 8 bytes and the CRC 4
+        let programs = section[8..section.len() - 4].chunks_exact(4);
+
+        let pmt_pid = programs
+            .map(|entry| {
+                (
+                    u16::from_be_bytes([entry[0], entry[1]]),
+                    u16::from_be_bytes([entry[2] & 0x1f, entry[3]]),
+                )
+            })
+            // Program 0 is the network information table
+            .find(|(program, _)| *program != 0)
+            .map(|(_, pid)| pid);
+
+        if pmt_pid.is_some() && self.pmt_pid.is_none() {
+            self.pmt_pid = pmt_pid;
+        }
+    }
+
+    fn parse_pmt(&mut self, section: &[u8]) {
+        if !self.streams.is_empty() || !self.probing {
+            return;
+        }
+
+        let mut reader = ByteReader::new(&section[8..section.len() - 4]);
+        let (Some(pcr_pid), Some(info_length)) = (reader.read_u16(), reader.read_u16()) else {
+            return;
+        };
+
+        self.pcr_pid = Some(pcr_pid & 0x1fff);
+        if reader.skip((info_length & 0xfff) as usize).is_none() {
+            return;
+        }
+
+        while reader.remaining() >= 5 {
+            let stream_type = reader.read_u8().unwrap_or_default();
+            let pid = reader.read_u16().unwrap_or_default() & 0x1fff;
+            let info_length = (reader.read_u16().unwrap_or_default() & 0xfff) as usize;
+            let Some(descriptors) = reader.read_bytes(info_length) else {
+                break;
+            };
+
+            match stream_mime(stream_type, descriptors) {
+                Some(mime) => {
+                    let language = stream_language(descriptors);
+                    self.streams
+                        .push(TsStream::new(pid, stream_type, mime, language));
+                }
+                None => debug!("Skipping stream {pid:#x} of type {stream_type:#x}"),
+            }
+        }
+    }
+
+    /// Turns the PES packet being reassembled for a stream into samples
+    fn flush_pes(&mut self, index: usize) {
+        let Some(pes) = self.streams[index].pes.take() else {
+            return;
+        };
+
+        let Some((pts, payload)) = parse_pes(&pes) else {
+            debug!("Invalid PES packet in PID {:#x}", self.streams[index].pid);
+            return;
+        };
+
+        if payload.is_empty() {
+            return;
+        }
+
+        let time_us = pts.map(|pts| {
+            let stream = &mut self.streams[index];
+            let pts = unwrap_timestamp(stream.last_timestamp.or(self.first_timestamp), pts);
+            stream.last_timestamp = Some(pts);
+
+            let first = *self.first_timestamp.get_or_insert(pts);
+            (pts - first) * 1_000_000 / TS_CLOCK_RATE
+        });
+
+        let stream = &mut self.streams[index];
+        if !stream.configured && !stream.configure(payload) {
+            debug!(
+                "Dropping a PES packet of PID {:#x} before its format is known",
+                stream.pid
+            );
+            return;
+        }
+
+        let Some(time_us) = time_us.or(stream.next_time_us) else {
+            debug!(
+                "Dropping a PES packet of PID {:#x} without a timestamp",
+                stream.pid
+            );
+            return;
+        };
+
+        let pid = stream.pid;
+        if stream.format.mime == "audio/mp4a-latm" {
+            let mut time_us = time_us;
+
+            for frame in adts_frames(payload) {
+                self.queue.push_back(TsSample {
+                    pid,
+                    time_us,
+                    sync: true,
+                    data: frame.payload.to_vec(),
+                });
+
+                let config = frame.header.audio_specific_config();
+                time_us += config.frame_duration_us() * frame.header.raw_data_blocks as i64;
+            }
+
+            self.streams[index].next_time_us = Some(time_us);
+            return;
+        }
+
+        let sync = stream.is_sync(payload);
+        self.queue.push_back(TsSample {
+            pid,
+            time_us,
+            sync,
+            data: payload.to_vec(),
+        });
+    }
+}
+
+/// Returns the PTS of a PES packet, and its payload
+fn parse_pes(data: &[u8]) -> Option<(Option<i64>, &[u8])> {
+    if !data.starts_with(&[0, 0, 1]) {
+        return None;
+    }
+
+    let stream_id = data[3];
+    let length = u16::from_be_bytes([*data.get(4)?, *data.get(5)?]) as usize;
+    let data = if length > 0 {
+        &data[..data.len().min(6 + length)]
+    } else {
+        data
+    };
+
+    // Padding, private stream 2 and the streams without the optional PES header
+    if matches!(
+        stream_id,
+        0xbc | 0xbe | 0xbf | 0xf0 | 0xf1 | 0xf2 | 0xf8 | 0xff
+    ) {
+        return Some((None, &data[6..]));
+    }
+
+    let flags = *data.get(7)?;
+    let header_length = *data.get(8)? as usize;
+    let payload = data.get(9 + header_length..)?;
+
+    let pts = if flags & 0x80 != 0 {
+        Some(read_timestamp(data.get(9..14)?))
+    } else {
+        None
+    };
+
+    Some((pts, payload))
+}
+
+/// Reads a 33 bit PTS or DTS, split by marker bits
+fn read_timestamp(data: &[u8]) -> i64 {
+    (((data[0] as i64 >> 1) & 0x7) << 30)
+        | ((data[1] as i64) << 22)
+        | ((data[2] as i64 >> 1) << 15)
+        | ((data[3] as i64) << 7)
+        | (data[4] as i64 >> 1)
+}
+
+/// Puts a 33 bit timestamp in the same wrap around period as `reference`, or the next or the previous one if it's closer
+fn unwrap_timestamp(reference: Option<i64>, timestamp: i64) -> i64 {
+    let Some(reference) = reference else {
+        return timestamp;
+    };
+
+    let base = reference - reference.rem_euclid(TIMESTAMP_WRAP);
+    [base - TIMESTAMP_WRAP, base, base + TIMESTAMP_WRAP]
+        .into_iter()
+        .map(|base| base + timestamp)
+        .min_by_key(|candidate| (candidate - reference).abs())
+        .unwrap_or(timestamp)
+}
+
+/// The mime type of a stream of the PMT, `None` for the streams we don't demux
+fn stream_mime(stream_type: u8, descriptors: &[u8]) -> Option<&'static str> {
+    let mut registration = None;
+    let mut private_mime = None;
+
+    for (tag, data) in descriptors_of(descriptors) {
+        match tag {
+            REGISTRATION_DESCRIPTOR => registration = data.get(..4),
+            DVB_AC3_DESCRIPTOR => private_mime = Some("audio/ac3"),
+            DVB_EAC3_DESCRIPTOR => private_mime = Some("audio/eac3"),
+            _ => {}
+        }
+    }
+
+    match (stream_type, registration) {
+        (STREAM_TYPE_AVC, _) => Some("video/avc"),
+        (STREAM_TYPE_HEVC, _) => Some("video/hevc"),
+        (STREAM_TYPE_AAC_ADTS, _) => Some("audio/mp4a-latm"),
+        // The layer is found in the frame headers
+        (STREAM_TYPE_MPEG1_AUDIO | STREAM_TYPE_MPEG2_AUDIO, _) => Some("audio/mpeg"),
+        (STREAM_TYPE_AC3, _) | (_, Some(b"AC-3")) => Some("audio/ac3"),
+        (STREAM_TYPE_EAC3, _) | (_, Some(b"EAC3")) => Some("audio/eac3"),
+        (_, Some(b"HEVC")) => Some("video/hevc"),
+        (STREAM_TYPE_PRIVATE_DATA, _) => private_mime,
+        _ => None,
+    }
+}
+
+/// The ISO 639 language of a stream, from its descriptors
+fn stream_language(descriptors: &[u8]) -> Option<String> {
+    descriptors_of(descriptors)
+        .find(|(tag, _)| *tag == LANGUAGE_DESCRIPTOR)
+        .and_then(|(_, data)| std::str::from_utf8(data.get(..3)?).ok())
+        .filter(|language| !language.eq_ignore_ascii_case("und"))
+        .map(str::to_owned)
+}
+
+/// Iterates over the tags and the data of the descriptors of a PMT entry
+fn descriptors_of(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let tag = *data.first()?;
+        let length = *data.get(1)? as usize;
+        let descriptor = data.get(2..2 + length)?;
+
+        data = &data[2 + length..];
+        Some((tag, descriptor))
+    })
+}
+
+/// Returns the mime type, the sample rate and the channel count of the MPEG audio frame at the start of `data`
+fn parse_mpeg_audio_header(data: &[u8]) -> Option<(&'static str, u32, u32)> {
+    let header = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+    if header >> 21 != 0x7ff {
+        return None;
+    }
+
+    let version = (header >> 19) & 0x3;
+    let layer = (header >> 17) & 0x3;
+    let sample_rate_index = (header >> 10) & 0x3;
+    let channel_mode = (header >> 6) & 0x3;
+
+    let mime = match layer {
+        1 => "audio/mpeg",
+        2 => "audio/mpeg-L2",
+        3 => "audio/mpeg-L1",
+        _ => return None,
+    };
+
+    let sample_rate = [44100, 48000, 32000].get(sample_rate_index as usize)?;
+    let sample_rate = match version {
+        // MPEG-1, MPEG-2 and MPEG-2.5
+        3 => *sample_rate,
+        2 => sample_rate / 2,
+        0 => sample_rate / 4,
+        _ => return None,
+    };
+
+    let channel_count = if channel_mode == 3 { 1 } else { 2 };
+    Some((mime, sample_rate, channel_count))
+}
+
+/// Returns the sample rate and the channel count of the AC-3 or E-AC-3 sync frame at the start of `data`
+fn parse_ac3_header(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(&[0x0b, 0x77]) {
+        return None;
+    }
+
+    let bsid = *data.get(5)? >> 3;
+    if bsid <= 10 {
+        let sample_rate = *[48000, 44100, 32000].get((data[4] >> 6) as usize)?;
+        let acmod = data.get(6)? >> 5;
+
+        return Some((sample_rate, AC3_ACMOD_CHANNELS[acmod as usize]));
+    }
+
+    // E-AC-3: fscod, fscod2 or numblkscod, acmod and lfeon
+    let byte = data[4];
+    let sample_rate = match byte >> 6 {
+        3 => *[24000, 22050, 16000].get(((byte >> 4) & 0x3) as usize)?,
+        fscod => [48000, 44100, 32000][fscod as usize],
+    };
+    let channel_count = AC3_ACMOD_CHANNELS[((byte >> 1) & 0x7) as usize] + (byte & 1) as u32;
+
+    Some((sample_rate, channel_count))
+}
+
+/// The CRC of PSI sections. Computed over a whole section, including its CRC, it's 0
+fn mpeg_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// The PIDs and the repetition intervals of a [TsMuxer](TsMuxer)
+///
+/// By default, the program number is 1, the PMT is on PID 0x1000, the tracks get PIDs from 0x100,
+/// the tables are repeated every 100ms and the PCR every 40ms
+#[derive(Debug, Clone)]
+pub struct TsMuxerOptions {
+    program_number: u16,
+    pmt_pid: u16,
+    first_pid: u16,
+    psi_interval_us: i64,
+    pcr_interval_us: i64,
+}
+
+impl TsMuxerOptions {
+    /// Creates the default options
+    pub fn new() -> Self {
+        Self {
+            program_number: 1,
+            pmt_pid: 0x1000,
+            first_pid: 0x100,
+            psi_interval_us: 100_000,
+            pcr_interval_us: 40_000,
+        }
+    }
+
+    /// The program number of the PAT and the PMT, and the PID of the PMT
+    pub fn program(mut self, program_number: u16, pmt_pid: u16) -> Self {
+        self.program_number = program_number;
+        self.pmt_pid = pmt_pid;
+        self
+    }
+
+    /// The PID of the first track added with [add_track](TsMuxer::add_track), the next ones get the following PIDs
+    pub fn first_pid(mut self, pid: u16) -> Self {
+        self.first_pid = pid;
+        self
+    }
+
+    /// How often the PAT and the PMT are repeated. They're also written before every keyframe of the PCR track
+    pub fn psi_interval_us(mut self, interval_us: i64) -> Self {
+        self.psi_interval_us = interval_us;
+        self
+    }
+
+    /// How often the PCR is written. The MPEG-2 standard wants at most 100ms
+    pub fn pcr_interval_us(mut self, interval_us: i64) -> Self {
+        self.pcr_interval_us = interval_us;
+        self
+    }
+}
+
+impl Default for TsMuxerOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An elementary stream of a [TsMuxer](TsMuxer)
+#[derive(Debug)]
+struct TsMuxerTrack {
+    format: TrackFormat,
+    pid: u16,
+    stream_type: u8,
+    stream_id: u8,
+    continuity_counter: u8,
+    /// The parameter sets of AVC and HEVC tracks in Annex-B format, which go in front of every keyframe
+    parameter_sets: Vec<u8>,
+    /// How the NAL units of the AVC and HEVC samples are delimited
+    nal_format: NalFormat,
+    /// The config of AAC tracks, to write the ADTS headers
+    aac_config: Option<AudioSpecificConfig>,
+}
+
+impl TsMuxerTrack {
+    /// Updates the parameter sets and the AAC config after the codec private data changed
+    fn update_config(&mut self) {
+        let data = &self.format.codec_private;
+
+        match self.format.mime {
+            "video/avc" => {
+                if let Some(record) = AvcDecoderConfigurationRecord::parse(data) {
+                    self.parameter_sets = record.csd0();
+                    self.parameter_sets.extend_from_slice(&record.csd1());
+                }
+            }
+            "video/hevc" => {
+                if let Some(record) = HevcDecoderConfigurationRecord::parse(data) {
+                    self.parameter_sets = record.csd0();
+                }
+            }
+            "audio/mp4a-latm" => self.aac_config = AudioSpecificConfig::parse(data),
+            _ => {}
+        }
+    }
+
+    /// Builds the PES payload of a sample: an access unit delimiter and the parameter sets for video, ADTS framing for AAC
+    fn payload(&self, data: &[u8], sync: bool) -> Option<Vec<u8>> {
+        let (delimiter, parameter_set_type): (&[u8], fn(u8) -> bool) = match self.format.mime {
+            "video/avc" => (&[0x09, 0xf0], |header| {
+                AvcNalType::from_header(header) == AvcNalType::Sps
+            }),
+            "video/hevc" => (&[0x46, 0x01, 0x50], |header| {
+                HevcNalType::from_header(header) == HevcNalType::Sps
+            }),
+            "audio/mp4a-latm" if AdtsHeader::parse(data).is_none() => {
+                return self.aac_config.as_ref()?.to_adts(data);
+            }
+            _ => return Some(data.to_vec()),
+        };
+
+        let units = self.nal_format.nal_units(data);
+        let mut output = Vec::with_capacity(data.len() + self.parameter_sets.len() + 16);
+
+        output.extend_from_slice(&ANNEXB_START_CODE);
+        output.extend_from_slice(delimiter);
+
+        if sync && !units.iter().any(|nal| parameter_set_type(nal[0])) {
+            output.extend_from_slice(&self.parameter_sets);
+        }
+
+        for nal in units {
+            if nal[0] == delimiter[0] {
+                continue;
+            }
+
+            output.extend_from_slice(&ANNEXB_START_CODE);
+            output.extend_from_slice(nal);
+        }
+
+        Some(output)
+    }
+}
+
+/// A pure Rust muxer writing encoder output into an MPEG-2 transport stream, with a single program.
+///
+/// H.264, HEVC, AAC, MPEG audio, AC-3 and E-AC-3 tracks are supported. Video access units get an access unit delimiter,
+/// and the parameter sets in front of every keyframe; raw AAC frames get an ADTS header.
+///
+/// The PCR is carried by the first video track, or the first track if there's no video. The timestamps are put a
+/// little ahead of it, so the PCR never gets past a PES packet that has yet to be decoded
+#[derive(Debug)]
+pub struct TsMuxer<W: Write> {
+    writer: W,
+    options: TsMuxerOptions,
+    tracks: Vec<TsMuxerTrack>,
+    started: bool,
+    pat_continuity_counter: u8,
+    pmt_continuity_counter: u8,
+    last_psi_us: Option<i64>,
+    last_pcr_us: Option<i64>,
+    /// The last PCR written, in 90kHz units
+    pcr: i64,
+}
+
+impl TsMuxer<BufWriter<File>> {
+    /// Creates a transport stream file
+    pub fn create(path: &str, options: TsMuxerOptions) -> Result<Self, MediaStatus> {
+        let file = File::create(path).map_err(|error| {
+            warn!("Could not create transport stream {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Ok(Self::new(BufWriter::new(file), options))
+    }
+}
+
+impl<W: Write> TsMuxer<W> {
+    /// Creates a muxer. Tracks have to be added before the first sample is written
+    pub fn new(writer: W, options: TsMuxerOptions) -> Self {
+        Self {
+            writer,
+            options,
+            tracks: vec![],
+            started: false,
+            pat_continuity_counter: 0,
+            pmt_continuity_counter: 0,
+            last_psi_us: None,
+            last_pcr_us: None,
+            pcr: 0,
+        }
+    }
+
+    /// Adds a track, with the next PID after the ones of the previous tracks. Returns the index of the track
+    pub fn add_track(&mut self, format: TrackFormat) -> Result<usize, MediaStatus> {
+        let pid = self
+            .tracks
+            .last()
+            .map_or(self.options.first_pid, |track| track.pid + 1);
+        self.add_track_with_pid(format, pid)
+    }
+
+    /// Adds a track, from the output format of an encoder. Returns the index of the track
+    #[cfg(target_os = "android")]
+    pub fn add_media_format(&mut self, format: &MediaFormat) -> Result<usize, MediaStatus> {
+        let track = TrackFormat::from_media_format(format).ok_or(MediaStatus::ErrorUnsupported)?;
+        self.add_track(track)
+    }
+
+    /// Adds a track on a given PID. Returns the index of the track
+    pub fn add_track_with_pid(
+        &mut self,
+        format: TrackFormat,
+        pid: u16,
+    ) -> Result<usize, MediaStatus> {
+        if self.started {
+            warn!("Tracks can't be added to a transport stream after the first sample");
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        let reserved = [PAT_PID, NULL_PID, self.options.pmt_pid];
+        if !(0x10..NULL_PID).contains(&pid)
+            || reserved.contains(&pid)
+            || self.tracks.iter().any(|track| track.pid == pid)
+        {
+            warn!("PID {pid:#x} can't be used for a track");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let video_count = self
+            .tracks
+            .iter()
+            .filter(|track| track.format.is_video())
+            .count() as u8;
+        let audio_count = self.tracks.len() as u8 - video_count;
+
+        let (stream_type, stream_id) = match format.mime {
+            "video/avc" => (STREAM_TYPE_AVC, 0xe0 + video_count),
+            "video/hevc" => (STREAM_TYPE_HEVC, 0xe0 + video_count),
+            "audio/mp4a-latm" => (STREAM_TYPE_AAC_ADTS, 0xc0 + audio_count),
+            "audio/mpeg" => (STREAM_TYPE_MPEG1_AUDIO, 0xc0 + audio_count),
+            // private_stream_1
+            "audio/ac3" => (STREAM_TYPE_AC3, 0xbd),
+            "audio/eac3" => (STREAM_TYPE_EAC3, 0xbd),
+            mime => {
+                warn!("{mime} can't be muxed in a transport stream");
+                return Err(MediaStatus::ErrorUnsupported);
+            }
+        };
+
+        if video_count >= 16 || audio_count >= 32 {
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        let mut track = TsMuxerTrack {
+            format,
+            pid,
+            stream_type,
+            stream_id,
+            continuity_counter: 0,
+            parameter_sets: vec![],
+            nal_format: NalFormat::AnnexB,
+            aac_config: None,
+        };
+        track.update_config();
+
+        self.tracks.push(track);
+        Ok(self.tracks.len() - 1)
+    }
+
+    /// Returns the number of tracks
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns the description of a track
+    pub fn track(&self, index: usize) -> Option<&TrackFormat> {
+        self.tracks.get(index).map(|track| &track.format)
+    }
+
+    /// Sets how the NAL units of the AVC and HEVC samples of `track` are delimited. The default is Annex-B
+    pub fn set_nal_format(
+        &mut self,
+        track: usize,
+        nal_format: NalFormat,
+    ) -> Result<(), MediaStatus> {
+        let entry = self
+            .tracks
+            .get_mut(track)
+            .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        entry.nal_format = nal_format;
+        Ok(())
+    }
+
+    /// Writes an encoder output buffer for `track`. Codec config buffers update the parameter sets of the track
+    #[cfg(target_os = "android")]
+    pub fn write(&mut self, track: usize, buffer: &CodecOutputBuffer) -> Result<(), MediaStatus> {
+        let entry = self
+            .tracks
+            .get_mut(track)
+            .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        let info = *buffer.info();
+        let flags = info.flags() as i32;
+
+        let data = match buffer.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return Ok(()),
+        };
+
+        if BufferFlag::CodecConfig.is_contained_in(flags) {
+            if entry.format.set_codec_config(data) {
+                entry.update_config();
+            } else {
+                warn!("Invalid codec config for track {track}");
+            }
+
+            return Ok(());
+        }
+
+        let sync = BufferFlag::Encode.is_contained_in(flags);
+        self.write_sample(track, data, info.presentation_time_us(), sync)
+    }
+
+    /// Writes a sample for `track`, as one PES packet. AVC and HEVC samples are in Annex-B unless [set_nal_format](Self::set_nal_format) says otherwise
+    pub fn write_sample(
+        &mut self,
+        track: usize,
+        data: &[u8],
+        pts_us: i64,
+        sync: bool,
+    ) -> Result<(), MediaStatus> {
+        let pcr_track = self.pcr_track();
+        let entry = self
+            .tracks
+            .get(track)
+            .ok_or(MediaStatus::ErrorInvalidParameter)?;
+
+        let payload = entry.payload(data, sync).ok_or_else(|| {
+            warn!("Track {track} has no codec config to write its samples with");
+            MediaStatus::ErrorInvalidOperation
+        })?;
+
+        let write_psi = !self.started
+            || (track == pcr_track && sync && entry.format.is_video())
+            || self
+                .last_psi_us
+                .is_some_and(|last| pts_us - last >= self.options.psi_interval_us);
+
+        if write_psi {
+            self.write_psi()?;
+            self.last_psi_us = Some(pts_us);
+            self.started = true;
+        }
+
+        // Rounded, so the times survive the round trip through the 90kHz clock
+        let timestamp = (pts_us * TS_CLOCK_RATE + 500_000).div_euclid(1_000_000);
+
+        let write_pcr = match self.last_pcr_us {
+            Some(last) => sync || pts_us - last >= self.options.pcr_interval_us,
+            None => true,
+        };
+
+        let pcr = if track == pcr_track && write_pcr {
+            self.last_pcr_us = Some(pts_us);
+            self.pcr = self.pcr.max(timestamp);
+            Some(self.pcr.rem_euclid(TIMESTAMP_WRAP) as u64)
+        } else {
+            None
+        };
+
+        let pes = pes_packet(
+            self.tracks[track].stream_id,
+            (timestamp + MUX_DELAY).rem_euclid(TIMESTAMP_WRAP),
+            &payload,
+            self.tracks[track].format.is_video(),
+        );
+
+        self.write_pes(track, &pes, pcr, sync)
+    }
+
+    /// Flushes the stream and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, MediaStatus> {
+        self.writer.flush().map_err(write_error)?;
+        Ok(self.writer)
+    }
+
+    /// The track carrying the PCR: the first video track, or the first track
+    fn pcr_track(&self) -> usize {
+        self.tracks
+            .iter()
+            .position(|track| track.format.is_video())
+            .unwrap_or(0)
+    }
+
+    fn write_psi(&mut self) -> Result<(), MediaStatus> {
+        let options = &self.options;
+
+        let mut pat = vec![];
+        pat.extend_from_slice(&options.program_number.to_be_bytes());
+        pat.extend_from_slice(&(0xe000 | options.pmt_pid).to_be_bytes());
+        let pat = psi_section(PAT_TABLE_ID, 1, &pat);
+
+        let pcr_pid = self
+            .tracks
+            .get(self.pcr_track())
+            .map_or(NULL_PID, |track| track.pid);
+        let mut pmt = vec![];
+        pmt.extend_from_slice(&(0xe000 | pcr_pid).to_be_bytes());
+        // No program descriptors
+        pmt.extend_from_slice(&[0xf0, 0]);
+
+        for track in &self.tracks {
+            let mut descriptors = vec![];
+
+            let registration = match track.stream_type {
+                STREAM_TYPE_AC3 => Some(b"AC-3"),
+                STREAM_TYPE_EAC3 => Some(b"EAC3"),
+                _ => None,
+            };
+
+            if let Some(registration) = registration {
+                descriptors.extend_from_slice(&[REGISTRATION_DESCRIPTOR, 4]);
+                descriptors.extend_from_slice(registration);
+            }
+
+            if let Some(language) = track
+                .format
+                .language
+                .as_ref()
+                .filter(|language| language.len() == 3)
+            {
+                descriptors.extend_from_slice(&[LANGUAGE_DESCRIPTOR, 4]);
+                descriptors.extend_from_slice(language.as_bytes());
+                descriptors.push(0);
+            }
+
+            pmt.push(track.stream_type);
+            pmt.extend_from_slice(&(0xe000 | track.pid).to_be_bytes());
+            pmt.extend_from_slice(&(0xf000 | descriptors.len() as u16).to_be_bytes());
+            pmt.extend_from_slice(&descriptors);
+        }
+        let pmt = psi_section(PMT_TABLE_ID, options.program_number, &pmt);
+
+        let pmt_pid = options.pmt_pid;
+        let pat_packet = psi_packet(PAT_PID, &mut self.pat_continuity_counter, &pat)?;
+        let pmt_packet = psi_packet(pmt_pid, &mut self.pmt_continuity_counter, &pmt)?;
+
+        self.writer
+            .write_all(&pat_packet)
+            .and_then(|_| self.writer.write_all(&pmt_packet))
+            .map_err(write_error)
+    }
+
+    /// Splits a PES packet into transport packets, the first one having the PCR and the random access indicator
+    fn write_pes(
+        &mut self,
+        track: usize,
+        pes: &[u8],
+        pcr: Option<u64>,
+        random_access: bool,
+    ) -> Result<(), MediaStatus> {
+        let pid = self.tracks[track].pid;
+        let mut offset = 0;
+
+        while offset < pes.len() {
+            let first = offset == 0;
+            let remaining = pes.len() - offset;
+
+            let mut adaptation_field = vec![];
+            if first && (pcr.is_some() || random_access) {
+                let mut flags = 0;
+                if random_access {
+                    flags |= 0x40;
+                }
+                if pcr.is_some() {
+                    flags |= 0x10;
+                }
+
+                adaptation_field.push(flags);
+                if let Some(pcr) = pcr {
+                    adaptation_field.extend_from_slice(&[
+                        (pcr >> 25) as u8,
+                        (pcr >> 17) as u8,
+                        (pcr >> 9) as u8,
+                        (pcr >> 1) as u8,
+                        // The 6 reserved bits and a PCR extension of 0
+                        ((pcr & 1) << 7) as u8 | 0x7e,
+                        0,
+                    ]);
+                }
+            }
+
+            // The last packet is padded with stuffing bytes in the adaptation field
+            let has_adaptation_field = !adaptation_field.is_empty() || remaining < 184;
+            let adaptation_field_size = if has_adaptation_field {
+                1 + adaptation_field.len()
+            } else {
+                0
+            };
+            let payload_size = remaining.min(184 - adaptation_field_size);
+            let mut stuffing = 184 - adaptation_field_size - payload_size;
+
+            if stuffing > 0 && adaptation_field.is_empty() {
+                adaptation_field.push(0);
+                stuffing -= 1;
+            }
+            adaptation_field.resize(adaptation_field.len() + stuffing, 0xff);
+
+            let entry = &mut self.tracks[track];
+            let mut packet = Vec::with_capacity(TS_PACKET_SIZE);
+            packet.push(TS_SYNC_BYTE);
+            packet.extend_from_slice(&(pid | if first { 0x4000 } else { 0 }).to_be_bytes());
+            packet.push(if has_adaptation_field { 0x30 } else { 0x10 } | entry.continuity_counter);
+            entry.continuity_counter = (entry.continuity_counter + 1) & 0xf;
+
+            if has_adaptation_field {
+                packet.push(adaptation_field.len() as u8);
+                packet.extend_from_slice(&adaptation_field);
+            }
+            packet.extend_from_slice(&pes[offset..offset + payload_size]);
+
+            self.writer.write_all(&packet).map_err(write_error)?;
+            offset += payload_size;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds a PES packet with a PTS. Video packets have no length when they're too large for one
+fn pes_packet(stream_id: u8, pts: i64, payload: &[u8], video: bool) -> Vec<u8> {
+    let mut pes = Vec::with_capacity(payload.len() + 14);
+    pes.extend_from_slice(&[0, 0, 1, stream_id]);
+
+    // The optional header is 8 bytes
+    let length = payload.len() + 8;
+    let length = match u16::try_from(length) {
+        Ok(length) if !video || length < u16::MAX => length,
+        _ => 0,
+    };
+    pes.extend_from_slice(&length.to_be_bytes());
+
+    // data_alignment_indicator, then a PTS without a DTS
+    pes.extend_from_slice(&[0x84, 0x80, 5]);
+    pes.extend_from_slice(&[
+        0x21 | ((pts >> 29) & 0xe) as u8,
+        (pts >> 22) as u8,
+        ((pts >> 14) & 0xfe) as u8 | 1,
+        (pts >> 7) as u8,
+        ((pts << 1) & 0xfe) as u8 | 1,
+    ]);
+    pes.extend_from_slice(payload);
+
+    pes
+}
+
+/// Builds a PSI section with the long header: the table data is `data`, followed by the CRC
+fn psi_section(table_id: u8, table_id_extension: u16, data: &[u8]) -> Vec<u8> {
+    // The 5 bytes after the length, and the CRC
+    let length = data.len() + 5 + 4;
+
+    let mut section = vec![table_id];
+    // section_syntax_indicator and the reserved bits
+    section.extend_from_slice(&(0xb000 | length as u16).to_be_bytes());
+    section.extend_from_slice(&table_id_extension.to_be_bytes());
+    // Version 0, current, section 0 of 0
+    section.extend_from_slice(&[0xc1, 0, 0]);
+    section.extend_from_slice(data);
+
+    let crc = mpeg_crc32(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+
+    section
+}
+
+/// Puts a PSI section in a transport packet
+fn psi_packet(
+    pid: u16,
+    continuity_counter: &mut u8,
+    section: &[u8],
+) -> Result<[u8; TS_PACKET_SIZE], MediaStatus> {
+    // The header and the pointer field
+    if section.len() > TS_PACKET_SIZE - 5 {
+        warn!("The PMT doesn't fit in a transport packet");
+        return Err(MediaStatus::ErrorUnsupported);
+    }
+
+    let mut packet = [0xff; TS_PACKET_SIZE];
+    packet[0] = TS_SYNC_BYTE;
+    packet[1..3].copy_from_slice(&(0x4000 | pid).to_be_bytes());
+    packet[3] = 0x10 | *continuity_counter;
+    packet[4] = 0;
+    packet[5..5 + section.len()].copy_from_slice(section);
+
+    *continuity_counter = (*continuity_counter + 1) & 0xf;
+    Ok(packet)
+}
+
+fn write_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not write the transport stream: {error}");
+    MediaStatus::ErrorIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{annexb_to_length_prefixed, PacketSource};
+
+    /// A 1080p baseline SPS and its PPS, in Annex-B
+    const PARAMETER_SETS: [u8; 21] = [
+        0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95, 0, 0, 1, 0x68,
+        0xce, 0x3c, 0x80,
+    ];
+
+    /// The Annex-B frame `index` of a stream with a keyframe every 30 frames
+    fn video_frame(index: i64) -> Vec<u8> {
+        let header = if index % 30 == 0 { 0x65 } else { 0x41 };
+        let mut frame = vec![0, 0, 0, 1, header, 0x88];
+        frame.extend((0..index as usize * 37).map(|byte| (byte % 200) as u8 + 1));
+        frame
+    }
+
+    /// Two seconds of 30 fps video and of AAC, with the video in `nal_format`
+    fn mux(nal_format: NalFormat) -> Vec<u8> {
+        let mut video = TrackFormat {
+            mime: "video/avc",
+            ..Default::default()
+        };
+        assert!(video.set_codec_config(&PARAMETER_SETS));
+
+        let mut audio = TrackFormat {
+            mime: "audio/mp4a-latm",
+            sample_rate: 48000,
+            channel_count: 2,
+            language: Some("fra".into()),
+            ..Default::default()
+        };
+        assert!(audio.set_codec_config(&[0x11, 0x90]));
+
+        let options = TsMuxerOptions::new().program(3, 0x200).first_pid(0x300);
+        let mut muxer = TsMuxer::new(Vec::new(), options);
+        assert_eq!(muxer.add_track(video).unwrap(), 0);
+        assert_eq!(muxer.add_track_with_pid(audio, 0x3ff).unwrap(), 1);
+        muxer.set_nal_format(0, nal_format).unwrap();
+
+        for index in 0..60 {
+            let frame = match nal_format {
+                NalFormat::AnnexB => video_frame(index),
+                NalFormat::LengthPrefixed(length_size) => {
+                    annexb_to_length_prefixed(&video_frame(index), length_size)
+                }
+            };
+            muxer
+                .write_sample(0, &frame, index * 1_000_000 / 30, index % 30 == 0)
+                .unwrap();
+
+            if index % 2 == 0 {
+                let index = index / 2;
+                let time_us = index * 1024 * 1_000_000 / 48000;
+                muxer
+                    .write_sample(1, &[0x21, index as u8, 0x33], time_us, true)
+                    .unwrap();
+            }
+        }
+
+        muxer.finish().unwrap()
+    }
+
+    fn pid(packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[1] & 0x1f, packet[2]])
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = mux(NalFormat::AnnexB);
+        assert_eq!(data.len() % TS_PACKET_SIZE, 0);
+        assert!(data.chunks(TS_PACKET_SIZE).all(|packet| packet[0] == 0x47));
+
+        let mut demuxer = TsDemuxer::new(Cursor::new(data)).unwrap();
+        assert_eq!(demuxer.track_count(), 2);
+        assert_eq!(demuxer.track_pid(0), Some(0x300));
+        assert_eq!(demuxer.track_pid(1), Some(0x3ff));
+
+        let video = PacketSource::track_format(&demuxer, 0).unwrap();
+        assert_eq!(video.mime, "video/avc");
+        assert_eq!((video.width, video.height), (1920, 1080));
+
+        let audio = PacketSource::track_format(&demuxer, 1).unwrap();
+        assert_eq!(audio.mime, "audio/mp4a-latm");
+        assert_eq!((audio.sample_rate, audio.channel_count), (48000, 2));
+        assert_eq!(audio.language.as_deref(), Some("fra"));
+        assert_eq!(audio.codec_private, [0x11, 0x90]);
+
+        assert!(!demuxer.has_next());
+        demuxer.select_track(0);
+        demuxer.select_track(1);
+
+        let mut buffer = vec![0; 10_000];
+        let (mut video_count, mut audio_count) = (0, 0);
+        while demuxer.has_next() {
+            let size = demuxer.read_sample(&mut buffer).unwrap();
+            let time_us = demuxer.sample_time();
+
+            if demuxer.track_index() == 0 {
+                let sync = video_count % 30 == 0;
+                assert_eq!(time_us, video_count * 1_000_000 / 30);
+                assert_eq!(demuxer.sample_flags() == SAMPLE_FLAG_SYNC, sync);
+
+                // An access unit delimiter, then the parameter sets in front of keyframes
+                assert_eq!(buffer[..6], [0, 0, 0, 1, 0x09, 0xf0]);
+                let parameter_sets = if sync { PARAMETER_SETS.len() + 1 } else { 0 };
+                assert_eq!(size, 6 + parameter_sets + video_frame(video_count).len());
+                video_count += 1;
+            } else {
+                // The ADTS header is removed
+                assert_eq!(buffer[..size], [0x21, audio_count as u8, 0x33]);
+                assert!((time_us - audio_count * 1024 * 1_000_000 / 48000).abs() <= 11);
+                audio_count += 1;
+            }
+
+            demuxer.advance();
+        }
+
+        assert_eq!((video_count, audio_count), (60, 30));
+        assert_eq!(demuxer.continuity_errors(), 0);
+        assert!(demuxer.pcr_us().is_some());
+    }
+
+    #[test]
+    fn length_prefixed_samples() {
+        assert_eq!(mux(NalFormat::LengthPrefixed(4)), mux(NalFormat::AnnexB));
+    }
+
+    #[test]
+    fn lost_packets() {
+        let mut data = mux(NalFormat::AnnexB);
+
+        // Drops a video packet that doesn't start a PES packet
+        let index = data
+            .chunks(TS_PACKET_SIZE)
+            .enumerate()
+            .filter(|(_, packet)| pid(packet) == 0x300 && packet[1] & 0x40 == 0)
+            .nth(40)
+            .unwrap()
+            .0;
+        data.drain(index * TS_PACKET_SIZE..(index + 1) * TS_PACKET_SIZE);
+
+        // And garbage before the first packet
+        let mut stream = vec![1, 2, 3];
+        stream.extend(data);
+
+        let mut demuxer = TsDemuxer::new(Cursor::new(stream)).unwrap();
+        demuxer.select_track(0);
+
+        let mut count = 0;
+        while demuxer.has_next() {
+            count += 1;
+            demuxer.advance();
+        }
+
+        assert_eq!(count, 59);
+        assert_eq!(demuxer.continuity_errors(), 1);
+    }
+}
@@ -0,0 +1,544 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{bitstream::BitReader, VideoColorInfo};
+
+/// Returns whether a VP8 frame is a key frame
+pub fn vp8_is_keyframe(frame: &[u8]) -> bool {
+    frame.first().is_some_and(|tag| tag & 0x01 == 0)
+}
+
+/// The dimensions of a VP8 key frame, from its frame header
+pub fn vp8_keyframe_size(frame: &[u8]) -> Option<(u32, u32)> {
+    if !vp8_is_keyframe(frame) || frame.get(3..6)? != [0x9d, 0x01, 0x2a] {
+        return None;
+    }
+
+    // The top two bits are the scaling mode
+    let width = u16::from_le_bytes([frame[6], *frame.get(7)?]) & 0x3fff;
+    let height = u16::from_le_bytes([*frame.get(8)?, *frame.get(9)?]) & 0x3fff;
+
+    Some((width as u32, height as u32))
+}
+
+/// Creates a `MediaFormat` that can be used to initialize a `video/x-vnd.on2.vp8` decoder, from a key frame
+#[cfg(target_os = "android")]
+pub fn vp8_media_format(keyframe: &[u8]) -> Option<MediaFormat> {
+    let (width, height) = vp8_keyframe_size(keyframe)?;
+    let mut format = MediaFormat::new()?;
+
+    format.set_string("mime", "video/x-vnd.on2.vp8");
+    format.set_i32("width", width as i32);
+    format.set_i32("height", height as i32);
+
+    Some(format)
+}
+
+/// The VP9 `color_space` values
+const VP9_CS_BT_601: u8 = 1;
+const VP9_CS_BT_709: u8 = 2;
+const VP9_CS_SMPTE_170: u8 = 3;
+const VP9_CS_SMPTE_240: u8 = 4;
+const VP9_CS_BT_2020: u8 = 5;
+const VP9_CS_RGB: u8 = 7;
+
+/// The `frame_sync_code` of VP9 key frames and intra-only frames
+const VP9_FRAME_SYNC_CODE: u32 = 0x498342;
+
+/// The information found in the uncompressed header of a VP9 frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vp9FrameHeader {
+    pub profile: u8,
+    pub show_existing_frame: bool,
+    pub keyframe: bool,
+    pub show_frame: bool,
+    pub error_resilient: bool,
+    /// Intra-only frames don't use other frames, but unlike key frames don't reset the references
+    pub intra_only: bool,
+    /// The color config and size are only known for key frames, and intra-only frames of profiles above 0
+    pub bit_depth: u8,
+    pub color_space: u8,
+    pub full_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub width: u32,
+    pub height: u32,
+    /// The reference slots updated with this frame. Frames that update none aren't used as reference
+    pub refresh_frame_flags: u8,
+    /// The base quantizer index of the frame (0 to 255), when the header could be read that far
+    pub base_q_idx: Option<u8>,
+}
+
+impl Vp9FrameHeader {
+    /// Parses the start of the uncompressed header of a frame
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(frame);
+
+        // frame_marker
+        if reader.read_bits(2)? != 2 {
+            return None;
+        }
+
+        let profile_low = reader.read_bits(1)? as u8;
+        let profile = ((reader.read_bits(1)? as u8) << 1) | profile_low;
+        if profile == 3 {
+            // reserved_zero
+            reader.skip_bits(1)?;
+        }
+
+        let mut header = Self {
+            profile,
+            show_existing_frame: reader.read_bit()?,
+            keyframe: false,
+            show_frame: true,
+            error_resilient: false,
+            intra_only: false,
+            bit_depth: 8,
+            color_space: 0,
+            full_range: false,
+            subsampling_x: true,
+            subsampling_y: true,
+            width: 0,
+            height: 0,
+            refresh_frame_flags: 0,
+            base_q_idx: None,
+        };
+
+        if header.show_existing_frame {
+            return Some(header);
+        }
+
+        header.keyframe = !reader.read_bit()?;
+        header.show_frame = reader.read_bit()?;
+        header.error_resilient = reader.read_bit()?;
+
+        if !header.keyframe {
+            header.base_q_idx = header.read_inter_header(&mut reader);
+            return Some(header);
+        }
+
+        if reader.read_bits(24)? != VP9_FRAME_SYNC_CODE {
+            return None;
+        }
+
+        header.read_color_config(&mut reader)?;
+        header.width = reader.read_bits(16)? + 1;
+        header.height = reader.read_bits(16)? + 1;
+        header.refresh_frame_flags = 0xff;
+
+        header.base_q_idx =
+            skip_vp9_render_size(&mut reader).and_then(|_| header.read_base_q_idx(&mut reader));
+
+        Some(header)
+    }
+
+    fn read_color_config(&mut self, reader: &mut BitReader) -> Option<()> {
+        if self.profile >= 2 {
+            self.bit_depth = if reader.read_bit()? { 12 } else { 10 };
+        }
+
+        self.color_space = reader.read_bits(3)? as u8;
+        if self.color_space != VP9_CS_RGB {
+            self.full_range = reader.read_bit()?;
+            if self.profile == 1 || self.profile == 3 {
+                self.subsampling_x = reader.read_bit()?;
+                self.subsampling_y = reader.read_bit()?;
+                reader.skip_bits(1)?;
+            }
+        } else {
+            self.full_range = true;
+            self.subsampling_x = false;
+            self.subsampling_y = false;
+            if self.profile == 1 || self.profile == 3 {
+                reader.skip_bits(1)?;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Reads the header of a frame that isn't a key frame, up to the quantizer
+    fn read_inter_header(&mut self, reader: &mut BitReader) -> Option<u8> {
+        self.intra_only = !self.show_frame && reader.read_bit()?;
+
+        if !self.error_resilient {
+            // reset_frame_context
+            reader.skip_bits(2)?;
+        }
+
+        if self.intra_only {
+            if reader.read_bits(24)? != VP9_FRAME_SYNC_CODE {
+                return None;
+            }
+
+            if self.profile > 0 {
+                self.read_color_config(reader)?;
+            } else {
+                self.color_space = VP9_CS_BT_601;
+            }
+
+            self.refresh_frame_flags = reader.read_bits(8)? as u8;
+            self.width = reader.read_bits(16)? + 1;
+            self.height = reader.read_bits(16)? + 1;
+            skip_vp9_render_size(reader)?;
+        } else {
+            self.refresh_frame_flags = reader.read_bits(8)? as u8;
+            // ref_frame_idx and ref_frame_sign_bias of the 3 references
+            reader.skip_bits(12)?;
+
+            // found_ref, the size is only coded when it's not the size of a reference
+            let mut found_ref = false;
+            for _ in 0..3 {
+                if reader.read_bit()? {
+                    found_ref = true;
+                    break;
+                }
+            }
+
+            if !found_ref {
+                self.width = reader.read_bits(16)? + 1;
+                self.height = reader.read_bits(16)? + 1;
+            }
+
+            skip_vp9_render_size(reader)?;
+            // allow_high_precision_mv
+            reader.skip_bits(1)?;
+            // is_filter_switchable, or the raw interpolation filter
+            if !reader.read_bit()? {
+                reader.skip_bits(2)?;
+            }
+        }
+
+        self.read_base_q_idx(reader)
+    }
+
+    /// Reads the header from `refresh_frame_context` to `base_q_idx`
+    fn read_base_q_idx(&self, reader: &mut BitReader) -> Option<u8> {
+        if !self.error_resilient {
+            // refresh_frame_context and frame_parallel_decoding_mode
+            reader.skip_bits(2)?;
+        }
+
+        // frame_context_idx, filter_level and sharpness_level
+        reader.skip_bits(2 + 6 + 3)?;
+
+        // loop_filter_delta_enabled and loop_filter_delta_update
+        if reader.read_bit()? && reader.read_bit()? {
+            // 4 reference and 2 mode deltas, each with an update bit and a 7 bit value
+            for _ in 0..6 {
+                if reader.read_bit()? {
+                    reader.skip_bits(7)?;
+                }
+            }
+        }
+
+        reader.read_u8()
+    }
+
+    /// The colors of the stream, translated from the VP9 color space
+    pub fn color(&self) -> VideoColorInfo {
+        let (primaries, transfer, matrix) = match self.color_space {
+            VP9_CS_BT_601 => (6, 6, 6),
+            VP9_CS_BT_709 => (1, 1, 1),
+            VP9_CS_SMPTE_170 => (6, 6, 6),
+            VP9_CS_SMPTE_240 => (7, 7, 7),
+            VP9_CS_BT_2020 => (9, 14, 9),
+            VP9_CS_RGB => (1, 13, 0),
+            _ => (2, 2, 2),
+        };
+
+        VideoColorInfo {
+            full_range: self.full_range,
+            primaries,
+            transfer,
+            matrix,
+        }
+    }
+
+    /// The value of the `profile` key in a `MediaFormat` (the `VP9Profile*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_profile(&self) -> i32 {
+        1 << self.profile
+    }
+}
+
+fn skip_vp9_render_size(reader: &mut BitReader) -> Option<()> {
+    // render_and_frame_size_different
+    if reader.read_bit()? {
+        reader.skip_bits(32)?;
+    }
+
+    Some(())
+}
+
+/// The vpcC record, used as the codec private data of VP8 and VP9 in MP4.
+///
+/// This is the content of the `vpcC` box after its version (1) and flags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VpCodecConfigurationRecord {
+    pub profile: u8,
+    /// The level times 10, 0 when unknown
+    pub level: u8,
+    pub bit_depth: u8,
+    /// 0 and 1 are 4:2:0 (vertical and colocated chroma), 2 is 4:2:2 and 3 is 4:4:4
+    pub chroma_subsampling: u8,
+    pub color: VideoColorInfo,
+    pub codec_initialization_data: Vec<u8>,
+}
+
+impl VpCodecConfigurationRecord {
+    /// Builds a record from the header of a VP9 key frame
+    pub fn from_vp9_header(header: &Vp9FrameHeader) -> Option<Self> {
+        if !header.keyframe {
+            return None;
+        }
+
+        let chroma_subsampling = match (header.subsampling_x, header.subsampling_y) {
+            (true, true) => 0,
+            (true, false) => 2,
+            _ => 3,
+        };
+
+        Some(Self {
+            profile: header.profile,
+            level: 0,
+            bit_depth: header.bit_depth,
+            chroma_subsampling,
+            color: header.color(),
+            codec_initialization_data: vec![],
+        })
+    }
+
+    /// Parses a record
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let header = data.get(..8)?;
+        let size = u16::from_be_bytes([header[6], header[7]]) as usize;
+
+        Some(Self {
+            profile: header[0],
+            level: header[1],
+            bit_depth: header[2] >> 4,
+            chroma_subsampling: (header[2] >> 1) & 0x07,
+            color: VideoColorInfo {
+                full_range: header[2] & 0x01 != 0,
+                primaries: header[3],
+                transfer: header[4],
+                matrix: header[5],
+            },
+            codec_initialization_data: data.get(8..8 + size)?.to_vec(),
+        })
+    }
+
+    /// Serializes the record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = vec![
+            self.profile,
+            self.level,
+            (self.bit_depth << 4)
+                | ((self.chroma_subsampling & 0x07) << 1)
+                | self.color.full_range as u8,
+            self.color.primaries,
+            self.color.transfer,
+            self.color.matrix,
+        ];
+
+        output.extend_from_slice(&(self.codec_initialization_data.len() as u16).to_be_bytes());
+        output.extend_from_slice(&self.codec_initialization_data);
+
+        output
+    }
+
+    /// The value of the `level` key in a `MediaFormat` (the `VP9Level*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_level(&self) -> Option<i32> {
+        let index = [10, 11, 20, 21, 30, 31, 40, 41, 50, 51, 52, 60, 61, 62]
+            .iter()
+            .position(|&level| level == self.level)?;
+
+        Some(1 << index)
+    }
+
+    /// The value of the `profile` key in a `MediaFormat` (the `VP9Profile*` constants of `MediaCodecInfo.CodecProfileLevel`)
+    pub fn media_codec_profile(&self) -> i32 {
+        // Profiles 2 and 3 have HDR variants, for PQ and HLG streams
+        let hdr = matches!(self.color.transfer, 16 | 18);
+        match (self.profile, hdr) {
+            (2, true) => 0x1000,
+            (3, true) => 0x2000,
+            (profile, _) => 1 << profile.min(3),
+        }
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize a `video/x-vnd.on2.vp9` decoder.
+    ///
+    /// The record doesn't know about the dimensions of the video, so they have to be provided
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self, width: u32, height: u32) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "video/x-vnd.on2.vp9");
+        format.set_i32("width", width as i32);
+        format.set_i32("height", height as i32);
+        format.set_i32("profile", self.media_codec_profile());
+
+        if let Some(level) = self.media_codec_level() {
+            format.set_i32("level", level);
+        }
+
+        if !self.codec_initialization_data.is_empty() {
+            format.set_buffer("csd-0", &self.codec_initialization_data);
+        }
+
+        self.color.apply_to(&mut format);
+
+        Some(format)
+    }
+}
+
+/// Creates a `MediaFormat` that can be used to initialize a `video/x-vnd.on2.vp9` decoder, from a key frame
+#[cfg(target_os = "android")]
+pub fn vp9_media_format(keyframe: &[u8]) -> Option<MediaFormat> {
+    let header = Vp9FrameHeader::parse(keyframe)?;
+    let record = VpCodecConfigurationRecord::from_vp9_header(&header)?;
+
+    record.media_format(header.width, header.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start of a 720p profile 0 key frame in BT.709
+    const KEY_FRAME: [u8; 14] = [
+        0x82, 0x49, 0x83, 0x42, 0x40, 0x4f, 0xf0, 0x2c, 0xf6, 0x14, 0x38, 0x10, 0x1e, 0x00,
+    ];
+
+    /// The start of a 2160p profile 2 key frame, 10 bit in BT.2020
+    const PROFILE_2_KEY_FRAME: [u8; 12] = [
+        0x92, 0x49, 0x83, 0x42, 0x50, 0x77, 0xf8, 0x43, 0x7b, 0x0a, 0x06, 0x40,
+    ];
+
+    /// The start of an inter frame using the size of its first reference
+    const INTER_FRAME: [u8; 7] = [0x86, 0x00, 0x40, 0x02, 0xf0, 0xa0, 0x50];
+
+    /// A vpcC record for 8 bit 4:2:0 (colocated chroma) at level 3.1, in BT.709
+    const RECORD: [u8; 8] = [0x00, 0x1f, 0x82, 0x01, 0x01, 0x01, 0x00, 0x00];
+
+    #[test]
+    fn vp8() {
+        let key_frame = [0x50, 0x42, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0x68, 0x41];
+        assert!(vp8_is_keyframe(&key_frame));
+        // The scaling bits are left out
+        assert_eq!(vp8_keyframe_size(&key_frame), Some((640, 360)));
+        assert_eq!(vp8_keyframe_size(&key_frame[..9]), None);
+
+        assert!(!vp8_is_keyframe(&[0x51, 0x42, 0x00]));
+        assert!(!vp8_is_keyframe(&[]));
+    }
+
+    #[test]
+    fn vp9_key_frames() {
+        let header = Vp9FrameHeader::parse(&KEY_FRAME).unwrap();
+        assert_eq!(header.profile, 0);
+        assert!(header.keyframe && header.show_frame);
+        assert!(!header.show_existing_frame && !header.error_resilient && !header.intra_only);
+        assert_eq!((header.bit_depth, header.color_space), (8, VP9_CS_BT_709));
+        assert!(header.subsampling_x && header.subsampling_y);
+        assert_eq!((header.width, header.height), (1280, 720));
+        assert_eq!(header.refresh_frame_flags, 0xff);
+        assert_eq!(header.base_q_idx, Some(60));
+        let color = VideoColorInfo {
+            full_range: false,
+            primaries: 1,
+            transfer: 1,
+            matrix: 1,
+        };
+        assert_eq!(header.color(), color);
+        assert_eq!(header.media_codec_profile(), 0x1);
+
+        let header = Vp9FrameHeader::parse(&PROFILE_2_KEY_FRAME).unwrap();
+        assert_eq!(header.profile, 2);
+        assert_eq!((header.bit_depth, header.color_space), (10, VP9_CS_BT_2020));
+        assert!(header.subsampling_x && header.subsampling_y);
+        assert_eq!((header.width, header.height), (3840, 2160));
+        assert_eq!(header.base_q_idx, Some(100));
+        assert_eq!(header.media_codec_profile(), 0x4);
+    }
+
+    #[test]
+    fn vp9_other_frames() {
+        let header = Vp9FrameHeader::parse(&INTER_FRAME).unwrap();
+        assert!(!header.keyframe && !header.intra_only && header.show_frame);
+        assert_eq!(header.refresh_frame_flags, 0x01);
+        // The size comes from the reference
+        assert_eq!((header.width, header.height), (0, 0));
+        assert_eq!(header.base_q_idx, Some(80));
+
+        let header = Vp9FrameHeader::parse(&[0x8a]).unwrap();
+        assert!(header.show_existing_frame && !header.keyframe);
+    }
+
+    #[test]
+    fn vp9_truncated_frames() {
+        // Without the sync code or the size, key frames can't be parsed
+        for end in 0..9 {
+            assert_eq!(Vp9FrameHeader::parse(&KEY_FRAME[..end]), None);
+        }
+
+        // The quantizer is optional
+        let header = Vp9FrameHeader::parse(&KEY_FRAME[..9]).unwrap();
+        assert_eq!((header.width, header.height), (1280, 720));
+        assert_eq!(header.base_q_idx, None);
+
+        let mut bad_sync = KEY_FRAME;
+        bad_sync[2] = 0x84;
+        assert_eq!(Vp9FrameHeader::parse(&bad_sync), None);
+
+        let mut bad_marker = KEY_FRAME;
+        bad_marker[0] = 0x42;
+        assert_eq!(Vp9FrameHeader::parse(&bad_marker), None);
+    }
+
+    #[test]
+    fn record() {
+        let record = VpCodecConfigurationRecord::parse(&RECORD).unwrap();
+        assert_eq!((record.profile, record.level, record.bit_depth), (0, 31, 8));
+        assert_eq!(record.chroma_subsampling, 1);
+        assert_eq!(record.color.primaries, 1);
+        assert!(!record.color.full_range);
+        assert!(record.codec_initialization_data.is_empty());
+        assert_eq!(record.to_bytes(), RECORD);
+        assert_eq!(record.media_codec_level(), Some(0x20));
+        assert_eq!(record.media_codec_profile(), 0x1);
+
+        assert_eq!(VpCodecConfigurationRecord::parse(&RECORD[..7]), None);
+        // Initialization data past the end
+        let mut truncated = RECORD.to_vec();
+        truncated[7] = 2;
+        truncated.push(0xaa);
+        assert_eq!(VpCodecConfigurationRecord::parse(&truncated), None);
+        truncated.push(0xbb);
+        let record = VpCodecConfigurationRecord::parse(&truncated).unwrap();
+        assert_eq!(record.codec_initialization_data, [0xaa, 0xbb]);
+        assert_eq!(record.to_bytes(), truncated);
+
+        // From a key frame, the level is unknown
+        let header = Vp9FrameHeader::parse(&PROFILE_2_KEY_FRAME).unwrap();
+        let record = VpCodecConfigurationRecord::from_vp9_header(&header).unwrap();
+        assert_eq!((record.profile, record.level, record.bit_depth), (2, 0, 10));
+        assert_eq!(record.chroma_subsampling, 0);
+        assert_eq!(
+            (
+                record.color.primaries,
+                record.color.transfer,
+                record.color.matrix
+            ),
+            (9, 14, 9)
+        );
+        assert_eq!(record.media_codec_level(), None);
+        assert_eq!(
+            VpCodecConfigurationRecord::parse(&record.to_bytes()),
+            Some(record)
+        );
+
+        let inter = Vp9FrameHeader::parse(&INTER_FRAME).unwrap();
+        assert_eq!(VpCodecConfigurationRecord::from_vp9_header(&inter), None);
+    }
+}
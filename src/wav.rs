@@ -0,0 +1,765 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    AudioFrame, ChannelLayout, MediaStatus, SampleFormat, ENCODING_PCM_16BIT,
+    ENCODING_PCM_24BIT_PACKED, ENCODING_PCM_32BIT, ENCODING_PCM_8BIT, ENCODING_PCM_FLOAT,
+};
+#[cfg(target_os = "android")]
+use crate::{BufferFlag, CodecInputBuffer, CodecOutputBuffer, MediaFormat};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// The end of the sub format GUIDs of `WAVE_FORMAT_EXTENSIBLE`, after the format tag
+const WAVE_SUBFORMAT_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// The size of the RIFF header, with the `WAVE` form type
+const RIFF_HEADER_SIZE: usize = 12;
+/// The size of a chunk header: its ID and its size
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// The largest `fmt ` chunk we're willing to read
+const MAX_FMT_SIZE: u32 = 1024;
+
+/// The sample format and layout of a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// 8, 16, 24 or 32 for integer samples, 32 for float samples
+    pub bits_per_sample: u16,
+    pub float: bool,
+    /// The speaker positions of the channels, like the `channel-mask` key. Written with `WAVE_FORMAT_EXTENSIBLE`
+    pub channel_mask: Option<u32>,
+}
+
+impl WavFormat {
+    /// A format with integer samples
+    pub fn new(sample_rate: u32, channels: u16, bits_per_sample: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            float: false,
+            channel_mask: None,
+        }
+    }
+
+    /// A format with 32 bit float samples
+    pub fn new_float(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            float: true,
+            ..Self::new(sample_rate, channels, 32)
+        }
+    }
+
+    /// The format of the PCM output of a decoder, from its `sample-rate`, `channel-count`, `pcm-encoding` and `channel-mask`.
+    ///
+    /// Samples are 16 bit when there's no `pcm-encoding`, like Android does
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(format: &MediaFormat) -> Option<Self> {
+        let sample_rate = format.get_i32("sample-rate")?;
+        let channels = format.get_i32("channel-count")?;
+        if sample_rate <= 0 || channels <= 0 {
+            return None;
+        }
+
+        let encoding = format
+            .get_i32("pcm-encoding")
+            .map_or(ENCODING_PCM_16BIT, |encoding| encoding as usize);
+
+        let mut me = match encoding {
+            ENCODING_PCM_8BIT => Self::new(sample_rate as u32, channels as u16, 8),
+            ENCODING_PCM_16BIT => Self::new(sample_rate as u32, channels as u16, 16),
+            ENCODING_PCM_24BIT_PACKED => Self::new(sample_rate as u32, channels as u16, 24),
+            ENCODING_PCM_32BIT => Self::new(sample_rate as u32, channels as u16, 32),
+            ENCODING_PCM_FLOAT => Self::new_float(sample_rate as u32, channels as u16),
+            _ => {
+                debug!("Unsupported PCM encoding {encoding}");
+                return None;
+            }
+        };
+
+        me.channel_mask = format
+            .get_i32("channel-mask")
+            .filter(|mask| *mask > 0)
+            .map(|mask| mask as u32);
+
+        Some(me)
+    }
+
+    /// Creates a `MediaFormat` describing the samples, with the `audio/raw` mime type
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "audio/raw");
+        format.set_i32("sample-rate", self.sample_rate as i32);
+        format.set_i32("channel-count", self.channels as i32);
+        format.set_i32("pcm-encoding", self.pcm_encoding()? as i32);
+
+        if let Some(mask) = self.channel_mask {
+            format.set_i32("channel-mask", mask as i32);
+        }
+
+        Some(format)
+    }
+
+    /// The value of the `pcm-encoding` key for these samples
+    pub fn pcm_encoding(&self) -> Option<usize> {
+        match (self.float, self.bits_per_sample) {
+            (true, 32) => Some(ENCODING_PCM_FLOAT),
+            (false, 8) => Some(ENCODING_PCM_8BIT),
+            (false, 16) => Some(ENCODING_PCM_16BIT),
+            (false, 24) => Some(ENCODING_PCM_24BIT_PACKED),
+            (false, 32) => Some(ENCODING_PCM_32BIT),
+            _ => None,
+        }
+    }
+
+    /// The size of one sample of every channel, in bytes
+    pub fn block_align(&self) -> usize {
+        self.channels as usize * self.bits_per_sample.div_ceil(8) as usize
+    }
+
+    /// Converts a number of samples (per channel) to microseconds
+    pub fn frames_to_us(&self, frames: u64) -> i64 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+
+        (frames as i128 * 1_000_000 / self.sample_rate as i128) as i64
+    }
+
+    /// Parses the content of a `fmt ` chunk
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let le_u16 = |index: usize| {
+            Some(u16::from_le_bytes(
+                data.get(index..index + 2)?.try_into().ok()?,
+            ))
+        };
+        let le_u32 = |index: usize| {
+            Some(u32::from_le_bytes(
+                data.get(index..index + 4)?.try_into().ok()?,
+            ))
+        };
+
+        let mut tag = le_u16(0)?;
+        let channels = le_u16(2)?;
+        let sample_rate = le_u32(4)?;
+        let bits_per_sample = le_u16(14)?;
+        let mut channel_mask = None;
+
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+            // The real format tag starts the sub format GUID
+            if le_u16(16)? < 22 || data.get(26..40)? != WAVE_SUBFORMAT_SUFFIX {
+                return None;
+            }
+
+            channel_mask = le_u32(20)
+                .map(|mask| ChannelLayout::from_wav_mask(mask).mask())
+                .filter(|mask| *mask != 0);
+            tag = le_u16(24)?;
+        }
+
+        let float = match tag {
+            WAVE_FORMAT_PCM => false,
+            WAVE_FORMAT_IEEE_FLOAT => true,
+            _ => {
+                debug!("Unsupported WAV format tag {tag:#x}");
+                return None;
+            }
+        };
+
+        let me = Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            float,
+            channel_mask,
+        };
+
+        if channels == 0 || sample_rate == 0 || me.pcm_encoding().is_none() {
+            return None;
+        }
+
+        Some(me)
+    }
+
+    /// Serializes the `fmt ` chunk content.
+    ///
+    /// `WAVE_FORMAT_EXTENSIBLE` is used for more than 2 channels, more than 16 bits, or with a channel mask
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tag = if self.float {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        let extensible =
+            self.channels > 2 || self.bits_per_sample > 16 || self.channel_mask.is_some();
+
+        let mut output = Vec::with_capacity(40);
+        output.extend_from_slice(
+            &if extensible {
+                WAVE_FORMAT_EXTENSIBLE
+            } else {
+                tag
+            }
+            .to_le_bytes(),
+        );
+        output.extend_from_slice(&self.channels.to_le_bytes());
+        output.extend_from_slice(&self.sample_rate.to_le_bytes());
+        output.extend_from_slice(&(self.sample_rate * self.block_align() as u32).to_le_bytes());
+        output.extend_from_slice(&(self.block_align() as u16).to_le_bytes());
+        output.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+
+        if extensible {
+            output.extend_from_slice(&22u16.to_le_bytes());
+            output.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+            let mask = self
+                .channel_mask
+                .map_or(0, |mask| ChannelLayout::from_mask(mask).wav_mask());
+            output.extend_from_slice(&mask.to_le_bytes());
+            output.extend_from_slice(&tag.to_le_bytes());
+            output.extend_from_slice(&WAVE_SUBFORMAT_SUFFIX);
+        } else if self.float {
+            // Formats other than PCM have an extension size
+            output.extend_from_slice(&0u16.to_le_bytes());
+        }
+
+        output
+    }
+}
+
+/// A chunk of interleaved samples read from a WAV file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WavChunk {
+    /// The presentation time of the first sample, from the number of samples before it
+    pub pts_us: i64,
+    /// The number of samples per channel
+    pub frames: usize,
+    /// The samples, in the little endian format of the file
+    pub data: Vec<u8>,
+}
+
+/// Reads the samples of a WAV file, in chunks that can feed an encoder
+#[derive(Debug)]
+pub struct WavReader<R: Read> {
+    reader: R,
+    format: WavFormat,
+    /// The number of samples per channel in the file
+    frame_count: u64,
+    /// The number of samples per channel read so far
+    position: u64,
+}
+
+impl WavReader<BufReader<File>> {
+    /// Opens a WAV file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open WAV file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> WavReader<R> {
+    /// Creates a reader, reading the chunks up to the samples
+    pub fn new(mut reader: R) -> Result<Self, MediaStatus> {
+        let mut header = [0; RIFF_HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(read_error)?;
+
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            warn!("Not a WAV file");
+            return Err(MediaStatus::ErrorMalformed);
+        }
+
+        let mut format = None;
+        loop {
+            let mut chunk = [0; CHUNK_HEADER_SIZE];
+            reader.read_exact(&mut chunk).map_err(|error| {
+                warn!("No data chunk in the WAV file: {error}");
+                MediaStatus::ErrorMalformed
+            })?;
+
+            let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            match &chunk[0..4] {
+                b"fmt " if size <= MAX_FMT_SIZE => {
+                    let mut data = vec![0; size as usize + (size as usize & 1)];
+                    reader.read_exact(&mut data).map_err(read_error)?;
+
+                    format = Some(WavFormat::parse(&data).ok_or_else(|| {
+                        warn!("Unsupported WAV format");
+                        MediaStatus::ErrorUnsupported
+                    })?);
+                }
+                b"data" => {
+                    let Some(format) = format else {
+                        warn!("The WAV data chunk comes before the format");
+                        return Err(MediaStatus::ErrorMalformed);
+                    };
+
+                    return Ok(Self {
+                        reader,
+                        format,
+                        // Streamed files can have a size of 0 or -1, the samples go to the end of the file
+                        frame_count: match size {
+                            0 | u32::MAX => u64::MAX,
+                            _ => size as u64 / format.block_align() as u64,
+                        },
+                        position: 0,
+                    });
+                }
+                _ => {
+                    // Chunks are padded to an even size
+                    let padded = size as u64 + (size as u64 & 1);
+                    let skipped =
+                        std::io::copy(&mut (&mut reader).take(padded), &mut std::io::sink())
+                            .map_err(read_error)?;
+                    if skipped != padded {
+                        warn!("Truncated WAV chunk");
+                        return Err(MediaStatus::ErrorMalformed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The format of the samples
+    pub fn format(&self) -> &WavFormat {
+        &self.format
+    }
+
+    /// Creates a `MediaFormat` describing the samples, see [WavFormat::media_format]
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        self.format.media_format()
+    }
+
+    /// The number of samples per channel in the file, `None` if the file doesn't say
+    pub fn frame_count(&self) -> Option<u64> {
+        (self.frame_count != u64::MAX).then_some(self.frame_count)
+    }
+
+    /// The duration of the file in microseconds, `None` if the file doesn't say
+    pub fn duration_us(&self) -> Option<i64> {
+        Some(self.format.frames_to_us(self.frame_count()?))
+    }
+
+    /// Returns the time of the next sample in microseconds
+    pub fn sample_time(&self) -> i64 {
+        self.format.frames_to_us(self.position)
+    }
+
+    /// Returns whether there may still be samples to read
+    pub fn has_next(&self) -> bool {
+        self.position < self.frame_count
+    }
+
+    /// Reads up to `max_frames` samples per channel.
+    ///
+    /// Returns `None` at the end of the file. A truncated last sample is dropped
+    pub fn read_chunk(&mut self, max_frames: usize) -> Option<WavChunk> {
+        let frames = (max_frames as u64).min(self.frame_count - self.position) as usize;
+        if frames == 0 {
+            return None;
+        }
+
+        let block_align = self.format.block_align();
+        let mut data = vec![0; frames * block_align];
+
+        let mut length = 0;
+        while length < data.len() {
+            match self.reader.read(&mut data[length..]) {
+                Ok(0) => break,
+                Ok(read) => length += read,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    warn!("Could not read the WAV samples: {error}");
+                    break;
+                }
+            }
+        }
+
+        let frames = length / block_align;
+        data.truncate(frames * block_align);
+
+        if frames == 0 {
+            // The end of the file comes before the size of the data chunk
+            self.frame_count = self.position;
+            return None;
+        }
+
+        let chunk = WavChunk {
+            pts_us: self.sample_time(),
+            frames,
+            data,
+        };
+        self.position += frames as u64;
+
+        Some(chunk)
+    }
+
+    /// Fills `buffer` with as many whole samples as it can hold and advances the reader.
+    /// Returns true if there's still more data to read
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        let max_frames = buffer.size() / self.format.block_align();
+        let Some(chunk) = self.read_chunk(max_frames) else {
+            return false;
+        };
+
+        buffer.write_data(&chunk.data);
+        buffer.set_time(chunk.pts_us as u64);
+        buffer.set_flags(0);
+
+        self.has_next()
+    }
+}
+
+impl<R: Read> Iterator for WavReader<R> {
+    type Item = WavChunk;
+
+    /// Returns chunks of 1024 samples per channel
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_chunk(1024)
+    }
+}
+
+/// Writes PCM samples into a WAV file, like the output of an audio decoder
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: WavFormat,
+    /// The size of the samples written so far, in bytes
+    data_size: u64,
+    /// The position of the RIFF header
+    start: u64,
+    /// The size of the headers, up to the samples
+    header_size: u64,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates a WAV file
+    pub fn create(path: &str, format: WavFormat) -> Result<Self, MediaStatus> {
+        let file = File::create(path).map_err(|error| {
+            warn!("Could not create WAV file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a writer, writing the headers. The sizes are updated by [finish](Self::finish)
+    pub fn new(mut writer: W, format: WavFormat) -> Result<Self, MediaStatus> {
+        if format.pcm_encoding().is_none() || format.channels == 0 {
+            warn!("Unsupported WAV format {format:?}");
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let fmt = format.to_bytes();
+        let mut header = Vec::with_capacity(RIFF_HEADER_SIZE + 2 * CHUNK_HEADER_SIZE + fmt.len());
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        header.extend_from_slice(&fmt);
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        let start = writer.stream_position().map_err(write_error)?;
+        writer.write_all(&header).map_err(write_error)?;
+
+        Ok(Self {
+            writer,
+            format,
+            data_size: 0,
+            start,
+            header_size: header.len() as u64,
+        })
+    }
+
+    /// The format of the samples
+    pub fn format(&self) -> &WavFormat {
+        &self.format
+    }
+
+    /// The number of samples per channel written so far
+    pub fn frame_count(&self) -> u64 {
+        self.data_size / self.format.block_align() as u64
+    }
+
+    /// Appends interleaved samples, in the little endian format of the file
+    pub fn write_samples(&mut self, data: &[u8]) -> Result<(), MediaStatus> {
+        if data.len() / self.format.block_align() * self.format.block_align() != data.len() {
+            warn!("Partial WAV sample of {} bytes", data.len());
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        // The sizes in the headers are 32 bits
+        if self.data_size + data.len() as u64 > (u32::MAX - 64) as u64 {
+            warn!("WAV file too large");
+            return Err(MediaStatus::ErrorInvalidOperation);
+        }
+
+        self.writer.write_all(data).map_err(write_error)?;
+        self.data_size += data.len() as u64;
+
+        Ok(())
+    }
+
+    /// Appends a decoded audio frame. Its sample format and channels must match the file
+    pub fn write_frame(&mut self, frame: &AudioFrame) -> Result<(), MediaStatus> {
+        if frame.channels() != self.format.channels as u32 {
+            warn!(
+                "Audio frame with {} channels in a WAV file with {}",
+                frame.channels(),
+                self.format.channels
+            );
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        let data: Vec<u8> = match (frame.format(), self.format.pcm_encoding()) {
+            (SampleFormat::S16(samples), Some(ENCODING_PCM_16BIT)) => samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+            (SampleFormat::F32(samples), Some(ENCODING_PCM_FLOAT)) => samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+            _ => {
+                warn!("The audio frame doesn't have the sample format of the WAV file");
+                return Err(MediaStatus::ErrorInvalidParameter);
+            }
+        };
+
+        // A partial sample at the end of the buffer is dropped
+        let whole = data.len() - data.len() % self.format.block_align();
+        self.write_samples(&data[..whole])
+    }
+
+    /// Appends the samples of a decoder output buffer. Codec config and empty buffers are skipped
+    #[cfg(target_os = "android")]
+    pub fn write(&mut self, buffer: &CodecOutputBuffer) -> Result<(), MediaStatus> {
+        if BufferFlag::CodecConfig.is_contained_in(buffer.info().flags() as i32) {
+            return Ok(());
+        }
+
+        match buffer.frame() {
+            Some(crate::Frame::Audio(frame)) => self.write_frame(&frame),
+            _ => match buffer.data() {
+                Some(data) if !data.is_empty() => {
+                    let whole = data.len() - data.len() % self.format.block_align();
+                    self.write_samples(&data[..whole])
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Updates the sizes in the headers and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, MediaStatus> {
+        // The data chunk is padded to an even size
+        let padding = self.data_size & 1;
+        if padding != 0 {
+            self.writer.write_all(&[0]).map_err(write_error)?;
+        }
+
+        // The RIFF size counts everything after it
+        let riff_size = (self.header_size - 8 + self.data_size + padding) as u32;
+        let data_size = self.data_size as u32;
+
+        self.writer
+            .seek(SeekFrom::Start(self.start + 4))
+            .and_then(|_| self.writer.write_all(&riff_size.to_le_bytes()))
+            .and_then(|_| {
+                self.writer
+                    .seek(SeekFrom::Start(self.start + self.header_size - 4))
+            })
+            .and_then(|_| self.writer.write_all(&data_size.to_le_bytes()))
+            .and_then(|_| self.writer.seek(SeekFrom::End(0)))
+            .and_then(|_| self.writer.flush())
+            .map_err(write_error)?;
+
+        Ok(self.writer)
+    }
+}
+
+fn read_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not read the WAV file: {error}");
+    MediaStatus::ErrorIO
+}
+
+fn write_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not write WAV data: {error}");
+    MediaStatus::ErrorIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn le_u32(data: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(data[index..index + 4].try_into().unwrap())
+    }
+
+    /// 50 ms of 16 bit stereo at 48 kHz
+    fn s16_file() -> Vec<u8> {
+        let format = WavFormat::new(48000, 2, 16);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+
+        let samples: Vec<i16> = (0..4800)
+            .map(|index| (index % 3000) as i16 - 1000)
+            .collect();
+        writer
+            .write_frame(&AudioFrame::new(SampleFormat::S16(&samples), 2))
+            .unwrap();
+        assert_eq!(writer.frame_count(), 2400);
+
+        // Frames and samples that don't match the format are rejected
+        assert_eq!(
+            writer.write_frame(&AudioFrame::new(SampleFormat::S16(&samples), 1)),
+            Err(MediaStatus::ErrorInvalidParameter)
+        );
+        assert_eq!(
+            writer.write_frame(&AudioFrame::new(SampleFormat::F32(&[0.5; 4]), 2)),
+            Err(MediaStatus::ErrorInvalidParameter)
+        );
+        assert_eq!(
+            writer.write_samples(&[1, 2, 3]),
+            Err(MediaStatus::ErrorInvalidParameter)
+        );
+        assert_eq!(writer.frame_count(), 2400);
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn s16_round_trip() {
+        let data = s16_file();
+        assert_eq!(data.len(), 44 + 9600);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(le_u32(&data, 4), 36 + 9600);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(le_u32(&data, 40), 9600);
+        // Plain PCM, without the extensible header
+        assert_eq!(&data[20..22], &WAVE_FORMAT_PCM.to_le_bytes());
+
+        let mut reader = WavReader::new(Cursor::new(data.clone())).unwrap();
+        assert_eq!(*reader.format(), WavFormat::new(48000, 2, 16));
+        assert_eq!(reader.format().pcm_encoding(), Some(ENCODING_PCM_16BIT));
+        assert_eq!(reader.frame_count(), Some(2400));
+        assert_eq!(reader.duration_us(), Some(50_000));
+
+        let chunks: Vec<WavChunk> = reader.by_ref().collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!((chunks[1].pts_us, chunks[1].frames), (21_333, 1024));
+        assert_eq!((chunks[2].pts_us, chunks[2].frames), (42_666, 352));
+        let samples: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.data.clone()).collect();
+        assert_eq!(samples, data[44..]);
+        assert!(!reader.has_next());
+        assert_eq!(reader.sample_time(), 50_000);
+    }
+
+    #[test]
+    fn skipped_chunks_and_streamed_size() {
+        let data = s16_file();
+
+        // An odd sized chunk before the samples, and the size of a file that was streamed
+        let mut streamed = data[..36].to_vec();
+        streamed.extend_from_slice(b"LIST");
+        streamed.extend_from_slice(&3u32.to_le_bytes());
+        streamed.extend_from_slice(&[1, 2, 3, 0]);
+        streamed.extend_from_slice(b"data");
+        streamed.extend_from_slice(&u32::MAX.to_le_bytes());
+        streamed.extend_from_slice(&data[44..]);
+        // A truncated last sample
+        streamed.push(9);
+
+        let mut reader = WavReader::new(Cursor::new(streamed)).unwrap();
+        assert_eq!(reader.frame_count(), None);
+        assert!(reader.has_next());
+
+        let chunk = reader.read_chunk(5000).unwrap();
+        assert_eq!(chunk.frames, 2400);
+        assert_eq!(chunk.data, data[44..]);
+        assert!(reader.read_chunk(5000).is_none());
+        assert!(!reader.has_next());
+    }
+
+    #[test]
+    fn extensible_formats() {
+        let mut format = WavFormat::new_float(44100, 6);
+        format.channel_mask = Some(ChannelLayout::default_for(6).unwrap().mask());
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        let samples: Vec<f32> = (0..600).map(|index| index as f32 / 600.0).collect();
+        writer
+            .write_frame(&AudioFrame::new(SampleFormat::F32(&samples), 6))
+            .unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(data.len(), RIFF_HEADER_SIZE + 8 + 40 + 8 + 2400);
+        assert_eq!(&data[20..22], &WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+
+        let reader = WavReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(*reader.format(), format);
+        assert_eq!(reader.format().pcm_encoding(), Some(ENCODING_PCM_FLOAT));
+
+        // 24 bit samples, with an odd sized data chunk that gets padded
+        let format = WavFormat::new(8000, 1, 24);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.write_samples(&[1, 2, 3]).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(data.len(), RIFF_HEADER_SIZE + 8 + 40 + 8 + 4);
+        assert_eq!(le_u32(&data, 4) as usize, data.len() - 8);
+
+        let mut reader = WavReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(
+            reader.format().pcm_encoding(),
+            Some(ENCODING_PCM_24BIT_PACKED)
+        );
+        assert_eq!(reader.read_chunk(10).unwrap().data, [1, 2, 3]);
+        assert!(reader.read_chunk(10).is_none());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert_eq!(
+            WavWriter::new(Cursor::new(Vec::new()), WavFormat::new(8000, 1, 12)).unwrap_err(),
+            MediaStatus::ErrorInvalidParameter
+        );
+        assert_eq!(
+            WavReader::new(Cursor::new(b"RIFF\0\0\0\0AVI ".to_vec())).unwrap_err(),
+            MediaStatus::ErrorMalformed
+        );
+
+        // The data chunk must come after the format
+        let mut data = b"RIFF\0\0\0\0WAVEdata".to_vec();
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            WavReader::new(Cursor::new(data)).unwrap_err(),
+            MediaStatus::ErrorMalformed
+        );
+
+        // A-law isn't supported
+        let mut fmt = WavFormat::new(8000, 1, 8).to_bytes();
+        fmt[0] = 6;
+        assert_eq!(WavFormat::parse(&fmt), None);
+    }
+}
@@ -0,0 +1,596 @@
+#[cfg(target_os = "android")]
+use crate::MediaFormat;
+use crate::{bitstream::BitReader, AudioFrame};
+
+/// The sample rate Opus always decodes at, and the unit of the pre-skip
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// The seek pre-roll recommended by the Opus spec (RFC 7845), in nanoseconds
+pub const OPUS_DEFAULT_SEEK_PRE_ROLL_NS: u64 = 80_000_000;
+
+/// The identification header of an Opus stream, which is the `csd-0` buffer of `audio/opus`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpusHead {
+    pub version: u8,
+    pub channel_count: u8,
+    /// The number of samples (at 48kHz) to drop from the start of the decoded audio
+    pub pre_skip: u16,
+    /// The sample rate of the original audio. This is informational only, Opus always decodes at 48kHz
+    pub input_sample_rate: u32,
+    /// The gain to apply to the decoded audio, in Q7.8 dB
+    pub output_gain: i16,
+    pub mapping_family: u8,
+    /// The channel mapping table, only present when `mapping_family` isn't 0
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    /// Creates a header for a mono or stereo stream
+    pub fn new(channel_count: u8, pre_skip: u16, input_sample_rate: u32) -> Self {
+        Self {
+            version: 1,
+            channel_count,
+            pre_skip,
+            input_sample_rate,
+            output_gain: 0,
+            mapping_family: 0,
+            stream_count: 1,
+            coupled_count: channel_count.saturating_sub(1),
+            channel_mapping: vec![],
+        }
+    }
+
+    /// Parses an `OpusHead` packet, like the codec private data of Matroska or the `csd-0` buffer of a `MediaFormat`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 19 || !data.starts_with(b"OpusHead") {
+            return None;
+        }
+
+        let version = data[8];
+        // Only the major version is guaranteed to stay compatible
+        if version >> 4 != 0 {
+            return None;
+        }
+
+        let mut head = Self {
+            version,
+            channel_count: data[9],
+            pre_skip: u16::from_le_bytes([data[10], data[11]]),
+            input_sample_rate: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+            output_gain: i16::from_le_bytes([data[16], data[17]]),
+            mapping_family: data[18],
+            stream_count: 1,
+            coupled_count: data[9].saturating_sub(1),
+            channel_mapping: vec![],
+        };
+
+        if head.mapping_family != 0 {
+            let table = data.get(19..21 + head.channel_count as usize)?;
+            head.stream_count = table[0];
+            head.coupled_count = table[1];
+            head.channel_mapping = table[2..].to_vec();
+        }
+
+        Some(head)
+    }
+
+    /// Serializes the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(21 + self.channel_mapping.len());
+
+        output.extend_from_slice(b"OpusHead");
+        output.push(self.version);
+        output.push(self.channel_count);
+        output.extend_from_slice(&self.pre_skip.to_le_bytes());
+        output.extend_from_slice(&self.input_sample_rate.to_le_bytes());
+        output.extend_from_slice(&self.output_gain.to_le_bytes());
+        output.push(self.mapping_family);
+
+        if self.mapping_family != 0 {
+            output.push(self.stream_count);
+            output.push(self.coupled_count);
+            output.extend_from_slice(&self.channel_mapping);
+        }
+
+        output
+    }
+
+    /// The pre-skip, in nanoseconds
+    pub fn pre_skip_ns(&self) -> u64 {
+        self.pre_skip as u64 * 1_000_000_000 / OPUS_SAMPLE_RATE as u64
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize an `audio/opus` decoder.
+    ///
+    /// Android wants the header in `csd-0`, the pre-skip (codec delay) in `csd-1` and the seek pre-roll in `csd-2`,
+    /// both in nanoseconds as 64 bit little endian integers. The decoder drops the pre-skip samples itself,
+    /// so don't trim its output with [PreSkip::opus] again
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self, seek_pre_roll_ns: u64) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "audio/opus");
+        format.set_i32("sample-rate", OPUS_SAMPLE_RATE as i32);
+        format.set_i32("channel-count", self.channel_count as i32);
+        format.set_buffer("csd-0", &self.to_bytes());
+        format.set_buffer("csd-1", &self.pre_skip_ns().to_le_bytes());
+        format.set_buffer("csd-2", &seek_pre_roll_ns.to_le_bytes());
+
+        Some(format)
+    }
+
+    /// Reads the header from the `csd-0` buffer of a `MediaFormat`.
+    ///
+    /// If there's a `csd-1` buffer, the pre-skip is taken from there, as that's what the decoder will use
+    #[cfg(target_os = "android")]
+    pub fn from_media_format(format: &MediaFormat) -> Option<Self> {
+        let mut head = Self::parse(format.get_buffer("csd-0")?)?;
+
+        if let Some(csd1) = format.get_buffer("csd-1") {
+            let pre_skip_ns = u64::from_le_bytes(csd1.get(..8)?.try_into().ok()?);
+            let pre_skip = pre_skip_ns * OPUS_SAMPLE_RATE as u64 / 1_000_000_000;
+            head.pre_skip = pre_skip.min(u16::MAX as u64) as u16;
+        }
+
+        Some(head)
+    }
+}
+
+/// Drops the first samples of decoded audio, like the Opus pre-skip or the AAC encoder delay.
+///
+/// Feed it every decoded frame in order, and play (or encode) what it returns. Only use it for decoders that keep the
+/// delay in their output: the default trims nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreSkip {
+    remaining: usize,
+}
+
+impl PreSkip {
+    /// Skips `samples` samples (per channel) of audio at `sample_rate`, for a delay expressed at `delay_rate`.
+    ///
+    /// For Opus, `samples` is the pre-skip and `delay_rate` is 48000. The decoder output might be resampled, so pass its actual sample rate as `sample_rate`
+    pub fn new(samples: u64, delay_rate: u32, sample_rate: u32) -> Self {
+        let remaining = if delay_rate == 0 {
+            0
+        } else {
+            (samples * sample_rate as u64 / delay_rate as u64) as usize
+        };
+
+        Self { remaining }
+    }
+
+    /// Creates the trimmer for an Opus stream decoded at `sample_rate`.
+    ///
+    /// This is only for decoders that don't apply the pre-skip themselves. The Android decoder drops the samples given in `csd-1`,
+    /// which [OpusHead::media_format] always sets, so its output is already trimmed
+    pub fn opus(head: &OpusHead, sample_rate: u32) -> Self {
+        Self::new(head.pre_skip as u64, OPUS_SAMPLE_RATE, sample_rate)
+    }
+
+    /// The number of samples (per channel) left to skip
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Trims the start of `frame`.
+    ///
+    /// Returns `None` if the whole frame is skipped
+    pub fn trim<'a>(&mut self, frame: AudioFrame<'a>) -> Option<AudioFrame<'a>> {
+        if self.remaining == 0 {
+            return Some(frame);
+        }
+
+        let samples = frame.nb_samples();
+        let skipped = samples.min(self.remaining);
+        self.remaining -= skipped;
+
+        if skipped == samples {
+            return None;
+        }
+
+        Some(frame.skip_samples(skipped))
+    }
+}
+
+/// Splits Xiph laced packets, as used by the codec private data of Vorbis in Matroska
+pub fn xiph_unlace(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let count = *data.first()? as usize + 1;
+    let mut position = 1;
+
+    let mut sizes = Vec::with_capacity(count);
+    for _ in 0..count - 1 {
+        let mut size = 0;
+        loop {
+            let byte = *data.get(position)?;
+            position += 1;
+            size += byte as usize;
+
+            if byte != 255 {
+                break;
+            }
+        }
+
+        sizes.push(size);
+    }
+
+    let mut packets = Vec::with_capacity(count);
+    for size in sizes {
+        packets.push(data.get(position..position + size)?);
+        position += size;
+    }
+
+    // The last packet takes whatever is left
+    packets.push(&data[position..]);
+
+    Some(packets)
+}
+
+/// Joins packets with Xiph lacing, the reverse of [xiph_unlace]
+pub fn xiph_lace<P: AsRef<[u8]>>(packets: &[P]) -> Vec<u8> {
+    let mut output = vec![packets.len().saturating_sub(1) as u8];
+
+    if let Some((_, rest)) = packets.split_last() {
+        for packet in rest {
+            let mut size = packet.as_ref().len();
+            while size >= 255 {
+                output.push(255);
+                size -= 255;
+            }
+            output.push(size as u8);
+        }
+    }
+
+    for packet in packets {
+        output.extend_from_slice(packet.as_ref());
+    }
+
+    output
+}
+
+/// The identification header of a Vorbis stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VorbisIdentification {
+    pub channel_count: u8,
+    pub sample_rate: u32,
+    pub bitrate_maximum: i32,
+    pub bitrate_nominal: i32,
+    pub bitrate_minimum: i32,
+    pub blocksize_0: u16,
+    pub blocksize_1: u16,
+}
+
+impl VorbisIdentification {
+    /// Parses an identification header packet
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 30 || !is_vorbis_header(data, 1) {
+            return None;
+        }
+
+        let le_u32 = |index: usize| u32::from_le_bytes(data[index..index + 4].try_into().unwrap());
+
+        // vorbis_version must be 0
+        if le_u32(7) != 0 {
+            return None;
+        }
+
+        Some(Self {
+            channel_count: data[11],
+            sample_rate: le_u32(12),
+            bitrate_maximum: le_u32(16) as i32,
+            bitrate_nominal: le_u32(20) as i32,
+            bitrate_minimum: le_u32(24) as i32,
+            blocksize_0: 1 << (data[28] & 0x0f),
+            blocksize_1: 1 << (data[28] >> 4),
+        })
+    }
+}
+
+fn is_vorbis_header(data: &[u8], packet_type: u8) -> bool {
+    data.len() >= 7 && data[0] == packet_type && &data[1..7] == b"vorbis"
+}
+
+/// The three header packets of a Vorbis stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VorbisHeaders {
+    pub identification: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub setup: Vec<u8>,
+}
+
+impl VorbisHeaders {
+    /// Creates the headers from their packets, checking their types
+    pub fn new(identification: &[u8], comment: &[u8], setup: &[u8]) -> Option<Self> {
+        if !is_vorbis_header(identification, 1)
+            || !is_vorbis_header(comment, 3)
+            || !is_vorbis_header(setup, 5)
+        {
+            return None;
+        }
+
+        Some(Self {
+            identification: identification.to_vec(),
+            comment: comment.to_vec(),
+            setup: setup.to_vec(),
+        })
+    }
+
+    /// Reads the headers from the Xiph laced codec private data of Matroska
+    pub fn from_codec_private(data: &[u8]) -> Option<Self> {
+        match xiph_unlace(data)?.as_slice() {
+            [identification, comment, setup] => Self::new(identification, comment, setup),
+            _ => None,
+        }
+    }
+
+    /// The Xiph laced codec private data for Matroska
+    pub fn to_codec_private(&self) -> Vec<u8> {
+        xiph_lace(&[&self.identification, &self.comment, &self.setup])
+    }
+
+    pub fn parse_identification(&self) -> Option<VorbisIdentification> {
+        VorbisIdentification::parse(&self.identification)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize an `audio/vorbis` decoder.
+    ///
+    /// Android wants the identification header in `csd-0` and the setup header in `csd-1`, the comment header isn't needed
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let identification = self.parse_identification()?;
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "audio/vorbis");
+        format.set_i32("sample-rate", identification.sample_rate as i32);
+        format.set_i32("channel-count", identification.channel_count as i32);
+        format.set_buffer("csd-0", &self.identification);
+        format.set_buffer("csd-1", &self.setup);
+
+        if identification.bitrate_nominal > 0 {
+            format.set_i32("bitrate", identification.bitrate_nominal);
+        }
+
+        Some(format)
+    }
+}
+
+/// The marker at the start of FLAC streams
+pub const FLAC_MAGIC: &[u8; 4] = b"fLaC";
+
+/// The STREAMINFO metadata block of a FLAC stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlacStreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    /// The smallest frame size in bytes, 0 when unknown
+    pub min_frame_size: u32,
+    /// The largest frame size in bytes, 0 when unknown
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channel_count: u8,
+    pub bits_per_sample: u8,
+    /// The number of samples (per channel) in the stream, 0 when unknown
+    pub total_samples: u64,
+    pub md5: [u8; 16],
+}
+
+impl FlacStreamInfo {
+    /// Parses the 34 bytes of a STREAMINFO block, without its block header
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data.get(..34)?);
+
+        let mut info = Self {
+            min_block_size: reader.read_u16()?,
+            max_block_size: reader.read_u16()?,
+            min_frame_size: reader.read_bits(24)?,
+            max_frame_size: reader.read_bits(24)?,
+            sample_rate: reader.read_bits(20)?,
+            channel_count: reader.read_bits(3)? as u8 + 1,
+            bits_per_sample: reader.read_bits(5)? as u8 + 1,
+            total_samples: reader.read_bits_u64(36)?,
+            md5: [0; 16],
+        };
+        info.md5.copy_from_slice(&data[18..34]);
+
+        Some(info)
+    }
+
+    /// Finds the STREAMINFO block in codec private data: the metadata blocks, optionally preceded by `fLaC`.
+    ///
+    /// This covers the Matroska codec private data, the `dfLa` box of MP4 and the `csd-0` buffer of a `MediaFormat`
+    pub fn from_codec_private(data: &[u8]) -> Option<Self> {
+        let mut data = data.strip_prefix(FLAC_MAGIC).unwrap_or(data);
+
+        while data.len() >= 4 {
+            let block_type = data[0] & 0x7f;
+            let size = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+            let block = data.get(4..4 + size)?;
+
+            if block_type == 0 {
+                return Self::parse(block);
+            }
+
+            if data[0] & 0x80 != 0 {
+                break;
+            }
+
+            data = &data[4 + size..];
+        }
+
+        None
+    }
+
+    /// Serializes the block, without its block header
+    pub fn to_bytes(&self) -> [u8; 34] {
+        let mut output = [0; 34];
+
+        output[0..2].copy_from_slice(&self.min_block_size.to_be_bytes());
+        output[2..4].copy_from_slice(&self.max_block_size.to_be_bytes());
+        output[4..7].copy_from_slice(&self.min_frame_size.to_be_bytes()[1..]);
+        output[7..10].copy_from_slice(&self.max_frame_size.to_be_bytes()[1..]);
+
+        let packed = ((self.sample_rate as u64 & 0xfffff) << 44)
+            | (((self.channel_count.max(1) - 1) as u64 & 0x07) << 41)
+            | (((self.bits_per_sample.max(1) - 1) as u64 & 0x1f) << 36)
+            | (self.total_samples & 0xf_ffff_ffff);
+        output[10..18].copy_from_slice(&packed.to_be_bytes());
+        output[18..34].copy_from_slice(&self.md5);
+
+        output
+    }
+
+    /// The `fLaC` marker followed by this block as the last metadata block, which is what Android wants in `csd-0`
+    pub fn csd0(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(42);
+
+        output.extend_from_slice(FLAC_MAGIC);
+        output.extend_from_slice(&[0x80, 0, 0, 34]);
+        output.extend_from_slice(&self.to_bytes());
+
+        output
+    }
+
+    /// The duration of the stream in microseconds, if it's known
+    pub fn duration_us(&self) -> Option<i64> {
+        if self.total_samples == 0 || self.sample_rate == 0 {
+            return None;
+        }
+
+        Some((self.total_samples * 1_000_000 / self.sample_rate as u64) as i64)
+    }
+
+    /// Creates a `MediaFormat` that can be used to initialize an `audio/flac` decoder
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "audio/flac");
+        format.set_i32("sample-rate", self.sample_rate as i32);
+        format.set_i32("channel-count", self.channel_count as i32);
+        format.set_buffer("csd-0", &self.csd0());
+
+        if self.max_frame_size > 0 {
+            format.set_i32("max-input-size", self.max_frame_size as i32);
+        }
+
+        if let Some(duration_us) = self.duration_us() {
+            format.set_i64("durationUs", duration_us);
+        }
+
+        Some(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SampleFormat;
+
+    #[test]
+    fn opus_head_round_trip() {
+        let head = OpusHead::new(2, 312, 44100);
+        let bytes = head.to_bytes();
+
+        assert_eq!(bytes.len(), 19);
+        assert_eq!(OpusHead::parse(&bytes), Some(head.clone()));
+        assert_eq!(head.pre_skip_ns(), 6_500_000);
+
+        let surround = OpusHead {
+            mapping_family: 1,
+            stream_count: 4,
+            coupled_count: 2,
+            channel_mapping: vec![0, 4, 1, 2, 3, 5],
+            ..OpusHead::new(6, 312, 48000)
+        };
+        assert_eq!(OpusHead::parse(&surround.to_bytes()), Some(surround));
+    }
+
+    #[test]
+    fn pre_skip() {
+        let head = OpusHead::new(2, 312, 48000);
+        let samples = vec![0i16; 400];
+
+        assert_eq!(PreSkip::default().remaining(), 0);
+
+        let mut pre_skip = PreSkip::opus(&head, 48000);
+        let frame = AudioFrame::new(SampleFormat::S16(&samples[..200]), 2);
+        assert!(pre_skip.trim(frame).is_none());
+        let frame = AudioFrame::new(SampleFormat::S16(&samples), 2);
+        assert!(pre_skip.trim(frame).is_none());
+        assert_eq!(pre_skip.remaining(), 12);
+
+        let frame = AudioFrame::new(SampleFormat::S16(&samples), 2);
+        assert_eq!(pre_skip.trim(frame).unwrap().nb_samples(), 188);
+
+        // Resampled to 24kHz, half as many samples are skipped
+        assert_eq!(PreSkip::opus(&head, 24000).remaining(), 156);
+    }
+
+    #[test]
+    fn xiph_lacing() {
+        let packets = vec![vec![1; 300], vec![2; 255], vec![3; 7]];
+        let laced = xiph_lace(&packets);
+
+        // Two sizes: 300 = 255 + 45, and 255 = 255 + 0
+        assert_eq!(laced[..5], [2, 255, 45, 255, 0]);
+        assert_eq!(laced.len(), 5 + 300 + 255 + 7);
+
+        let unlaced = xiph_unlace(&laced).unwrap();
+        assert_eq!(unlaced, [&packets[0][..], &packets[1], &packets[2]]);
+
+        assert_eq!(xiph_unlace(&laced[..100]), None);
+    }
+
+    #[test]
+    fn vorbis_headers() {
+        let mut identification = vec![1];
+        identification.extend_from_slice(b"vorbis");
+        identification.extend_from_slice(&0u32.to_le_bytes());
+        identification.push(2);
+        identification.extend_from_slice(&44100u32.to_le_bytes());
+        identification.extend_from_slice(&0i32.to_le_bytes());
+        identification.extend_from_slice(&128000i32.to_le_bytes());
+        identification.extend_from_slice(&0i32.to_le_bytes());
+        identification.extend_from_slice(&[0xb8, 1]);
+
+        let mut comment = vec![3];
+        comment.extend_from_slice(b"vorbis");
+        let mut setup = vec![5];
+        setup.extend_from_slice(b"vorbis");
+
+        let headers = VorbisHeaders::new(&identification, &comment, &setup).unwrap();
+        let codec_private = headers.to_codec_private();
+        assert_eq!(
+            VorbisHeaders::from_codec_private(&codec_private),
+            Some(headers.clone())
+        );
+
+        let info = headers.parse_identification().unwrap();
+        assert_eq!((info.channel_count, info.sample_rate), (2, 44100));
+        assert_eq!(info.bitrate_nominal, 128000);
+        assert_eq!((info.blocksize_0, info.blocksize_1), (256, 2048));
+
+        assert_eq!(VorbisHeaders::new(&comment, &identification, &setup), None);
+    }
+
+    #[test]
+    fn flac_stream_info() {
+        let info = FlacStreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            min_frame_size: 14,
+            max_frame_size: 12000,
+            sample_rate: 44100,
+            channel_count: 2,
+            bits_per_sample: 16,
+            total_samples: 441000,
+            md5: [7; 16],
+        };
+
+        assert!(info.csd0().starts_with(FLAC_MAGIC));
+        assert_eq!(FlacStreamInfo::from_codec_private(&info.csd0()), Some(info));
+        assert_eq!(FlacStreamInfo::parse(&info.to_bytes()), Some(info));
+        assert_eq!(info.duration_us(), Some(10_000_000));
+    }
+}
@@ -0,0 +1,814 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+};
+
+use log::{debug, warn};
+
+#[cfg(target_os = "android")]
+use crate::{CodecInputBuffer, MediaFormat};
+use crate::{MediaStatus, PixelFormat, RawVideoFrame, VideoPlane};
+
+const Y4M_MAGIC: &str = "YUV4MPEG2";
+const Y4M_FRAME: &str = "FRAME";
+
+/// The longest header line we're willing to read
+const MAX_HEADER_SIZE: usize = 4096;
+
+/// How the samples of a plane are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SampleEncoding {
+    Bits8,
+    /// 16 bit little endian, with the value in the high 10 bits (P010)
+    High10,
+    /// 16 bit little endian, with the value in the low 10 bits (Y4M)
+    Low10,
+}
+
+impl SampleEncoding {
+    /// How the samples of a pixel format are stored
+    fn of(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::P010 => Self::High10,
+            _ => Self::Bits8,
+        }
+    }
+
+    /// Scales a sample to 16 bits
+    fn to_u16(self, sample: u16) -> u16 {
+        match self {
+            Self::Bits8 => sample << 8,
+            Self::High10 => sample & 0xffc0,
+            Self::Low10 => (sample & 0x3ff) << 6,
+        }
+    }
+
+    fn write(self, sample: u16, output: &mut Vec<u8>) {
+        match self {
+            Self::Bits8 => output.push((sample >> 8) as u8),
+            Self::High10 => output.extend_from_slice(&(sample & 0xffc0).to_le_bytes()),
+            Self::Low10 => output.extend_from_slice(&(sample >> 6).to_le_bytes()),
+        }
+    }
+}
+
+/// The visible rectangle of the Y plane: left, top, width and height
+pub(crate) type Rect = (usize, usize, usize, usize);
+
+/// Copies the samples of `rect` into `output` without padding, in the plane layout of `format` with samples stored as `encoding`
+pub(crate) fn pack_planes(
+    planes: &[VideoPlane; 3],
+    input: SampleEncoding,
+    (left, top, width, height): Rect,
+    format: PixelFormat,
+    encoding: SampleEncoding,
+    output: &mut Vec<u8>,
+) {
+    let copy_plane = |plane: &VideoPlane,
+                      left: usize,
+                      top: usize,
+                      width: usize,
+                      height: usize,
+                      output: &mut Vec<u8>| {
+        for y in top..top + height {
+            // 8 bit rows are copied as is
+            if let (SampleEncoding::Bits8, SampleEncoding::Bits8, Some(row)) =
+                (input, encoding, plane.row(left, y, width))
+            {
+                output.extend_from_slice(row);
+                continue;
+            }
+
+            for x in left..left + width {
+                encoding.write(input.to_u16(plane.sample(x, y)), output);
+            }
+        }
+    };
+
+    copy_plane(&planes[0], left, top, width, height, output);
+
+    let (left, top) = (left / 2, top / 2);
+    let (width, height) = (width.div_ceil(2), height.div_ceil(2));
+    let [_, u, v] = planes;
+
+    match format {
+        PixelFormat::I420 => {
+            copy_plane(u, left, top, width, height, output);
+            copy_plane(v, left, top, width, height, output);
+        }
+        PixelFormat::Yv12 => {
+            copy_plane(v, left, top, width, height, output);
+            copy_plane(u, left, top, width, height, output);
+        }
+        PixelFormat::Nv12 | PixelFormat::Nv21 | PixelFormat::P010 => {
+            let (first, second) = match format {
+                PixelFormat::Nv21 => (v, u),
+                _ => (u, v),
+            };
+
+            for y in top..top + height {
+                for x in left..left + width {
+                    encoding.write(input.to_u16(first.sample(x, y)), output);
+                    encoding.write(input.to_u16(second.sample(x, y)), output);
+                }
+            }
+        }
+    }
+}
+
+/// Copies the visible part of a decoded frame into `output`, converting the sample size if needed
+fn pack_frame(
+    frame: &RawVideoFrame,
+    format: PixelFormat,
+    encoding: SampleEncoding,
+    output: &mut Vec<u8>,
+) -> Result<(), MediaStatus> {
+    let Some(planes) = frame.planes() else {
+        warn!(
+            "The buffer of {} bytes is too small for a {}x{} frame",
+            frame.buffer().len(),
+            frame.width(),
+            frame.height()
+        );
+        return Err(MediaStatus::ErrorMalformed);
+    };
+
+    let input = SampleEncoding::of(frame.pixel_format());
+
+    let crop = frame.crop();
+    let rect = (
+        crop.left as usize,
+        crop.top as usize,
+        crop.width() as usize,
+        crop.height() as usize,
+    );
+
+    pack_planes(&planes, input, rect, format, encoding, output);
+    Ok(())
+}
+
+/// The stream header of a YUV4MPEG2 file. Only 4:2:0 chroma subsampling is supported, with 8 or 10 bit samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Y4mHeader {
+    pub width: u32,
+    pub height: u32,
+    /// The frame rate is `frame_rate_num / frame_rate_den` frames per second
+    pub frame_rate_num: u32,
+    pub frame_rate_den: u32,
+    /// 8, or 10 for `C420p10` files
+    pub bit_depth: u8,
+}
+
+impl Y4mHeader {
+    /// Creates the header of an 8 bit stream
+    pub fn new(width: u32, height: u32, frame_rate_num: u32, frame_rate_den: u32) -> Self {
+        Self {
+            width,
+            height,
+            frame_rate_num,
+            frame_rate_den,
+            bit_depth: 8,
+        }
+    }
+
+    /// Creates the header of the visible part of a decoded frame, 10 bit for P010 frames
+    pub fn from_frame(frame: &RawVideoFrame, frame_rate_num: u32, frame_rate_den: u32) -> Self {
+        let crop = frame.crop();
+
+        Self {
+            bit_depth: match frame.pixel_format() {
+                PixelFormat::P010 => 10,
+                _ => 8,
+            },
+            ..Self::new(crop.width(), crop.height(), frame_rate_num, frame_rate_den)
+        }
+    }
+
+    /// Parses the header line, without its line feed
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parameters = line.split(' ');
+        if parameters.next()? != Y4M_MAGIC {
+            return None;
+        }
+
+        let mut me = Self::new(0, 0, 25, 1);
+        for parameter in parameters.filter(|parameter| !parameter.is_empty()) {
+            let value = parameter.get(1..)?;
+
+            match parameter.as_bytes()[0] {
+                b'W' => me.width = value.parse().ok()?,
+                b'H' => me.height = value.parse().ok()?,
+                b'F' => {
+                    let (num, den) = value.split_once(':')?;
+                    me.frame_rate_num = num.parse().ok()?;
+                    me.frame_rate_den = den.parse().ok()?;
+                }
+                b'C' => {
+                    me.bit_depth = match value {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => 8,
+                        "420p10" => 10,
+                        _ => {
+                            debug!("Unsupported Y4M chroma format {value}");
+                            return None;
+                        }
+                    }
+                }
+                // Interlacing, aspect ratio and extensions don't change how frames are read
+                _ => {}
+            }
+        }
+
+        if me.width == 0 || me.height == 0 || me.frame_rate_num == 0 || me.frame_rate_den == 0 {
+            return None;
+        }
+
+        Some(me)
+    }
+
+    /// Serializes the header line, with its line feed
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let chroma = match self.bit_depth {
+            10 => "420p10",
+            _ => "420jpeg",
+        };
+
+        format!(
+            "{Y4M_MAGIC} W{} H{} F{}:{} Ip A1:1 C{chroma}\n",
+            self.width, self.height, self.frame_rate_num, self.frame_rate_den
+        )
+        .into_bytes()
+    }
+
+    /// The size of the samples of a frame, in bytes
+    pub fn frame_size(&self) -> usize {
+        let size = PixelFormat::I420.frame_size(self.width, self.height);
+
+        match self.bit_depth {
+            10 => size * 2,
+            _ => size,
+        }
+    }
+
+    /// The presentation time of a frame in microseconds, from its index
+    pub fn frame_time_us(&self, index: u64) -> i64 {
+        if self.frame_rate_num == 0 {
+            return 0;
+        }
+
+        (index as i128 * self.frame_rate_den as i128 * 1_000_000 / self.frame_rate_num as i128)
+            as i64
+    }
+}
+
+/// Writes decoded frames into a YUV4MPEG2 file, which tools like ffplay can show.
+///
+/// Only the visible part of the frames is written, without the stride and slice height padding
+#[derive(Debug)]
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    header: Y4mHeader,
+    frame_count: u64,
+    /// Reused between frames
+    frame: Vec<u8>,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    /// Creates a YUV4MPEG2 file
+    pub fn create(path: &str, header: Y4mHeader) -> Result<Self, MediaStatus> {
+        let file = File::create(path).map_err(|error| {
+            warn!("Could not create Y4M file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Creates a writer, writing the stream header
+    pub fn new(mut writer: W, header: Y4mHeader) -> Result<Self, MediaStatus> {
+        if header.bit_depth != 8 && header.bit_depth != 10 {
+            warn!("Unsupported Y4M bit depth {}", header.bit_depth);
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        writer.write_all(&header.to_bytes()).map_err(write_error)?;
+
+        Ok(Self {
+            writer,
+            header,
+            frame_count: 0,
+            frame: vec![],
+        })
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    /// The number of frames written so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Writes the visible part of a decoded frame, which must have the size of the header.
+    ///
+    /// 8 bit frames are written in 10 bit files and the other way around by scaling the samples
+    pub fn write_frame(&mut self, frame: &RawVideoFrame) -> Result<(), MediaStatus> {
+        let crop = frame.crop();
+        if crop.width() != self.header.width || crop.height() != self.header.height {
+            warn!(
+                "{}x{} frame in a {}x{} Y4M file",
+                crop.width(),
+                crop.height(),
+                self.header.width,
+                self.header.height
+            );
+            return Err(MediaStatus::ErrorInvalidParameter);
+        }
+
+        // Y4M has the 10 bits in the low bits, unlike P010
+        let encoding = match self.header.bit_depth {
+            10 => SampleEncoding::Low10,
+            _ => SampleEncoding::Bits8,
+        };
+
+        self.frame.clear();
+        pack_frame(frame, PixelFormat::I420, encoding, &mut self.frame)?;
+
+        self.writer
+            .write_all(Y4M_FRAME.as_bytes())
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.write_all(&self.frame))
+            .map_err(write_error)?;
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Flushes the file and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, MediaStatus> {
+        self.writer.flush().map_err(write_error)?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes the visible part of decoded frames into a file of raw frames without any header, in I420, YV12, NV12, NV21
+/// or P010 layout.
+///
+/// Tools need to be told the size and the layout, like `ffplay -f rawvideo -pixel_format nv12 -video_size 1280x720`
+#[derive(Debug)]
+pub struct RawVideoWriter<W: Write> {
+    writer: W,
+    format: PixelFormat,
+    frame_count: u64,
+    /// Reused between frames
+    frame: Vec<u8>,
+}
+
+impl RawVideoWriter<BufWriter<File>> {
+    /// Creates a raw video file
+    pub fn create(path: &str, format: PixelFormat) -> Result<Self, MediaStatus> {
+        let file = File::create(path).map_err(|error| {
+            warn!("Could not create raw video file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Ok(Self::new(BufWriter::new(file), format))
+    }
+}
+
+impl<W: Write> RawVideoWriter<W> {
+    pub fn new(writer: W, format: PixelFormat) -> Self {
+        Self {
+            writer,
+            format,
+            frame_count: 0,
+            frame: vec![],
+        }
+    }
+
+    /// The layout of the frames in the file
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// The number of frames written so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Writes the visible part of a decoded frame, in the layout of the file
+    pub fn write_frame(&mut self, frame: &RawVideoFrame) -> Result<(), MediaStatus> {
+        self.frame.clear();
+        pack_frame(
+            frame,
+            self.format,
+            SampleEncoding::of(self.format),
+            &mut self.frame,
+        )?;
+
+        self.writer.write_all(&self.frame).map_err(write_error)?;
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Flushes the file and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, MediaStatus> {
+        self.writer.flush().map_err(write_error)?;
+        Ok(self.writer)
+    }
+}
+
+/// A frame read from a YUV4MPEG2 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Y4mFrame {
+    /// The presentation time, from the index of the frame and the frame rate
+    pub pts_us: i64,
+    /// The samples, in the pixel format of the reader
+    pub data: Vec<u8>,
+}
+
+/// Reads the frames of a YUV4MPEG2 file, to feed an encoder.
+///
+/// Frames are returned without padding, in I420 by default for 8 bit files and in P010 for 10 bit files.
+/// [set_pixel_format](Self::set_pixel_format) changes the layout to the one the encoder wants
+#[derive(Debug)]
+pub struct Y4mReader<R: Read> {
+    reader: BufReader<R>,
+    header: Y4mHeader,
+    format: PixelFormat,
+    /// The frame that will be returned next, in the layout of the file
+    current: Option<Y4mFrame>,
+    frame_index: u64,
+}
+
+impl Y4mReader<File> {
+    /// Opens a YUV4MPEG2 file
+    pub fn open(path: &str) -> Result<Self, MediaStatus> {
+        let file = File::open(path).map_err(|error| {
+            warn!("Could not open Y4M file {path}: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        Self::new(file)
+    }
+}
+
+impl<R: Read> Y4mReader<R> {
+    /// Creates a reader, reading the stream header and the first frame
+    pub fn new(reader: R) -> Result<Self, MediaStatus> {
+        let mut reader = BufReader::new(reader);
+
+        let line = read_line(&mut reader)?.ok_or_else(|| {
+            warn!("Empty Y4M file");
+            MediaStatus::ErrorMalformed
+        })?;
+        let header = Y4mHeader::parse(&line).ok_or_else(|| {
+            warn!("Unsupported Y4M header {line:?}");
+            MediaStatus::ErrorUnsupported
+        })?;
+
+        let mut me = Self {
+            reader,
+            header,
+            format: match header.bit_depth {
+                10 => PixelFormat::P010,
+                _ => PixelFormat::I420,
+            },
+            current: None,
+            frame_index: 0,
+        };
+        me.current = me.read_frame_inner()?;
+
+        Ok(me)
+    }
+
+    /// The stream header
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    /// The layout of the frames returned by the reader
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Changes the layout of the frames returned by the reader
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.format = format;
+    }
+
+    /// Creates a `MediaFormat` with the size, frame rate and `color-format` of the frames, to configure an encoder with.
+    ///
+    /// Returns `None` if MediaCodec has no `color-format` for the pixel format
+    #[cfg(target_os = "android")]
+    pub fn media_format(&self) -> Option<MediaFormat> {
+        let mut format = MediaFormat::new()?;
+
+        format.set_string("mime", "video/raw");
+        format.set_i32("width", self.header.width as i32);
+        format.set_i32("height", self.header.height as i32);
+        format.set_i32("color-format", self.format.color_format()?);
+        format.set_f32(
+            "frame-rate",
+            self.header.frame_rate_num as f32 / self.header.frame_rate_den as f32,
+        );
+
+        Some(format)
+    }
+
+    /// Returns whether there are still frames to read
+    pub fn has_next(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the time of the next frame in microseconds, or -1 at the end of the file
+    pub fn sample_time(&self) -> i64 {
+        self.current.as_ref().map_or(-1, |frame| frame.pts_us)
+    }
+
+    /// Returns the next frame and advances the reader
+    pub fn read_frame(&mut self) -> Option<Y4mFrame> {
+        let frame = self.current.take()?;
+
+        // Errors in the middle of the file end the stream, like a truncated file
+        self.current = self.read_frame_inner().unwrap_or(None);
+
+        Some(Y4mFrame {
+            pts_us: frame.pts_us,
+            data: self.convert(frame.data),
+        })
+    }
+
+    /// Read a frame into `buffer` and advance the reader.
+    /// Returns true if there's still more data to read
+    ///
+    /// Frames that don't fit in `buffer` are dropped
+    #[cfg(target_os = "android")]
+    pub fn read_next(&mut self, buffer: &mut CodecInputBuffer) -> bool {
+        let Some(frame) = self.read_frame() else {
+            return false;
+        };
+
+        if buffer.write_data(&frame.data) {
+            buffer.set_time(frame.pts_us as u64);
+            buffer.set_flags(0);
+        } else {
+            warn!(
+                "Frame at {}us doesn't fit in the input buffer ({} > {})",
+                frame.pts_us,
+                frame.data.len(),
+                buffer.size()
+            );
+        }
+
+        self.has_next()
+    }
+
+    fn read_frame_inner(&mut self) -> Result<Option<Y4mFrame>, MediaStatus> {
+        let Some(line) = read_line(&mut self.reader)? else {
+            return Ok(None);
+        };
+
+        if line.split(' ').next() != Some(Y4M_FRAME) {
+            warn!("Invalid Y4M frame header {line:?}");
+            return Err(MediaStatus::ErrorMalformed);
+        }
+
+        let mut data = vec![0; self.header.frame_size()];
+        self.reader.read_exact(&mut data).map_err(|error| {
+            warn!("Truncated Y4M frame: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+        let pts_us = self.header.frame_time_us(self.frame_index);
+        self.frame_index += 1;
+
+        Ok(Some(Y4mFrame { pts_us, data }))
+    }
+
+    /// Converts the samples of a frame from the layout of the file to the pixel format of the reader
+    fn convert(&self, data: Vec<u8>) -> Vec<u8> {
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+        let ten_bit = self.header.bit_depth == 10;
+        let sample_size = if ten_bit { 2 } else { 1 };
+
+        // The file has I420 planes, with 16 bit samples in 10 bit files
+        let luma_size = width * height * sample_size;
+        let chroma_stride = width.div_ceil(2) * sample_size;
+        let chroma_size = chroma_stride * height.div_ceil(2);
+        let plane = |offset: usize, row_stride: usize| VideoPlane {
+            data: &data[offset..],
+            row_stride,
+            pixel_stride: sample_size,
+            bytes_per_sample: sample_size,
+        };
+        let planes = [
+            plane(0, width * sample_size),
+            plane(luma_size, chroma_stride),
+            plane(luma_size + chroma_size, chroma_stride),
+        ];
+
+        match (ten_bit, self.format) {
+            (false, PixelFormat::I420) => data,
+            (ten_bit, format) => {
+                let input = if ten_bit {
+                    SampleEncoding::Low10
+                } else {
+                    SampleEncoding::Bits8
+                };
+
+                let mut output = Vec::with_capacity(format.frame_size(width as u32, height as u32));
+                let rect = (0, 0, width, height);
+                let encoding = SampleEncoding::of(format);
+                pack_planes(&planes, input, rect, format, encoding, &mut output);
+                output
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Y4mReader<R> {
+    type Item = Y4mFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame()
+    }
+}
+
+/// Reads a header line without its line feed. Returns `None` at the end of the file
+fn read_line<R: Read>(reader: &mut BufReader<R>) -> Result<Option<String>, MediaStatus> {
+    let mut line = vec![];
+    let read = reader
+        .by_ref()
+        .take(MAX_HEADER_SIZE as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|error| {
+            warn!("Could not read the Y4M file: {error}");
+            MediaStatus::ErrorIO
+        })?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if line.pop() != Some(b'\n') {
+        warn!("Y4M header line too long or truncated");
+        return Err(MediaStatus::ErrorMalformed);
+    }
+
+    String::from_utf8(line).map(Some).map_err(|_| {
+        warn!("Invalid Y4M header line");
+        MediaStatus::ErrorMalformed
+    })
+}
+
+fn write_error(error: std::io::Error) -> MediaStatus {
+    warn!("Could not write video frames: {error}");
+    MediaStatus::ErrorIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::VideoCrop;
+
+    /// An NV12 8x4 frame with a stride of 10 and a slice height of 6, without the padding after the last row
+    fn nv12() -> Vec<u8> {
+        let (stride, slice_height) = (10, 6);
+        let mut buffer = vec![0xee; stride * slice_height + stride + 8];
+        for y in 0..4 {
+            for x in 0..8 {
+                buffer[y * stride + x] = (y * 16 + x) as u8;
+            }
+        }
+
+        for y in 0..2 {
+            for x in 0..4 {
+                let index = stride * slice_height + y * stride + 2 * x;
+                buffer[index] = 100 + (y * 4 + x) as u8;
+                buffer[index + 1] = 200 + (y * 4 + x) as u8;
+            }
+        }
+
+        buffer
+    }
+
+    /// The frame with its first 2 columns cropped out
+    fn cropped(buffer: &[u8]) -> RawVideoFrame<'_> {
+        RawVideoFrame::new(buffer, PixelFormat::Nv12, 8, 4)
+            .with_layout(10, 6)
+            .with_crop(VideoCrop {
+                left: 2,
+                top: 0,
+                right: 7,
+                bottom: 3,
+            })
+    }
+
+    #[test]
+    fn round_trip() {
+        let buffer = nv12();
+        let frame = cropped(&buffer);
+        assert!(cropped(&buffer[..buffer.len() - 1]).planes().is_none());
+
+        let header = Y4mHeader::from_frame(&frame, 30000, 1001);
+        assert_eq!((header.width, header.height, header.bit_depth), (6, 4, 8));
+
+        let mut writer = Y4mWriter::new(Vec::new(), header).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+        // Frames must have the size of the header
+        assert!(writer
+            .write_frame(&RawVideoFrame::new(&buffer, PixelFormat::Nv12, 8, 4))
+            .is_err());
+        assert_eq!(writer.frame_count(), 2);
+        let data = writer.finish().unwrap();
+
+        let line = b"YUV4MPEG2 W6 H4 F30000:1001 Ip A1:1 C420jpeg\n";
+        assert_eq!(&data[..line.len()], line);
+        assert_eq!(&data[line.len()..line.len() + 6], b"FRAME\n");
+
+        // The visible samples, in I420
+        let i420 = data[line.len() + 6..line.len() + 6 + header.frame_size()].to_vec();
+        assert_eq!(&i420[..6], &[2, 3, 4, 5, 6, 7]);
+        assert_eq!(&i420[18..24], &[50, 51, 52, 53, 54, 55]);
+        assert_eq!(&i420[24..30], &[101, 102, 103, 105, 106, 107]);
+        assert_eq!(&i420[30..36], &[201, 202, 203, 205, 206, 207]);
+
+        let mut reader = Y4mReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(*reader.header(), header);
+        assert_eq!(reader.pixel_format(), PixelFormat::I420);
+
+        let first = reader.read_frame().unwrap();
+        assert_eq!((first.pts_us, &first.data), (0, &i420));
+
+        // Encoders may want NV12
+        reader.set_pixel_format(PixelFormat::Nv12);
+        let second = reader.read_frame().unwrap();
+        assert_eq!(second.pts_us, 33_366);
+        assert_eq!(&second.data[24..30], &[101, 201, 102, 202, 103, 203]);
+        assert!(reader.read_frame().is_none());
+        assert!(!reader.has_next());
+
+        let raw = |format| {
+            let mut writer = RawVideoWriter::new(Vec::new(), format);
+            writer.write_frame(&frame).unwrap();
+            writer.finish().unwrap()
+        };
+        assert_eq!(raw(PixelFormat::I420), i420);
+        assert_eq!(raw(PixelFormat::Nv12), second.data);
+        assert_eq!(&raw(PixelFormat::Yv12)[24..30], &i420[30..36]);
+    }
+
+    #[test]
+    fn ten_bit() {
+        // A 2x2 P010 frame
+        let mut buffer = vec![];
+        for value in [0x3ffu16, 0x200, 0x100, 0x000, 0x155, 0x2aa] {
+            buffer.extend_from_slice(&(value << 6).to_le_bytes());
+        }
+        let frame = RawVideoFrame::new(&buffer, PixelFormat::P010, 2, 2);
+
+        let header = Y4mHeader::from_frame(&frame, 25, 1);
+        assert_eq!(header.bit_depth, 10);
+        let mut writer = Y4mWriter::new(Vec::new(), header).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let data = writer.finish().unwrap();
+
+        // Y4M has the values in the low bits
+        let line = b"YUV4MPEG2 W2 H2 F25:1 Ip A1:1 C420p10\n";
+        assert_eq!(&data[..line.len()], line);
+        assert_eq!(
+            &data[line.len() + 6..],
+            &[0xff, 3, 0, 2, 0, 1, 0, 0, 0x55, 1, 0xaa, 2]
+        );
+
+        let mut reader = Y4mReader::new(Cursor::new(data.clone())).unwrap();
+        assert_eq!(reader.pixel_format(), PixelFormat::P010);
+        assert_eq!(reader.read_frame().unwrap().data, buffer);
+
+        let mut reader = Y4mReader::new(Cursor::new(data)).unwrap();
+        reader.set_pixel_format(PixelFormat::I420);
+        assert_eq!(
+            reader.read_frame().unwrap().data,
+            [0xff, 0x80, 0x40, 0, 0x55, 0xaa]
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(Y4mReader::new(Cursor::new(b"YUV4MPEG2 W2 H2 C444\n".to_vec())).is_err());
+        assert_eq!(Y4mHeader::parse("YUV4MPEG2 W2 H2 \u{e9}x"), None);
+        assert_eq!(Y4mHeader::parse("YUV4MPEG2 W0 H0"), None);
+        assert_eq!(Y4mHeader::parse("YUV4MPEG W2 H2"), None);
+        assert_eq!(
+            Y4mHeader::parse("YUV4MPEG2 W2 H2 XCOLORRANGE=FULL"),
+            Some(Y4mHeader::new(2, 2, 25, 1))
+        );
+    }
+}